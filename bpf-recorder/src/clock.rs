@@ -0,0 +1,92 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Converts the kernel timestamps, nanoseconds of `CLOCK_MONOTONIC`, to the wall clock.
+/// The offset between the clocks is measured once per `PERIOD` of the event time,
+/// rather than for each event. The offset grows at once, but shrinks by at most
+/// `MAX_SLEW` per measurement, so the wall clock of ordered events never goes back,
+/// even if the realtime clock is stepped back.
+pub struct ClockSync {
+    // realtime minus monotonic
+    offset: Duration,
+    // the monotonic clock at the last measurement
+    synced_at: Duration,
+    // the event timestamp which triggered the last measurement
+    synced_ts: Duration,
+}
+
+impl Default for ClockSync {
+    fn default() -> Self {
+        let (mono, real) = read_clocks();
+        ClockSync {
+            offset: real.saturating_sub(mono),
+            synced_at: mono,
+            synced_ts: mono,
+        }
+    }
+}
+
+impl ClockSync {
+    const PERIOD: Duration = Duration::from_secs(1);
+    const MAX_SLEW: Duration = Duration::from_millis(1);
+
+    fn update(&mut self, mono: Duration, real: Duration, ts: Duration) {
+        let offset = real.saturating_sub(mono);
+        self.offset = offset.max(self.offset.saturating_sub(Self::MAX_SLEW));
+        self.synced_at = mono;
+        self.synced_ts = ts;
+    }
+
+    /// The wall clock of the kernel timestamp and how long ago it happened,
+    /// the latter is underestimated by at most `PERIOD`.
+    pub fn convert(&mut self, ts: u64) -> (SystemTime, Duration) {
+        let ts = Duration::from_nanos(ts);
+        if ts >= self.synced_ts + Self::PERIOD {
+            let (mono, real) = read_clocks();
+            self.update(mono, real, ts);
+        }
+        (
+            UNIX_EPOCH + self.offset + ts,
+            self.synced_at.saturating_sub(ts),
+        )
+    }
+}
+
+fn clock(id: libc::clockid_t) -> Duration {
+    let mut tp = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(id, &mut tp) };
+    Duration::new(tp.tv_sec as _, tp.tv_nsec as _)
+}
+
+// the realtime is read between two monotonic reads, the middle is taken
+fn read_clocks() -> (Duration, Duration) {
+    let before = clock(libc::CLOCK_MONOTONIC);
+    let real = clock(libc::CLOCK_REALTIME);
+    let after = clock(libc::CLOCK_MONOTONIC);
+    (before + after.saturating_sub(before) / 2, real)
+}
+
+#[cfg(test)]
+#[test]
+fn slew_back() {
+    let s = Duration::from_secs;
+    let mut clock = ClockSync {
+        offset: s(1000),
+        synced_at: s(10),
+        synced_ts: s(10),
+    };
+    let (a, _) = clock.convert(s(10).as_nanos() as u64);
+
+    // the realtime is stepped back by a second
+    clock.update(s(11), s(1010), s(11));
+    assert_eq!(clock.offset, s(1000) - ClockSync::MAX_SLEW);
+    let (b, lag) = clock.convert(s(11).as_nanos() as u64);
+    assert!(b > a);
+    assert_eq!(lag, Duration::ZERO);
+
+    // forward at once
+    clock.update(s(12), s(1020), s(12));
+    assert_eq!(clock.offset, s(1008));
+}
@@ -38,14 +38,28 @@ pub enum Variant {
         data_ptr: u64,
         _pad: u64,
     },
+    // `addr_ptr` is null for a connected socket
     Send {
         fd: u32,
         data_ptr: u64,
-        _pad: u64,
+        addr_ptr: u64,
+        addr_len: u64,
     },
     Recv {
         fd: u32,
         data_ptr: u64,
+        addr_ptr: u64,
+        addr_len_ptr: u64,
+    },
+    // `struct msghdr`, the data is in its iovec array
+    SendMsg {
+        fd: u32,
+        msghdr_ptr: u64,
+        _pad: u64,
+    },
+    RecvMsg {
+        fd: u32,
+        msghdr_ptr: u64,
         _pad: u64,
     },
 
@@ -60,6 +74,12 @@ pub enum Variant {
         val_ptr: u64,
         len_ptr: u64,
     },
+    // level == 1, opt == 17, `SO_PEERCRED`
+    GetSockOptL1O17 {
+        fd: u32,
+        val_ptr: u64,
+        len_ptr: u64,
+    },
     GetSockOptIrrelevant {
         fd: u32,
         val_ptr: u64,
@@ -78,8 +98,11 @@ impl Variant {
             Variant::Read { data_ptr, .. } => *data_ptr as *const u8,
             Variant::Send { data_ptr, .. } => *data_ptr as *const u8,
             Variant::Recv { data_ptr, .. } => *data_ptr as *const u8,
+            Variant::SendMsg { msghdr_ptr, .. } => *msghdr_ptr as *const u8,
+            Variant::RecvMsg { msghdr_ptr, .. } => *msghdr_ptr as *const u8,
             Variant::GetRandom { data_ptr, .. } => *data_ptr as *const u8,
             Variant::GetSockOptL1O4 { val_ptr, .. } => *val_ptr as *const u8,
+            Variant::GetSockOptL1O17 { val_ptr, .. } => *val_ptr as *const u8,
             Variant::GetSockOptIrrelevant { val_ptr, .. } => *val_ptr as *const u8,
         }
     }
@@ -3,6 +3,9 @@
 #[cfg(feature = "user")]
 pub mod proc;
 
+#[cfg(feature = "user")]
+pub mod clock;
+
 #[derive(Clone, Copy, Debug)]
 #[repr(C)]
 pub struct StatsBlocked {
@@ -68,6 +71,11 @@ pub enum DataTag {
     Random,
     GetSockOpt,
     SnarkWorker,
+    PeerCred,
+    ShutdownRead,
+    ShutdownWrite,
+    UdpPeer,
+    ConnectFailed,
 }
 
 impl DataTag {
@@ -85,6 +93,11 @@ impl DataTag {
             DataTag::Random,
             DataTag::GetSockOpt,
             DataTag::SnarkWorker,
+            DataTag::PeerCred,
+            DataTag::ShutdownRead,
+            DataTag::ShutdownWrite,
+            DataTag::UdpPeer,
+            DataTag::ConnectFailed,
         ];
         values.into_iter().find(|&v| v as u32 == c)
     }
@@ -92,35 +105,145 @@ impl DataTag {
 
 #[cfg(feature = "user")]
 pub mod sniffer_event {
-    use std::net::{IpAddr, SocketAddr};
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 
-    use bpf_ring_buffer::RingBufferData;
+    use bpf_ring_buffer::{RingBufferData, RingBufferDataRef};
 
     use super::{DataTag, Event};
 
+    /// The bytes are `B`, `&[u8]` if the event borrows the ring buffer record.
     #[derive(Debug)]
-    pub struct SnifferEvent {
+    pub struct SnifferEvent<B = Vec<u8>> {
         pub pid: u32,
         pub tid: u32,
         pub fd: u32,
         pub ts0: u64,
         pub ts1: u64,
-        pub variant: SnifferEventVariant,
+        pub variant: SnifferEventVariant<B>,
     }
 
     #[derive(Debug)]
-    pub enum SnifferEventVariant {
+    pub enum SnifferEventVariant<B = Vec<u8>> {
         NewApp(String),
         NewSnarkWorkerApp,
         Bind(SocketAddr),
         IncomingConnection(SocketAddr),
         OutgoingConnection(SocketAddr),
         Disconnected,
-        IncomingData(Vec<u8>),
-        OutgoingData(Vec<u8>),
-        Random(Vec<u8>),
-        GetSockOpt(Vec<u8>),
+        // `shutdown` of one direction, `write` means `SHUT_WR`, otherwise `SHUT_RD`
+        HalfClosed { write: bool },
+        IncomingData(B),
+        OutgoingData(B),
+        Random(B),
+        GetSockOpt(B),
+        // `struct ucred` of the peer process, obtained by `SO_PEERCRED`
+        PeerCred { pid: u32, uid: u32, gid: u32 },
         Error(DataTag, i32),
+        // the source or destination of a datagram, `recvfrom`/`sendto` with the address
+        UdpPeer(SocketAddr),
+        // `connect` failed, other than `EINPROGRESS`, `errno` is positive
+        ConnectFailed { addr: SocketAddr, errno: i32 },
+    }
+
+    impl SnifferEvent<&[u8]> {
+        /// Copies the bytes out of the ring buffer, so the event outlives the record.
+        pub fn into_owned(self) -> SnifferEvent {
+            use self::SnifferEventVariant::*;
+
+            let variant = match self.variant {
+                NewApp(alias) => NewApp(alias),
+                NewSnarkWorkerApp => NewSnarkWorkerApp,
+                Bind(addr) => Bind(addr),
+                IncomingConnection(addr) => IncomingConnection(addr),
+                OutgoingConnection(addr) => OutgoingConnection(addr),
+                Disconnected => Disconnected,
+                HalfClosed { write } => HalfClosed { write },
+                IncomingData(data) => IncomingData(data.to_vec()),
+                OutgoingData(data) => OutgoingData(data.to_vec()),
+                Random(data) => Random(data.to_vec()),
+                GetSockOpt(data) => GetSockOpt(data.to_vec()),
+                PeerCred { pid, uid, gid } => PeerCred { pid, uid, gid },
+                Error(tag, code) => Error(tag, code),
+                UdpPeer(addr) => UdpPeer(addr),
+                ConnectFailed { addr, errno } => ConnectFailed { addr, errno },
+            };
+            SnifferEvent {
+                pid: self.pid,
+                tid: self.tid,
+                fd: self.fd,
+                ts0: self.ts0,
+                ts1: self.ts1,
+                variant,
+            }
+        }
+    }
+
+    impl<B> SnifferEvent<B>
+    where
+        B: AsRef<[u8]>,
+    {
+        /// Version of the format produced by `encode`.
+        pub const ENCODING_VERSION: u8 = 1;
+
+        /// Stable binary encoding for external processors, little endian.
+        /// Header is version `u8`, variant `u8`, pid, tid, fd `u32`, ts0, ts1 `u64`,
+        /// then the variant specific body, see README.
+        pub fn encode(&self) -> Vec<u8> {
+            // family `4` or `6`, ip octets, port
+            fn addr(addr: &SocketAddr) -> Vec<u8> {
+                let (family, octets) = match addr.ip() {
+                    IpAddr::V4(ip) => (4, ip.octets().to_vec()),
+                    IpAddr::V6(ip) => (6, ip.octets().to_vec()),
+                };
+                let mut v = vec![family];
+                v.extend_from_slice(&octets);
+                v.extend_from_slice(&addr.port().to_le_bytes());
+                v
+            }
+
+            let (variant, body) = match &self.variant {
+                SnifferEventVariant::NewApp(alias) => (0, alias.as_bytes().to_vec()),
+                SnifferEventVariant::NewSnarkWorkerApp => (1, vec![]),
+                SnifferEventVariant::Bind(a) => (2, addr(a)),
+                SnifferEventVariant::IncomingConnection(a) => (3, addr(a)),
+                SnifferEventVariant::OutgoingConnection(a) => (4, addr(a)),
+                SnifferEventVariant::Disconnected => (5, vec![]),
+                SnifferEventVariant::HalfClosed { write } => (6, vec![*write as u8]),
+                SnifferEventVariant::IncomingData(data) => (7, data.as_ref().to_vec()),
+                SnifferEventVariant::OutgoingData(data) => (8, data.as_ref().to_vec()),
+                SnifferEventVariant::Random(data) => (9, data.as_ref().to_vec()),
+                SnifferEventVariant::GetSockOpt(data) => (10, data.as_ref().to_vec()),
+                SnifferEventVariant::PeerCred { pid, uid, gid } => {
+                    let body = [pid, uid, gid]
+                        .iter()
+                        .flat_map(|x| x.to_le_bytes())
+                        .collect();
+                    (11, body)
+                }
+                SnifferEventVariant::Error(tag, code) => {
+                    let mut body = (*tag as u32).to_le_bytes().to_vec();
+                    body.extend_from_slice(&code.to_le_bytes());
+                    (12, body)
+                }
+                SnifferEventVariant::UdpPeer(a) => (13, addr(a)),
+                SnifferEventVariant::ConnectFailed { addr: a, errno } => {
+                    let mut body = addr(a);
+                    body.extend_from_slice(&errno.to_le_bytes());
+                    (14, body)
+                }
+            };
+
+            let mut v = Vec::with_capacity(30 + body.len());
+            v.push(Self::ENCODING_VERSION);
+            v.push(variant);
+            v.extend_from_slice(&self.pid.to_le_bytes());
+            v.extend_from_slice(&self.tid.to_le_bytes());
+            v.extend_from_slice(&self.fd.to_le_bytes());
+            v.extend_from_slice(&self.ts0.to_le_bytes());
+            v.extend_from_slice(&self.ts1.to_le_bytes());
+            v.extend_from_slice(&body);
+            v
+        }
     }
 
     #[derive(Debug)]
@@ -130,6 +253,15 @@ pub mod sniffer_event {
         type Error = ErrorSliceTooShort;
 
         fn from_rb_slice(slice: &[u8]) -> Result<Option<Self>, Self::Error> {
+            let event = <SnifferEvent<&[u8]> as RingBufferDataRef>::from_rb_slice(slice)?;
+            Ok(event.map(SnifferEvent::into_owned))
+        }
+    }
+
+    impl<'a> RingBufferDataRef<'a> for SnifferEvent<&'a [u8]> {
+        type Error = ErrorSliceTooShort;
+
+        fn from_rb_slice(slice: &'a [u8]) -> Result<Option<Self>, Self::Error> {
             use core::{mem, ptr};
 
             if slice.is_empty() {
@@ -172,7 +304,7 @@ pub mod sniffer_event {
                 std::process::exit(1);
             }
             let data = &slice[mem::size_of::<Event>()..(mem::size_of::<Event>() + size)];
-            if let DataTag::Accept | DataTag::Connect | DataTag::Bind = tag {
+            if let DataTag::Accept | DataTag::Connect | DataTag::Bind | DataTag::UdpPeer = tag {
                 let address_family = u16::from_ne_bytes(data[0..2].try_into().unwrap());
                 let port = u16::from_be_bytes(data[2..4].try_into().unwrap());
                 let addr = match address_family {
@@ -190,23 +322,56 @@ pub mod sniffer_event {
                     DataTag::Accept => ret(SnifferEventVariant::IncomingConnection(addr)),
                     DataTag::Connect => ret(SnifferEventVariant::OutgoingConnection(addr)),
                     DataTag::Bind => ret(SnifferEventVariant::Bind(addr)),
+                    DataTag::UdpPeer => ret(SnifferEventVariant::UdpPeer(addr)),
                     _ => unreachable!(),
                 }
+            } else if let DataTag::ConnectFailed = tag {
+                // errno `i32`, ip `[u8; 16]` (ipv4 is mapped), port big endian
+                if data.len() < 22 {
+                    return Ok(None);
+                }
+                let errno = i32::from_ne_bytes(data[..4].try_into().unwrap());
+                let ip = Ipv6Addr::from(<[u8; 16]>::try_from(&data[4..20]).unwrap());
+                let ip = match ip.octets() {
+                    [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, a, b, c, d] => {
+                        IpAddr::V4(Ipv4Addr::new(a, b, c, d))
+                    }
+                    _ => IpAddr::V6(ip),
+                };
+                let port = u16::from_be_bytes(data[20..22].try_into().unwrap());
+                ret(SnifferEventVariant::ConnectFailed {
+                    addr: SocketAddr::new(ip, port),
+                    errno,
+                })
             } else if let DataTag::Read = tag {
-                ret(SnifferEventVariant::IncomingData(data.to_vec()))
+                ret(SnifferEventVariant::IncomingData(data))
             } else if let DataTag::Write = tag {
-                ret(SnifferEventVariant::OutgoingData(data.to_vec()))
+                ret(SnifferEventVariant::OutgoingData(data))
             } else if let DataTag::Close = tag {
                 ret(SnifferEventVariant::Disconnected)
+            } else if let DataTag::ShutdownRead = tag {
+                ret(SnifferEventVariant::HalfClosed { write: false })
+            } else if let DataTag::ShutdownWrite = tag {
+                ret(SnifferEventVariant::HalfClosed { write: true })
             } else if let DataTag::Alias = tag {
                 ret(SnifferEventVariant::NewApp(
                     String::from_utf8(data[..(data.len() - 1)].to_vec())
                         .unwrap_or("invalid_uft8_alias".to_string()),
                 ))
             } else if let DataTag::Random = tag {
-                ret(SnifferEventVariant::Random(data.to_vec()))
+                ret(SnifferEventVariant::Random(data))
             } else if let DataTag::GetSockOpt = tag {
-                ret(SnifferEventVariant::GetSockOpt(data.to_vec()))
+                ret(SnifferEventVariant::GetSockOpt(data))
+            } else if let DataTag::PeerCred = tag {
+                if data.len() < 12 {
+                    return Ok(None);
+                }
+                let field = |i: usize| u32::from_ne_bytes(data[i..(i + 4)].try_into().unwrap());
+                ret(SnifferEventVariant::PeerCred {
+                    pid: field(0),
+                    uid: field(4),
+                    gid: field(8),
+                })
             } else if let DataTag::SnarkWorker = tag {
                 ret(SnifferEventVariant::NewSnarkWorkerApp)
             } else if let DataTag::Debug = tag {
@@ -224,4 +389,143 @@ pub mod sniffer_event {
             }
         }
     }
+
+    #[cfg(test)]
+    #[test]
+    fn decode_borrowed() {
+        use core::{mem, ptr};
+
+        let body = b"hello";
+        let event = Event::new(1, 2, 3, 4)
+            .set_tag_fd(DataTag::Read, 5)
+            .set_ok(body.len() as u64);
+        let mut record = vec![0; mem::size_of::<Event>()];
+        unsafe { ptr::write_unaligned(record.as_mut_ptr() as *mut Event, event) };
+        record.extend_from_slice(body);
+
+        let event = <SnifferEvent<&[u8]> as RingBufferDataRef>::from_rb_slice(&record)
+            .unwrap()
+            .unwrap();
+        assert_eq!((event.pid, event.tid, event.fd), (1, 2, 5));
+        let SnifferEventVariant::IncomingData(data) = event.variant else {
+            panic!("expected incoming data");
+        };
+        assert_eq!(data, body);
+        // not copied, it points right into the record
+        assert_eq!(data.as_ptr(), record[mem::size_of::<Event>()..].as_ptr());
+    }
+
+    #[cfg(test)]
+    #[test]
+    fn decode_peer_cred() {
+        use core::{mem, ptr};
+
+        let to_record = |body: &[u8]| {
+            let event = Event::new(1, 2, 3, 4)
+                .set_tag_fd(DataTag::PeerCred, 5)
+                .set_ok(body.len() as u64);
+            let mut record = vec![0; mem::size_of::<Event>()];
+            unsafe { ptr::write_unaligned(record.as_mut_ptr() as *mut Event, event) };
+            record.extend_from_slice(body);
+            record
+        };
+
+        // `struct ucred`, three native endian `u32`
+        let mut body = vec![];
+        for v in [1234_u32, 1000, 100] {
+            body.extend_from_slice(&v.to_ne_bytes());
+        }
+        let record = to_record(&body);
+        let event = <SnifferEvent<&[u8]> as RingBufferDataRef>::from_rb_slice(&record)
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.fd, 5);
+        let SnifferEventVariant::PeerCred { pid, uid, gid } = event.variant else {
+            panic!("expected peer credentials");
+        };
+        assert_eq!((pid, uid, gid), (1234, 1000, 100));
+
+        // truncated, the event is dropped
+        let record = to_record(&body[..8]);
+        let event = <SnifferEvent<&[u8]> as RingBufferDataRef>::from_rb_slice(&record).unwrap();
+        assert!(event.is_none());
+    }
+
+    #[cfg(test)]
+    #[test]
+    fn encode_golden_bytes() {
+        let event = |variant| SnifferEvent {
+            pid: 1,
+            tid: 2,
+            fd: 3,
+            ts0: 4,
+            ts1: 5,
+            variant,
+        };
+        #[rustfmt::skip]
+        let header = [
+            1, 0,
+            1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0,
+            4, 0, 0, 0, 0, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        let v4 = "10.0.0.1:8302".parse().unwrap();
+        let v6 = "[::1]:8302".parse().unwrap();
+        let v4_bytes = vec![4, 10, 0, 0, 1, 0x6e, 0x20];
+        let mut v6_bytes = vec![6];
+        v6_bytes.extend_from_slice(&[0; 15]);
+        v6_bytes.extend_from_slice(&[1, 0x6e, 0x20]);
+
+        let cases = [
+            (
+                SnifferEventVariant::NewApp("node".to_owned()),
+                b"node".to_vec(),
+            ),
+            (SnifferEventVariant::NewSnarkWorkerApp, vec![]),
+            (SnifferEventVariant::Bind(v4), v4_bytes.clone()),
+            (SnifferEventVariant::IncomingConnection(v6), v6_bytes),
+            (
+                SnifferEventVariant::OutgoingConnection(v4),
+                v4_bytes.clone(),
+            ),
+            (SnifferEventVariant::Disconnected, vec![]),
+            (SnifferEventVariant::HalfClosed { write: true }, vec![1]),
+            (
+                SnifferEventVariant::IncomingData(vec![0xaa, 0xbb]),
+                vec![0xaa, 0xbb],
+            ),
+            (SnifferEventVariant::OutgoingData(vec![0xcc]), vec![0xcc]),
+            (SnifferEventVariant::Random(vec![0x11; 4]), vec![0x11; 4]),
+            (
+                SnifferEventVariant::GetSockOpt(vec![0x22; 2]),
+                vec![0x22; 2],
+            ),
+            (
+                SnifferEventVariant::PeerCred {
+                    pid: 10,
+                    uid: 20,
+                    gid: 0x01020304,
+                },
+                vec![10, 0, 0, 0, 20, 0, 0, 0, 4, 3, 2, 1],
+            ),
+            (
+                SnifferEventVariant::Error(DataTag::Read, -104),
+                vec![7, 0, 0, 0, 0x98, 0xff, 0xff, 0xff],
+            ),
+            (SnifferEventVariant::UdpPeer(v4), v4_bytes.clone()),
+            (
+                SnifferEventVariant::ConnectFailed {
+                    addr: v4,
+                    errno: 111,
+                },
+                [v4_bytes, vec![111, 0, 0, 0]].concat(),
+            ),
+        ];
+        // the tag of the variant is its position in the list
+        for (tag, (variant, body)) in cases.into_iter().enumerate() {
+            let mut expected = header.to_vec();
+            expected[1] = tag as u8;
+            expected.extend_from_slice(&body);
+            assert_eq!(event(variant).encode(), expected, "variant {tag}");
+        }
+    }
 }
@@ -20,14 +20,19 @@ pub struct App {
     pub pid: ebpf::HashMapRef<4, 4>,
     #[hashmap(size = 0x1000)]
     pub pid_snark_worker: ebpf::HashMapRef<4, 4>,
+    // pid -> (read_bytes, write_bytes), `u64` each
+    #[hashmap(size = 0x1000)]
+    pub pid_bytes: ebpf::HashMapRef<4, 16>,
     #[prog("tracepoint/syscalls/sys_enter_execve")]
     pub execve: ebpf::ProgRef,
     #[prog("tracepoint/syscalls/sys_enter_execveat")]
     pub execveat: ebpf::ProgRef,
+    #[prog("tracepoint/sched/sched_process_exit")]
+    pub process_exit: ebpf::ProgRef,
     // store/load context parameters
     // 0x100 cpus maximum
     #[hashmap(size = 0x100)]
-    pub context_parameters: ebpf::HashMapRef<4, 0x20>,
+    pub context_parameters: ebpf::HashMapRef<4, 0x28>,
     #[prog("tracepoint/syscalls/sys_enter_bind")]
     pub enter_bind: ebpf::ProgRef,
     #[prog("tracepoint/syscalls/sys_exit_bind")]
@@ -51,8 +56,16 @@ pub struct App {
     // #[prog("tracepoint/syscalls/sys_exit_open")]
     // pub exit_open: ebpf::ProgRef,
     // 0x4000 simultaneous connections maximum
+    // the value is local and remote port, big endian, local is `0` if unknown
     #[hashmap(size = 0x4000)]
     pub connections: ebpf::HashMapRef<8, 4>,
+    // port of the bound socket, to know the local port of accepted connections
+    #[hashmap(size = 0x1000)]
+    pub listen_ports: ebpf::HashMapRef<8, 4>,
+    // if not empty only the data of connections with the local or remote port listed is sent,
+    // the key `0` marks that the set is not empty
+    #[hashmap(size = 0x100)]
+    pub capture_ports: ebpf::HashMapRef<2, 4>,
     // the whitelist is only applied to TCP packets
     // whose src or dst port is listed in `whitelist_ports`
     #[hashmap(size = 0x4000)]
@@ -78,6 +91,14 @@ pub struct App {
     pub enter_recvfrom: ebpf::ProgRef,
     #[prog("tracepoint/syscalls/sys_exit_recvfrom")]
     pub exit_recvfrom: ebpf::ProgRef,
+    #[prog("tracepoint/syscalls/sys_enter_sendmsg")]
+    pub enter_sendmsg: ebpf::ProgRef,
+    #[prog("tracepoint/syscalls/sys_exit_sendmsg")]
+    pub exit_sendmsg: ebpf::ProgRef,
+    #[prog("tracepoint/syscalls/sys_enter_recvmsg")]
+    pub enter_recvmsg: ebpf::ProgRef,
+    #[prog("tracepoint/syscalls/sys_exit_recvmsg")]
+    pub exit_recvmsg: ebpf::ProgRef,
     #[prog("tracepoint/syscalls/sys_enter_getrandom")]
     pub enter_getrandom: ebpf::ProgRef,
     #[prog("tracepoint/syscalls/sys_exit_getrandom")]
@@ -86,6 +107,9 @@ pub struct App {
     pub enter_shutdown: ebpf::ProgRef,
     #[prog("xdp")]
     pub disable_connections: ebpf::ProgRef,
+    // the same firewall for outgoing packets, attached to the clsact qdisc
+    #[prog("classifier")]
+    pub filter_egress: ebpf::ProgRef,
 }
 
 #[cfg(feature = "kern")]
@@ -442,6 +466,78 @@ impl App {
         self.check_env_flag(env)
     }
 
+    // the process is killed and never closed its sockets, forget them,
+    // otherwise the entries stay in the fixed size maps forever
+    #[inline(always)]
+    pub fn process_exit(&mut self, ctx: ebpf::Context) -> Result<(), i32> {
+        use core::ptr;
+        use ebpf::helpers;
+
+        // there is no way to iterate the map here, try every fd below the bound,
+        // the same as the default `MAX_CNS_PER_PID`, userspace doesn't track more
+        // connections of a pid either; the loop is bounded, so the verifier accepts it
+        const MAX_FD: u32 = 0x1000;
+
+        let _ = ctx;
+        let (pid, tid) = {
+            let x = unsafe { helpers::get_current_pid_tgid() };
+            ((x >> 32) as u32, (x & 0xffffffff) as u32)
+        };
+        // the tracepoint fires for each thread, only the main thread means the process
+        if pid != tid {
+            return Ok(());
+        }
+        let key = pid.to_ne_bytes();
+        self.pid_bytes.remove(&key)?;
+        let tracked = self.pid.remove(&key)?.is_some();
+        let tracked_snark_worker = self.pid_snark_worker.remove(&key)?.is_some();
+        if !tracked && !tracked_snark_worker {
+            return Ok(());
+        }
+
+        let ts = unsafe { helpers::ktime_get_boot_ns() };
+        for fd in 3..MAX_FD {
+            let socket_id = ((fd as u64) << 32) + (pid as u64);
+            self.listen_ports.remove(&socket_id.to_ne_bytes())?;
+            if self.connections.remove(&socket_id.to_ne_bytes())?.is_none() {
+                continue;
+            }
+            let event = Event::new(pid, tid, ts, ts);
+            let event = event.set_tag_fd(DataTag::Close, fd);
+            send::dyn_sized::<typenum::B0>(&mut self.event_queue, event, ptr::null())?;
+        }
+
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn port_captured(&self, socket_id: u64) -> bool {
+        // the set is empty, capture everything
+        if self.capture_ports.get(&[0, 0]).is_none() {
+            return true;
+        }
+        match self.connections.get(&socket_id.to_ne_bytes()) {
+            Some(&[l0, l1, r0, r1]) => {
+                ([l0, l1] != [0, 0] && self.capture_ports.get(&[l0, l1]).is_some())
+                    || self.capture_ports.get(&[r0, r1]).is_some()
+            }
+            None => false,
+        }
+    }
+
+    #[inline(always)]
+    fn count_bytes(&mut self, pid: u32, write: bool, bytes: u64) -> Result<(), i32> {
+        use core::mem;
+
+        let key = pid.to_ne_bytes();
+        let value = self.pid_bytes.get(&key).copied().unwrap_or([0; 16]);
+        let mut counters = unsafe { mem::transmute::<[u8; 16], [u64; 2]>(value) };
+        let counter = &mut counters[write as usize];
+        *counter = counter.saturating_add(bytes);
+        let value = unsafe { mem::transmute::<[u64; 2], [u8; 16]>(counters) };
+        self.pid_bytes.insert(key, value)
+    }
+
     #[inline(always)]
     fn enter(&mut self, snark_worker: bool, data: context::Variant) -> Result<(), i32> {
         use core::{mem, ptr};
@@ -545,7 +641,8 @@ impl App {
             return Ok(());
         }
 
-        fn check_addr(ptr: *const u8) -> Result<[u8; 16], i32> {
+        // returns the ip and the port, big endian
+        fn check_addr(ptr: *const u8) -> Result<([u8; 16], [u8; 2]), i32> {
             const AF_INET: u16 = 2;
             const AF_INET6: u16 = 10;
 
@@ -578,9 +675,9 @@ impl App {
                 return Err(0);
             }
 
-            let mut port = 0u16;
+            let mut port = [0_u8; 2];
             let c = unsafe {
-                helpers::probe_read_user((&mut port) as *mut _ as _, 2, ptr.offset(2) as _)
+                helpers::probe_read_user(port.as_mut_ptr() as *mut _, 2, ptr.offset(2) as _)
             };
             if c != 0 {
                 return Err(0);
@@ -589,7 +686,7 @@ impl App {
                 return Err(0);
             }
 
-            Ok(ip)
+            Ok((ip, port))
         }
 
         let (pid, tid) = {
@@ -598,6 +695,50 @@ impl App {
         };
 
         let event = Event::new(pid, tid, ts0, ts1);
+
+        // the peer of a datagram socket, useful for peer discovery
+        if ret >= 0 {
+            match data {
+                context::Variant::Send {
+                    fd,
+                    addr_ptr,
+                    addr_len,
+                    ..
+                } if addr_ptr != 0 => {
+                    if check_addr(addr_ptr as *const u8).is_ok() {
+                        let event = event.set_tag_fd(DataTag::UdpPeer, fd).set_ok(addr_len);
+                        send::dyn_sized::<typenum::B0>(
+                            &mut self.event_queue,
+                            event,
+                            addr_ptr as *const u8,
+                        )?;
+                    }
+                }
+                context::Variant::Recv {
+                    fd,
+                    addr_ptr,
+                    addr_len_ptr,
+                    ..
+                } if addr_ptr != 0 && addr_len_ptr != 0 => {
+                    let mut addr_len_bytes = [0_u8; 4];
+                    let c = unsafe {
+                        let p = addr_len_bytes.as_mut_ptr() as *mut _;
+                        helpers::probe_read_user(p, 4, addr_len_ptr as _)
+                    };
+                    if c == 0 && check_addr(addr_ptr as *const u8).is_ok() {
+                        let addr_len = u32::from_ne_bytes(addr_len_bytes) as u64;
+                        let event = event.set_tag_fd(DataTag::UdpPeer, fd).set_ok(addr_len);
+                        send::dyn_sized::<typenum::B0>(
+                            &mut self.event_queue,
+                            event,
+                            addr_ptr as *const u8,
+                        )?;
+                    }
+                }
+                _ => (),
+            }
+        }
+
         let ptr = data.ptr();
         let event = match data {
             context::Variant::Empty { len, .. } => {
@@ -613,22 +754,35 @@ impl App {
                 if ret < 0 {
                     event.set_err(ret)
                 } else {
+                    if let Ok((_, [p0, p1])) = check_addr(ptr) {
+                        let socket_id = ((fd as u64) << 32) + (pid as u64);
+                        self.listen_ports
+                            .insert(socket_id.to_ne_bytes(), [p0, p1, 0, 0])?;
+                    }
                     event.set_ok(addr_len)
                 }
             }
             context::Variant::Connect { fd, addr_len, .. } => {
-                let _ip = check_addr(ptr)?;
+                let (ip, [r0, r1]) = check_addr(ptr)?;
 
                 const EINPROGRESS: i64 = -115;
-                let event = event.set_tag_fd(DataTag::Connect, fd);
                 if ret < 0 && ret != EINPROGRESS {
-                    event.set_err(ret)
-                } else {
-                    let socket_id = ((fd as u64) << 32) + (pid as u64);
-                    self.connections
-                        .insert(socket_id.to_ne_bytes(), 0x1_u32.to_ne_bytes())?;
-                    event.set_ok(addr_len)
+                    // the error code, the ip and the port, so the failed dial is not lost
+                    let mut body = [0_u8; 24];
+                    body[..4].copy_from_slice(&(-ret as i32).to_ne_bytes());
+                    body[4..20].copy_from_slice(&ip);
+                    body[20..22].copy_from_slice(&[r0, r1]);
+                    let event = event.set_tag_fd(DataTag::ConnectFailed, fd).set_ok(22);
+                    return send::dyn_sized::<typenum::B1>(
+                        &mut self.event_queue,
+                        event,
+                        body.as_ptr(),
+                    );
                 }
+                let socket_id = ((fd as u64) << 32) + (pid as u64);
+                self.connections
+                    .insert(socket_id.to_ne_bytes(), [0, 0, r0, r1])?;
+                event.set_tag_fd(DataTag::Connect, fd).set_ok(addr_len)
             }
             context::Variant::GetSockOptL1O4 { fd, len_ptr, .. } => {
                 let event = event.set_tag_fd(DataTag::GetSockOpt, fd);
@@ -647,6 +801,23 @@ impl App {
                     event.set_ok(len)
                 }
             }
+            context::Variant::GetSockOptL1O17 { fd, len_ptr, .. } => {
+                let event = event.set_tag_fd(DataTag::PeerCred, fd);
+                if ret < 0 {
+                    event.set_err(ret)
+                } else {
+                    let mut len_bytes = [0_u8; 4];
+                    let c = unsafe {
+                        let p = len_bytes.as_mut_ptr() as *mut _;
+                        helpers::probe_read_user(p, 4, len_ptr as _)
+                    };
+                    if c != 0 {
+                        return Err(0);
+                    }
+                    let len = u32::from_ne_bytes(len_bytes) as u64;
+                    event.set_ok(len)
+                }
+            }
             context::Variant::GetSockOptIrrelevant { .. } => {
                 return Ok(());
             }
@@ -655,16 +826,21 @@ impl App {
                 addr_len_ptr,
                 ..
             } => {
-                let _ = listen_on_fd;
                 let fd = ret as _;
                 let event = event.set_tag_fd(DataTag::Accept, fd);
                 if ret < 0 {
                     event.set_err(ret)
                 } else {
-                    let _ip = check_addr(ptr)?;
+                    let (_ip, [r0, r1]) = check_addr(ptr)?;
+                    let listen_id = ((listen_on_fd as u64) << 32) + (pid as u64);
+                    let [l0, l1, _, _] = self
+                        .listen_ports
+                        .get(&listen_id.to_ne_bytes())
+                        .copied()
+                        .unwrap_or_default();
                     let socket_id = ((fd as u64) << 32) + (pid as u64);
                     self.connections
-                        .insert(socket_id.to_ne_bytes(), 0x1_u32.to_ne_bytes())?;
+                        .insert(socket_id.to_ne_bytes(), [l0, l1, r0, r1])?;
 
                     let mut addr_len_bytes = [0_u8; 4];
                     let c = unsafe {
@@ -701,6 +877,11 @@ impl App {
                         send::dyn_sized::<typenum::B0>(&mut self.event_queue, event, ptr::null())?;
                         close_ev
                     } else {
+                        // the accounting is best effort, never lose the data because of it
+                        self.count_bytes(pid, true, ret as u64).unwrap_or_default();
+                        if !self.port_captured(socket_id) {
+                            return Ok(());
+                        }
                         event.set_ok(ret as _)
                     }
                 }
@@ -727,6 +908,10 @@ impl App {
                         send::dyn_sized::<typenum::B0>(&mut self.event_queue, event, ptr::null())?;
                         close_ev
                     } else {
+                        self.count_bytes(pid, false, ret as u64).unwrap_or_default();
+                        if !self.port_captured(socket_id) {
+                            return Ok(());
+                        }
                         event.set_ok(ret as _)
                     }
                 }
@@ -734,10 +919,96 @@ impl App {
             context::Variant::GetRandom { data_len, .. } => {
                 event.set_tag_fd(DataTag::Random, 0).set_ok(data_len)
             }
+            context::Variant::SendMsg { fd, msghdr_ptr, .. } => {
+                let event = event.set_tag_fd(DataTag::Write, fd);
+                return self.on_ret_msg(ret, event, msghdr_ptr);
+            }
+            context::Variant::RecvMsg { fd, msghdr_ptr, .. } => {
+                let event = event.set_tag_fd(DataTag::Read, fd);
+                return self.on_ret_msg(ret, event, msghdr_ptr);
+            }
         };
         send::dyn_sized::<typenum::B0>(&mut self.event_queue, event, ptr)
     }
 
+    // vectored io, each segment of `struct iovec` is sent as a separate write or read,
+    // the same as if the process called `write`/`read` for each,
+    // bpf functions take at most 5 arguments, the pid and fd are in the `event`
+    #[inline(never)]
+    fn on_ret_msg(&mut self, ret: i64, event: Event, msghdr_ptr: u64) -> Result<(), i32> {
+        use core::ptr;
+        use ebpf::helpers;
+
+        // the verifier needs the loop to be bounded
+        const MAX_IOV: u64 = 8;
+        // reported when the segments beyond `MAX_IOV` are dropped
+        const EMSGSIZE: i64 = 90;
+
+        let (pid, fd) = (event.pid, event.fd);
+        let socket_id = ((fd as u64) << 32) + (pid as u64);
+        if self.connections.get(&socket_id.to_ne_bytes()).is_none() {
+            return Ok(());
+        }
+        if ret < 0 {
+            if self.connections.remove(&socket_id.to_ne_bytes())?.is_none() {
+                return Ok(());
+            }
+            let close_ev = event.set_tag_fd(DataTag::Close, fd);
+            let event = event.set_err(ret);
+            send::dyn_sized::<typenum::B0>(&mut self.event_queue, event, ptr::null())?;
+            return send::dyn_sized::<typenum::B0>(&mut self.event_queue, close_ev, ptr::null());
+        }
+
+        // `struct msghdr`, `msg_iov` at 0x10, `msg_iovlen` at 0x18
+        let mut iov = [0_u64; 2];
+        let c = unsafe {
+            let p = iov.as_mut_ptr() as *mut _;
+            helpers::probe_read_user(p, 16, (msghdr_ptr + 0x10) as _)
+        };
+        if c != 0 {
+            return Err(0);
+        }
+        let [iov_ptr, iov_len] = iov;
+
+        let captured = self.port_captured(socket_id);
+        // the kernel fills the segments in order, only `ret` bytes in total are relevant
+        let mut remaining = ret as u64;
+        for i in 0..MAX_IOV {
+            if i >= iov_len || remaining == 0 {
+                break;
+            }
+            // `struct iovec`, `iov_base` and `iov_len`
+            let mut segment = [0_u64; 2];
+            let c = unsafe {
+                let p = segment.as_mut_ptr() as *mut _;
+                helpers::probe_read_user(p, 16, (iov_ptr + i * 16) as _)
+            };
+            if c != 0 {
+                return Err(0);
+            }
+            let [base, len] = segment;
+            let len = if len < remaining { len } else { remaining };
+            remaining -= len;
+            if len == 0 {
+                continue;
+            }
+            let write = matches!(event.tag, DataTag::Write);
+            self.count_bytes(pid, write, len).unwrap_or_default();
+            if !captured {
+                continue;
+            }
+            let event = event.set_ok(len);
+            send::dyn_sized::<typenum::B0>(&mut self.event_queue, event, base as *const u8)?;
+        }
+        if remaining != 0 && captured {
+            // the stream is missing some bytes, let the userspace know
+            let event = event.set_err(-EMSGSIZE);
+            send::dyn_sized::<typenum::B0>(&mut self.event_queue, event, ptr::null())?;
+        }
+
+        Ok(())
+    }
+
     #[inline(always)]
     pub fn enter_bind(&mut self, ctx: ebpf::Context) -> Result<(), i32> {
         self.enter(
@@ -785,6 +1056,15 @@ impl App {
                     len_ptr: ctx.read_here::<u64>(0x30),
                 },
             )
+        } else if level == 1 && opt == 17 {
+            self.enter(
+                false,
+                context::Variant::GetSockOptL1O17 {
+                    fd: ctx.read_here::<u64>(0x10) as u32,
+                    val_ptr: ctx.read_here::<u64>(0x28),
+                    len_ptr: ctx.read_here::<u64>(0x30),
+                },
+            )
         } else {
             self.enter(
                 false,
@@ -834,6 +1114,7 @@ impl App {
         let ts = unsafe { helpers::ktime_get_boot_ns() };
 
         let socket_id = ((fd as u64) << 32) + (pid as u64);
+        self.listen_ports.remove(&socket_id.to_ne_bytes())?;
         if self.connections.remove(&socket_id.to_ne_bytes())?.is_none() {
             return Ok(());
         }
@@ -894,7 +1175,8 @@ impl App {
             context::Variant::Send {
                 fd: ctx.read_here::<u64>(0x10) as u32,
                 data_ptr: ctx.read_here::<u64>(0x18),
-                _pad: 0,
+                addr_ptr: ctx.read_here::<u64>(0x30),
+                addr_len: ctx.read_here::<u64>(0x38),
             },
         )
     }
@@ -911,7 +1193,8 @@ impl App {
             context::Variant::Recv {
                 fd: ctx.read_here::<u64>(0x10) as u32,
                 data_ptr: ctx.read_here::<u64>(0x18),
-                _pad: 0,
+                addr_ptr: ctx.read_here::<u64>(0x30),
+                addr_len_ptr: ctx.read_here::<u64>(0x38),
             },
         )
     }
@@ -921,6 +1204,40 @@ impl App {
         self.exit(ctx)
     }
 
+    #[inline(always)]
+    pub fn enter_sendmsg(&mut self, ctx: ebpf::Context) -> Result<(), i32> {
+        self.enter(
+            false,
+            context::Variant::SendMsg {
+                fd: ctx.read_here::<u64>(0x10) as u32,
+                msghdr_ptr: ctx.read_here::<u64>(0x18),
+                _pad: 0,
+            },
+        )
+    }
+
+    #[inline(always)]
+    pub fn exit_sendmsg(&mut self, ctx: ebpf::Context) -> Result<(), i32> {
+        self.exit(ctx)
+    }
+
+    #[inline(always)]
+    pub fn enter_recvmsg(&mut self, ctx: ebpf::Context) -> Result<(), i32> {
+        self.enter(
+            false,
+            context::Variant::RecvMsg {
+                fd: ctx.read_here::<u64>(0x10) as u32,
+                msghdr_ptr: ctx.read_here::<u64>(0x18),
+                _pad: 0,
+            },
+        )
+    }
+
+    #[inline(always)]
+    pub fn exit_recvmsg(&mut self, ctx: ebpf::Context) -> Result<(), i32> {
+        self.exit(ctx)
+    }
+
     #[inline(always)]
     pub fn enter_getrandom(&mut self, ctx: ebpf::Context) -> Result<(), i32> {
         let len = ctx.read_here::<u64>(0x18);
@@ -941,17 +1258,76 @@ impl App {
 
     #[inline(always)]
     pub fn enter_shutdown(&mut self, ctx: ebpf::Context) -> Result<(), i32> {
-        self.enter_close(ctx)
+        use core::ptr;
+        use ebpf::helpers;
+
+        let how = ctx.read_here::<u64>(0x18);
+        // SHUT_RDWR, nothing can flow anymore
+        if how == 2 {
+            return self.enter_close(ctx);
+        }
+
+        self.check_pid()?;
+
+        let fd = ctx.read_here::<u64>(0x10) as u32;
+        let (pid, tid) = {
+            let x = unsafe { helpers::get_current_pid_tgid() };
+            ((x >> 32) as u32, (x & 0xffffffff) as u32)
+        };
+        let ts = unsafe { helpers::ktime_get_boot_ns() };
+
+        // the connection stays, the other direction may still carry data
+        let socket_id = ((fd as u64) << 32) + (pid as u64);
+        if self.connections.get(&socket_id.to_ne_bytes()).is_none() {
+            return Ok(());
+        }
+
+        let tag = if how == 1 {
+            DataTag::ShutdownWrite
+        } else {
+            DataTag::ShutdownRead
+        };
+        let event = Event::new(pid, tid, ts, ts);
+        let event = event.set_tag_fd(tag, fd);
+        send::dyn_sized::<typenum::B0>(&mut self.event_queue, event, ptr::null())
     }
 
     #[inline(always)]
     fn disable_connections(&mut self, ctx: ebpf::xdp::Context) -> Result<ebpf::xdp::Action, i32> {
+        use ebpf::xdp::Action;
+
+        match self.filter_packet(ctx.data as usize, ctx.data_end as usize, false)? {
+            Verdict::Pass => Ok(Action::Pass),
+            Verdict::Drop => Ok(Action::Drop),
+            Verdict::Malformed => Ok(Action::Aborted),
+        }
+    }
+
+    #[inline(always)]
+    fn filter_egress(&mut self, ctx: ebpf::tc::Context) -> Result<ebpf::tc::Action, i32> {
+        use ebpf::tc::Action;
+
+        // a malformed outgoing packet is not our business, let the kernel send it
+        match self.filter_packet(ctx.data as usize, ctx.data_end as usize, true)? {
+            Verdict::Pass | Verdict::Malformed => Ok(Action::Ok),
+            Verdict::Drop => Ok(Action::Shot),
+        }
+    }
+
+    // the same whitelist governs both directions, the remote ip is the source
+    // of an incoming packet and the destination of an outgoing one
+    #[inline(always)]
+    fn filter_packet(
+        &mut self,
+        data: usize,
+        data_end: usize,
+        egress: bool,
+    ) -> Result<Verdict, i32> {
         use network_types::{
             eth::{EthHdr, EtherType},
             ip::{Ipv4Hdr, Ipv6Hdr, IpProto},
             tcp::TcpHdr,
         };
-        use ebpf::xdp::Action;
 
         // fn debug(app: &mut App, v: u32) {
         //     let event = Event::new(0, 0, 0, 0);
@@ -971,36 +1347,36 @@ impl App {
 
         // whitelist is disabled
         if self.whitelist.get(&[0; 16]).is_some() {
-            return Ok(Action::Pass);
+            return Ok(Verdict::Pass);
         }
 
-        let packet_ptr = ctx.data as usize as *const u8;
+        let packet_ptr = data as *const u8;
 
         let ethhdr = packet_ptr as *const EthHdr;
 
-        if ethhdr as usize + EthHdr::LEN >= ctx.data_end as usize {
-            return Ok(Action::Aborted);
+        if ethhdr as usize + EthHdr::LEN >= data_end {
+            return Ok(Verdict::Malformed);
         }
         let ethhdr = unsafe { &*(packet_ptr as *const EthHdr) };
 
         match ethhdr.ether_type {
             EtherType::Ipv4 => {
                 let ipv4hdr = unsafe { packet_ptr.add(EthHdr::LEN) } as *const Ipv4Hdr;
-                if ipv4hdr as usize + Ipv4Hdr::LEN >= ctx.data_end as usize {
-                    return Ok(Action::Aborted);
+                if ipv4hdr as usize + Ipv4Hdr::LEN >= data_end {
+                    return Ok(Verdict::Malformed);
                 }
                 let ipv4hdr = unsafe { &*ipv4hdr };
 
                 // do not block non TCP packets
                 let IpProto::Tcp = ipv4hdr.proto else {
-                    return Ok(Action::Pass);
+                    return Ok(Verdict::Pass);
                 };
 
                 let tcphdr = unsafe { packet_ptr.add(EthHdr::LEN + Ipv4Hdr::LEN) } as *const TcpHdr;
-                if tcphdr as usize + TcpHdr::LEN > ctx.data_end as usize {
-                    return Ok(Action::Aborted);
+                if tcphdr as usize + TcpHdr::LEN > data_end {
+                    return Ok(Verdict::Malformed);
                 }
-                let packet_size = (ctx.data_end as usize) - tcphdr as usize + TcpHdr::LEN;
+                let packet_size = data_end - tcphdr as usize + TcpHdr::LEN;
                 let tcphdr = unsafe { &*tcphdr };
 
                 let src_port = u16::from_be(tcphdr.source);
@@ -1010,7 +1386,7 @@ impl App {
                 if self.whitelist_ports.get(&src_port.to_be_bytes()).is_none()
                     && self.whitelist_ports.get(&dst_port.to_be_bytes()).is_none()
                 {
-                    return Ok(Action::Pass);
+                    return Ok(Verdict::Pass);
                 }
 
                 let src_ip = {
@@ -1020,10 +1396,6 @@ impl App {
                     b[12..].clone_from_slice(&u32::from_be(ipv4hdr.src_addr).to_be_bytes());
                     b
                 };
-                if self.whitelist.get(&src_ip).is_some() {
-                    return Ok(Action::Pass);
-                }
-
                 let dst_ip = {
                     let mut b = [0; 16];
                     b[10] = 0xff;
@@ -1032,44 +1404,32 @@ impl App {
                     b
                 };
 
-                let key = {
-                    let mut b = [0; 36];
-                    b[0..16].clone_from_slice(&src_ip);
-                    b[16..18].clone_from_slice(&src_port.to_be_bytes());
-                    b[18..34].clone_from_slice(&dst_ip);
-                    b[34..36].clone_from_slice(&dst_port.to_be_bytes());
-                    b
-                };
-                if let Some(value) = self.blocked.get_mut_unsafe::<StatsBlocked>(&key) {
-                    value.packets += 1;
-                    value.bytes += packet_size as u32;
-                } else {
-                    let value = StatsBlocked {
-                        packets: 1,
-                        bytes: packet_size as u32,
-                    };
-                    self.blocked.insert_unsafe(key, value)?;
+                let remote_ip = if egress { &dst_ip } else { &src_ip };
+                if self.whitelist.get(remote_ip).is_some() {
+                    return Ok(Verdict::Pass);
                 }
 
-                Ok(Action::Drop)
+                self.count_blocked(&src_ip, src_port, &dst_ip, dst_port, packet_size)?;
+
+                Ok(Verdict::Drop)
             }
             EtherType::Ipv6 => {
                 let ipv6hdr = unsafe { packet_ptr.add(EthHdr::LEN) } as *const Ipv6Hdr;
-                if ipv6hdr as usize + Ipv6Hdr::LEN >= ctx.data_end as usize {
-                    return Ok(Action::Aborted);
+                if ipv6hdr as usize + Ipv6Hdr::LEN >= data_end {
+                    return Ok(Verdict::Malformed);
                 }
                 let ipv6hdr = unsafe { &*ipv6hdr };
 
                 // do not block non TCP packets
                 let IpProto::Tcp = ipv6hdr.next_hdr else {
-                    return Ok(Action::Pass);
+                    return Ok(Verdict::Pass);
                 };
 
                 let tcphdr = unsafe { packet_ptr.add(EthHdr::LEN + Ipv6Hdr::LEN) } as *const TcpHdr;
-                if tcphdr as usize + TcpHdr::LEN > ctx.data_end as usize {
-                    return Ok(Action::Aborted);
+                if tcphdr as usize + TcpHdr::LEN > data_end {
+                    return Ok(Verdict::Malformed);
                 }
-                let packet_size = (ctx.data_end as usize) - tcphdr as usize + TcpHdr::LEN;
+                let packet_size = data_end - tcphdr as usize + TcpHdr::LEN;
                 let tcphdr = unsafe { &*tcphdr };
 
                 let src_port = u16::from_be(tcphdr.source);
@@ -1078,40 +1438,61 @@ impl App {
                 if self.whitelist_ports.get(&src_port.to_be_bytes()).is_none()
                     && self.whitelist_ports.get(&dst_port.to_be_bytes()).is_none()
                 {
-                    return Ok(Action::Pass);
+                    return Ok(Verdict::Pass);
                 }
 
                 let src_ip = unsafe { ipv6hdr.src_addr.in6_u.u6_addr8 };
-                if self.whitelist.get(&src_ip).is_some() {
-                    return Ok(Action::Pass);
-                }
-
                 let dst_ip = unsafe { ipv6hdr.dst_addr.in6_u.u6_addr8 };
 
-                let key = {
-                    let mut b = [0; 36];
-                    b[0..16].clone_from_slice(&src_ip);
-                    b[16..18].clone_from_slice(&src_port.to_be_bytes());
-                    b[18..34].clone_from_slice(&dst_ip);
-                    b[34..36].clone_from_slice(&dst_port.to_be_bytes());
-                    b
-                };
-                if let Some(value) = self.blocked.get_mut_unsafe::<StatsBlocked>(&key) {
-                    value.packets += 1;
-                    value.bytes += packet_size as u32;
-                } else {
-                    let value = StatsBlocked {
-                        packets: 1,
-                        bytes: packet_size as u32,
-                    };
-                    self.blocked.insert_unsafe(key, value)?;
+                let remote_ip = if egress { &dst_ip } else { &src_ip };
+                if self.whitelist.get(remote_ip).is_some() {
+                    return Ok(Verdict::Pass);
                 }
 
-                Ok(Action::Drop)
+                self.count_blocked(&src_ip, src_port, &dst_ip, dst_port, packet_size)?;
+
+                Ok(Verdict::Drop)
             }
-            _ => Ok(Action::Pass),
+            _ => Ok(Verdict::Pass),
         }
     }
+
+    #[inline(always)]
+    fn count_blocked(
+        &mut self,
+        src_ip: &[u8; 16],
+        src_port: u16,
+        dst_ip: &[u8; 16],
+        dst_port: u16,
+        packet_size: usize,
+    ) -> Result<(), i32> {
+        let key = {
+            let mut b = [0; 36];
+            b[0..16].clone_from_slice(src_ip);
+            b[16..18].clone_from_slice(&src_port.to_be_bytes());
+            b[18..34].clone_from_slice(dst_ip);
+            b[34..36].clone_from_slice(&dst_port.to_be_bytes());
+            b
+        };
+        if let Some(value) = self.blocked.get_mut_unsafe::<StatsBlocked>(&key) {
+            value.packets += 1;
+            value.bytes += packet_size as u32;
+        } else {
+            let value = StatsBlocked {
+                packets: 1,
+                bytes: packet_size as u32,
+            };
+            self.blocked.insert_unsafe(key, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "kern")]
+enum Verdict {
+    Pass,
+    Drop,
+    Malformed,
 }
 
 #[cfg(feature = "user")]
@@ -1120,22 +1501,26 @@ fn main() {
         collections::{BTreeMap, BTreeSet},
         sync::{
             atomic::{AtomicBool, Ordering},
-            Arc, mpsc,
+            Arc, Mutex, mpsc,
         },
-        time::{SystemTime, Duration},
+        time::{SystemTime, Duration, Instant},
         env, thread,
         path::PathBuf,
+        ffi::CString,
     };
 
     use bpf_recorder::{
         sniffer_event::{SnifferEventVariant, SnifferEvent},
-        proc,
+        DataTag, proc,
+        clock::ClockSync,
     };
     use simulator::registry::messages::{DebuggerReport, ConnectionMetadata};
-    use bpf_ring_buffer::RingBuffer;
+    use bpf_ring_buffer::{OverflowPolicy, RingBuffer};
     use mina_recorder::{
-        EventMetadata, ConnectionInfo, server, P2pRecorder, libp2p_helper::CapnpReader,
-        SnarkWorkerState, application,
+        EventMetadata, ConnectionInfo, server, P2pRecorder, CxHandle, IpcPeer,
+        libp2p_helper::{CapnpReader, CapnpStatus},
+        SnarkWorkerState, TimeSource, application, metrics,
+        feed::RawFeed,
     };
     use ebpf::{kind::AppItem, Skeleton};
 
@@ -1149,6 +1534,209 @@ fn main() {
         });
     }
 
+    // the node is alive, but no events arrive, likely the capture is broken
+    fn watchdog(stale: Duration, pids: Arc<Mutex<BTreeSet<u32>>>) {
+        thread::spawn(move || {
+            let start = SystemTime::now();
+            loop {
+                thread::sleep(Duration::from_secs(1));
+                let now = SystemTime::now();
+                let idle = match metrics::since_last_event(now) {
+                    Some(secs) => Duration::from_secs(secs),
+                    None => now.duration_since(start).unwrap_or_default(),
+                };
+                let alive = pids.lock().expect("poisoned").iter().copied().find(|&pid| {
+                    proc::cmd_prefix_matches(pid, "coda-libp2p_helper").unwrap_or_default()
+                });
+                let healthy = metrics::HEALTHY.load(Ordering::SeqCst);
+                match alive {
+                    Some(pid) if idle >= stale => {
+                        if healthy {
+                            log::error!(
+                                "no events for {idle:?}, but pid {pid} is alive, capture is broken"
+                            );
+                            metrics::HEALTHY.store(false, Ordering::SeqCst);
+                        }
+                    }
+                    _ => {
+                        if !healthy {
+                            log::info!("events are coming again");
+                            metrics::HEALTHY.store(true, Ordering::SeqCst);
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    // rocksdb fails to write on a full disk, stop recording new connections before that,
+    // resume once there is some margin above the floor, so it doesn't flap;
+    // the connections already open keep writing, the floor must leave room for them
+    fn disk_watchdog(path: PathBuf, floor: u64) {
+        use std::os::unix::ffi::OsStrExt;
+
+        let mut c_path = path.as_os_str().as_bytes().to_vec();
+        c_path.push(0);
+        thread::spawn(move || loop {
+            let mut stat = unsafe { std::mem::zeroed::<libc::statvfs>() };
+            if unsafe { libc::statvfs(c_path.as_ptr() as _, &mut stat) } != 0 {
+                let err = std::io::Error::last_os_error();
+                log::error!("cannot get free space of {}: {err}", path.display());
+            } else {
+                let free = stat.f_bavail as u64 * stat.f_frsize as u64;
+                metrics::DISK_FREE_BYTES.store(free, Ordering::SeqCst);
+                let low = metrics::DISK_LOW.load(Ordering::SeqCst);
+                if !low && free < floor {
+                    log::error!(
+                        "free disk space {free} is below {floor}, stop recording new connections"
+                    );
+                    metrics::DISK_LOW.store(true, Ordering::SeqCst);
+                } else if low && free >= floor + floor / 10 {
+                    log::info!("free disk space {free}, recording new connections again");
+                    metrics::DISK_LOW.store(false, Ordering::SeqCst);
+                }
+            }
+            thread::sleep(Duration::from_secs(5));
+        });
+    }
+
+    type Chunk = (bool, SystemTime, SystemTime, Duration, Duration, Vec<u8>);
+
+    /// Due to reordering in the ring buffer the data may come before the connection event,
+    /// hold it for a while, and replay once the connection is established.
+    #[derive(Default)]
+    struct EarlyData(BTreeMap<(u32, u32), (SystemTime, Vec<Chunk>)>);
+
+    impl EarlyData {
+        const GRACE: Duration = Duration::from_millis(500);
+        const MAX_CHUNKS: usize = 64;
+
+        // gives the chunk back if there is no room
+        fn hold(&mut self, key: (u32, u32), chunk: Chunk) -> Result<(), Chunk> {
+            let (_, chunks) = self.0.entry(key).or_insert_with(|| (chunk.1, vec![]));
+            if chunks.len() >= Self::MAX_CHUNKS {
+                return Err(chunk);
+            }
+            chunks.push(chunk);
+            Ok(())
+        }
+
+        fn take(&mut self, key: &(u32, u32)) -> Vec<Chunk> {
+            self.0.remove(key).map(|(_, v)| v).unwrap_or_default()
+        }
+
+        // the connection is established, its data goes to the recorder and the checksum
+        fn replay(
+            &mut self,
+            id: &ConnectionInfo,
+            time_source: TimeSource,
+            buffered: usize,
+            watching: &mut BTreeMap<u32, DebuggerReport>,
+            recorder: &mut P2pRecorder,
+        ) {
+            for (incoming, time, better_time, duration, lag, data) in self.take(&(id.pid, id.fd)) {
+                log::info!("replay {} bytes held before connection", data.len());
+                if let Some(connection) = watching
+                    .get_mut(&id.pid)
+                    .and_then(|report| report.connection_mut(id.fd, id.generation))
+                {
+                    if incoming {
+                        connection.checksum.0 += &data;
+                    } else {
+                        connection.checksum.1 += &data;
+                    }
+                }
+                let metadata = EventMetadata {
+                    id: id.clone(),
+                    time,
+                    better_time,
+                    duration,
+                    time_source,
+                    lag,
+                };
+                recorder.on_data(incoming, metadata, buffered, data);
+            }
+        }
+
+        // drop data if the connection was not established within the grace window
+        fn expire(&mut self, now: SystemTime) {
+            self.0.retain(|(pid, fd), (first, chunks)| {
+                let alive = now.duration_since(*first).unwrap_or_default() < Self::GRACE;
+                if !alive {
+                    let len = chunks.iter().map(|(.., data)| data.len()).sum::<usize>();
+                    log::warn!("{pid} cannot handle data on {fd}, not connected, {len} bytes");
+                }
+                alive
+            });
+        }
+    }
+
+    /// Counts connections on each (pid, fd), the kernel reuses the fd of a closed socket
+    /// right away, the generation tells apart the connections on it.
+    #[derive(Default)]
+    struct Generations(BTreeMap<(u32, u32), u32>);
+
+    impl Generations {
+        // starts from `1`, `0` means unknown
+        fn next(&mut self, key: (u32, u32)) -> u32 {
+            let generation = self.0.entry(key).or_default();
+            *generation = generation.wrapping_add(1).max(1);
+            *generation
+        }
+    }
+
+    /// Connections keyed by (pid, fd), at most `cap` per pid, if a node leaks fds
+    /// the oldest connection of the pid is evicted.
+    struct Capped<V> {
+        inner: BTreeMap<(u32, u32), (u64, V)>,
+        seq: u64,
+        cap: usize,
+    }
+
+    impl<V> Capped<V> {
+        fn new(cap: usize) -> Self {
+            Capped {
+                inner: BTreeMap::new(),
+                seq: 0,
+                cap,
+            }
+        }
+
+        // returns the previous value and the evicted entry
+        #[allow(clippy::type_complexity)]
+        fn insert(&mut self, key: (u32, u32), value: V) -> (Option<V>, Option<((u32, u32), V)>) {
+            self.seq += 1;
+            if let Some((_, old)) = self.inner.insert(key, (self.seq, value)) {
+                return (Some(old), None);
+            }
+            let (pid, _) = key;
+            let it = self.inner.range((pid, 0)..=(pid, u32::MAX));
+            if it.clone().count() <= self.cap {
+                return (None, None);
+            }
+            let oldest = it
+                .min_by_key(|(_, (seq, _))| *seq)
+                .map(|(key, _)| *key)
+                .expect("the range is not empty");
+            let (_, evicted) = self.inner.remove(&oldest).expect("the key is in the map");
+            log::warn!(
+                "pid {pid} has more than {} connections, evict fd {}",
+                self.cap,
+                oldest.1
+            );
+            metrics::connection_evicted();
+            (None, Some((oldest, evicted)))
+        }
+
+        fn get(&self, key: &(u32, u32)) -> Option<&V> {
+            self.inner.get(key).map(|(_, v)| v)
+        }
+
+        fn remove(&mut self, key: &(u32, u32)) -> Option<V> {
+            self.inner.remove(key).map(|(_, v)| v)
+        }
+    }
+
     // let env = env_logger::Env::default().default_filter_or("warn");
     // env_logger::init_from_env(env);
     // if let Err(err) = sudo::escalate_if_needed() {
@@ -1176,7 +1764,7 @@ fn main() {
 
     let terminating = Arc::new(AtomicBool::new(dry));
 
-    let mut interface = env::var("FIREWALL_INTERFACE").unwrap_or("eth0".to_string());
+    let interfaces = env::var("FIREWALL_INTERFACE").unwrap_or("eth0".to_string());
 
     static CODE: &[u8] = include_bytes!(concat!("../", env!("BPF_CODE_RECORDER")));
 
@@ -1192,19 +1780,63 @@ fn main() {
         .insert([0; 16], [0, 0, 0, 1])
         .unwrap();
 
-    interface.push('\0');
-    let if_index = unsafe { libc::if_nametoindex(interface.as_ptr() as _) };
+    // comma separated, an interface which doesn't exist is skipped
+    let interfaces = interfaces
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .filter_map(|name| {
+            let c_name = CString::new(name).ok()?;
+            let if_index = unsafe { libc::if_nametoindex(c_name.as_ptr()) };
+            if if_index == 0 {
+                log::error!("no such interface {name}, the firewall is not attached to it");
+                return None;
+            }
+            Some((name.to_owned(), if_index as i32))
+        })
+        .collect::<Vec<_>>();
 
+    let direction = application::FirewallDirection::from_env();
     const XDP_FLAGS_SKB_MODE: u32 = 1 << 1;
-    skeleton
-        .attach_xdp("disable_connections", if_index as i32, XDP_FLAGS_SKB_MODE)
-        .unwrap();
+    // dropped at the end of `main`, detaches the program from each interface
+    let mut xdp = vec![];
+    if direction.ingress() {
+        for (name, if_index) in &interfaces {
+            match skeleton.attach_xdp("disable_connections", *if_index, XDP_FLAGS_SKB_MODE) {
+                Ok(_) => {
+                    log::info!("attached xdp to {name}");
+                    xdp.push(application::XdpAttachment::new(
+                        name.clone(),
+                        *if_index,
+                        XDP_FLAGS_SKB_MODE,
+                    ));
+                }
+                Err(err) => log::error!("failed to attach xdp to {name}: {err:?}"),
+            }
+        }
+    }
 
     let (skeleton, mut app) = skeleton
         .attach()
         .unwrap_or_else(|code| panic!("failed to attach bpf: {}", code));
     log::info!("attached bpf module");
 
+    // dropped at the end of `main`, after the skeleton
+    let mut tc_egress = vec![];
+    if direction.egress() {
+        let prog_fd = match app.filter_egress.kind() {
+            ebpf::kind::AppItemKind::Prog(prog) => prog.fd(),
+            _ => unreachable!(),
+        };
+        for (name, if_index) in &interfaces {
+            match application::TcEgress::attach(*if_index, prog_fd) {
+                Ok(v) => tc_egress.push(v),
+                Err(code) => log::error!("failed to attach tc egress to {name}: {code}"),
+            }
+        }
+    }
+    log::info!("firewall direction: {direction:?}");
+
     let fd = match app.event_queue.kind_mut() {
         ebpf::kind::AppItemKindMut::Map(map) => map.fd(),
         _ => unreachable!(),
@@ -1227,29 +1859,92 @@ fn main() {
         }
     };
 
+    // by default the data on every port is captured
+    if let Ok(list) = env::var("CAPTURE_PORTS") {
+        let ports = list
+            .split(',')
+            .filter_map(|s| s.trim().parse::<u16>().ok())
+            .filter(|&port| port != 0)
+            .collect::<Vec<_>>();
+        for &port in &ports {
+            if let Err(code) = app.capture_ports.insert(port.to_be_bytes(), [0, 0, 0, 1]) {
+                log::error!("cannot add capture port {port}: {code}");
+            }
+        }
+        if !ports.is_empty() {
+            // mark the set is not empty, the kernel starts filtering
+            app.capture_ports
+                .insert([0, 0], [0, 0, 0, 1])
+                .unwrap_or_default();
+            log::info!("capture only ports {ports:?}");
+        }
+    }
+
     let (app_client, app_server) = application::new(
         app.whitelist.clone(),
         app.whitelist_ports.clone(),
         app.blocked.clone(),
+        app.pid_bytes.clone(),
     );
 
-    let (main_tx, main_rx) = mpsc::channel();
+    // bounded, so the slow consumer blocks the reader and the kernel side overflows,
+    // instead of the memory growing unbounded
+    let capacity = env::var("EVENT_CHANNEL_CAP")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(0x1000);
+    metrics::EVENT_CHANNEL_CAPACITY.store(capacity, Ordering::SeqCst);
+    // by default the overflow is fatal, `resync` loses the unread data, but keeps recording
+    if env::var("RING_BUFFER_OVERFLOW").as_deref() == Ok("resync") {
+        rb.set_overflow_policy(OverflowPolicy::Resync);
+    }
+    metrics::RING_BUFFER_CAPACITY.store(rb.capacity(), Ordering::SeqCst);
+    let rb_fill = rb.fill();
+    let (main_tx, main_rx) = mpsc::sync_channel(capacity);
     let main_thread = thread::spawn({
         let terminating = terminating.clone();
         move || {
-            while let Ok(event) = rb.read_blocking::<SnifferEvent>(&terminating) {
-                main_tx.send(event).unwrap_or_default();
+            let (mut events, mut batches, mut since) = (0, 0, Instant::now());
+            // decoded right in the ring buffer, the bytes are copied only once,
+            // to hand the event over to the recorder thread
+            while let Ok((event, remaining)) = rb.read_ref::<SnifferEvent<&[u8]>>(&terminating) {
+                let event = event.map(SnifferEvent::into_owned);
+                metrics::RING_BUFFER_USED.store(rb_fill.used(), Ordering::SeqCst);
+                metrics::RING_BUFFER_SKIPPED.store(rb_fill.skipped(), Ordering::SeqCst);
+                events += 1;
+                // everything written by the kernel so far is read
+                if remaining == 0 {
+                    batches += 1;
+                }
+                metrics::event_sent();
+                if main_tx.send((event, remaining)).is_err() {
+                    metrics::event_received();
+                }
+                let elapsed = since.elapsed();
+                if elapsed >= Duration::from_secs(10) {
+                    let rate = events as f64 / elapsed.as_secs_f64();
+                    log::info!(
+                        "ring buffer: {events} events in {batches} batches, {rate:.0} events/s"
+                    );
+                    events = 0;
+                    batches = 0;
+                    since = Instant::now();
+                }
             }
         }
     });
 
     let consumer_thread = thread::spawn(move || {
+        let feed = RawFeed::new();
+        let cx_handle = CxHandle::default();
         let (db, callback, server_thread) = server::spawn(
             port,
             db_path.clone(),
             Some(app_client.clone()),
             key_path,
             cert_path,
+            feed.clone(),
+            cx_handle.clone(),
         );
         {
             let terminating = terminating.clone();
@@ -1267,18 +1962,32 @@ fn main() {
             }
         }
         let db_capnp = db.core();
+        let db_flush = db.core();
 
         let test = env::var("TEST").is_ok();
 
         let mut origin = proc::S::read().ok().and_then(|s| s.b_time);
+        // every timestamp is relative to the same origin
+        let time_source = if origin.is_some() {
+            TimeSource::BootTime
+        } else {
+            TimeSource::FirstEvent
+        };
         if let Some(boot_time) = &origin {
             log::info!("boot time: {boot_time:?}");
         }
 
-        let mut p2p_cns = BTreeMap::new();
+        let max_cns = env::var("MAX_CNS_PER_PID")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0x1000);
+        let mut p2p_cns = Capped::new(max_cns);
+        let mut generations = Generations::default();
+        let mut close_reasons = BTreeMap::new();
         let counter = db.messages.clone();
-        let mut pending_out_cns = BTreeMap::new();
+        let mut pending_out_cns = Capped::new(max_cns);
         let mut recorder = P2pRecorder::new(db, test);
+        cx_handle.set(recorder.cx.clone());
         let mut watching = BTreeMap::new();
         let mut capnp_readers = BTreeMap::<_, CapnpReader>::new();
         let mut capnp_blacklist = BTreeSet::new();
@@ -1288,13 +1997,34 @@ fn main() {
         let mut subscriptions = BTreeMap::new();
         let mut chain_id = BTreeMap::new();
         let mut max_lag = Duration::ZERO;
+        let mut clock = ClockSync::default();
 
         let mut snark_workers = BTreeMap::new();
+        let mut early_data = EarlyData::default();
+
+        let stale = env::var("STALE_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(60));
+        let watched_pids = Arc::new(Mutex::new(BTreeSet::new()));
+        watchdog(stale, watched_pids.clone());
+
+        let floor = env::var("DISK_FREE_FLOOR_MB")
+            .ok()
+            .and_then(|s| s.parse::<u64>().ok())
+            .unwrap_or(1024);
+        if floor != 0 {
+            disk_watchdog(db_path.clone(), floor * 1024 * 1024);
+        }
 
         while let Ok((event, buffered)) = main_rx.recv() {
+            metrics::event_received();
             let Some(event) = event else {
                 continue;
             };
+            metrics::event_seen(SystemTime::now());
+            feed.send_with(|| event.encode());
 
             if buffered > max_buffered {
                 max_buffered = buffered;
@@ -1323,22 +2053,13 @@ fn main() {
                 }
                 Some(origin) => *origin + Duration::from_nanos(event.ts1),
             };
-            let better_time = {
-                let instant_there = Duration::from_nanos(event.ts1);
-                let mut tp = libc::timespec {
-                    tv_sec: 0,
-                    tv_nsec: 0,
-                };
-                unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut tp) };
-                let instant_here = Duration::new(tp.tv_sec as _, tp.tv_nsec as _);
-                let delta = instant_here.checked_sub(instant_there).unwrap_or_default();
-                if delta >= max_lag + Duration::from_secs(60) {
-                    max_lag = delta;
-                    log::warn!("lagging: {delta:?}");
-                }
-                SystemTime::now() - delta
-            };
+            let (better_time, lag) = clock.convert(event.ts1);
+            if lag >= max_lag + Duration::from_secs(60) {
+                max_lag = lag;
+                log::warn!("lagging: {lag:?}");
+            }
             let duration = Duration::from_nanos(event.ts1 - event.ts0);
+            early_data.expire(time);
             match event.variant {
                 SnifferEventVariant::NewSnarkWorkerApp => {
                     snark_workers.insert(event.pid, SnarkWorkerState::default());
@@ -1347,6 +2068,7 @@ fn main() {
                     log::info!("exec {alias} pid: {}", event.pid);
                     recorder.on_alias(event.pid, alias);
                     if !watching.contains_key(&event.pid) {
+                        watched_pids.lock().expect("poisoned").insert(event.pid);
                         let version = env!("GIT_HASH");
                         watching.insert(
                             event.pid,
@@ -1370,10 +2092,13 @@ fn main() {
                             addr,
                             pid: event.pid,
                             fd: event.fd,
+                            generation: 0,
                         },
                         time,
                         better_time,
                         duration,
+                        time_source,
+                        lag,
                     };
 
                     log::info!("new unconfirmed {metadata}");
@@ -1383,7 +2108,8 @@ fn main() {
                     if value.len() != 4 {
                         continue;
                     }
-                    let Some(addr) = pending_out_cns.remove(&(event.pid, event.fd)) else {
+                    let key = (event.pid, event.fd);
+                    let Some(addr) = pending_out_cns.remove(&key) else {
                         continue;
                     };
                     let metadata = EventMetadata {
@@ -1391,10 +2117,13 @@ fn main() {
                             addr,
                             pid: event.pid,
                             fd: event.fd,
+                            generation: generations.next(key),
                         },
                         time,
                         better_time,
                         duration,
+                        time_source,
+                        lag,
                     };
                     let value = u32::from_ne_bytes(
                         value
@@ -1403,7 +2132,11 @@ fn main() {
                             .expect("must be checked above `value.len() != 4`"),
                     );
                     log::info!("getsockopt {value}, {metadata}");
+                    // the ephemeral port, the socket is gone if the process closed it already
+                    let local_port = proc::local_port(event.pid, event.fd).unwrap_or_default();
                     if value != 0 {
+                        // `SO_ERROR` of the non-blocking `connect`
+                        recorder.on_connect_failed(metadata, local_port, value as i32);
                         continue;
                     }
                     if let Some(report) = watching.get_mut(&event.pid) {
@@ -1417,26 +2150,73 @@ fn main() {
                             counter,
                             incoming: false,
                             fd: event.fd as i32,
+                            generation: metadata.id.generation,
                             checksum: Default::default(),
                             timestamp: better_time,
                         });
                     }
 
-                    if let Some(old_addr) = p2p_cns.insert((event.pid, event.fd), addr) {
+                    let (old, evicted) = p2p_cns.insert(key, metadata.id.clone());
+                    if let Some(old) = old {
                         log::warn!("new outgoing connection on already allocated fd");
                         let mut metadata = metadata.clone();
-                        metadata.id.addr = old_addr;
-                        recorder.on_disconnect(metadata, buffered);
+                        metadata.id = old;
+                        recorder.on_disconnect(metadata, buffered, None);
+                    }
+                    if let Some((_, info)) = evicted {
+                        let mut metadata = metadata.clone();
+                        metadata.id = info;
+                        recorder.on_disconnect(metadata, buffered, None);
                     }
                     log::info!("new outgoing connection {}", metadata);
                     recorder.on_connect::<true>(
                         false,
-                        metadata,
+                        metadata.clone(),
+                        local_port,
                         buffered,
                         chain_id.get(&event.pid).cloned().unwrap_or_default(),
                     );
+                    early_data.replay(
+                        &metadata.id,
+                        time_source,
+                        buffered,
+                        &mut watching,
+                        &mut recorder,
+                    );
+                }
+                SnifferEventVariant::PeerCred { pid, uid, gid } => {
+                    // the peer of the IPC socket, should be the libp2p_helper or the daemon
+                    let watched = watching.contains_key(&pid);
+                    log::info!(
+                        "{} fd: {}, peer credentials pid: {pid} ({}), uid: {uid}, gid: {gid}",
+                        event.pid,
+                        event.fd,
+                        if watched { "watched" } else { "unknown" },
+                    );
+                    recorder.on_peer_cred(IpcPeer {
+                        pid: event.pid,
+                        fd: event.fd,
+                        peer_pid: pid,
+                        uid,
+                        gid,
+                        watched,
+                    });
                 }
                 SnifferEventVariant::IncomingConnection(addr) => {
+                    let key = (event.pid, event.fd);
+                    let metadata = EventMetadata {
+                        id: ConnectionInfo {
+                            addr,
+                            pid: event.pid,
+                            fd: event.fd,
+                            generation: generations.next(key),
+                        },
+                        time,
+                        better_time,
+                        duration,
+                        time_source,
+                        lag,
+                    };
                     if let Some(report) = watching.get_mut(&event.pid) {
                         let counter = report
                             .network
@@ -1448,51 +2228,56 @@ fn main() {
                             counter,
                             incoming: true,
                             fd: event.fd as i32,
+                            generation: metadata.id.generation,
                             checksum: Default::default(),
                             timestamp: better_time,
                         });
                     }
 
-                    let metadata = EventMetadata {
-                        id: ConnectionInfo {
-                            addr,
-                            pid: event.pid,
-                            fd: event.fd,
-                        },
-                        time,
-                        better_time,
-                        duration,
-                    };
-                    if let Some(old_addr) = p2p_cns.insert((event.pid, event.fd), addr) {
+                    let (old, evicted) = p2p_cns.insert(key, metadata.id.clone());
+                    if let Some(old) = old {
                         log::warn!("new incoming connection on already allocated fd");
                         let mut metadata = metadata.clone();
-                        metadata.id.addr = old_addr;
-                        recorder.on_disconnect(metadata, buffered);
+                        metadata.id = old;
+                        recorder.on_disconnect(metadata, buffered, None);
+                    }
+                    if let Some((_, info)) = evicted {
+                        let mut metadata = metadata.clone();
+                        metadata.id = info;
+                        recorder.on_disconnect(metadata, buffered, None);
                     }
                     log::info!("new incoming connection {}", metadata);
                     recorder.on_connect::<true>(
                         true,
-                        metadata,
+                        metadata.clone(),
+                        0,
                         buffered,
                         chain_id.get(&event.pid).cloned().unwrap_or_default(),
                     );
+                    early_data.replay(
+                        &metadata.id,
+                        time_source,
+                        buffered,
+                        &mut watching,
+                        &mut recorder,
+                    );
                 }
                 SnifferEventVariant::Disconnected => {
                     let key = (event.pid, event.fd);
-                    if let Some(addr) = p2p_cns.remove(&key) {
+                    let close_reason = close_reasons.remove(&key);
+                    if let Some(info) = p2p_cns.remove(&key) {
                         let metadata = EventMetadata {
-                            id: ConnectionInfo {
-                                addr,
-                                pid: event.pid,
-                                fd: event.fd,
-                            },
+                            id: info,
                             time,
                             better_time,
                             duration,
+                            time_source,
+                            lag,
                         };
                         log::info!("disconnected {}", metadata);
-                        recorder.on_disconnect(metadata, buffered);
+                        recorder.on_disconnect(metadata, buffered, close_reason);
                     } else {
+                        early_data.take(&key);
                         // `close` means close socket, not necessarily it was connected
                         // so it is ok
                         log::debug!(
@@ -1502,20 +2287,47 @@ fn main() {
                         );
                     }
                 }
-                SnifferEventVariant::Error(_, -104) => {}
+                SnifferEventVariant::HalfClosed { write } => {
+                    let key = (event.pid, event.fd);
+                    if let Some(info) = p2p_cns.get(&key) {
+                        let metadata = EventMetadata {
+                            id: info.clone(),
+                            time,
+                            better_time,
+                            duration,
+                            time_source,
+                            lag,
+                        };
+                        let direction = if write { "write" } else { "read" };
+                        log::info!("{direction} half closed {}", metadata);
+                        recorder.on_shutdown(metadata, write);
+                    }
+                }
                 SnifferEventVariant::Error(tag, code) => {
                     let key = (event.pid, event.fd);
-                    if let Some(addr) = p2p_cns.get(&key) {
+                    if let Some(info) = p2p_cns.get(&key) {
                         let metadata = EventMetadata {
-                            id: ConnectionInfo {
-                                addr: *addr,
-                                pid: event.pid,
-                                fd: event.fd,
-                            },
+                            id: info.clone(),
                             time,
                             better_time,
                             duration,
+                            time_source,
+                            lag,
                         };
+                        // `sendmsg`/`recvmsg` with more segments than the kernel code walks,
+                        // the connection stays open, but its stream is missing the rest
+                        if code == -90 {
+                            log::error!("{metadata}, tag: {tag:?}, vectored io truncated");
+                            continue;
+                        }
+                        // the kernel closes the connection right after this error
+                        if let DataTag::Read | DataTag::Write = tag {
+                            close_reasons.insert(key, -code);
+                        }
+                        // the peer has reset the connection, it is normal
+                        if code == -104 {
+                            continue;
+                        }
 
                         log::error!("{metadata},  tag: {tag:?}, code: {code}");
                     }
@@ -1537,7 +2349,7 @@ fn main() {
                         let reader = capnp_readers.entry(key).or_default();
                         reader.extend_from_slice(&data);
                         let local_node_address = recorder.cx.pid_to_addr(event.pid);
-                        if !reader.process(
+                        match reader.process(
                             event.pid,
                             true,
                             local_node_address,
@@ -1547,8 +2359,18 @@ fn main() {
                             &mut subscriptions,
                             chain_id.entry(event.pid).or_default(),
                         ) {
-                            capnp_readers.remove(&key);
-                            capnp_blacklist.insert(key);
+                            CapnpStatus::Continue => (),
+                            CapnpStatus::Stop => {
+                                capnp_readers.remove(&key);
+                                capnp_blacklist.insert(key);
+                            }
+                            CapnpStatus::SchemaMismatch => {
+                                // don't trust the IPC of this pid in both directions
+                                for key in [(event.pid, true), (event.pid, false)] {
+                                    capnp_readers.remove(&key);
+                                    capnp_blacklist.insert(key);
+                                }
+                            }
                         }
                         continue;
                     }
@@ -1557,30 +2379,24 @@ fn main() {
                         continue;
                     }
                     let key = (event.pid, event.fd);
-                    if let Some(addr) = p2p_cns.get(&key) {
+                    if let Some(info) = p2p_cns.get(&key) {
                         watching
                             .get_mut(&event.pid)
-                            .and_then(|report| {
-                                report
-                                    .network
-                                    .iter_mut()
-                                    .rev()
-                                    .find(|cn| addr.ip() == cn.ip && event.fd == cn.fd as u32)
-                            })
+                            .and_then(|report| report.connection_mut(event.fd, info.generation))
                             .map(|connection| connection.checksum.0 += &data);
 
                         let metadata = EventMetadata {
-                            id: ConnectionInfo {
-                                addr: *addr,
-                                pid: event.pid,
-                                fd: event.fd,
-                            },
+                            id: info.clone(),
                             time,
                             better_time,
                             duration,
+                            time_source,
+                            lag,
                         };
                         recorder.on_data(true, metadata, buffered, data);
-                    } else {
+                    } else if let Err((.., data)) =
+                        early_data.hold(key, (true, time, better_time, duration, lag, data))
+                    {
                         log::warn!(
                             "{} cannot handle data on {}, not connected, {}",
                             event.pid,
@@ -1606,7 +2422,7 @@ fn main() {
                         let reader = capnp_readers.entry(key).or_default();
                         reader.extend_from_slice(&data);
                         let local_node_address = recorder.cx.pid_to_addr(event.pid);
-                        if !reader.process(
+                        match reader.process(
                             event.pid,
                             false,
                             local_node_address,
@@ -1616,8 +2432,18 @@ fn main() {
                             &mut subscriptions,
                             chain_id.entry(event.pid).or_default(),
                         ) {
-                            capnp_readers.remove(&key);
-                            capnp_blacklist.insert(key);
+                            CapnpStatus::Continue => (),
+                            CapnpStatus::Stop => {
+                                capnp_readers.remove(&key);
+                                capnp_blacklist.insert(key);
+                            }
+                            CapnpStatus::SchemaMismatch => {
+                                // don't trust the IPC of this pid in both directions
+                                for key in [(event.pid, true), (event.pid, false)] {
+                                    capnp_readers.remove(&key);
+                                    capnp_blacklist.insert(key);
+                                }
+                            }
                         }
                         continue;
                     }
@@ -1626,29 +2452,23 @@ fn main() {
                         continue;
                     }
                     let key = (event.pid, event.fd);
-                    if let Some(addr) = p2p_cns.get(&key) {
+                    if let Some(info) = p2p_cns.get(&key) {
                         watching
                             .get_mut(&event.pid)
-                            .and_then(|report| {
-                                report
-                                    .network
-                                    .iter_mut()
-                                    .rev()
-                                    .find(|cn| addr.ip() == cn.ip && event.fd == cn.fd as u32)
-                            })
+                            .and_then(|report| report.connection_mut(event.fd, info.generation))
                             .map(|connection| connection.checksum.1 += &data);
                         let metadata = EventMetadata {
-                            id: ConnectionInfo {
-                                addr: *addr,
-                                pid: event.pid,
-                                fd: event.fd,
-                            },
+                            id: info.clone(),
                             time,
                             better_time,
                             duration,
+                            time_source,
+                            lag,
                         };
                         recorder.on_data(false, metadata, buffered, data);
-                    } else {
+                    } else if let Err((.., data)) =
+                        early_data.hold(key, (false, time, better_time, duration, lag, data))
+                    {
                         log::warn!(
                             "{} cannot handle data on {}, not connected, {}",
                             event.pid,
@@ -1662,8 +2482,34 @@ fn main() {
                         recorder.on_randomness(event.pid, random, time);
                     }
                 }
+                SnifferEventVariant::ConnectFailed { addr, errno } => {
+                    let metadata = EventMetadata {
+                        id: ConnectionInfo {
+                            addr,
+                            pid: event.pid,
+                            fd: event.fd,
+                            generation: generations.next((event.pid, event.fd)),
+                        },
+                        time,
+                        better_time,
+                        duration,
+                        time_source,
+                        lag,
+                    };
+                    log::info!("connect failed {metadata}, errno: {errno}");
+                    let local_port = proc::local_port(event.pid, event.fd).unwrap_or_default();
+                    recorder.on_connect_failed(metadata, local_port, errno);
+                }
+                SnifferEventVariant::UdpPeer(addr) => {
+                    log::debug!("{} udp peer {addr} on fd {}", event.pid, event.fd);
+                }
             }
         }
+        // the connections still open store their close time, then it is persisted
+        recorder.join();
+        if let Err(err) = db_flush.flush_all() {
+            log::error!("cannot flush the database: {err}");
+        }
 
         let list = || -> std::io::Result<Vec<String>> {
             let mut res = vec![];
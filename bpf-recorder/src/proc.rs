@@ -50,3 +50,55 @@ impl S {
         Ok(s)
     }
 }
+
+/// Local port of the TCP socket behind the file descriptor, found by the inode in
+/// `/proc/{pid}/net/tcp` and `/proc/{pid}/net/tcp6`, the socket must still be open.
+pub fn local_port(pid: u32, fd: u32) -> io::Result<u16> {
+    let link = std::fs::read_link(format!("/proc/{pid}/fd/{fd}"))?;
+    let inode = link
+        .to_str()
+        .and_then(|s| s.strip_prefix("socket:["))
+        .and_then(|s| s.strip_suffix(']'))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not a socket"))?;
+    for table in ["tcp", "tcp6"] {
+        let f = io::BufReader::new(File::open(format!("/proc/{pid}/net/{table}"))?);
+        if let Some(port) = find_local_port(f, inode)? {
+            return Ok(port);
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::NotFound, "no such socket"))
+}
+
+// sl local_address rem_address st tx_queue:rx_queue tr:tm->when retrnsmt uid timeout inode
+fn find_local_port(table: impl BufRead, inode: &str) -> io::Result<Option<u16>> {
+    for line in table.lines().skip(1) {
+        let line = line?;
+        let mut it = line.split_whitespace();
+        let local = it.nth(1);
+        if it.nth(7) != Some(inode) {
+            continue;
+        }
+        return Ok(local
+            .and_then(|s| s.rsplit_once(':'))
+            .and_then(|(_, port)| u16::from_str_radix(port, 16).ok()));
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+#[test]
+fn local_port_by_inode() {
+    let table = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 00000000:206E 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 20631 1 0000000000000000 100 0 0 10 0
+   1: 0100000A:9C40 0200000A:206E 01 00000000:00000000 02:000A7A1E 00000000     0        0 20718 2 0000000000000000 20 4 30 10 -1
+";
+    assert_eq!(
+        find_local_port(table.as_bytes(), "20718").unwrap(),
+        Some(40000)
+    );
+    assert_eq!(
+        find_local_port(table.as_bytes(), "20631").unwrap(),
+        Some(8302)
+    );
+    assert_eq!(find_local_port(table.as_bytes(), "1").unwrap(), None);
+}
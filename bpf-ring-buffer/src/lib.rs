@@ -1,8 +1,12 @@
 use std::{
     fmt, io, mem,
+    ops::Range,
     os::unix::io::AsRawFd,
     ptr, slice,
-    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
 };
 
 pub trait RingBufferData
@@ -14,12 +18,46 @@ where
     fn from_rb_slice(slice: &[u8]) -> Result<Option<Self>, Self::Error>;
 }
 
+/// Same as `RingBufferData`, but the value may borrow the memory of the ring buffer
+/// instead of copying it, see `RingBuffer::read_ref`.
+pub trait RingBufferDataRef<'a>
+where
+    Self: Sized,
+{
+    type Error: fmt::Debug;
+
+    fn from_rb_slice(slice: &'a [u8]) -> Result<Option<Self>, Self::Error>;
+}
+
+impl<'a> RingBufferDataRef<'a> for &'a [u8] {
+    type Error = ();
+
+    fn from_rb_slice(slice: &'a [u8]) -> Result<Option<Self>, Self::Error> {
+        Ok(Some(slice))
+    }
+}
+
 pub enum Output<D> {
     Value(D),
     /// How many bytes are remaining in the ring buffer
     Remaining(usize),
 }
 
+/// What to do if the kernel has overwritten the data we have not read yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// The reader fails with an error.
+    Abort,
+    /// Skip everything unread and continue from the current producer position.
+    Resync,
+}
+
+impl Default for OverflowPolicy {
+    fn default() -> Self {
+        OverflowPolicy::Abort
+    }
+}
+
 pub struct RingBuffer {
     fd: i32,
     mask: usize,
@@ -27,6 +65,37 @@ pub struct RingBuffer {
     // pointers to shared memory
     observer: RingBufferObserver,
     previous_distance: usize,
+    fill: Arc<Fill>,
+    overflow_policy: OverflowPolicy,
+}
+
+/// How much unread data is in the ring buffer, updated by the reader on every read,
+/// can be queried from any thread.
+pub struct Fill {
+    used: AtomicUsize,
+    capacity: usize,
+    skipped: AtomicUsize,
+}
+
+impl Fill {
+    /// Bytes written by the kernel, but not yet read.
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Bytes lost due to overflows, see `OverflowPolicy::Resync`.
+    pub fn skipped(&self) -> usize {
+        self.skipped.load(Ordering::Relaxed)
+    }
+
+    /// From 0.0 to 1.0.
+    pub fn ratio(&self) -> f32 {
+        self.used() as f32 / self.capacity as f32
+    }
 }
 
 impl AsRawFd for RingBuffer {
@@ -148,9 +217,32 @@ impl RingBuffer {
                 event: [event],
             },
             previous_distance: 0,
+            fill: Arc::new(Fill {
+                used: AtomicUsize::new(0),
+                capacity: max_length,
+                skipped: AtomicUsize::new(0),
+            }),
+            overflow_policy: OverflowPolicy::default(),
         })
     }
 
+    pub fn set_overflow_policy(&mut self, policy: OverflowPolicy) {
+        self.overflow_policy = policy;
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.fill.capacity()
+    }
+
+    pub fn fill_ratio(&self) -> f32 {
+        self.fill.ratio()
+    }
+
+    /// The handle to watch the fullness from another thread.
+    pub fn fill(&self) -> Arc<Fill> {
+        self.fill.clone()
+    }
+
     fn read_value<D>(&mut self) -> Result<(Option<D>, usize), Error>
     where
         D: RingBufferData,
@@ -172,6 +264,30 @@ impl RingBuffer {
     where
         D: RingBufferData,
     {
+        let (range, distance) = self.next_record()?;
+        let value = match range.map(|range| D::from_rb_slice(self.record(range))) {
+            Some(Err(err)) => {
+                log::error!("rb parse data: {:?}", err);
+                None
+            }
+            Some(Ok(value)) => value,
+            None => None,
+        };
+        Ok((value, distance))
+    }
+
+    fn record(&self, range: Range<usize>) -> &[u8] {
+        unsafe {
+            slice::from_raw_parts(
+                ((self.observer.data.as_ptr() as usize) + range.start) as *mut u8,
+                range.len(),
+            )
+        }
+    }
+
+    // advances our position, but doesn't publish it, the kernel will not overwrite the record
+    // until `read_finish`; returns the position of the record in the data, `None` if discarded
+    fn next_record(&mut self) -> Result<(Option<Range<usize>>, usize), Error> {
         const HEADER_SIZE: usize = 8;
         const BUSY_BIT: usize = 1 << 31;
         const DISCARD_BIT: usize = 1 << 30;
@@ -181,7 +297,19 @@ impl RingBuffer {
             // determine how far we are, how many unseen data is in the buffer
             let distance = pr_pos - self.consumer_pos_value;
             if distance > self.mask + 1 {
-                return Err(Error::Overflown);
+                if self.overflow_policy == OverflowPolicy::Abort {
+                    return Err(Error::Overflown);
+                }
+                // the data is lost anyway, continue with the fresh data
+                self.consumer_pos_value = pr_pos;
+                self.read_finish();
+                self.previous_distance = 0;
+                self.fill.used.store(0, Ordering::Relaxed);
+                let total = self.fill.skipped.fetch_add(distance, Ordering::Relaxed) + distance;
+                log::warn!(
+                    "ring buffer overflown, resync, skipped_bytes={distance} skipped_bytes_total={total}"
+                );
+                return Err(Error::WouldBlock);
             }
             if distance > self.previous_distance {
                 let percent = distance * 100 / (self.mask + 1);
@@ -191,6 +319,7 @@ impl RingBuffer {
                 }
             }
             self.previous_distance = distance;
+            self.fill.used.store(distance, Ordering::Relaxed);
             // the first 8 bytes of the memory slice is a header (length and flags)
             let (header, data_offset) = {
                 let masked_pos = self.consumer_pos_value & self.mask;
@@ -226,24 +355,12 @@ impl RingBuffer {
 
             if !discard {
                 // if not discard, yield the slice
-                let s = unsafe {
-                    slice::from_raw_parts(
-                        ((self.observer.data.as_ptr() as usize) + data_offset) as *mut u8,
-                        length,
-                    )
-                };
-                match D::from_rb_slice(s) {
-                    Err(err) => {
-                        log::error!("rb parse data: {:?}", err);
-                        Ok((None, distance))
-                    }
-                    Ok(None) => Ok((None, distance)),
-                    Ok(Some(value)) => Ok((Some(value), distance)),
-                }
+                Ok((Some(data_offset..(data_offset + length)), distance))
             } else {
                 Ok((None, distance))
             }
         } else {
+            self.fill.used.store(0, Ordering::Relaxed);
             Err(Error::WouldBlock)
         }
     }
@@ -304,26 +421,106 @@ impl RingBuffer {
         }
     }
 
-    pub fn read_blocking<D>(&mut self, terminating: &AtomicBool) -> io::Result<(Option<D>, usize)>
+    /// Reads at most `max` records which are already in the buffer, never waits.
+    /// The vector is empty if nothing is ready. Each item is the same as `read_blocking` returns.
+    pub fn try_drain<D>(&mut self, max: usize) -> io::Result<Vec<(Option<D>, usize)>>
     where
         D: RingBufferData,
     {
-        let mut tries = 0;
+        let mut values = Vec::with_capacity(max.min(0x100));
+        while values.len() < max {
+            match self.read_value() {
+                Err(Error::WouldBlock) => break,
+                // the position is not advanced, so the next call reports the overflow again,
+                // give away what is already read
+                Err(Error::Overflown) if !values.is_empty() => break,
+                Err(Error::Overflown) => {
+                    return Err(io::Error::new(io::ErrorKind::Other, "overflow"));
+                }
+                Ok(value) => values.push(value),
+            }
+        }
+        Ok(values)
+    }
+
+    /// Waits for the data, then reads everything already written by the kernel,
+    /// the consumer position is published once for the whole batch.
+    /// The length of the vector is the batch size.
+    pub fn read_batch<D>(&mut self, terminating: &AtomicBool) -> io::Result<Vec<(Option<D>, usize)>>
+    where
+        D: RingBufferData,
+    {
+        let mut values = vec![];
         loop {
-            if tries > 10 {
-                log::debug!("cannot read ring buffer: {} attempts", tries);
+            match self.read_slice() {
+                // the kernel is writing the next record or nothing is left
+                Err(Error::WouldBlock) if !values.is_empty() => break,
+                Err(Error::WouldBlock) => {
+                    self.wait_epoll(terminating);
+                    if terminating.load(Ordering::SeqCst) {
+                        return Err(io::Error::new(io::ErrorKind::Other, "terminate"));
+                    }
+                }
+                Err(Error::Overflown) if !values.is_empty() => break,
+                Err(Error::Overflown) => {
+                    return Err(io::Error::new(io::ErrorKind::Other, "overflow"));
+                }
+                Ok(value) => values.push(value),
             }
-            match self.read_value() {
+        }
+        self.read_finish();
+        Ok(values)
+    }
+
+    /// Like `read_blocking`, but the value borrows the record right in the ring buffer.
+    /// The record is released, and the kernel may overwrite it, only on the next read,
+    /// so the value must be dropped before that, the borrow checker ensures it.
+    pub fn read_ref<'a, D>(&'a mut self, terminating: &AtomicBool) -> io::Result<(Option<D>, usize)>
+    where
+        D: RingBufferDataRef<'a>,
+    {
+        // release the record yielded by the previous call
+        self.read_finish();
+        let (range, distance) = loop {
+            match self.next_record() {
                 Err(Error::WouldBlock) => {
                     self.wait_epoll(terminating);
                     if terminating.load(Ordering::SeqCst) {
-                        break Err(io::Error::new(io::ErrorKind::Other, "terminate"));
+                        return Err(io::Error::new(io::ErrorKind::Other, "terminate"));
                     }
                 }
                 Err(Error::Overflown) => {
                     return Err(io::Error::new(io::ErrorKind::Other, "overflow"));
                 }
-                Ok(value) => return Ok(value),
+                Ok(v) => break v,
+            }
+        };
+        let value = match range.map(|range| D::from_rb_slice(self.record(range))) {
+            Some(Err(err)) => {
+                log::error!("rb parse data: {:?}", err);
+                None
+            }
+            Some(Ok(value)) => value,
+            None => None,
+        };
+        Ok((value, distance))
+    }
+
+    pub fn read_blocking<D>(&mut self, terminating: &AtomicBool) -> io::Result<(Option<D>, usize)>
+    where
+        D: RingBufferData,
+    {
+        let mut tries = 0;
+        loop {
+            if tries > 10 {
+                log::debug!("cannot read ring buffer: {} attempts", tries);
+            }
+            if let Some(value) = self.try_drain(1)?.pop() {
+                return Ok(value);
+            }
+            self.wait_epoll(terminating);
+            if terminating.load(Ordering::SeqCst) {
+                break Err(io::Error::new(io::ErrorKind::Other, "terminate"));
             }
             tries += 1;
         }
@@ -344,3 +541,103 @@ impl Drop for RingBufferObserver {
         Box::leak(data);
     }
 }
+
+#[cfg(test)]
+impl RingBuffer {
+    // the same layout as in `new`, but anonymous memory stands for the kernel's,
+    // the test plays the producer
+    fn anonymous(max_length: usize) -> Self {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let map = |len| unsafe {
+            let p = libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            assert_ne!(p, libc::MAP_FAILED);
+            p as usize
+        };
+        let consumer_pos = unsafe { Box::from_raw(map(page_size) as *mut AtomicUsize) };
+        let (producer_pos, data) = unsafe {
+            let p = map(page_size + max_length * 2);
+            let length = max_length * 2 / mem::size_of::<AtomicUsize>();
+            let q = slice::from_raw_parts_mut((p + page_size) as *mut AtomicUsize, length);
+            (
+                Box::from_raw(p as *mut AtomicUsize),
+                Box::from_raw(q as *mut [AtomicUsize]),
+            )
+        };
+        RingBuffer {
+            fd: -1,
+            mask: max_length - 1,
+            consumer_pos_value: 0,
+            observer: RingBufferObserver {
+                page_size,
+                data,
+                consumer_pos,
+                producer_pos,
+                epfd: epoll::create(true).unwrap(),
+                event: [epoll::Event::new(epoll::Events::EPOLLIN, 1)],
+            },
+            previous_distance: 0,
+            fill: Arc::new(Fill {
+                used: AtomicUsize::new(0),
+                capacity: max_length,
+                skipped: AtomicUsize::new(0),
+            }),
+            overflow_policy: OverflowPolicy::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn read_ref_borrows_the_record() {
+    // a tag and the body, as the kernel side would lay it out
+    struct Tagged<'a> {
+        tag: u8,
+        body: &'a [u8],
+    }
+
+    impl<'a> RingBufferDataRef<'a> for Tagged<'a> {
+        type Error = ();
+
+        fn from_rb_slice(slice: &'a [u8]) -> Result<Option<Self>, Self::Error> {
+            let (tag, body) = slice.split_first().ok_or(())?;
+            Ok(Some(Tagged { tag: *tag, body }))
+        }
+    }
+
+    let mut rb = RingBuffer::anonymous(0x1000);
+    let record = b"\x07borrowed";
+    let data = rb.observer.data.as_ptr() as *mut u8;
+    // the 8 bytes header holds the length
+    rb.observer.data[0].store(record.len(), Ordering::Release);
+    unsafe { ptr::copy_nonoverlapping(record.as_ptr(), data.add(8), record.len()) };
+    // the header and the record aligned by 8
+    let next = 8 + 16;
+    rb.observer.producer_pos.store(next, Ordering::Release);
+    let consumer_pos: *const AtomicUsize = &*rb.observer.consumer_pos;
+    let consumer_pos = || unsafe { (*consumer_pos).load(Ordering::Acquire) };
+
+    let terminating = AtomicBool::new(false);
+    {
+        let (value, remaining) = rb.read_ref::<Tagged>(&terminating).unwrap();
+        let value = value.unwrap();
+        assert_eq!(remaining, 0);
+        assert_eq!(value.tag, 7);
+        assert_eq!(value.body, b"borrowed");
+        // decoded right in the ring buffer, not copied
+        assert_eq!(value.body.as_ptr(), unsafe { data.add(9) } as *const u8);
+        // the kernel may not overwrite the record while it is borrowed
+        assert_eq!(consumer_pos(), 0);
+    }
+
+    // nothing more to read, the record is released anyway
+    terminating.store(true, Ordering::SeqCst);
+    assert!(rb.read_ref::<Tagged>(&terminating).is_err());
+    assert_eq!(consumer_pos(), next);
+}
@@ -114,6 +114,46 @@ pub struct State {
     counter: u32,
 }
 
+impl State {
+    /// The latest height goes to the cache, so `latest` serves it after restart,
+    /// node ids are collected from every height, so they stay the same.
+    fn restore(db: &DbInner) -> Self {
+        let mut state = State {
+            height: 0,
+            last: BTreeMap::new(),
+            ids: BTreeMap::new(),
+            counter: 0,
+        };
+        for (i, (height, blocks)) in db.blocks_rev().enumerate() {
+            let latest = i == 0;
+            for GlobalBlockState { hash, events } in blocks {
+                for event in events {
+                    state.ids.entry(event.node_addr).or_insert(event.node_id);
+                    state.counter = state.counter.max(event.node_id + 1);
+                    if latest {
+                        let key = Key {
+                            debugger_hostname: event.debugger_name.clone(),
+                            node_addr: event.node_addr,
+                        };
+                        state.last.entry(hash).or_default().insert(key, event);
+                    }
+                }
+            }
+            if latest {
+                state.height = height;
+            }
+        }
+        if state.height != 0 {
+            log::info!(
+                "restored height {}, {} nodes",
+                state.height,
+                state.ids.len()
+            );
+        }
+        state
+    }
+}
+
 #[derive(Clone)]
 pub struct Database {
     cache: Arc<Mutex<State>>,
@@ -125,14 +165,11 @@ impl Database {
     where
         P: AsRef<Path>,
     {
+        let db = DbInner::open(path)?;
+        let state = State::restore(&db);
         Ok(Database {
-            cache: Arc::new(Mutex::new(State {
-                height: 0,
-                last: BTreeMap::new(),
-                ids: BTreeMap::new(),
-                counter: 0,
-            })),
-            db: Arc::new(DbInner::open(path)?),
+            cache: Arc::new(Mutex::new(state)),
+            db: Arc::new(db),
         })
     }
 
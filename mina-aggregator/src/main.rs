@@ -26,7 +26,9 @@ fn main() {
         }
     };
 
-    let database = Database::open("/tmp/mina-aggregator-db").expect("open db");
+    let db_path = env::var("DB_PATH").unwrap_or_else(|_| "/tmp/mina-aggregator-db".to_owned());
+    let database = Database::open(&db_path).expect("open db");
+    log::info!("using db {db_path}");
 
     let _guard = rt.enter();
     let (tx, rx) = oneshot::channel();
@@ -65,4 +65,23 @@ impl DbInner {
         let Collection(v) = AbsorbExt::absorb_ext(&b).map_err(|e| e.map(ParseError::into_vec))?;
         Ok(Some(v))
     }
+
+    /// Every stored block from the highest, to restore the state after restart.
+    pub fn blocks_rev(&self) -> impl Iterator<Item = (u32, Vec<GlobalBlockState>)> + '_ {
+        self.0
+            .iterator_cf(self.block(), rocksdb::IteratorMode::End)
+            .filter_map(|item| {
+                let (key, value) = item.ok()?;
+                let height = u32::from_be_bytes(key.as_ref().try_into().ok()?);
+                let v = AbsorbExt::absorb_ext(value.as_ref())
+                    .map_err(|e| DbError::from(e.map(ParseError::into_vec)));
+                match v {
+                    Ok(Collection(v)) => Some((height, v)),
+                    Err(err) => {
+                        log::error!("cannot decode block {height}: {err}");
+                        None
+                    }
+                }
+            })
+    }
 }
@@ -15,6 +15,8 @@ fn main() {
             "src/decode/structs.proto",
             "src/decode/envelope.proto",
             "src/decode/identify.proto",
+            "src/decode/circuit.proto",
+            "src/decode/bitswap.proto",
         ],
         &["src/decode"],
     )
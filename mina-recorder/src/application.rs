@@ -1,10 +1,19 @@
 use std::{
     env,
-    sync::{mpsc, Mutex, Arc},
+    io::ErrorKind,
+    num::ParseIntError,
+    str::FromStr,
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Mutex, Arc,
+    },
+    time::{Duration, Instant},
     collections::BTreeMap,
-    net::{IpAddr, Ipv6Addr, SocketAddr},
+    net::{AddrParseError, IpAddr, Ipv6Addr, SocketAddr},
 };
 
+use thiserror::Error;
+
 use ebpf_user::{
     kind::{AppItem, AppItemKind},
     HashMapRef,
@@ -24,16 +33,152 @@ pub struct StatsItem {
     pub dst: SocketAddr,
 }
 
+impl StatsItem {
+    /// The key of the `blocked` map: source ip and port, destination ip and port,
+    /// ipv4 is mapped to ipv6, ports are big endian.
+    fn decode(key: &[u8; 36]) -> Self {
+        let addr = |b: &[u8]| {
+            let ip = Ipv6Addr::from(<[u8; 16]>::try_from(&b[..16]).expect("cannot fail"));
+            let ip = ip.to_ipv4_mapped().map(IpAddr::from).unwrap_or(ip.into());
+            let port = u16::from_be_bytes([b[16], b[17]]);
+            SocketAddr::new(ip, port)
+        };
+        StatsItem {
+            src: addr(&key[..18]),
+            dst: addr(&key[18..]),
+        }
+    }
+}
+
+/// Entry of the XDP `blocked` map: the 4-tuple and how much traffic was dropped.
+#[derive(Serialize)]
+pub struct FirewallStatsEntry {
+    #[serde(flatten)]
+    pub item: StatsItem,
+    #[serde(flatten)]
+    pub blocked: StatsBlocked,
+}
+
+/// Entry of the `pid_bytes` map, the traffic on the connections of the process
+/// since it is tracked, saturating.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct PidBytes {
+    pub pid: u32,
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+}
+
 #[derive(Deserialize)]
 pub struct EnableWhitelist {
     pub ips: Vec<IpAddr>,
     pub ports: Vec<u16>,
+    #[serde(default)]
+    pub cidrs: Vec<Cidr>,
+    /// The whitelist is disabled after this many seconds, a new enable resets the timer.
+    pub ttl_secs: Option<u64>,
+}
+
+impl EnableWhitelist {
+    /// The moment the whitelist enabled at `now` expires, if it has a ttl.
+    pub fn expires(&self, now: Instant) -> Result<Option<Instant>, TtlTooBig> {
+        self.ttl_secs
+            .map(|s| now.checked_add(Duration::from_secs(s)).ok_or(TtlTooBig(s)))
+            .transpose()
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("ttl {0} s is too big")]
+pub struct TtlTooBig(u64);
+
+#[derive(Default)]
+struct WhitelistState {
+    enabled: bool,
+    expires: Option<Instant>,
+}
+
+impl WhitelistState {
+    // how long to wait for a command before the whitelist expires, zero if it is already expired
+    fn timeout(&self, now: Instant) -> Option<Duration> {
+        self.expires.map(|t| t.saturating_duration_since(now))
+    }
+}
+
+#[derive(Serialize)]
+pub struct WhitelistStatus {
+    pub enabled: bool,
+    pub ttl_secs: Option<u64>,
+}
+
+/// The size of the kernel `whitelist` map, ips and expanded cidrs together must fit.
+const WHITELIST_CAPACITY: u128 = 0x4000;
+
+#[derive(Debug, Error)]
+pub enum CidrError {
+    #[error("no prefix length in {0}")]
+    NoPrefix(String),
+    #[error("{0}")]
+    Addr(#[from] AddrParseError),
+    #[error("{0}")]
+    PrefixLen(#[from] ParseIntError),
+    #[error("prefix length {0} is too big")]
+    PrefixTooBig(u8),
+}
+
+/// A range of ips, `10.0.0.0/24` or `fd00::/120`, the ipv4 is mapped to ipv6
+/// like in the kernel map.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Cidr {
+    addr: Ipv6Addr,
+    prefix: u8,
+}
+
+impl FromStr for Cidr {
+    type Err = CidrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = s
+            .split_once('/')
+            .ok_or_else(|| CidrError::NoPrefix(s.to_owned()))?;
+        let prefix = prefix.parse::<u8>()?;
+        let (addr, prefix) = match addr.parse::<IpAddr>()? {
+            IpAddr::V4(addr) if prefix <= 32 => (addr.to_ipv6_mapped(), prefix + 96),
+            IpAddr::V6(addr) if prefix <= 128 => (addr, prefix),
+            _ => return Err(CidrError::PrefixTooBig(prefix)),
+        };
+        Ok(Cidr { addr, prefix })
+    }
+}
+
+impl TryFrom<String> for Cidr {
+    type Error = CidrError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl Cidr {
+    /// Number of ips in the range, saturating.
+    pub fn size(&self) -> u128 {
+        1u128
+            .checked_shl(128 - self.prefix as u32)
+            .unwrap_or(u128::MAX)
+    }
+
+    pub fn addresses(&self) -> impl Iterator<Item = Ipv6Addr> {
+        let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+        let base = u128::from(self.addr) & mask;
+        (0..self.size()).map(move |i| Ipv6Addr::from(base + i))
+    }
 }
 
 enum ApplicationCommand {
-    EnableWhitelist(EnableWhitelist),
+    EnableWhitelist(EnableWhitelist, Option<Instant>),
     DisableWhitelist,
-    GetFirewallStats,
+    GetFirewallStats { reset: bool },
+    GetPidBytes,
     Terminate,
 }
 
@@ -41,6 +186,8 @@ enum ApplicationCommand {
 pub struct Application {
     ctx: mpsc::SyncSender<ApplicationCommand>,
     drx: Arc<Mutex<mpsc::Receiver<BTreeMap<StatsItem, StatsBlocked>>>>,
+    prx: Arc<Mutex<mpsc::Receiver<Vec<PidBytes>>>>,
+    state: Arc<Mutex<WhitelistState>>,
 }
 
 /// It is !Send, so will block thread where created
@@ -48,15 +195,20 @@ pub struct ApplicationServer {
     whitelist: HashMapRef<16, 4>,
     whitelist_ports: HashMapRef<2, 4>,
     blocked: HashMapRef<36, 8>,
+    pid_bytes: HashMapRef<4, 16>,
     crx: mpsc::Receiver<ApplicationCommand>,
     dtx: mpsc::Sender<BTreeMap<StatsItem, StatsBlocked>>,
+    ptx: mpsc::Sender<Vec<PidBytes>>,
+    state: Arc<Mutex<WhitelistState>>,
 }
 
 impl Application {
-    pub fn enable_firewall(&self, list: EnableWhitelist) {
+    pub fn enable_firewall(&self, list: EnableWhitelist) -> Result<(), TtlTooBig> {
+        let expires = list.expires(Instant::now())?;
         self.ctx
-            .send(ApplicationCommand::EnableWhitelist(list))
+            .send(ApplicationCommand::EnableWhitelist(list, expires))
             .unwrap_or_default();
+        Ok(())
     }
 
     pub fn disable_firewall(&self) {
@@ -65,15 +217,42 @@ impl Application {
             .unwrap_or_default();
     }
 
-    pub fn get_firewall_stats(&self) -> BTreeMap<StatsItem, StatsBlocked> {
+    /// If `reset` is set, the counters start from zero after the read.
+    pub fn get_firewall_stats(&self, reset: bool) -> Vec<FirewallStatsEntry> {
         let drx = self
             .drx
             .lock()
             .expect("must not panic while hold this lock");
         self.ctx
-            .send(ApplicationCommand::GetFirewallStats)
+            .send(ApplicationCommand::GetFirewallStats { reset })
             .unwrap_or_default();
-        drx.recv().unwrap_or_default()
+        drx.recv()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(item, blocked)| FirewallStatsEntry { item, blocked })
+            .collect()
+    }
+
+    pub fn whitelist_status(&self) -> WhitelistStatus {
+        let state = self
+            .state
+            .lock()
+            .expect("must not panic while hold this lock");
+        WhitelistStatus {
+            enabled: state.enabled,
+            ttl_secs: state.timeout(Instant::now()).map(|t| t.as_secs()),
+        }
+    }
+
+    pub fn get_pid_bytes(&self) -> Vec<PidBytes> {
+        let prx = self
+            .prx
+            .lock()
+            .expect("must not panic while hold this lock");
+        self.ctx
+            .send(ApplicationCommand::GetPidBytes)
+            .unwrap_or_default();
+        prx.recv().unwrap_or_default()
     }
 
     pub fn terminate(&self) {
@@ -114,8 +293,9 @@ impl ApplicationServer {
         }
     }
 
-    fn list(&self) -> BTreeMap<StatsItem, StatsBlocked> {
+    fn list(&self, reset: bool) -> BTreeMap<StatsItem, StatsBlocked> {
         let mut list = BTreeMap::new();
+        let mut keys = vec![];
 
         let fd = match self.blocked.kind() {
             AppItemKind::Map(map) => map.fd(),
@@ -126,40 +306,98 @@ impl ApplicationServer {
         while unsafe { libbpf_sys::bpf_map_get_next_key(fd, it, next_key.as_mut_ptr() as _) } == 0 {
             let value = self.blocked.get(&next_key).unwrap();
 
-            let src_ip = Ipv6Addr::from(<[u8; 16]>::try_from(&next_key[0..16]).unwrap());
-            let src_ip = src_ip
-                .to_ipv4_mapped()
-                .map(IpAddr::from)
-                .unwrap_or(src_ip.into());
-            let src_port = u16::from_be_bytes(next_key[16..18].try_into().unwrap());
-
-            let dst_ip = Ipv6Addr::from(<[u8; 16]>::try_from(&next_key[18..34]).unwrap());
-            let dst_ip = dst_ip
-                .to_ipv4_mapped()
-                .map(IpAddr::from)
-                .unwrap_or(src_ip.into());
-            let dst_port = u16::from_be_bytes(next_key[34..36].try_into().unwrap());
-
-            let key = StatsItem {
-                src: SocketAddr::new(src_ip, src_port),
-                dst: SocketAddr::new(dst_ip, dst_port),
-            };
+            let key = StatsItem::decode(&next_key);
             let value = StatsBlocked {
                 packets: u32::from_ne_bytes(value[..4].try_into().unwrap()),
                 bytes: u32::from_ne_bytes(value[4..].try_into().unwrap()),
             };
 
             list.insert(key, value);
+            keys.push(next_key);
+            it = &next_key as *const _ as _;
+        }
+
+        // removing while iterating would restart the iteration,
+        // packets blocked in between are lost, it is fine for statistics
+        if reset {
+            for key in &keys {
+                self.blocked.remove(key).unwrap_or_default();
+            }
+        }
+
+        list
+    }
+
+    fn list_pid_bytes(&self) -> Vec<PidBytes> {
+        let mut list = vec![];
+
+        let fd = match self.pid_bytes.kind() {
+            AppItemKind::Map(map) => map.fd(),
+            _ => unreachable!(),
+        };
+        let mut it = std::ptr::null();
+        let mut next_key = [0; 4];
+        while unsafe { libbpf_sys::bpf_map_get_next_key(fd, it, next_key.as_mut_ptr() as _) } == 0 {
+            if let Ok(value) = self.pid_bytes.get(&next_key) {
+                list.push(PidBytes {
+                    pid: u32::from_ne_bytes(next_key),
+                    read_bytes: u64::from_ne_bytes(value[..8].try_into().unwrap()),
+                    write_bytes: u64::from_ne_bytes(value[8..].try_into().unwrap()),
+                });
+            }
             it = &next_key as *const _ as _;
         }
 
         list
     }
 
+    fn disable_whitelist(&self) {
+        self.clear_whitelist();
+
+        // insert mark that whitelist is disabled
+        self.whitelist.insert([0; 16], [0, 0, 0, 1]).unwrap();
+
+        *self
+            .state
+            .lock()
+            .expect("must not panic while hold this lock") = WhitelistState::default();
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        self.state
+            .lock()
+            .expect("must not panic while hold this lock")
+            .timeout(Instant::now())
+    }
+
     pub fn run(mut self) {
-        while let Ok(command) = self.crx.recv() {
+        loop {
+            // wait for a command, or until the whitelist expires
+            let command = match self.timeout() {
+                None => match self.crx.recv() {
+                    Ok(v) => v,
+                    Err(_) => break,
+                },
+                Some(t) => match self.crx.recv_timeout(t) {
+                    Ok(v) => v,
+                    Err(RecvTimeoutError::Timeout) => {
+                        self.disable_whitelist();
+                        log::info!("firewall: whitelist expired");
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => break,
+                },
+            };
             match command {
-                ApplicationCommand::EnableWhitelist(EnableWhitelist { mut ips, ports }) => {
+                ApplicationCommand::EnableWhitelist(
+                    EnableWhitelist {
+                        mut ips,
+                        ports,
+                        cidrs,
+                        ttl_secs,
+                    },
+                    expires,
+                ) => {
                     self.clear_whitelist();
 
                     // remove mark that whitelist is disabled
@@ -177,23 +415,45 @@ impl ApplicationServer {
                         };
                         self.whitelist.insert(ipv6.octets(), [0, 0, 0, 1]).unwrap();
                     }
+                    // the kernel map is exact match, so each cidr takes as many entries as it has ips
+                    let mut free = WHITELIST_CAPACITY.saturating_sub(ips.len() as u128);
+                    for cidr in &cidrs {
+                        if cidr.size() > free {
+                            log::error!("firewall: {cidr:?} does not fit, {free} entries left");
+                            continue;
+                        }
+                        free -= cidr.size();
+                        for ip in cidr.addresses() {
+                            self.whitelist.insert(ip.octets(), [0, 0, 0, 1]).unwrap();
+                        }
+                    }
                     for &port in &ports {
                         self.whitelist_ports
                             .insert(port.to_be_bytes(), [0, 0, 0, 1])
                             .unwrap();
                     }
-                    log::info!("firewall: whitelist {ips:?}, ports: {ports:?}");
+                    log::info!("firewall: whitelist {ips:?}, cidrs: {cidrs:?}, ports: {ports:?}");
+                    if let Some(ttl_secs) = ttl_secs {
+                        log::info!("firewall: whitelist expires in {ttl_secs} s");
+                    }
+                    *self
+                        .state
+                        .lock()
+                        .expect("must not panic while hold this lock") = WhitelistState {
+                        enabled: true,
+                        expires,
+                    };
                 }
                 ApplicationCommand::DisableWhitelist => {
-                    self.clear_whitelist();
-
-                    // insert mark that whitelist is disabled
-                    self.whitelist.insert([0; 16], [0, 0, 0, 1]).unwrap();
+                    self.disable_whitelist();
 
                     log::info!("firewall: whitelist disable");
                 }
-                ApplicationCommand::GetFirewallStats => {
-                    self.dtx.send(self.list()).unwrap_or_default();
+                ApplicationCommand::GetFirewallStats { reset } => {
+                    self.dtx.send(self.list(reset)).unwrap_or_default();
+                }
+                ApplicationCommand::GetPidBytes => {
+                    self.ptx.send(self.list_pid_bytes()).unwrap_or_default();
                 }
                 ApplicationCommand::Terminate => break,
             }
@@ -205,19 +465,203 @@ pub fn new(
     whitelist: HashMapRef<16, 4>,
     whitelist_ports: HashMapRef<2, 4>,
     blocked: HashMapRef<36, 8>,
+    pid_bytes: HashMapRef<4, 16>,
 ) -> (Application, ApplicationServer) {
     let (ctx, crx) = mpsc::sync_channel(256);
     let (dtx, drx) = mpsc::channel();
     let drx = Arc::new(Mutex::new(drx));
+    let (ptx, prx) = mpsc::channel();
+    let prx = Arc::new(Mutex::new(prx));
+    let state = Arc::new(Mutex::new(WhitelistState::default()));
 
     (
-        Application { ctx, drx },
+        Application {
+            ctx,
+            drx,
+            prx,
+            state: state.clone(),
+        },
         ApplicationServer {
             whitelist,
             whitelist_ports,
             blocked,
+            pid_bytes,
             crx,
             dtx,
+            ptx,
+            state,
         },
     )
 }
+
+/// Which packets the firewall filters, `FIREWALL_DIRECTION` is `ingress` (default),
+/// `egress` or `both`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FirewallDirection {
+    Ingress,
+    Egress,
+    Both,
+}
+
+impl FirewallDirection {
+    pub fn from_env() -> Self {
+        match env::var("FIREWALL_DIRECTION").as_deref() {
+            Err(_) | Ok("ingress") => FirewallDirection::Ingress,
+            Ok("egress") => FirewallDirection::Egress,
+            Ok("both") => FirewallDirection::Both,
+            Ok(s) => {
+                log::warn!("unknown `FIREWALL_DIRECTION={s}`, using ingress");
+                FirewallDirection::Ingress
+            }
+        }
+    }
+
+    pub fn ingress(&self) -> bool {
+        matches!(self, FirewallDirection::Ingress | FirewallDirection::Both)
+    }
+
+    pub fn egress(&self) -> bool {
+        matches!(self, FirewallDirection::Egress | FirewallDirection::Both)
+    }
+}
+
+/// The interface the XDP program is attached to, detached on drop,
+/// so every interface of `FIREWALL_INTERFACE` is cleaned up on shutdown.
+pub struct XdpAttachment {
+    name: String,
+    if_index: i32,
+    flags: u32,
+}
+
+impl XdpAttachment {
+    pub fn new(name: String, if_index: i32, flags: u32) -> Self {
+        XdpAttachment {
+            name,
+            if_index,
+            flags,
+        }
+    }
+}
+
+impl Drop for XdpAttachment {
+    fn drop(&mut self) {
+        let code =
+            unsafe { libbpf_sys::bpf_xdp_detach(self.if_index, self.flags, std::ptr::null()) };
+        if code != 0 {
+            // might be detached already together with the skeleton
+            log::debug!("cannot detach xdp from {}: {code}", self.name);
+        } else {
+            log::info!("detached xdp from {}", self.name);
+        }
+    }
+}
+
+/// The egress program attached to the clsact qdisc of the interface.
+/// Unlike XDP, the tc filter outlives the process, so the qdisc is removed on drop.
+pub struct TcEgress {
+    hook: libbpf_sys::bpf_tc_hook,
+}
+
+impl TcEgress {
+    pub fn attach(if_index: i32, prog_fd: i32) -> Result<Self, i32> {
+        let mut hook = libbpf_sys::bpf_tc_hook {
+            sz: std::mem::size_of::<libbpf_sys::bpf_tc_hook>() as _,
+            ifindex: if_index,
+            attach_point: libbpf_sys::BPF_TC_EGRESS,
+            ..Default::default()
+        };
+        // the qdisc may exist, if the previous run crashed
+        let code = unsafe { libbpf_sys::bpf_tc_hook_create(&mut hook) };
+        if code != 0 && std::io::Error::from_raw_os_error(-code).kind() != ErrorKind::AlreadyExists
+        {
+            return Err(code);
+        }
+        let mut opts = libbpf_sys::bpf_tc_opts {
+            sz: std::mem::size_of::<libbpf_sys::bpf_tc_opts>() as _,
+            prog_fd,
+            handle: 1,
+            priority: 1,
+            ..Default::default()
+        };
+        let code = unsafe { libbpf_sys::bpf_tc_attach(&hook, &mut opts) };
+        if code != 0 {
+            return Err(code);
+        }
+        Ok(TcEgress { hook })
+    }
+}
+
+impl Drop for TcEgress {
+    fn drop(&mut self) {
+        // destroying the qdisc detaches the filter as well
+        self.hook.attach_point = libbpf_sys::BPF_TC_INGRESS | libbpf_sys::BPF_TC_EGRESS;
+        let code = unsafe { libbpf_sys::bpf_tc_hook_destroy(&mut self.hook) };
+        if code != 0 {
+            log::warn!("cannot remove clsact qdisc: {code}");
+        }
+    }
+}
+
+#[cfg(test)]
+use std::net::Ipv4Addr;
+
+#[cfg(test)]
+#[test]
+fn decode_blocked_key() {
+    let mut key = [0; 36];
+    key[..16].clone_from_slice(&Ipv4Addr::new(10, 0, 0, 1).to_ipv6_mapped().octets());
+    key[16..18].clone_from_slice(&8302u16.to_be_bytes());
+    key[18..34].clone_from_slice(&"fd00::2".parse::<Ipv6Addr>().unwrap().octets());
+    key[34..].clone_from_slice(&443u16.to_be_bytes());
+
+    let item = StatsItem::decode(&key);
+    assert_eq!(item.src, "10.0.0.1:8302".parse().unwrap());
+    assert_eq!(item.dst, "[fd00::2]:443".parse().unwrap());
+}
+
+#[cfg(test)]
+#[test]
+fn cidr_expand() {
+    let cidr = "10.0.1.7/30".parse::<Cidr>().unwrap();
+    assert_eq!(cidr.size(), 4);
+    let ips = cidr.addresses().collect::<Vec<_>>();
+    assert_eq!(
+        ips[0],
+        "10.0.1.4".parse::<Ipv4Addr>().unwrap().to_ipv6_mapped()
+    );
+    assert_eq!(
+        ips[3],
+        "10.0.1.7".parse::<Ipv4Addr>().unwrap().to_ipv6_mapped()
+    );
+
+    let cidr = "fd00::/120".parse::<Cidr>().unwrap();
+    assert_eq!(cidr.size(), 0x100);
+    assert_eq!("::/0".parse::<Cidr>().unwrap().size(), u128::MAX);
+    assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+    assert!("10.0.0.0".parse::<Cidr>().is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn whitelist_expires() {
+    let list = |ttl_secs| EnableWhitelist {
+        ips: vec![],
+        ports: vec![],
+        cidrs: vec![],
+        ttl_secs,
+    };
+    let now = Instant::now();
+    let state = WhitelistState {
+        enabled: true,
+        expires: list(Some(10)).expires(now).unwrap(),
+    };
+    assert_eq!(state.timeout(now), Some(Duration::from_secs(10)));
+    // the server stops waiting for commands and disables the whitelist
+    assert_eq!(
+        state.timeout(now + Duration::from_secs(11)),
+        Some(Duration::ZERO)
+    );
+
+    assert_eq!(list(None).expires(now).unwrap(), None);
+    assert!(list(Some(u64::MAX)).expires(now).is_err());
+}
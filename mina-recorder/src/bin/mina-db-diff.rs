@@ -0,0 +1,38 @@
+use std::{env, path::Path};
+
+use mina_recorder::database::{DbCore, CaptureDiff};
+
+// the databases must not be in use by a running debugger, rocksdb holds a lock on it,
+// nothing is written, but it cannot be opened read only, values are stored with ttl
+fn main() {
+    let mut args = env::args().skip(1);
+    let (Some(a), Some(b)) = (args.next(), args.next()) else {
+        eprintln!("usage: mina-db-diff <DB_PATH of capture a> <DB_PATH of capture b>");
+        std::process::exit(1);
+    };
+
+    let summary = |path: &str| {
+        // otherwise the empty database is created
+        if !Path::new(path).join("rocksdb").exists() {
+            eprintln!("no database at {path}");
+            std::process::exit(1);
+        }
+        match DbCore::open(path) {
+            Ok(db) => db.fetch_capture_summary(),
+            Err(err) => {
+                eprintln!("cannot open {path}: {err}");
+                std::process::exit(1);
+            }
+        }
+    };
+    let a = summary(&a);
+    let b = summary(&b);
+
+    let report = serde_json::json!({
+        "a": a,
+        "b": b,
+        "diff": CaptureDiff::new(&a, &b),
+    });
+    let report = serde_json::to_string_pretty(&report).expect("must not fail");
+    println!("{report}");
+}
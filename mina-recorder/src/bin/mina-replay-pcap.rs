@@ -0,0 +1,87 @@
+use std::{env, fs, path::Path, process};
+
+use mina_recorder::{
+    P2pRecorder,
+    database::DbFacade,
+    pcap::{self, Replay},
+};
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: mina-replay-pcap <pcap file> <node ip> <DB_PATH> \
+         [--chain <id>] [--randomness <file>] [--fast]"
+    );
+    process::exit(1);
+}
+
+// feeds the capture through the decoders into a fresh database, which the debugger can serve,
+// the randomness file holds hex encoded 32 bytes per line, without it noise is not decrypted
+fn main() {
+    let mut positional = vec![];
+    let mut chain_id = "mainnet".to_owned();
+    let mut randomness_file = None;
+    let mut realtime = true;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--chain" => chain_id = args.next().unwrap_or_else(|| usage()),
+            "--randomness" => randomness_file = Some(args.next().unwrap_or_else(|| usage())),
+            "--fast" => realtime = false,
+            _ => positional.push(arg),
+        }
+    }
+    let [file, node, db_path] = <[String; 3]>::try_from(positional).unwrap_or_else(|_| usage());
+
+    let node = node.parse().unwrap_or_else(|err| {
+        eprintln!("bad node ip {node}: {err}");
+        process::exit(1);
+    });
+    let randomness = randomness_file
+        .map(|path| {
+            let s = fs::read_to_string(&path).unwrap_or_else(|err| {
+                eprintln!("cannot read {path}: {err}");
+                process::exit(1);
+            });
+            s.lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| {
+                    let mut v = [0; 32];
+                    hex::decode_to_slice(line.trim(), &mut v).unwrap_or_else(|err| {
+                        eprintln!("bad randomness {line}: {err}");
+                        process::exit(1);
+                    });
+                    v
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    let bytes = fs::read(&file).unwrap_or_else(|err| {
+        eprintln!("cannot read {file}: {err}");
+        process::exit(1);
+    });
+    let segments = pcap::parse(&bytes).unwrap_or_else(|err| {
+        eprintln!("cannot parse {file}: {err}");
+        process::exit(1);
+    });
+
+    // otherwise the replayed messages are mixed with the old ones
+    if Path::new(&db_path).join("rocksdb").exists() {
+        eprintln!("database at {db_path} already exists, the replay needs a fresh one");
+        process::exit(1);
+    }
+    let db = DbFacade::open(&db_path).unwrap_or_else(|err| {
+        eprintln!("cannot open {db_path}: {err}");
+        process::exit(1);
+    });
+    let mut recorder = P2pRecorder::new(db, false);
+
+    let replay = Replay {
+        node,
+        chain_id: &chain_id,
+        randomness: &randomness,
+        realtime,
+    };
+    let connections = replay.run(&mut recorder, segments);
+    let messages = recorder.cx.db.next_message_id();
+    println!("replayed {connections} connections, {messages} messages into {db_path}");
+}
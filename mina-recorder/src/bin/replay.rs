@@ -2,6 +2,7 @@ use std::{env, fs, io::Read, time::Duration};
 
 use mina_recorder::{
     P2pRecorder, database::DbFacade, EventMetadata, ChunkHeader, ConnectionInfo, EncryptionStatus,
+    TimeSource,
 };
 use radiation::AbsorbExt;
 
@@ -15,7 +16,7 @@ fn main() {
     let mut recorder = P2pRecorder::new(db, false);
     let metadata = EventMetadata::default();
     recorder.on_alias(metadata.id.pid, "mainnet-node".to_owned());
-    recorder.on_connect::<true>(true, metadata, 0, String::new());
+    recorder.on_connect::<true>(true, metadata, 0, 0, String::new());
 
     let mut bytes = Vec::new();
     fs::File::open(filename)
@@ -34,6 +35,8 @@ fn main() {
                 time: header.time,
                 better_time: header.time,
                 duration: Duration::from_secs(0),
+                time_source: TimeSource::Replay,
+                lag: Duration::ZERO,
             };
             recorder.on_data(
                 header.incoming,
@@ -0,0 +1,183 @@
+use crate::decode::prometheus;
+
+use super::{HandleData, DirectedId, Cx, Db, DbResult};
+
+/// Plain HTTP on a tracked fd, most likely somebody scrapes the node's metrics.
+/// Keeps the responses carrying Prometheus exposition, the rest is ignored.
+#[derive(Default)]
+pub struct State {
+    // per direction, outgoing then incoming
+    buf: [Vec<u8>; 2],
+    skip: [bool; 2],
+}
+
+enum Body {
+    Length(usize),
+    Chunked,
+}
+
+enum Parsed {
+    Incomplete,
+    // the lengths overflow, the rest of the direction is skipped
+    Malformed,
+    Skip(usize),
+    Metrics(usize, String),
+}
+
+impl State {
+    // the biggest response we are willing to accumulate
+    const LIMIT: usize = 0x1000000;
+
+    pub fn detect(bytes: &[u8]) -> bool {
+        const METHODS: [&[u8]; 3] = [b"GET ", b"POST ", b"HEAD "];
+        bytes.starts_with(b"HTTP/1.") || METHODS.iter().any(|m| bytes.starts_with(m))
+    }
+
+    fn parse(buf: &[u8]) -> Parsed {
+        let header_end = match buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            Some(v) => v + 4,
+            None => return Parsed::Incomplete,
+        };
+        let head = String::from_utf8_lossy(&buf[..header_end]);
+        let mut lines = head.split("\r\n");
+        let response = lines.next().unwrap_or_default().starts_with("HTTP/1.");
+
+        let mut body = Body::Length(0);
+        let mut metrics = false;
+        let mut identity = true;
+        for line in lines {
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "content-length" => body = Body::Length(value.parse().unwrap_or_default()),
+                "transfer-encoding" if value.eq_ignore_ascii_case("chunked") => {
+                    body = Body::Chunked
+                }
+                "content-type" => metrics = prometheus::is_exposition(value),
+                // TODO: gzip
+                "content-encoding" => identity = value.eq_ignore_ascii_case("identity"),
+                _ => (),
+            }
+        }
+
+        let (content, end) = match body {
+            Body::Length(len) => {
+                let Some(end) = header_end.checked_add(len) else {
+                    return Parsed::Malformed;
+                };
+                if buf.len() < end {
+                    return Parsed::Incomplete;
+                }
+                (buf[header_end..end].to_vec(), end)
+            }
+            Body::Chunked => match Self::dechunk(&buf[header_end..]) {
+                Ok((content, len)) => (content, header_end + len),
+                Err(parsed) => return parsed,
+            },
+        };
+        if response && metrics && identity {
+            Parsed::Metrics(end, String::from_utf8_lossy(&content).into_owned())
+        } else {
+            Parsed::Skip(end)
+        }
+    }
+
+    // returns the content and the length of the encoded body,
+    // or `Incomplete` if it needs more bytes, or `Malformed`
+    fn dechunk(buf: &[u8]) -> Result<(Vec<u8>, usize), Parsed> {
+        let mut content = vec![];
+        let mut pos = 0;
+        loop {
+            let line_end = buf[pos..]
+                .windows(2)
+                .position(|w| w == b"\r\n")
+                .ok_or(Parsed::Incomplete)?
+                + pos;
+            let size = std::str::from_utf8(&buf[pos..line_end]).map_err(|_| Parsed::Malformed)?;
+            let size = size.split(';').next().unwrap_or_default().trim();
+            let size = usize::from_str_radix(size, 16).map_err(|_| Parsed::Malformed)?;
+            let start = line_end + 2;
+            // the chunk is followed by CRLF
+            let end = start
+                .checked_add(size)
+                .and_then(|v| v.checked_add(2))
+                .ok_or(Parsed::Malformed)?;
+            if buf.len() < end {
+                return Err(Parsed::Incomplete);
+            }
+            if size == 0 {
+                // no trailers
+                return Ok((content, start + 2));
+            }
+            content.extend_from_slice(&buf[start..(start + size)]);
+            pos = end;
+        }
+    }
+}
+
+impl HandleData for State {
+    fn on_data(&mut self, id: DirectedId, bytes: &mut [u8], _: &Cx, db: &Db) -> DbResult<()> {
+        let i = id.incoming as usize;
+        if self.skip[i] {
+            return Ok(());
+        }
+        let buf = &mut self.buf[i];
+        buf.extend_from_slice(bytes);
+        loop {
+            match Self::parse(buf) {
+                Parsed::Incomplete => break,
+                Parsed::Malformed => {
+                    log::warn!("{id} {} malformed http message, skip", db.id());
+                    self.skip[i] = true;
+                    *buf = vec![];
+                    return Ok(());
+                }
+                Parsed::Skip(end) => drop(buf.drain(..end)),
+                Parsed::Metrics(end, text) => {
+                    buf.drain(..end);
+                    let samples = prometheus::parse(&text);
+                    log::debug!("{id} {} scraped {} metrics", db.id(), samples.len());
+                    db.add_node_metrics(id.metadata.time, samples)?;
+                }
+            }
+        }
+        if buf.len() > Self::LIMIT {
+            log::warn!("{id} {} http message is too big, skip", db.id());
+            self.skip[i] = true;
+            *buf = vec![];
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn parse_chunked_response() {
+    let buf = b"HTTP/1.1 200 OK\r\n\
+Content-Type: text/plain; version=0.0.4\r\n\
+Transfer-Encoding: chunked\r\n\r\n\
+c\r\nMina_peers 1\r\n\
+d\r\n\nMina_slot 42\r\n\
+0\r\n\r\nHTTP/1.1";
+    match State::parse(buf) {
+        Parsed::Metrics(end, text) => {
+            assert_eq!(&buf[end..], b"HTTP/1.1");
+            assert_eq!(text, "Mina_peers 1\nMina_slot 42");
+        }
+        _ => panic!("must be metrics"),
+    }
+    assert!(matches!(State::parse(&buf[..40]), Parsed::Incomplete));
+}
+
+#[cfg(test)]
+#[test]
+fn parse_overflowing_lengths() {
+    let buf = b"HTTP/1.1 200 OK\r\nContent-Length: 18446744073709551615\r\n\r\n";
+    assert!(matches!(State::parse(buf), Parsed::Malformed));
+
+    let buf = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nffffffffffffffff\r\n";
+    assert!(matches!(State::parse(buf), Parsed::Malformed));
+}
@@ -2,10 +2,13 @@ use super::accumulator;
 
 mod meshsub;
 mod rpc;
+mod ping;
 
 use crate::{
-    database::{StreamId, StreamKind, ConnectionStats, DbStream},
-    stats::update_block_stats,
+    database::{
+        StreamId, StreamKind, ConnectionStats, DbStream, MessageId, Sampling, BlockProvenance,
+    },
+    stats::block_stats,
 };
 
 use super::{HandleData, DirectedId, DynamicProtocol, Cx, Db, DbResult};
@@ -15,6 +18,9 @@ pub struct State {
     kind: StreamKind,
     rpc_state: Option<rpc::State>,
     meshsub_state: Option<meshsub::State>,
+    ping_state: Option<ping::State>,
+    sampling: Option<Sampling>,
+    decode_disabled: bool,
 }
 
 impl DynamicProtocol for State {
@@ -37,6 +43,15 @@ impl DynamicProtocol for State {
                     None
                 }
             },
+            ping_state: {
+                if let StreamKind::Ping = kind {
+                    Some(ping::State::default())
+                } else {
+                    None
+                }
+            },
+            sampling: kind.sampling(),
+            decode_disabled: kind.decode_disabled(),
         }
     }
 }
@@ -45,7 +60,10 @@ impl HandleData for State {
     #[inline(never)]
     fn on_data(&mut self, id: DirectedId, bytes: &mut [u8], cx: &Cx, db: &Db) -> DbResult<()> {
         let stream = db.get(self.stream_id);
-        if self.kind == StreamKind::Rpc {
+        // `DECODE_DISABLE`, the bytes are stored as is, not framed and no block stats
+        if self.decode_disabled {
+            add(&mut self.sampling, &stream, &id, self.kind, bytes)?;
+        } else if self.kind == StreamKind::Rpc {
             let st = self.rpc_state.as_mut().expect("must exist");
             match st.extend(bytes) {
                 Err(err) => log::error!("{id} {}: {err}", db.id()),
@@ -54,14 +72,15 @@ impl HandleData for State {
                         Err(err) => log::error!("{id} {}: {err}", db.id()),
                         Ok(None) => break,
                         Ok(Some(msg)) => {
-                            if let Err(err) = stream.add(&id, self.kind, &msg) {
+                            if let Err(err) = add(&mut self.sampling, &stream, &id, self.kind, &msg)
+                            {
                                 log::error!("{id} {}: {err}", db.id());
                             }
                         }
                     }
                 },
                 Ok(Some(msg)) => {
-                    if let Err(err) = stream.add(&id, self.kind, &msg) {
+                    if let Err(err) = add(&mut self.sampling, &stream, &id, self.kind, &msg) {
                         log::error!("{id} {}: {err}, {}", db.id(), hex::encode(bytes));
                     }
                 }
@@ -69,14 +88,22 @@ impl HandleData for State {
         } else if self.kind == StreamKind::Meshsub {
             let st = self.meshsub_state.as_mut().expect("must exist");
             if !st.extend(bytes) {
-                meshsub_sink(&id, db, &stream, bytes, cx);
+                meshsub_sink(&id, db, &stream, &mut self.sampling, bytes, cx);
             } else {
                 while let Some(slice) = st.next_msg() {
-                    meshsub_sink(&id, db, &stream, slice, cx);
+                    meshsub_sink(&id, db, &stream, &mut self.sampling, slice, cx);
+                }
+            }
+        } else if self.kind == StreamKind::Ping {
+            let st = self.ping_state.as_mut().expect("must exist");
+            for ping::Ping { payload, rtt } in st.extend(id.incoming, id.metadata.time, bytes) {
+                let message_id = add(&mut self.sampling, &stream, &id, self.kind, &payload)?;
+                if let Some(rtt) = rtt {
+                    db.add_ping_rtt(message_id, id.metadata.time, rtt)?;
                 }
             }
         } else {
-            stream.add(&id, self.kind, bytes)?;
+            add(&mut self.sampling, &stream, &id, self.kind, bytes)?;
         }
 
         db.update(
@@ -87,11 +114,46 @@ impl HandleData for State {
                 messages: 1,
             },
             id.incoming,
+            id.metadata.time,
         )
     }
 }
 
-fn meshsub_sink(id: &DirectedId, db: &Db, stream: &DbStream, msg: &[u8], cx: &Cx) {
+// the message in full, or only the header if it is sampled out
+fn add(
+    sampling: &mut Option<Sampling>,
+    stream: &DbStream,
+    id: &DirectedId,
+    kind: StreamKind,
+    bytes: &[u8],
+) -> DbResult<MessageId> {
+    add_with_blocks(sampling, stream, id, kind, bytes, vec![])
+}
+
+fn add_with_blocks(
+    sampling: &mut Option<Sampling>,
+    stream: &DbStream,
+    id: &DirectedId,
+    kind: StreamKind,
+    bytes: &[u8],
+    blocks: Vec<BlockProvenance>,
+) -> DbResult<MessageId> {
+    match sampling {
+        Some(sampling) if !sampling.full(bytes.len()) => {
+            stream.add_header(id, kind, bytes.len(), blocks)
+        }
+        _ => stream.add_with_blocks(id, kind, bytes, blocks),
+    }
+}
+
+fn meshsub_sink(
+    id: &DirectedId,
+    db: &Db,
+    stream: &DbStream,
+    sampling: &mut Option<Sampling>,
+    msg: &[u8],
+    cx: &Cx,
+) {
     let node_address = {
         let lock = cx.apps.lock();
         lock.get(&id.metadata.id.pid)
@@ -99,19 +161,27 @@ fn meshsub_sink(id: &DirectedId, db: &Db, stream: &DbStream, msg: &[u8], cx: &Cx
             .unwrap_or("0.0.0.0:8302".parse().expect("valid constant socket addr"))
     };
     let mut lock = cx.stats_state.lock();
-    match stream.add(id, StreamKind::Meshsub, msg) {
+    // the block stats see every message, even sampled out
+    let mut block_stats = block_stats(
+        msg,
+        id.incoming,
+        id.metadata.time,
+        id.metadata.better_time,
+        id.metadata.id.addr,
+        node_address,
+        &cx.db,
+    )
+    .map_err(|err| log::error!("{id} {}: {err}, {}", db.id(), hex::encode(msg)))
+    .ok();
+    // the decode is already done, store the blocks with the message so the fetch doesn't repeat it
+    let blocks = block_stats
+        .as_mut()
+        .map(|stats| std::mem::take(&mut stats.blocks))
+        .unwrap_or_default();
+    match add_with_blocks(sampling, stream, id, StreamKind::Meshsub, msg, blocks) {
         Ok(message_id) => {
-            if let Err(err) = update_block_stats(
-                message_id.0,
-                msg,
-                id.incoming,
-                id.metadata.time,
-                id.metadata.better_time,
-                id.metadata.id.addr,
-                node_address,
-                &cx.db,
-            ) {
-                log::error!("{id} {}: {err}, {}", db.id(), hex::encode(msg));
+            if let Some(Err(err)) = block_stats.map(|stats| stats.store(message_id.0, &cx.db)) {
+                log::error!("{id} {}: {err}", db.id());
             }
             let st = lock.entry(node_address).or_default();
             let (b, t, events) = st.observe(
@@ -128,8 +198,13 @@ fn meshsub_sink(id: &DirectedId, db: &Db, stream: &DbStream, msg: &[u8], cx: &Cx
             drop(lock);
             // perform io, after lock is dropped and mutex unlock
             if let Some(aggregator) = &cx.aggregator {
-                for event in events {
-                    aggregator.post_event(&event);
+                for event in &events {
+                    aggregator.post_event(event);
+                }
+            }
+            if let Some(kafka) = &cx.kafka {
+                for event in &events {
+                    kafka.send(event);
                 }
             }
             if b {
@@ -0,0 +1,73 @@
+use std::{
+    collections::BTreeMap,
+    time::{SystemTime, Duration},
+};
+
+/// The size of the ping payload, the responder echoes it back unchanged.
+pub const PAYLOAD_SIZE: usize = 32;
+
+#[derive(Default)]
+pub struct State {
+    incoming: Vec<u8>,
+    outgoing: Vec<u8>,
+    // payload -> direction and time of the request, waiting for the echo
+    pending: BTreeMap<[u8; PAYLOAD_SIZE], (bool, SystemTime)>,
+}
+
+pub struct Ping {
+    pub payload: [u8; PAYLOAD_SIZE],
+    // present if this payload is an echo of the previously seen request
+    pub rtt: Option<Duration>,
+}
+
+impl State {
+    pub fn extend(&mut self, incoming: bool, time: SystemTime, bytes: &[u8]) -> Vec<Ping> {
+        let acc = if incoming {
+            &mut self.incoming
+        } else {
+            &mut self.outgoing
+        };
+        acc.extend_from_slice(bytes);
+
+        let mut pings = vec![];
+        let mut chunks = acc.chunks_exact(PAYLOAD_SIZE);
+        for chunk in &mut chunks {
+            let payload = <[u8; PAYLOAD_SIZE]>::try_from(chunk).expect("cannot fail");
+            let rtt = match self.pending.get(&payload) {
+                Some((request_incoming, request_time)) if *request_incoming != incoming => {
+                    let rtt = time.duration_since(*request_time).unwrap_or_default();
+                    self.pending.remove(&payload);
+                    Some(rtt)
+                }
+                _ => {
+                    self.pending.insert(payload, (incoming, time));
+                    None
+                }
+            };
+            pings.push(Ping { payload, rtt });
+        }
+        *acc = chunks.remainder().to_vec();
+
+        pings
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn echo_rtt() {
+    let mut st = State::default();
+    let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1);
+    let t1 = t0 + Duration::from_millis(15);
+
+    let payload = [7; PAYLOAD_SIZE];
+    // request is split in two chunks
+    assert!(st.extend(false, t0, &payload[..10]).is_empty());
+    let pings = st.extend(false, t0, &payload[10..]);
+    assert_eq!(pings.len(), 1);
+    assert!(pings[0].rtt.is_none());
+
+    let pings = st.extend(true, t1, &payload);
+    assert_eq!(pings.len(), 1);
+    assert_eq!(pings[0].rtt, Some(Duration::from_millis(15)));
+    assert!(st.pending.is_empty());
+}
@@ -52,12 +52,18 @@ impl State {
         }
     }
 
+    /// Returns `Ok(None)` only when no complete frame left in the accumulator,
+    /// heartbeats and other skipped frames of a batch must not stop the caller.
     pub fn next_msg(&mut self) -> Result<Option<Vec<u8>>, Error> {
-        let mut msg = match self.acc.next_msg(Self::decode_size) {
-            Some(v) => v.to_vec(),
-            None => return Ok(None),
-        };
-        self.post_process(&mut msg).map(|x| x.map(|c| c.to_vec()))
+        loop {
+            let mut msg = match self.acc.next_msg(Self::decode_size) {
+                Some(v) => v.to_vec(),
+                None => return Ok(None),
+            };
+            if let Some(msg) = self.post_process(&mut msg)? {
+                return Ok(Some(msg.into_owned()));
+            }
+        }
     }
 
     fn post_process<'a>(&mut self, bytes: &'a mut [u8]) -> Result<Option<Cow<'a, [u8]>>, Error> {
@@ -98,3 +104,41 @@ impl State {
         }
     }
 }
+
+#[cfg(test)]
+#[test]
+fn batched_frames() {
+    use crate::decode::{rpc, MessageType};
+
+    fn frame(body: &[u8]) -> Vec<u8> {
+        let mut b = (body.len() as u64).to_le_bytes().to_vec();
+        b.extend_from_slice(body);
+        b
+    }
+
+    fn query(tag: &str, id: i64) -> Vec<u8> {
+        let mut b = vec![1];
+        let header = QueryHeader {
+            tag: BString::from(tag),
+            version: 1,
+            id,
+        };
+        header.binprot_write(&mut b).unwrap();
+        // payload, the decoder does not look at it here
+        b.extend_from_slice(&[0, 0]);
+        frame(&b)
+    }
+
+    let mut batch = query("get_some_initial_peers", 1);
+    batch.extend(frame(&[0]));
+    batch.extend(query("get_best_tip", 2));
+
+    let mut st = State::default();
+    assert!(st.extend(&mut batch).unwrap().is_none());
+    let mut types = vec![];
+    while let Some(msg) = st.next_msg().unwrap() {
+        types.extend(rpc::parse_types(&msg).unwrap());
+    }
+    assert!(types == [MessageType::GetSomeInitialPeers, MessageType::GetBestTip]);
+    assert_eq!(st.pending.len(), 2);
+}
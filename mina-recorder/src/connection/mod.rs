@@ -16,6 +16,7 @@ pub trait HandleData {
 mod accumulator;
 
 pub mod pnet;
+pub mod http;
 pub mod multistream_select;
 pub mod noise;
 pub mod mux;
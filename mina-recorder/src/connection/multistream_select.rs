@@ -1,4 +1,4 @@
-use crate::database::StreamKind;
+use crate::database::{StreamKind, Desync};
 
 use super::{HandleData, DirectedId, DynamicProtocol, Cx, Db, DbResult, StreamId};
 
@@ -7,6 +7,8 @@ pub struct State<Inner> {
     error: bool,
     inner: Option<Inner>,
     hl: hl::State,
+    // bytes received, outgoing and incoming
+    received: [u64; 2],
 }
 
 // high level state machine
@@ -170,6 +172,7 @@ impl<Inner> From<StreamId> for State<Inner> {
             error: false,
             inner: None,
             hl: hl::State::default(),
+            received: [0; 2],
         }
     }
 }
@@ -190,6 +193,9 @@ where
             return Ok(());
         }
 
+        let offset = self.received[id.incoming as usize];
+        let len = bytes.len();
+        self.received[id.incoming as usize] += len as u64;
         let output = self.hl.poll(id.incoming, bytes);
 
         if !output.tokens.is_empty() {
@@ -204,9 +210,20 @@ where
                 "{id}, {}, stream_id: {}, unparsed {}, {error}",
                 db.id(),
                 self.stream_id,
-                hex::encode(msg)
+                hex::encode(&msg)
             );
             self.error = true;
+            // the message is the tail of the chunk, that is not parsed
+            let chunk_offset = (offset + len as u64).saturating_sub(msg.len() as u64);
+            db.set_desync(Desync::new(
+                "multistream_select",
+                id.incoming,
+                id.metadata.time,
+                chunk_offset,
+                &msg,
+                0,
+                error.to_string(),
+            ))?;
         }
 
         if let Some((protocol, mut data)) = output.agreed {
@@ -476,3 +493,25 @@ fn simultaneous_connect_misordered_noise_test() {
     let result = state.hl.poll(true, &mut data);
     assert!(dbg!(result).agreed.is_some());
 }
+
+#[cfg(test)]
+#[test]
+#[rustfmt::skip]
+fn negotiation_with_rejection() {
+    use crate::decode::multistream_select::Negotiation;
+
+    let mut state = State::<()>::from(StreamId::Handshake);
+
+    // the initiator offers simultaneous connect and noise at once, the responder rejects the first
+    let mut data = hex::decode("132f6d756c746973747265616d2f312e302e300a1d2f6c69627032702f73696d756c74616e656f75732d636f6e6e6563740a072f6e6f6973650a").expect("valid constant");
+    let outgoing = state.hl.poll(false, &mut data).tokens;
+    let mut data = hex::decode("132f6d756c746973747265616d2f312e302e300a036e610a072f6e6f6973650a").expect("valid constant");
+    let incoming = state.hl.poll(true, &mut data).tokens;
+    let stream = outgoing.into_iter().map(|s| (false, s))
+        .chain(incoming.into_iter().map(|s| (true, s)));
+
+    let v = Negotiation::new("na".to_string(), stream);
+    assert_eq!(v.proposed, ["/libp2p/simultaneous-connect", "/noise"]);
+    assert_eq!(v.rejected, ["/libp2p/simultaneous-connect"]);
+    assert_eq!(v.accepted.as_deref(), Some("/noise"));
+}
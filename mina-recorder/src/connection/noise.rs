@@ -14,7 +14,8 @@ use vru_noise::{
 use thiserror::Error;
 
 use crate::{
-    database::{StreamId, StreamKind, RandomnessDatabase, ConnectionStats},
+    database::{StreamId, StreamKind, RandomnessDatabase, ConnectionStats, Desync},
+    decode::noise::UNRECOGNIZED,
     key_recover::KeyDatabase,
 };
 
@@ -84,6 +85,8 @@ where
 }
 
 pub struct NoiseState<Inner> {
+    // the noise protocol name, the handshake hash starts from it
+    protocol: &'static str,
     machine: Option<St>,
     initiator_is_incoming: bool,
     error: bool,
@@ -91,6 +94,8 @@ pub struct NoiseState<Inner> {
     decrypted: usize,
     failed_to_decrypt: usize,
     mux_proposed: Option<(DirectedId, Vec<u8>)>,
+    // bytes of frames received, outgoing and incoming
+    received: [u64; 2],
 }
 
 impl<Inner> DynamicProtocol for NoiseState<Inner>
@@ -98,14 +103,17 @@ where
     Inner: From<StreamId>,
 {
     fn from_name(name: &str, stream_id: StreamId) -> Self {
+        let protocol = super::super::decode::noise::protocol(name);
         NoiseState {
+            protocol: protocol.unwrap_or(UNRECOGNIZED),
             machine: None,
             initiator_is_incoming: false,
-            error: name != "/noise",
+            error: protocol.is_none(),
             inner: Inner::from(stream_id),
             decrypted: 0,
             failed_to_decrypt: 0,
             mux_proposed: None,
+            received: [0; 2],
         }
     }
 }
@@ -144,6 +152,8 @@ where
             Some(St::SecondMessage { .. }) => Msg::Third,
             Some(_) => Msg::Other,
         };
+        let offset = self.received[id.incoming as usize];
+        self.received[id.incoming as usize] += bytes.len() as u64;
         if !self.error {
             match self.on_data_(id.incoming, bytes, &cx.keygen) {
                 Ok(range) => {
@@ -158,10 +168,14 @@ where
                             messages: 0,
                         },
                         id.incoming,
+                        id.metadata.time,
                     )?;
                     match msg {
                         Msg::First => (),
                         Msg::Second => {
+                            // the responder's MAC covers the handshake hash, which starts
+                            // from the protocol name, so the peers use this very protocol
+                            db.set_noise_protocol(self.protocol)?;
                             db.get(StreamId::Handshake)
                                 .add(&id, StreamKind::Handshake, bytes)?;
                             let payload = super::super::decode::noise::payload(bytes)?;
@@ -189,6 +203,23 @@ where
                 }
                 Err(err) => {
                     self.error = true;
+                    // not a missing key, the messages do not fit the protocol
+                    if let NoiseError::FirstMessageTooShort
+                    | NoiseError::FirstMessageTooBig
+                    | NoiseError::SecondMessageTooShort
+                    | NoiseError::SecondMessageMacMismatch = &err
+                    {
+                        db.set_noise_protocol(UNRECOGNIZED)?;
+                    }
+                    db.set_desync(Desync::new(
+                        "noise",
+                        id.incoming,
+                        id.metadata.time,
+                        offset,
+                        bytes,
+                        0,
+                        err.to_string(),
+                    ))?;
                     self.on_error(id, bytes, cx, db, err)?;
                 }
             }
@@ -263,6 +294,7 @@ impl<Inner> NoiseState<Inner> {
                 messages: 0,
             },
             id.incoming,
+            id.metadata.time,
         )?;
 
         log::error!(
@@ -353,7 +385,7 @@ impl<Inner> NoiseState<Inner> {
 
                 let i_epk =
                     MontgomeryPoint(bytes[2..34].try_into().expect("cannot fail, checked above"));
-                let st = SymmetricState::new("Noise_XX_25519_ChaChaPoly_SHA256")
+                let st = SymmetricState::new(self.protocol)
                     .mix_hash(&[])
                     .mix_hash(i_epk.as_bytes())
                     .mix_hash(&[]);
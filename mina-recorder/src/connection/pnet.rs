@@ -6,13 +6,15 @@ use salsa20::{
 
 use crate::chunk::EncryptionStatus;
 
-use super::{HandleData, DirectedId, Cx, Db, DbResult, StreamId};
+use super::{HandleData, DirectedId, Cx, Db, DbResult, StreamId, http};
 
 pub struct State<Inner> {
     shared_secret: GenericArray<u8, typenum::U32>,
     cipher_in: Option<XSalsa20>,
     cipher_out: Option<XSalsa20>,
     skip: bool,
+    // not a libp2p connection, but plain http
+    http: Option<http::State>,
     inner: Inner,
 }
 
@@ -26,6 +28,7 @@ where
             cipher_in: None,
             cipher_out: None,
             skip: false,
+            http: None,
             inner: Inner::from(StreamId::Handshake),
         }
     }
@@ -63,7 +66,14 @@ where
             &mut self.cipher_out
         };
         db.add_raw(EncryptionStatus::Raw, id.incoming, id.metadata.time, bytes)?;
-        if let Some(cipher) = cipher {
+        if let Some(http) = &mut self.http {
+            http.on_data(id, bytes, cx, db)?;
+        } else if cipher.is_none() && http::State::detect(bytes) {
+            log::info!("{id} {} http connection", db.id());
+            let mut http = http::State::default();
+            http.on_data(id, bytes, cx, db)?;
+            self.http = Some(http);
+        } else if let Some(cipher) = cipher {
             cipher.apply_keystream(bytes);
             db.add_raw(
                 EncryptionStatus::DecryptedPnet,
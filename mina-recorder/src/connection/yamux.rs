@@ -1,9 +1,16 @@
 use std::{
     collections::{BTreeMap, VecDeque},
     task::Poll,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
-use crate::database::StreamKind;
+use radiation::{Absorb, Emit};
+use serde::Serialize;
+
+use crate::{
+    database::{StreamKind, Desync},
+    custom_coding,
+};
 
 use super::{HandleData, DirectedId, DynamicProtocol, Cx, Db, DbResult, StreamId};
 
@@ -13,6 +20,112 @@ pub struct State<Inner> {
     error: bool,
     inners: BTreeMap<StreamId, Status<Inner>>,
     recent_reset: VecDeque<StreamId>,
+    // offset of the next frame in the stream, outgoing and incoming
+    frame_offset: [u64; 2],
+    windows: BTreeMap<u32, WindowStats>,
+}
+
+/// Flow control of the stream, from the point of view of the local node.
+#[derive(Clone, Absorb, Emit, Serialize)]
+pub struct WindowStats {
+    pub stream_id: u32,
+    #[custom_absorb(custom_coding::time_absorb)]
+    #[custom_emit(custom_coding::time_emit)]
+    pub timestamp: SystemTime,
+    // how many bytes the node may send
+    pub send_window: u32,
+    // how many bytes the peer may send
+    pub recv_window: u32,
+    // received, but not yet given back to the peer by a window update
+    pub pending_bytes: u64,
+    // how many times any of the windows hit zero
+    pub stalls: u32,
+    // unix time, milliseconds, since when the window is zero, or 0 if it is not
+    pub send_zero_since: u64,
+    pub recv_zero_since: u64,
+}
+
+impl WindowStats {
+    /// The window every stream starts with.
+    pub const INITIAL: u32 = 256 * 1024;
+
+    pub fn new(stream_id: u32, timestamp: SystemTime) -> Self {
+        WindowStats {
+            stream_id,
+            timestamp,
+            send_window: Self::INITIAL,
+            recv_window: Self::INITIAL,
+            pending_bytes: 0,
+            stalls: 0,
+            send_zero_since: 0,
+            recv_zero_since: 0,
+        }
+    }
+
+    fn add(window: u32, delta: i32) -> u32 {
+        (window as i64 + delta as i64).clamp(0, u32::MAX as i64) as u32
+    }
+
+    // returns true if the window hit zero or left zero
+    fn check_zero(window: u32, since: &mut u64, stalls: &mut u32, now: u64) -> bool {
+        match (window == 0, *since == 0) {
+            (true, true) => {
+                *since = now.max(1);
+                *stalls += 1;
+                true
+            }
+            (false, false) => {
+                *since = 0;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Accounts the frame, returns true if the snapshot is worth to store,
+    /// that is the window was updated, or hit zero, or left zero.
+    pub fn on_frame(&mut self, header: &Header, incoming: bool, timestamp: SystemTime) -> bool {
+        self.timestamp = timestamp;
+        let updated = match (&header.ty, incoming) {
+            (HeaderType::Data { length }, false) => {
+                self.send_window = self.send_window.saturating_sub(*length);
+                false
+            }
+            (HeaderType::Data { length }, true) => {
+                self.recv_window = self.recv_window.saturating_sub(*length);
+                self.pending_bytes += *length as u64;
+                false
+            }
+            (HeaderType::WindowUpdate { delta }, true) => {
+                self.send_window = Self::add(self.send_window, *delta);
+                true
+            }
+            (HeaderType::WindowUpdate { delta }, false) => {
+                self.recv_window = Self::add(self.recv_window, *delta);
+                // the node may give back only a part of what it received
+                self.pending_bytes = self.pending_bytes.saturating_sub((*delta).max(0) as u64);
+                true
+            }
+            _ => false,
+        };
+        let now = timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let send = Self::check_zero(
+            self.send_window,
+            &mut self.send_zero_since,
+            &mut self.stalls,
+            now,
+        );
+        let recv = Self::check_zero(
+            self.recv_window,
+            &mut self.recv_zero_since,
+            &mut self.stalls,
+            now,
+        );
+        updated || send || recv
+    }
 }
 
 pub enum Status<Inner> {
@@ -40,6 +153,8 @@ impl<Inner> DynamicProtocol for State<Inner> {
             error: false,
             inners: BTreeMap::new(),
             recent_reset: VecDeque::with_capacity(512),
+            frame_offset: [0; 2],
+            windows: BTreeMap::new(),
         }
     }
 }
@@ -239,7 +354,16 @@ mod acc {
     #[derive(Debug, Error)]
     pub enum Error {
         #[error("header parse error: {0}")]
-        HeaderParse(HeaderParseError),
+        HeaderParse(HeaderParseError, Vec<u8>),
+    }
+
+    impl Error {
+        /// The bytes starting from the bad header.
+        pub fn bytes(&self) -> &[u8] {
+            match self {
+                Error::HeaderParse(_, bytes) => bytes,
+            }
+        }
     }
 
     impl<const INCOMING: bool> State<INCOMING> {
@@ -251,7 +375,9 @@ mod acc {
                     let header_bytes = <[u8; 12]>::try_from(&bytes[..offset]).expect("cannot fail");
                     let header = match Header::try_from(header_bytes) {
                         Ok(v) => v,
-                        Err(err) => return Poll::Ready(Err(Error::HeaderParse(err))),
+                        Err(err) => {
+                            return Poll::Ready(Err(Error::HeaderParse(err, bytes.to_vec())))
+                        }
                     };
                     let end = offset + header.payload_length();
                     match bytes.len().cmp(&end) {
@@ -283,7 +409,9 @@ mod acc {
                         <[u8; 12]>::try_from(&self.acc[..offset]).expect("cannot fail");
                     let header = match Header::try_from(header_bytes) {
                         Ok(v) => v,
-                        Err(err) => return Poll::Ready(Err(Error::HeaderParse(err))),
+                        Err(err) => {
+                            return Poll::Ready(Err(Error::HeaderParse(err, self.acc.clone())))
+                        }
                     };
                     let end = offset + header.payload_length();
                     match self.acc.len().cmp(&end) {
@@ -335,6 +463,7 @@ where
             if let Poll::Ready(result) = acc {
                 if let Ok(out) = &result {
                     let header = &out.header;
+                    self.frame_offset[incoming as usize] += (12 + header.payload_length()) as u64;
                     let stream_id = if header.stream_id == 0 {
                         StreamId::Handshake
                     } else if header.stream_id % 2 == 0 {
@@ -396,8 +525,16 @@ where
             match result {
                 Err(err) => {
                     self.error = true;
-                    // TODO: report
                     log::error!("{id} {} {err}", db.id());
+                    db.set_desync(Desync::new(
+                        "yamux",
+                        id.incoming,
+                        id.metadata.time,
+                        self.frame_offset[id.incoming as usize],
+                        err.bytes(),
+                        0,
+                        err.to_string(),
+                    ))?;
                     return Ok(());
                 }
                 Ok(acc::Output { header, mut bytes }) => {
@@ -409,6 +546,22 @@ where
                         StreamId::Backward((header.stream_id / 2) as u64)
                     };
                     let db_stream = db.get(stream_id);
+                    if header.stream_id != 0 {
+                        let time = id.metadata.time;
+                        let window = self
+                            .windows
+                            .entry(header.stream_id)
+                            .or_insert_with(|| WindowStats::new(header.stream_id, time));
+                        if window.on_frame(&header, id.incoming, time) {
+                            db_stream.set_window_stats(window)?;
+                        }
+                        // reset, or both sides sent FIN, so the stream is already forgotten
+                        let fin = header.flags.contains(HeaderFlags::FIN)
+                            && !self.inners.contains_key(&stream_id);
+                        if fin || header.flags.contains(HeaderFlags::RST) {
+                            self.windows.remove(&header.stream_id);
+                        }
+                    }
                     if let HeaderType::Data { .. } = &header.ty {
                         if let Some(s) = self.inners.get_mut(&stream_id) {
                             s.as_mut().on_data(id.clone(), bytes.to_mut(), cx, db)?;
@@ -433,9 +586,11 @@ where
 
 #[cfg(test)]
 mod tests {
+    use std::time::{Duration, SystemTime};
+
     use crate::database::StreamId;
 
-    use super::{State, DynamicProtocol};
+    use super::{State, DynamicProtocol, WindowStats, Header, HeaderType, HeaderFlags};
 
     #[test]
     fn trivial_acc() {
@@ -452,4 +607,45 @@ mod tests {
         assert_eq!(output.header.payload_length(), 16);
         assert_eq!(output.bytes.as_ref(), [0xff; 16]);
     }
+
+    #[test]
+    fn window_stall() {
+        let header = |ty| Header {
+            version: 0,
+            ty,
+            flags: HeaderFlags::empty(),
+            stream_id: 3,
+        };
+        let t = SystemTime::UNIX_EPOCH + Duration::from_secs(10);
+        let mut w = WindowStats::new(3, t);
+
+        let data = header(HeaderType::Data {
+            length: WindowStats::INITIAL / 2,
+        });
+        assert!(!w.on_frame(&data, false, t));
+        // the peer doesn't give the window back
+        assert!(w.on_frame(&data, false, t));
+        assert_eq!(w.send_window, 0);
+        assert_eq!(w.send_zero_since, 10_000);
+        assert_eq!(w.stalls, 1);
+
+        // still zero, nothing new
+        let t = t + Duration::from_secs(1);
+        assert!(!w.on_frame(&header(HeaderType::Data { length: 0 }), false, t));
+        assert_eq!(w.send_zero_since, 10_000);
+
+        let update = header(HeaderType::WindowUpdate { delta: 1024 });
+        assert!(w.on_frame(&update, true, t));
+        assert_eq!(w.send_window, 1024);
+        assert_eq!(w.send_zero_since, 0);
+
+        // the receive side
+        assert!(!w.on_frame(&header(HeaderType::Data { length: 100 }), true, t));
+        assert_eq!(w.pending_bytes, 100);
+        assert!(w.on_frame(&header(HeaderType::WindowUpdate { delta: 60 }), false, t));
+        assert_eq!(w.pending_bytes, 40);
+        assert!(w.on_frame(&header(HeaderType::WindowUpdate { delta: 40 }), false, t));
+        assert_eq!(w.pending_bytes, 0);
+        assert_eq!(w.recv_window, WindowStats::INITIAL);
+    }
 }
@@ -85,6 +85,18 @@ where
     );
 }
 
+// the field is not stored, absorbed as default
+pub fn skip_absorb<T: Default>(input: &[u8]) -> nom::IResult<&[u8], T, ParseError<&[u8]>> {
+    Ok((input, T::default()))
+}
+
+pub fn skip_emit<T, W>(value: &T, buffer: &mut W)
+where
+    W: for<'a> Extend<&'a u8>,
+{
+    let _ = (value, buffer);
+}
+
 pub fn peer_id_absorb(input: &[u8]) -> nom::IResult<&[u8], PeerId, ParseError<&[u8]>> {
     nom::combinator::map_res(Vec::<u8>::absorb::<()>, |v| PeerId::from_bytes(&v))(input)
 }
@@ -1,42 +1,58 @@
 use std::{
     path::{PathBuf, Path},
     time::{Duration, SystemTime},
-    cmp::Ordering,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicU64, Ordering::SeqCst},
+        Arc, Weak, Mutex, MutexGuard,
+    },
     collections::{BTreeMap, HashSet, BTreeSet},
-    io,
+    io, env, thread, fs,
     convert::TryInto,
-    net::SocketAddr,
+    net::{SocketAddr, IpAddr, Ipv4Addr, Ipv6Addr},
 };
 
+use libp2p_identity::PeerId;
 use mina_p2p_messages::gossip::GossipNetMessageV2;
 use radiation::{AbsorbExt, nom, ParseError, Emit};
 
 use serde::Serialize;
 use thiserror::Error;
+use tokio::sync::broadcast;
 
 use super::{
     types::{
         Connection, ConnectionId, StreamFullId, Message, StreamKind, FullMessage, MessageId,
         Timestamp, StatsDbKey, StatsV2DbKey, CapnpEventWithMetadata, CapnpEventWithMetadataKey,
-        CapnpTableRow, CapnpEventDecoded,
+        CapnpTableRow, CapnpEventDecoded, PingRtt, PingRttKey, StableConnectionId,
+        ProblematicStream, Bandwidth, BandwidthKey, BandwidthBucket, SubnetStats, ConnectionCount,
+        DecryptStats, GossipCorrelation, GossipNetMessageV2Short, NodeStatusRecord,
+        BlockProvenance, GossipHop, BitswapSession, StreamOrderViolation, StreamOrderReport,
+        GatingConfigRecord, ProducerSummary, PruneStats, ReindexReport, Desync, NodeMetricSample,
+        WindowStatsKey, CheckpointReport, BlockPropagation, BlockArrivals, PeerArrival,
+        TxLatencySummary,
     },
     params::{ValidParams, Coordinate, StreamFilter, Direction, KindFilter, ValidParamsConnection},
     index::{
         ConnectionIdx, StreamIdx, StreamByKindIdx, MessageKindIdx, AddressIdx, LedgerHash,
-        LedgerHashIdx,
+        LedgerHashIdx, StableIdIdx, DecodeErrorIdx, GossipHashIdx, PeerIdIdx,
     },
     sorted_intersect::sorted_intersect,
+    diff::{CaptureSummary, LatencySummary},
+    rocksdb::DbStream,
 };
 
 use crate::{
     decode::{
-        DecodeError, MessageType,
+        DecodeError, MessageType, prometheus,
         meshsub_stats::{self, BlockStat, TxStat, Hash},
+        bitswap::ReceivedBlock,
     },
     strace::StraceLine,
-    meshsub::{SnarkByHash, Event, SnarkWithHash},
-    ChunkHeader,
+    feed::MessageFeed,
+    meshsub::{SnarkByHash, Event, SnarkWithHash, TopicRegistry},
+    stats::reputation::{self, PeerFactors, PeerReputation},
+    yamux::{Header, HeaderFlags, WindowStats},
+    ChunkHeader, EncryptionStatus,
 };
 
 #[derive(Debug, Error)]
@@ -69,6 +85,8 @@ pub enum DbError {
     ParamDeserialize(#[from] serde_json::Error),
     #[error("wrong sk size")]
     WrongSkSize,
+    #[error("the message is sampled out, only its header is stored")]
+    SampledOut,
 }
 
 impl From<DecodeError> for DbError {
@@ -92,11 +110,42 @@ impl<'pa> From<nom::Err<ParseError<&'pa [u8]>>> for DbError {
 #[derive(Clone)]
 pub struct DbCore {
     cache: Arc<Mutex<BTreeMap<ConnectionId, u64>>>,
+    write_opts: Arc<rocksdb::WriteOptions>,
+    counters: Arc<Counters>,
+    feed: MessageFeed,
     inner: Arc<rocksdb::DB>,
 }
 
+/// The iterator reads through the snapshot, so it owns the snapshot,
+/// the iterator is declared first to be dropped first.
+struct SnapshotIter<'a, I> {
+    inner: I,
+    _snapshot: rocksdb::Snapshot<'a>,
+}
+
+impl<I> Iterator for SnapshotIter<'_, I>
+where
+    I: Iterator,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+}
+
+/// Maintained on connect and disconnect, and as the message indexes are written
+/// and deleted, so the count doesn't require a scan.
+#[derive(Default)]
+struct Counters {
+    total: AtomicU64,
+    active: AtomicU64,
+    incoming: AtomicU64,
+    messages_by_kind: Mutex<BTreeMap<StreamKind, u64>>,
+}
+
 impl DbCore {
-    const CFS: [&'static str; 16] = [
+    const CFS: [&'static str; 31] = [
         Self::CONNECTIONS,
         Self::MESSAGES,
         Self::RANDOMNESS,
@@ -113,6 +162,21 @@ impl DbCore {
         Self::MESSAGE_KIND_INDEX,
         Self::ADDR_INDEX,
         Self::LEDGER_HASH_INDEX,
+        Self::PING_RTT,
+        Self::STABLE_ID_INDEX,
+        Self::DECODE_ERRORS,
+        Self::CONNECTION_BANDWIDTH,
+        Self::MESHSUB_TOPICS,
+        Self::NODE_STATUS,
+        Self::GOSSIP_HASH_INDEX,
+        Self::BITSWAP_SESSIONS,
+        Self::GATING_CONFIG,
+        Self::PRUNES,
+        Self::DESYNC,
+        Self::NODE_METRICS,
+        Self::PEER_ID_INDEX,
+        Self::YAMUX_WINDOWS,
+        Self::BITSWAP_BLOCKS,
     ];
 
     const TTL: Duration = Duration::from_secs(0);
@@ -133,6 +197,19 @@ impl DbCore {
 
     pub const STRACE_CNT: u8 = 3;
 
+    pub const INCOMING_CNT: u8 = 4;
+
+    pub const PRUNED_CONNECTIONS_CNT: u8 = 5;
+
+    pub const PRUNED_MESSAGES_CNT: u8 = 6;
+
+    // the pruner continues from these ids
+    const PRUNE_CURSOR_CONNECTIONS: u8 = 7;
+
+    const PRUNE_CURSOR_MESSAGES: u8 = 8;
+
+    const PRUNE_REORDER: Duration = Duration::from_secs(60);
+
     const STATS: &'static str = "stats";
 
     const STATS_TX: &'static str = "stats_tx";
@@ -145,6 +222,30 @@ impl DbCore {
 
     const KEYS: &'static str = "keys";
 
+    const PING_RTT: &'static str = "ping_rtt";
+
+    const DECODE_ERRORS: &'static str = "decode_errors";
+
+    const CONNECTION_BANDWIDTH: &'static str = "connection_bandwidth";
+
+    const MESHSUB_TOPICS: &'static str = "meshsub_topics";
+
+    const NODE_STATUS: &'static str = "node_status";
+
+    const BITSWAP_SESSIONS: &'static str = "bitswap_sessions";
+
+    const GATING_CONFIG: &'static str = "gating_config";
+
+    const PRUNES: &'static str = "prunes";
+
+    const DESYNC: &'static str = "desync";
+
+    const NODE_METRICS: &'static str = "node_metrics";
+
+    const YAMUX_WINDOWS: &'static str = "yamux_windows";
+
+    const BITSWAP_BLOCKS: &'static str = "bitswap_blocks";
+
     // indexes
 
     const CONNECTION_ID_INDEX: &'static str = "connection_id_index";
@@ -159,6 +260,12 @@ impl DbCore {
 
     const LEDGER_HASH_INDEX: &'static str = "ledger_hash_index";
 
+    const STABLE_ID_INDEX: &'static str = "stable_id_index";
+
+    const GOSSIP_HASH_INDEX: &'static str = "gossip_hash_index";
+
+    const PEER_ID_INDEX: &'static str = "peer_id_index";
+
     pub fn open<P>(path: P) -> Result<Self, DbError>
     where
         P: AsRef<Path>,
@@ -196,14 +303,151 @@ impl DbCore {
             rocksdb::ColumnFamilyDescriptor::new(Self::CFS[13], opts_with_prefix_extractor(2)),
             rocksdb::ColumnFamilyDescriptor::new(Self::CFS[14], opts_with_prefix_extractor(18)),
             rocksdb::ColumnFamilyDescriptor::new(Self::CFS[15], opts_with_prefix_extractor(32)),
+            // PING_RTT
+            rocksdb::ColumnFamilyDescriptor::new(Self::CFS[16], opts_with_prefix_extractor(8)),
+            // STABLE_ID_INDEX
+            rocksdb::ColumnFamilyDescriptor::new(Self::CFS[17], opts_with_prefix_extractor(8)),
+            // DECODE_ERRORS
+            rocksdb::ColumnFamilyDescriptor::new(Self::CFS[18], opts_with_prefix_extractor(16)),
+            // CONNECTION_BANDWIDTH
+            rocksdb::ColumnFamilyDescriptor::new(Self::CFS[19], opts_with_prefix_extractor(8)),
+            // MESHSUB_TOPICS
+            rocksdb::ColumnFamilyDescriptor::new(Self::CFS[20], opts_with_prefix_extractor(8)),
+            // NODE_STATUS
+            rocksdb::ColumnFamilyDescriptor::new(Self::CFS[21], Default::default()),
+            // GOSSIP_HASH_INDEX
+            rocksdb::ColumnFamilyDescriptor::new(Self::CFS[22], opts_with_prefix_extractor(32)),
+            // BITSWAP_SESSIONS
+            rocksdb::ColumnFamilyDescriptor::new(Self::CFS[23], Default::default()),
+            // GATING_CONFIG
+            rocksdb::ColumnFamilyDescriptor::new(Self::CFS[24], Default::default()),
+            // PRUNES
+            rocksdb::ColumnFamilyDescriptor::new(Self::CFS[25], Default::default()),
+            // DESYNC
+            rocksdb::ColumnFamilyDescriptor::new(Self::CFS[26], Default::default()),
+            // NODE_METRICS
+            rocksdb::ColumnFamilyDescriptor::new(Self::CFS[27], Default::default()),
+            // PEER_ID_INDEX
+            rocksdb::ColumnFamilyDescriptor::new(Self::CFS[28], opts_with_prefix_extractor(32)),
+            // YAMUX_WINDOWS
+            rocksdb::ColumnFamilyDescriptor::new(Self::CFS[29], opts_with_prefix_extractor(8)),
+            // BITSWAP_BLOCKS
+            rocksdb::ColumnFamilyDescriptor::new(Self::CFS[30], Default::default()),
         ];
         let inner =
             rocksdb::DB::open_cf_descriptors_with_ttl(&opts, path.join("rocksdb"), cfs, Self::TTL)?;
+        let inner = Arc::new(inner);
+
+        // durability vs throughput, by default rely on rocksdb and OS to persist the data;
+        // `SYNC_WRITES=1` makes every write wait for fsync, it is safe but slow,
+        // `FSYNC_INTERVAL_MS` bounds how much data may be lost on crash, it is cheaper
+        let mut write_opts = rocksdb::WriteOptions::default();
+        if env::var("SYNC_WRITES").map_or(false, |s| s == "1") {
+            log::info!("database: synchronous writes");
+            write_opts.set_sync(true);
+        }
+        if let Some(interval) = env::var("FSYNC_INTERVAL_MS")
+            .ok()
+            .and_then(|s| s.parse::<u64>().ok())
+            .filter(|ms| *ms != 0)
+            .map(Duration::from_millis)
+        {
+            log::info!("database: flush write ahead log every {interval:?}");
+            Self::spawn_wal_flusher(Arc::downgrade(&inner), interval);
+        }
 
-        Ok(DbCore {
+        let db = DbCore {
             cache: Arc::new(Mutex::new(BTreeMap::default())),
-            inner: Arc::new(inner),
-        })
+            write_opts: Arc::new(write_opts),
+            counters: Arc::default(),
+            feed: MessageFeed::new(),
+            inner,
+        };
+        // connections of the previous run are not active anymore
+        let total = db.ids_given::<{ Self::CONNECTIONS_CNT }>()?;
+        let pruned = db.total::<{ Self::PRUNED_CONNECTIONS_CNT }>()?;
+        db.counters
+            .total
+            .store(total.saturating_sub(pruned), SeqCst);
+        let incoming = db.total::<{ Self::INCOMING_CNT }>()?;
+        db.counters.incoming.store(incoming, SeqCst);
+        // the only scan of the stream kind index, then it is counted as it is written
+        *db.messages_by_kind() = db.count_messages_by_kind();
+
+        // `DB_RETENTION_SECS` bounds the size of the database, older data is deleted
+        if let Some(retention) = env::var("DB_RETENTION_SECS")
+            .ok()
+            .and_then(|s| s.parse::<u64>().ok())
+            .filter(|secs| *secs != 0)
+            .map(Duration::from_secs)
+        {
+            log::info!("database: retain {retention:?}");
+            db.spawn_pruner(retention);
+        }
+
+        Ok(db)
+    }
+
+    fn spawn_pruner(&self, retention: Duration) {
+        let cache = Arc::downgrade(&self.cache);
+        let write_opts = Arc::downgrade(&self.write_opts);
+        let counters = Arc::downgrade(&self.counters);
+        let inner = Arc::downgrade(&self.inner);
+        let feed = self.feed.clone();
+        let interval = (retention / 10).clamp(Duration::from_secs(10), Duration::from_secs(3600));
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            // the database is closed, nothing to prune
+            let (Some(cache), Some(write_opts), Some(counters), Some(inner)) = (
+                cache.upgrade(),
+                write_opts.upgrade(),
+                counters.upgrade(),
+                inner.upgrade(),
+            ) else {
+                break;
+            };
+            let db = DbCore {
+                cache,
+                write_opts,
+                counters,
+                feed: feed.clone(),
+                inner,
+            };
+            let Some(cutoff) = SystemTime::now().checked_sub(retention) else {
+                continue;
+            };
+            match db.prune_before(cutoff) {
+                Ok(0) => (),
+                Ok(n) => log::info!("database: pruned {n} messages older than {retention:?}"),
+                Err(err) => log::error!("database: failed to prune: {err}"),
+            }
+        });
+    }
+
+    fn spawn_wal_flusher(db: Weak<rocksdb::DB>, interval: Duration) {
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            // the database is closed, nothing to flush
+            let Some(db) = db.upgrade() else {
+                break;
+            };
+            if let Err(err) = db.flush_wal(true) {
+                log::error!("failed to flush write ahead log: {err}");
+            }
+        });
+    }
+
+    fn put_cf<K, V>(
+        &self,
+        cf: &rocksdb::ColumnFamily,
+        key: K,
+        value: V,
+    ) -> Result<(), rocksdb::Error>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        self.inner.put_cf_opt(cf, key, value, &self.write_opts)
     }
 
     fn connections(&self) -> &rocksdb::ColumnFamily {
@@ -250,6 +494,70 @@ impl DbCore {
         self.inner.cf_handle(Self::KEYS).expect("must exist")
     }
 
+    fn ping_rtt(&self) -> &rocksdb::ColumnFamily {
+        self.inner.cf_handle(Self::PING_RTT).expect("must exist")
+    }
+
+    fn decode_errors(&self) -> &rocksdb::ColumnFamily {
+        self.inner
+            .cf_handle(Self::DECODE_ERRORS)
+            .expect("must exist")
+    }
+
+    fn connection_bandwidth(&self) -> &rocksdb::ColumnFamily {
+        self.inner
+            .cf_handle(Self::CONNECTION_BANDWIDTH)
+            .expect("must exist")
+    }
+
+    fn meshsub_topics(&self) -> &rocksdb::ColumnFamily {
+        self.inner
+            .cf_handle(Self::MESHSUB_TOPICS)
+            .expect("must exist")
+    }
+
+    fn node_status(&self) -> &rocksdb::ColumnFamily {
+        self.inner.cf_handle(Self::NODE_STATUS).expect("must exist")
+    }
+
+    fn bitswap_sessions(&self) -> &rocksdb::ColumnFamily {
+        self.inner
+            .cf_handle(Self::BITSWAP_SESSIONS)
+            .expect("must exist")
+    }
+
+    fn gating_config(&self) -> &rocksdb::ColumnFamily {
+        self.inner
+            .cf_handle(Self::GATING_CONFIG)
+            .expect("must exist")
+    }
+
+    fn prunes(&self) -> &rocksdb::ColumnFamily {
+        self.inner.cf_handle(Self::PRUNES).expect("must exist")
+    }
+
+    fn desync(&self) -> &rocksdb::ColumnFamily {
+        self.inner.cf_handle(Self::DESYNC).expect("must exist")
+    }
+
+    fn yamux_windows(&self) -> &rocksdb::ColumnFamily {
+        self.inner
+            .cf_handle(Self::YAMUX_WINDOWS)
+            .expect("must exist")
+    }
+
+    fn bitswap_blocks(&self) -> &rocksdb::ColumnFamily {
+        self.inner
+            .cf_handle(Self::BITSWAP_BLOCKS)
+            .expect("must exist")
+    }
+
+    fn node_metrics(&self) -> &rocksdb::ColumnFamily {
+        self.inner
+            .cf_handle(Self::NODE_METRICS)
+            .expect("must exist")
+    }
+
     fn connection_id_index(&self) -> &rocksdb::ColumnFamily {
         self.inner
             .cf_handle(Self::CONNECTION_ID_INDEX)
@@ -284,9 +592,58 @@ impl DbCore {
             .expect("must exist")
     }
 
-    pub fn put_cn(&self, id: ConnectionId, v: Connection) -> Result<(), DbError> {
+    fn stable_id_index(&self) -> &rocksdb::ColumnFamily {
         self.inner
-            .put_cf(self.connections(), id.chain(vec![]), v.chain(vec![]))?;
+            .cf_handle(Self::STABLE_ID_INDEX)
+            .expect("must exist")
+    }
+
+    fn gossip_hash_index(&self) -> &rocksdb::ColumnFamily {
+        self.inner
+            .cf_handle(Self::GOSSIP_HASH_INDEX)
+            .expect("must exist")
+    }
+
+    fn peer_id_index(&self) -> &rocksdb::ColumnFamily {
+        self.inner
+            .cf_handle(Self::PEER_ID_INDEX)
+            .expect("must exist")
+    }
+
+    /// The connection where the peer presented the key in the handshake.
+    pub fn put_peer_id_index(&self, peer_id: &PeerId, id: ConnectionId) -> Result<(), DbError> {
+        let index = PeerIdIdx {
+            peer_id: PeerIdIdx::hash(peer_id),
+            id,
+        };
+        self.put_cf(self.peer_id_index(), index.chain(vec![]), vec![])?;
+
+        Ok(())
+    }
+
+    pub fn put_gossip_hash_index(&self, hash: [u8; 32], id: MessageId) -> Result<(), DbError> {
+        let index = GossipHashIdx {
+            hash: Hash(hash),
+            id,
+        };
+        self.put_cf(self.gossip_hash_index(), index.chain(vec![]), vec![])?;
+
+        Ok(())
+    }
+
+    pub fn put_stable_id_index(
+        &self,
+        stable_id: StableConnectionId,
+        id: ConnectionId,
+    ) -> Result<(), DbError> {
+        let index = StableIdIdx { stable_id, id };
+        self.put_cf(self.stable_id_index(), index.chain(vec![]), vec![])?;
+
+        Ok(())
+    }
+
+    pub fn put_cn(&self, id: ConnectionId, v: Connection) -> Result<(), DbError> {
+        self.put_cf(self.connections(), id.chain(vec![]), v.chain(vec![]))?;
 
         Ok(())
     }
@@ -298,18 +655,62 @@ impl DbCore {
         v: Message,
         tys: Vec<MessageType>,
         ledger_hashes: Vec<LedgerHash>,
+        peer_addrs: Vec<SocketAddr>,
     ) -> Result<(), DbError> {
-        self.inner
-            .put_cf(self.messages(), id.0.to_be_bytes(), v.chain(vec![]))?;
-        let index = AddressIdx { addr: *addr, id };
-        self.inner
-            .put_cf(self.addr_index(), index.chain(vec![]), vec![])?;
+        self.put_cf(self.messages(), id.0.to_be_bytes(), v.chain(vec![]))?;
+        self.put_message_indexes(addr, id, &v, tys, ledger_hashes, peer_addrs)?;
+        self.feed.send(id, v.stream_kind);
+
+        Ok(())
+    }
+
+    pub fn subscribe_messages(&self) -> broadcast::Receiver<(MessageId, StreamKind)> {
+        self.feed.subscribe()
+    }
+
+    fn put_message_indexes(
+        &self,
+        addr: &SocketAddr,
+        id: MessageId,
+        v: &Message,
+        tys: Vec<MessageType>,
+        ledger_hashes: Vec<LedgerHash>,
+        peer_addrs: Vec<SocketAddr>,
+    ) -> Result<(), DbError> {
+        let keys = self.message_index_keys(Some(addr), id, v, tys, ledger_hashes, peer_addrs);
+        for (cf, key) in keys {
+            self.put_cf(cf, key, vec![])?;
+        }
+        *self.messages_by_kind().entry(v.stream_kind).or_default() += 1;
+        Ok(())
+    }
+
+    // the keys of the message in the indexes, except the gossip hash index,
+    // the address is unknown if the connection is already deleted
+    fn message_index_keys(
+        &self,
+        addr: Option<&SocketAddr>,
+        id: MessageId,
+        v: &Message,
+        tys: Vec<MessageType>,
+        ledger_hashes: Vec<LedgerHash>,
+        peer_addrs: Vec<SocketAddr>,
+    ) -> Vec<(&rocksdb::ColumnFamily, Vec<u8>)> {
+        let mut keys = vec![];
+        if let Some(addr) = addr {
+            let index = AddressIdx { addr: *addr, id };
+            keys.push((self.addr_index(), index.chain(vec![])));
+        }
+        // the message mentions these peers, so it is found by their address too
+        for addr in peer_addrs.into_iter().filter(|a| Some(a) != addr) {
+            let index = AddressIdx { addr, id };
+            keys.push((self.addr_index(), index.chain(vec![])));
+        }
         let index = ConnectionIdx {
             connection_id: v.connection_id,
             id,
         };
-        self.inner
-            .put_cf(self.connection_id_index(), index.chain(vec![]), vec![])?;
+        keys.push((self.connection_id_index(), index.chain(vec![])));
         let index = StreamIdx {
             stream_full_id: StreamFullId {
                 cn: v.connection_id,
@@ -317,22 +718,15 @@ impl DbCore {
             },
             id,
         };
-        self.inner
-            .put_cf(self.stream_id_index(), index.chain(vec![]), vec![])?;
+        keys.push((self.stream_id_index(), index.chain(vec![])));
         let index = StreamByKindIdx {
             stream_kind: v.stream_kind,
             id,
         };
-        self.inner
-            .put_cf(self.stream_kind_index(), index.chain(vec![]), vec![])?;
+        keys.push((self.stream_kind_index(), index.chain(vec![])));
         for ty in tys {
-            if matches!(&ty, &MessageType::HandshakePayload) {
-                // peer id index
-            }
-
             let index = MessageKindIdx { ty, id };
-            self.inner
-                .put_cf(self.message_kind_index(), index.chain(vec![]), vec![])?;
+            keys.push((self.message_kind_index(), index.chain(vec![])));
         }
         for hash in ledger_hashes {
             let message_id = id;
@@ -346,76 +740,762 @@ impl DbCore {
                 },
                 message_id,
             };
-            self.inner
-                .put_cf(self.ledger_hash_index(), index.chain(vec![]), vec![])?;
+            keys.push((self.ledger_hash_index(), index.chain(vec![])));
+        }
+        keys
+    }
+
+    fn flush_cfs(&self) -> Result<(), DbError> {
+        self.inner.flush_wal(true)?;
+        self.inner.flush()?;
+        for name in Self::CFS {
+            let cf = self.inner.cf_handle(name).expect("must exist");
+            self.inner.flush_cf(cf)?;
         }
         Ok(())
     }
 
-    pub fn put_randomness(&self, id: u64, bytes: [u8; 32]) -> Result<(), DbError> {
-        self.inner
-            .put_cf(self.randomness(), id.to_be_bytes(), bytes)?;
+    /// Persists everything written so far, called on shutdown. The blob positions
+    /// are not stored, they are recovered from the blobs on open.
+    pub fn flush_all(&self) -> Result<(), DbError> {
+        // no blob takes a new position while flushing
+        let _cache = self.cache.lock().expect("must be ok");
+        self.flush_cfs()
+    }
+
+    /// Consistent copy of the whole database, rocksdb hard links the table files,
+    /// so it is cheap. The copy is opened by `DbCore::open(path)`.
+    pub fn create_checkpoint(&self, path: &Path) -> Result<(), DbError> {
+        // no blob takes a new position until the checkpoint is done
+        let _cache = self.cache.lock().expect("must be ok");
+        self.flush_cfs()?;
+        fs::create_dir_all(path).map_err(DbError::CreateDirError)?;
+        let checkpoint = rocksdb::checkpoint::Checkpoint::new(&*self.inner)?;
+        checkpoint.create_checkpoint(path.join("rocksdb"))?;
 
         Ok(())
     }
 
-    pub fn put_strace(&self, id: u64, bytes: Vec<u8>) -> Result<(), DbError> {
-        self.inner.put_cf(self.strace(), id.to_be_bytes(), bytes)?;
+    /// Checkpoint in `CHECKPOINT_DIR`, by default `checkpoints` next to the database,
+    /// named by the unix time in milliseconds.
+    pub fn checkpoint(&self) -> Result<CheckpointReport, DbError> {
+        fn dir_size(path: &Path) -> io::Result<u64> {
+            let mut size = 0;
+            for entry in fs::read_dir(path)? {
+                let entry = entry?;
+                let metadata = entry.metadata()?;
+                size += if metadata.is_dir() {
+                    dir_size(&entry.path())?
+                } else {
+                    metadata.len()
+                };
+            }
+            Ok(size)
+        }
 
-        Ok(())
+        let base = env::var("CHECKPOINT_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| {
+                let rocksdb = self.inner.path();
+                rocksdb.parent().unwrap_or(rocksdb).join("checkpoints")
+            });
+        let millis = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let path = base.join(millis.to_string());
+        self.create_checkpoint(&path)?;
+        let size = dir_size(&path).map_err(DbError::CreateDirError)?;
+
+        Ok(CheckpointReport { path, size })
     }
 
-    pub fn put_stats(
+    /// Clears the message indexes and builds them again from the `messages`,
+    /// the messages are decoded once again, the same way as at ingest.
+    pub fn reindex(&self) -> Result<ReindexReport, DbError> {
+        let index_ledger_hash = LedgerHashIdx::enabled();
+        let mut cfs = vec![
+            self.addr_index(),
+            self.connection_id_index(),
+            self.stream_id_index(),
+            self.stream_kind_index(),
+            self.message_kind_index(),
+            self.peer_id_index(),
+        ];
+        // otherwise the ledger hashes are not derived, keep what is there
+        if index_ledger_hash {
+            cfs.push(self.ledger_hash_index());
+        }
+        // every index key is shorter
+        let (from, to): (&[u8], &[u8]) = (&[], &[0xff; 0x100]);
+        for cf in cfs {
+            self.inner
+                .delete_range_cf_opt(cf, from, to, &self.write_opts)?;
+        }
+        self.messages_by_kind().clear();
+
+        let mut report = ReindexReport::default();
+        let mut addrs = BTreeMap::new();
+        for item in self
+            .inner
+            .iterator_cf(self.messages(), rocksdb::IteratorMode::Start)
+        {
+            let (key, value) = item?;
+            let Ok(key) = <[u8; 8]>::try_from(key.as_ref()) else {
+                continue;
+            };
+            let id = MessageId(u64::from_be_bytes(key));
+            let msg = Message::absorb_ext(&value)?;
+            let cn = msg.connection_id;
+            let addr = match addrs.get(&cn) {
+                Some(addr) => *addr,
+                None => match self.fetch_connection(cn.0) {
+                    Ok(v) => *addrs.entry(cn).or_insert(v.info.addr),
+                    Err(err) => {
+                        log::warn!("reindex message {}, connection {cn}: {err}", id.0);
+                        report.missing += 1;
+                        continue;
+                    }
+                },
+            };
+            let mut ledger_hashes = vec![];
+            let mut peer_addrs = vec![];
+            let parsed = self.with_blob(cn, msg.offset, |bytes| {
+                let tys = DbStream::parse_types(
+                    msg.stream_kind,
+                    bytes,
+                    index_ledger_hash,
+                    &mut ledger_hashes,
+                    &mut peer_addrs,
+                    &mut vec![],
+                    &mut None,
+                    &mut vec![],
+                );
+                let peer_id = match &tys {
+                    Ok(tys) if msg.incoming && tys.contains(&MessageType::HandshakePayload) => {
+                        crate::decode::noise::peer_id(bytes).ok().flatten()
+                    }
+                    _ => None,
+                };
+                (tys, peer_id)
+            });
+            let (tys, peer_id) = match parsed {
+                Ok(v) => v,
+                Err(err) => {
+                    log::warn!("reindex message {}: {err}", id.0);
+                    report.missing += 1;
+                    continue;
+                }
+            };
+
+            let tys = match tys {
+                Ok(v) => v,
+                Err(err) => {
+                    // still found by connection, stream and address
+                    log::warn!("reindex message {}: {err}", id.0);
+                    report.decode_errors += 1;
+                    vec![]
+                }
+            };
+            if let Some(peer_id) = peer_id {
+                self.put_peer_id_index(&peer_id, cn)?;
+            }
+            self.put_message_indexes(&addr, id, &msg, tys, ledger_hashes, peer_addrs)?;
+            report.messages += 1;
+        }
+
+        Ok(report)
+    }
+
+    /// Deletes the messages older than `cutoff` and the connections closed before it,
+    /// along with their blobs and index entries. The connections still open are kept.
+    /// The ids are never reused, the pruned items are counted separately.
+    /// Returns how many messages were deleted.
+    pub fn prune_before(&self, cutoff: SystemTime) -> Result<usize, DbError> {
+        let index_ledger_hash = LedgerHashIdx::enabled();
+        let cached = self
+            .cache
+            .lock()
+            .expect("must be ok")
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+        let open = cached
+            .into_iter()
+            .filter(|cn| {
+                self.fetch_connection(cn.0)
+                    .map_or(false, |v| v.timestamp_close == SystemTime::UNIX_EPOCH)
+            })
+            .collect::<BTreeSet<_>>();
+        // the ids follow the time only roughly, the events are reordered,
+        // nothing older than the cutoff is expected this far after it
+        let horizon = cutoff + Self::PRUNE_REORDER;
+
+        // the messages go first, the blob is needed to find the index keys
+        let mut messages = 0;
+        let mut addrs = BTreeMap::new();
+        let cursor = self.total::<{ Self::PRUNE_CURSOR_MESSAGES }>()?;
+        let (mut kept, mut end) = (None, cursor);
+        let mode = rocksdb::IteratorMode::From(&cursor.to_be_bytes(), rocksdb::Direction::Forward);
+        for item in self.inner.iterator_cf(self.messages(), mode) {
+            let (key, value) = item?;
+            let Ok(key) = <[u8; 8]>::try_from(key.as_ref()) else {
+                continue;
+            };
+            let id = u64::from_be_bytes(key);
+            end = id + 1;
+            let msg = Message::absorb_ext(&value)?;
+            if msg.timestamp >= horizon {
+                kept.get_or_insert(id);
+                break;
+            }
+            if msg.timestamp >= cutoff || open.contains(&msg.connection_id) {
+                kept.get_or_insert(id);
+                continue;
+            }
+            self.delete_message_indexes(&mut addrs, MessageId(id), &msg, index_ledger_hash)?;
+            self.inner
+                .delete_cf_opt(self.messages(), key, &self.write_opts)?;
+            messages += 1;
+        }
+        // everything before the first kept message is pruned, the next run starts there
+        self.set_total::<{ Self::PRUNE_CURSOR_MESSAGES }>(kept.unwrap_or(end))?;
+
+        let mut connections = BTreeSet::new();
+        let mut incoming = 0;
+        let cursor = self.total::<{ Self::PRUNE_CURSOR_CONNECTIONS }>()?;
+        let (mut kept, mut end) = (None, cursor);
+        let mode = rocksdb::IteratorMode::From(&cursor.to_be_bytes(), rocksdb::Direction::Forward);
+        for item in self.inner.iterator_cf(self.connections(), mode) {
+            let (key, value) = item?;
+            let Ok(key) = <[u8; 8]>::try_from(key.as_ref()) else {
+                continue;
+            };
+            let cn = ConnectionId(u64::from_be_bytes(key));
+            end = cn.0 + 1;
+            let v = Connection::absorb_ext(&value)?;
+            if v.timestamp >= horizon {
+                kept.get_or_insert(cn.0);
+                break;
+            }
+            if v.timestamp >= cutoff || open.contains(&cn) || v.timestamp_close >= cutoff {
+                kept.get_or_insert(cn.0);
+                continue;
+            }
+            self.inner
+                .delete_cf_opt(self.connections(), key, &self.write_opts)?;
+            let (from, to) = ((cn, 0_u64).chain(vec![]), (cn, u64::MAX).chain(vec![]));
+            self.inner
+                .delete_range_cf_opt(self.blobs(), from, to, &self.write_opts)?;
+            // the keys of these start with the connection id
+            for cf in [
+                self.connection_id_index(),
+                self.stream_id_index(),
+                self.decode_errors(),
+                self.ping_rtt(),
+                self.connection_bandwidth(),
+                self.yamux_windows(),
+                self.desync(),
+                self.meshsub_topics(),
+                self.bitswap_sessions(),
+            ] {
+                let (from, to) = (cn.0.to_be_bytes(), (cn.0 + 1).to_be_bytes());
+                self.inner
+                    .delete_range_cf_opt(cf, from, to, &self.write_opts)?;
+            }
+            if v.stable_id.is_known() {
+                let index = StableIdIdx {
+                    stable_id: v.stable_id,
+                    id: cn,
+                };
+                self.inner.delete_cf_opt(
+                    self.stable_id_index(),
+                    index.chain(vec![]),
+                    &self.write_opts,
+                )?;
+            }
+            incoming += v.incoming as u64;
+            connections.insert(cn);
+        }
+        self.set_total::<{ Self::PRUNE_CURSOR_CONNECTIONS }>(kept.unwrap_or(end))?;
+        {
+            let mut lock = self.cache.lock().expect("must be ok");
+            for cn in &connections {
+                lock.remove(cn);
+            }
+        }
+        if !connections.is_empty() {
+            let c = &connections;
+            // keyed by the hash of the peer id, one entry per connection, so it is small
+            self.prune_cf(self.peer_id_index(), |i: &PeerIdIdx| c.contains(&i.id))?;
+        }
+
+        // the totals are the ids, they stay, the pruned items are subtracted from the counts
+        let pruned = self.total::<{ Self::PRUNED_MESSAGES_CNT }>()?;
+        self.set_total::<{ Self::PRUNED_MESSAGES_CNT }>(pruned + messages as u64)?;
+        let pruned = self.total::<{ Self::PRUNED_CONNECTIONS_CNT }>()?;
+        self.set_total::<{ Self::PRUNED_CONNECTIONS_CNT }>(pruned + connections.len() as u64)?;
+        self.counters
+            .total
+            .fetch_sub(connections.len() as u64, SeqCst);
+        if incoming != 0 {
+            let v = self.counters.incoming.fetch_sub(incoming, SeqCst) - incoming;
+            self.set_total::<{ Self::INCOMING_CNT }>(v)?;
+        }
+
+        Ok(messages)
+    }
+
+    // the message is decoded once again, the same way as at ingest, to find its index keys
+    fn delete_message_indexes(
         &self,
-        height: u32,
-        node_address: SocketAddr,
-        bytes: Vec<u8>,
+        addrs: &mut BTreeMap<ConnectionId, SocketAddr>,
+        id: MessageId,
+        msg: &Message,
+        index_ledger_hash: bool,
     ) -> Result<(), DbError> {
-        let key = StatsDbKey {
-            height,
-            node_address,
+        let cn = msg.connection_id;
+        let addr = match addrs.get(&cn) {
+            Some(addr) => Some(*addr),
+            None => self
+                .fetch_connection(cn.0)
+                .ok()
+                .map(|v| *addrs.entry(cn).or_insert(v.info.addr)),
         };
+        let mut ledger_hashes = vec![];
+        let mut peer_addrs = vec![];
+        let mut gossip_hashes = vec![];
+        let tys = self
+            .with_blob(cn, msg.offset, |bytes| {
+                DbStream::parse_types(
+                    msg.stream_kind,
+                    bytes,
+                    index_ledger_hash,
+                    &mut ledger_hashes,
+                    &mut peer_addrs,
+                    &mut vec![],
+                    &mut None,
+                    &mut gossip_hashes,
+                )
+                .ok()
+            })
+            .ok()
+            .flatten()
+            .unwrap_or_default();
+        let keys = self.message_index_keys(addr.as_ref(), id, msg, tys, ledger_hashes, peer_addrs);
+        for (cf, key) in keys {
+            self.inner.delete_cf_opt(cf, key, &self.write_opts)?;
+        }
+        if let Some(count) = self.messages_by_kind().get_mut(&msg.stream_kind) {
+            *count = count.saturating_sub(1);
+        }
+        for hash in gossip_hashes {
+            let index = GossipHashIdx {
+                hash: Hash(hash),
+                id,
+            };
+            self.inner.delete_cf_opt(
+                self.gossip_hash_index(),
+                index.chain(vec![]),
+                &self.write_opts,
+            )?;
+        }
+
+        Ok(())
+    }
 
-        self.inner.put_cf(self.stats(), key.chain(vec![]), bytes)?;
+    // deletes every entry whose key matches
+    fn prune_cf<T, F>(&self, cf: &rocksdb::ColumnFamily, f: F) -> Result<(), DbError>
+    where
+        T: for<'pa> AbsorbExt<'pa>,
+        F: Fn(&T) -> bool,
+    {
+        for item in self.inner.iterator_cf(cf, rocksdb::IteratorMode::Start) {
+            let (key, _) = item?;
+            if T::absorb_ext(&key).map_or(false, |k| f(&k)) {
+                self.inner.delete_cf_opt(cf, key, &self.write_opts)?;
+            }
+        }
 
         Ok(())
     }
 
-    pub fn put_stats_block_v2(&self, event: meshsub_stats::Event) -> Result<(), DbError> {
-        let key = StatsV2DbKey {
-            height: event.block_height,
-            time: event.better_time,
+    /// Overwrites the previous snapshot of the stream.
+    pub fn put_window_stats(&self, cn: ConnectionId, v: &WindowStats) -> Result<(), DbError> {
+        let key = WindowStatsKey {
+            cn,
+            stream_id: v.stream_id,
         };
-
-        self.inner.put_cf(
-            self.stats_block_v2(),
+        self.put_cf(
+            self.yamux_windows(),
             key.chain(vec![]),
-            event.chain(vec![]),
+            v.clone().chain(vec![]),
         )?;
 
         Ok(())
     }
 
-    pub fn put_stats_tx(&self, height: u32, bytes: Vec<u8>) -> Result<(), DbError> {
-        self.inner
-            .put_cf(self.stats_tx(), height.to_be_bytes(), bytes)?;
+    pub fn put_ping_rtt(&self, cn: ConnectionId, v: PingRtt) -> Result<(), DbError> {
+        let key = PingRttKey {
+            cn,
+            message_id: v.message_id,
+        };
+        self.put_cf(self.ping_rtt(), key.chain(vec![]), v.chain(vec![]))?;
 
         Ok(())
     }
 
-    pub fn put_capnp(
+    pub fn put_decode_error(
         &self,
-        key: CapnpEventWithMetadataKey,
-        event: CapnpEventWithMetadata,
+        stream_full_id: StreamFullId,
+        offset: u64,
+        error: &str,
     ) -> Result<(), DbError> {
-        self.inner
-            .put_cf(self.capnp(), key.chain(vec![]), event.chain(vec![]))?;
+        let key = DecodeErrorIdx {
+            stream_full_id,
+            offset,
+        };
+        self.put_cf(self.decode_errors(), key.chain(vec![]), error)?;
 
         Ok(())
     }
 
-    pub fn put_blob(&self, cn: ConnectionId, data: &[u8]) -> Result<u64, DbError> {
-        let mut lock = self.cache.lock().expect("must be ok");
+    // topic subscribed on the connection, key is the connection id followed by the topic
+    pub fn put_meshsub_topic(&self, cn: ConnectionId, topic: &str) -> Result<(), DbError> {
+        let mut key = cn.chain(vec![]);
+        key.extend_from_slice(topic.as_bytes());
+        self.put_cf(self.meshsub_topics(), key, vec![])?;
+
+        Ok(())
+    }
+
+    pub fn fetch_topic_registry(
+        &self,
+        snapshot: &rocksdb::Snapshot,
+        cn: ConnectionId,
+    ) -> TopicRegistry {
+        let prefix = cn.chain(vec![]);
+        let mode = rocksdb::IteratorMode::From(&prefix, rocksdb::Direction::Forward);
+        let names = snapshot
+            .iterator_cf(self.meshsub_topics(), mode)
+            .filter_map(Result::ok)
+            .take_while(|(k, _)| k.starts_with(&prefix))
+            .filter_map(|(k, _)| String::from_utf8(k[prefix.len()..].to_vec()).ok())
+            .collect::<Vec<_>>();
+        TopicRegistry::new(names)
+    }
+
+    /// Keyed by the peer, only the latest status is kept.
+    pub fn put_node_status(&self, peer: &str, v: NodeStatusRecord) -> Result<(), DbError> {
+        self.put_cf(self.node_status(), peer.as_bytes(), v.chain(vec![]))?;
+
+        Ok(())
+    }
+
+    pub fn fetch_node_statuses(&self) -> Vec<serde_json::Value> {
+        self.inner
+            .iterator_cf(self.node_status(), rocksdb::IteratorMode::Start)
+            .filter_map(Result::ok)
+            .filter_map(|(key, value)| {
+                let peer = String::from_utf8(key.to_vec()).ok()?;
+                let record = match NodeStatusRecord::absorb_ext(&value) {
+                    Ok(v) => v,
+                    Err(err) => {
+                        log::error!("node status {peer}, err: {err}");
+                        return None;
+                    }
+                };
+                let status = serde_json::from_str::<serde_json::Value>(&record.status).ok()?;
+                let mut v = serde_json::to_value(&record).ok()?;
+                let obj = v.as_object_mut()?;
+                obj.insert("peer".to_owned(), peer.into());
+                obj.insert("status".to_owned(), status);
+                Some(v)
+            })
+            .collect()
+    }
+
+    // the key is the metric name, zero byte, the time in nanoseconds and the number in the scrape,
+    // so the samples of one metric are together and sorted by time
+    fn node_metric_key(name: &str, time: SystemTime, i: u32) -> Vec<u8> {
+        let nanos = time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        let mut key = name.as_bytes().to_vec();
+        key.push(0);
+        key.extend_from_slice(&nanos.to_be_bytes());
+        key.extend_from_slice(&i.to_be_bytes());
+        key
+    }
+
+    pub fn put_node_metrics(
+        &self,
+        cn: ConnectionId,
+        time: SystemTime,
+        samples: Vec<prometheus::Sample>,
+    ) -> Result<(), DbError> {
+        for (i, sample) in samples.into_iter().enumerate() {
+            let key = Self::node_metric_key(&sample.name, time, i as u32);
+            let v = NodeMetricSample {
+                connection_id: cn,
+                timestamp: time,
+                labels: sample.labels,
+                value: sample.value,
+            };
+            self.put_cf(self.node_metrics(), key, v.chain(vec![]))?;
+        }
+
+        Ok(())
+    }
+
+    /// The samples of the metric, the latest first.
+    pub fn fetch_node_metrics(&self, name: &str, limit: usize) -> Vec<serde_json::Value> {
+        let mut prefix = name.as_bytes().to_vec();
+        prefix.push(0);
+        let mut end = prefix.clone();
+        end.extend_from_slice(&[0xff; 12]);
+        let mode = rocksdb::IteratorMode::From(&end, rocksdb::Direction::Reverse);
+        self.inner
+            .iterator_cf(self.node_metrics(), mode)
+            .filter_map(Result::ok)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .filter_map(|(_, value)| NodeMetricSample::absorb_ext(&value).ok())
+            .take(limit)
+            .map(|v| v.post_process())
+            .collect()
+    }
+
+    pub fn put_gating_config(&self, pid: u32, v: GatingConfigRecord) -> Result<(), DbError> {
+        self.put_cf(self.gating_config(), pid.to_be_bytes(), v.chain(vec![]))?;
+
+        Ok(())
+    }
+
+    pub fn fetch_gating_configs(&self) -> Vec<serde_json::Value> {
+        self.inner
+            .iterator_cf(self.gating_config(), rocksdb::IteratorMode::Start)
+            .filter_map(Result::ok)
+            .filter_map(|(key, value)| {
+                let pid = u32::from_be_bytes(key.as_ref().try_into().ok()?);
+                let record = match GatingConfigRecord::absorb_ext(&value) {
+                    Ok(v) => v,
+                    Err(err) => {
+                        log::error!("gating config {pid}, err: {err}");
+                        return None;
+                    }
+                };
+                let mut v = serde_json::to_value(&record).ok()?;
+                v.as_object_mut()?.insert("pid".to_owned(), pid.into());
+                Some(v)
+            })
+            .collect()
+    }
+
+    /// Only the first desync of the connection is kept, later failures are the consequence.
+    pub fn put_desync(&self, cn: ConnectionId, v: Desync) -> Result<(), DbError> {
+        let key = cn.chain(vec![]);
+        if self.inner.get_cf(self.desync(), &key)?.is_none() {
+            self.put_cf(self.desync(), key, v.chain(vec![]))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn fetch_desync(&self, cn: u64) -> Result<Option<Desync>, DbError> {
+        let key = ConnectionId(cn).chain(vec![]);
+        match self.inner.get_cf(self.desync(), key)? {
+            Some(v) => Ok(Some(Desync::absorb_ext(&v)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn add_prune(
+        &self,
+        peer: SocketAddr,
+        time: SystemTime,
+        backoff: Option<u64>,
+    ) -> Result<(), DbError> {
+        let mut key = vec![];
+        crate::custom_coding::addr_emit(&peer, &mut key);
+        let mut stats = match self.inner.get_cf(self.prunes(), &key)? {
+            Some(v) => PruneStats::absorb_ext(&v)?,
+            None => PruneStats::new(peer, time),
+        };
+        stats.observe(time, backoff);
+        self.put_cf(self.prunes(), key, stats.chain(vec![]))?;
+
+        Ok(())
+    }
+
+    /// The peers which prune us most often go first.
+    pub fn fetch_prunes(&self) -> Vec<serde_json::Value> {
+        let mut v = self
+            .inner
+            .iterator_cf(self.prunes(), rocksdb::IteratorMode::Start)
+            .filter_map(Result::ok)
+            .filter_map(|(_, value)| PruneStats::absorb_ext(&value).ok())
+            .collect::<Vec<_>>();
+        v.sort_by(|a, b| b.prunes.cmp(&a.prunes));
+        v.iter().map(PruneStats::post_process).collect()
+    }
+
+    pub fn add_bitswap(
+        &self,
+        cn: ConnectionId,
+        incoming: bool,
+        time: SystemTime,
+        size: u64,
+        blocks: &[ReceivedBlock],
+    ) -> Result<(), DbError> {
+        let key = cn.chain(vec![]);
+        let mut session = match self.inner.get_cf(self.bitswap_sessions(), &key)? {
+            Some(v) => BitswapSession::absorb_ext(&v)?,
+            None => BitswapSession::new(time),
+        };
+        session.observe(incoming, time, size);
+        for block in blocks {
+            let provenance = self.fetch_bitswap_block(block.digest)?;
+            // the children are the parts of the same mina block
+            if let Some(provenance) = &provenance {
+                for link in &block.links {
+                    self.put_bitswap_block(*link, provenance.clone())?;
+                }
+            }
+            if incoming {
+                session.observe_block(block.size as u64, provenance);
+            }
+        }
+        self.put_cf(self.bitswap_sessions(), key, session.chain(vec![]))?;
+
+        Ok(())
+    }
+
+    /// The digest of the bitswap block is the body reference of the mina block,
+    /// or the digest of its part, the root links to the parts.
+    pub fn put_bitswap_block(
+        &self,
+        digest: [u8; 32],
+        block: BlockProvenance,
+    ) -> Result<(), DbError> {
+        self.put_cf(self.bitswap_blocks(), digest, block.chain(vec![]))?;
+
+        Ok(())
+    }
+
+    pub fn fetch_bitswap_block(
+        &self,
+        digest: [u8; 32],
+    ) -> Result<Option<BlockProvenance>, DbError> {
+        match self.inner.get_cf(self.bitswap_blocks(), digest)? {
+            Some(v) => Ok(Some(BlockProvenance::absorb_ext(&v)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn fetch_bitswap_session(&self, cn: u64) -> Result<Option<BitswapSession>, DbError> {
+        let key = ConnectionId(cn).chain(vec![]);
+        match self.inner.get_cf(self.bitswap_sessions(), key)? {
+            Some(v) => Ok(Some(BitswapSession::absorb_ext(&v)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn add_bandwidth(
+        &self,
+        cn: ConnectionId,
+        time: SystemTime,
+        incoming: bool,
+        bytes: u64,
+    ) -> Result<(), DbError> {
+        let secs = time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let key = BandwidthKey { cn, secs }.chain(vec![]);
+        // the connection is handled by a single thread, so read-modify-write is fine
+        let mut v = match self.inner.get_cf(self.connection_bandwidth(), &key)? {
+            None => Bandwidth::default(),
+            Some(v) => AbsorbExt::absorb_ext(&v)?,
+        };
+        if incoming {
+            v.bytes_in += bytes;
+        } else {
+            v.bytes_out += bytes;
+        }
+        self.put_cf(self.connection_bandwidth(), key, v.chain(vec![]))?;
+
+        Ok(())
+    }
+
+    /// Keeps only `retain` the most recent entries.
+    pub fn put_randomness(&self, id: u64, bytes: [u8; 32], retain: u64) -> Result<(), DbError> {
+        self.put_cf(self.randomness(), id.to_be_bytes(), bytes)?;
+        self.set_total::<{ Self::RANDOMNESS_CNT }>(id + 1)?;
+        if let Some(old) = id.checked_sub(retain) {
+            let key = old.to_be_bytes();
+            self.inner
+                .delete_cf_opt(self.randomness(), key, &self.write_opts)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn put_strace(&self, id: u64, bytes: Vec<u8>) -> Result<(), DbError> {
+        self.put_cf(self.strace(), id.to_be_bytes(), bytes)?;
+
+        Ok(())
+    }
+
+    pub fn put_stats(
+        &self,
+        height: u32,
+        node_address: SocketAddr,
+        bytes: Vec<u8>,
+    ) -> Result<(), DbError> {
+        let key = StatsDbKey {
+            height,
+            node_address,
+        };
+
+        self.put_cf(self.stats(), key.chain(vec![]), bytes)?;
+
+        Ok(())
+    }
+
+    pub fn put_stats_block_v2(&self, event: meshsub_stats::Event) -> Result<(), DbError> {
+        let key = StatsV2DbKey {
+            height: event.block_height,
+            time: event.better_time,
+        };
+
+        self.put_cf(
+            self.stats_block_v2(),
+            key.chain(vec![]),
+            event.chain(vec![]),
+        )?;
+
+        Ok(())
+    }
+
+    pub fn put_stats_tx(&self, height: u32, bytes: Vec<u8>) -> Result<(), DbError> {
+        self.put_cf(self.stats_tx(), height.to_be_bytes(), bytes)?;
+
+        Ok(())
+    }
+
+    pub fn put_capnp(
+        &self,
+        key: CapnpEventWithMetadataKey,
+        event: CapnpEventWithMetadata,
+    ) -> Result<(), DbError> {
+        self.put_cf(self.capnp(), key.chain(vec![]), event.chain(vec![]))?;
+
+        Ok(())
+    }
+
+    pub fn put_blob(&self, cn: ConnectionId, data: &[u8]) -> Result<u64, DbError> {
+        let mut lock = self.cache.lock().expect("must be ok");
         let position = lock.entry(cn).or_default();
         if *position == 0 {
             let key = (cn, u64::MAX).chain(vec![]);
@@ -439,18 +1519,98 @@ impl DbCore {
         drop(lock);
 
         let key = (cn, offset).chain(vec![]);
-        self.inner.put_cf(self.blobs(), key, data)?;
+        self.put_cf(self.blobs(), key, data)?;
 
         Ok(offset)
     }
 
-    pub fn fetch_blob(&self, cn: ConnectionId, offset: u64) -> Result<Vec<u8>, DbError> {
+    /// Calls `f` with the blob borrowed from the rocksdb's buffer, no intermediate copy.
+    pub fn with_blob<F, R>(&self, cn: ConnectionId, offset: u64, f: F) -> Result<R, DbError>
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        self.with_blob_opt(&rocksdb::ReadOptions::default(), cn, offset, f)
+    }
+
+    /// Same as `with_blob`, reads through a snapshot.
+    fn with_blob_at<F, R>(
+        &self,
+        snapshot: &rocksdb::Snapshot,
+        cn: ConnectionId,
+        offset: u64,
+        f: F,
+    ) -> Result<R, DbError>
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        self.with_blob_opt(&Self::read_opts(snapshot), cn, offset, f)
+    }
+
+    fn with_blob_opt<F, R>(
+        &self,
+        opts: &rocksdb::ReadOptions,
+        cn: ConnectionId,
+        offset: u64,
+        f: F,
+    ) -> Result<R, DbError>
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        if offset == Message::SAMPLED_OUT {
+            return Err(DbError::SampledOut);
+        }
         let key = (cn, offset).chain(vec![]);
         let data = self
             .inner
-            .get_cf(self.blobs(), key)?
+            .get_pinned_cf_opt(self.blobs(), key, opts)?
             .ok_or(DbError::NoItemAtCursor(format!("{cn}, offset: {offset}")))?;
-        Ok(data[ChunkHeader::SIZE..].to_vec())
+        Ok(f(&data[ChunkHeader::SIZE..]))
+    }
+
+    /// Every chunk of the connection in the order it was written.
+    pub fn fetch_chunks(
+        &self,
+        cn: ConnectionId,
+    ) -> impl Iterator<Item = (ChunkHeader, Vec<u8>)> + '_ {
+        let key = (cn, 0_u64).chain(vec![]);
+        let mode = rocksdb::IteratorMode::From(&key, rocksdb::Direction::Forward);
+        self.inner
+            .iterator_cf(self.blobs(), mode)
+            .filter_map(Result::ok)
+            .map_while(move |(key, value)| {
+                let (cn_this, _) = <(ConnectionId, u64)>::absorb_ext(&key).ok()?;
+                if cn_this != cn {
+                    return None;
+                }
+                let header = ChunkHeader::absorb_ext(&value).ok()?;
+                Some((header, value.get(ChunkHeader::SIZE..)?.to_vec()))
+            })
+    }
+
+    /// Synthetic pcap of the connection, either the raw bytes as on the wire,
+    /// or the bytes decrypted by noise. The node address is not recorded,
+    /// so the local end is the unspecified address.
+    pub fn export_pcap(&self, cn: ConnectionId, decrypted: bool) -> Result<Vec<u8>, DbError> {
+        let connection = self.fetch_connection(cn.0)?;
+        let remote = connection.info.addr;
+        let local = match remote {
+            SocketAddr::V4(_) => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+            SocketAddr::V6(_) => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+        };
+        let mut writer =
+            crate::pcap::Writer::new(local, remote, connection.incoming, connection.timestamp);
+        for (header, bytes) in self.fetch_chunks(cn) {
+            let take = match header.encryption_status {
+                EncryptionStatus::Raw => !decrypted,
+                EncryptionStatus::DecryptedPnet => false,
+                EncryptionStatus::DecryptedNoise => decrypted,
+            };
+            if take {
+                writer.data(header.time, header.incoming, &bytes);
+            }
+        }
+
+        Ok(writer.finish())
     }
 
     #[allow(clippy::type_complexity)]
@@ -512,32 +1672,58 @@ impl DbCore {
         Ok(v)
     }
 
+    fn get_at<T, K>(
+        &self,
+        snapshot: &rocksdb::Snapshot,
+        cf: &rocksdb::ColumnFamily,
+        key: K,
+    ) -> Result<T, DbError>
+    where
+        K: AsRef<[u8]>,
+        T: for<'pa> AbsorbExt<'pa>,
+    {
+        let v = snapshot
+            .get_cf(cf, &key)?
+            .ok_or_else(|| DbError::NoItemAtCursor(hex::encode(key.as_ref())))?;
+        let v = T::absorb_ext(&v)?;
+        Ok(v)
+    }
+
+    fn read_opts(snapshot: &rocksdb::Snapshot) -> rocksdb::ReadOptions {
+        let mut opts = rocksdb::ReadOptions::default();
+        opts.set_snapshot(snapshot);
+        opts
+    }
+
     fn search_timestamp<T>(
         &self,
         cf: &rocksdb::ColumnFamily,
         total: u64,
-        timestamp: u64,
+        timestamp: Duration,
     ) -> Result<u64, DbError>
     where
         T: for<'pa> AbsorbExt<'pa> + Timestamp,
     {
-        let timestamp = Duration::from_secs(timestamp);
         if total == 0 {
             return Err(DbError::NoItemAtCursor("".to_string()));
         }
-        let mut pos = total / 2;
-        let mut r = pos;
-        while r > 0 {
-            let v = self.get::<T, _>(cf, pos.to_be_bytes())?;
-
-            r /= 2;
-            match v.timestamp().cmp(&timestamp) {
-                Ordering::Less => pos += r,
-                Ordering::Equal => r = 0,
-                Ordering::Greater => pos -= r,
+        // the first item not earlier than the `timestamp`, `total` is the last id
+        let (mut lo, mut hi) = (0, total + 1);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            // the item might be pruned, it is older than anything retained
+            let earlier = match self.get::<T, _>(cf, mid.to_be_bytes()) {
+                Ok(v) => v.timestamp() < timestamp,
+                Err(DbError::NoItemAtCursor(_)) => true,
+                Err(err) => return Err(err),
+            };
+            if earlier {
+                lo = mid + 1;
+            } else {
+                hi = mid;
             }
         }
-        Ok(pos)
+        Ok(lo)
     }
 
     pub fn total<const K: u8>(&self) -> Result<u64, DbError> {
@@ -547,16 +1733,315 @@ impl DbCore {
         }
     }
 
+    // the total is the last id, so it is `0` for both the first item and an empty database
+    fn ids_given<const K: u8>(&self) -> Result<u64, DbError> {
+        match self.inner.get([K])? {
+            None => Ok(0),
+            Some(b) => Ok(u64::absorb_ext(&b)? + 1),
+        }
+    }
+
     pub fn set_total<const K: u8>(&self, v: u64) -> Result<(), DbError> {
-        Ok(self.inner.put([K], v.chain(vec![]))?)
+        Ok(self.inner.put_opt([K], v.chain(vec![]), &self.write_opts)?)
+    }
+
+    pub fn count_connection_open(&self, incoming: bool) -> Result<(), DbError> {
+        self.counters.total.fetch_add(1, SeqCst);
+        self.counters.active.fetch_add(1, SeqCst);
+        if incoming {
+            let v = self.counters.incoming.fetch_add(1, SeqCst) + 1;
+            self.set_total::<{ Self::INCOMING_CNT }>(v)?;
+        }
+        Ok(())
+    }
+
+    /// The failed dial is stored as a connection, so it is in the total, as on reopen,
+    /// but it is never active.
+    pub fn count_connection_failed(&self) {
+        self.counters.total.fetch_add(1, SeqCst);
+    }
+
+    pub fn count_connection_close(&self) {
+        self.counters.active.fetch_sub(1, SeqCst);
+    }
+
+    /// How many messages are stored, the pruned are not counted.
+    pub fn message_count(&self) -> Result<u64, DbError> {
+        let total = self.ids_given::<{ Self::MESSAGES_CNT }>()?;
+        let pruned = self.total::<{ Self::PRUNED_MESSAGES_CNT }>()?;
+        Ok(total.saturating_sub(pruned))
+    }
+
+    pub fn fetch_connection_count(&self) -> ConnectionCount {
+        let total = self.counters.total.load(SeqCst);
+        let incoming = self.counters.incoming.load(SeqCst);
+        ConnectionCount {
+            total,
+            active: self.counters.active.load(SeqCst),
+            incoming,
+            outgoing: total.saturating_sub(incoming),
+        }
+    }
+
+    fn messages_by_kind(&self) -> MutexGuard<'_, BTreeMap<StreamKind, u64>> {
+        self.counters
+            .messages_by_kind
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+
+    // scans the whole stream kind index, only on open
+    fn count_messages_by_kind(&self) -> BTreeMap<StreamKind, u64> {
+        let mut messages_by_kind = BTreeMap::new();
+        let it = self
+            .inner
+            .iterator_cf(self.stream_kind_index(), rocksdb::IteratorMode::Start)
+            .filter_map(Self::decode_index::<StreamByKindIdx>);
+        for index in it {
+            *messages_by_kind.entry(index.stream_kind).or_default() += 1;
+        }
+        messages_by_kind
+    }
+
+    /// How many messages of each stream kind are in the index.
+    pub fn fetch_messages_by_kind(&self) -> BTreeMap<String, u64> {
+        self.messages_by_kind()
+            .iter()
+            .filter(|(_, count)| **count != 0)
+            .map(|(kind, count)| (kind.to_string(), *count))
+            .collect()
+    }
+
+    /// Counts for comparing the capture with another one, see `CaptureDiff`.
+    pub fn fetch_capture_summary(&self) -> CaptureSummary {
+        let connections = self.fetch_connection_count();
+        let messages_by_kind = self.fetch_messages_by_kind();
+
+        let latencies = self
+            .inner
+            .iterator_cf(self.stats(), rocksdb::IteratorMode::Start)
+            .filter_map(Self::decode::<StatsDbKey, BlockStat>)
+            .flat_map(|(_, stat)| stat.events)
+            .filter_map(|event| event.latency)
+            .collect();
+
+        CaptureSummary {
+            connections: connections.total,
+            incoming_connections: connections.incoming,
+            messages: messages_by_kind.values().sum(),
+            messages_by_kind,
+            block_latency: LatencySummary::new(latencies),
+        }
     }
 
     pub fn fetch_connection(&self, id: u64) -> Result<Connection, DbError> {
         self.get(self.connections(), id.to_be_bytes())
     }
 
-    fn fetch_details(&self, (key, msg): (u64, Message)) -> Option<(u64, FullMessage)> {
-        let r = self.get::<Connection, _>(self.connections(), msg.connection_id.0.to_be_bytes());
+    pub fn fetch_connections_by_stable_id(
+        &self,
+        stable_id: StableConnectionId,
+    ) -> impl Iterator<Item = (u64, serde_json::Value)> + '_ {
+        let key = stable_id.chain(vec![]);
+        let mode = rocksdb::IteratorMode::From(&key, rocksdb::Direction::Forward);
+        let now = SystemTime::now();
+        self.inner
+            .iterator_cf(self.stable_id_index(), mode)
+            .filter_map(Self::decode_index::<StableIdIdx>)
+            .take_while(move |index| index.stable_id == stable_id)
+            .filter_map(
+                move |StableIdIdx { id, .. }| match self.fetch_connection(id.0) {
+                    Ok(cn) => Some((id.0, cn.post_process(Some(now)))),
+                    Err(err) => {
+                        log::error!("{err}");
+                        None
+                    }
+                },
+            )
+    }
+
+    /// Streams having more than `threshold` decode failures, most failing first.
+    pub fn fetch_problematic_streams(
+        &self,
+        threshold: u64,
+        limit: usize,
+    ) -> Vec<ProblematicStream> {
+        let mut streams = BTreeMap::<StreamFullId, (u64, Box<[u8]>)>::new();
+        let it = self
+            .inner
+            .iterator_cf(self.decode_errors(), rocksdb::IteratorMode::Start);
+        for item in it {
+            let (key, value) = match item {
+                Ok(v) => v,
+                Err(err) => {
+                    log::error!("{err}");
+                    continue;
+                }
+            };
+            let index = match DecodeErrorIdx::absorb_ext(&key) {
+                Ok(v) => v,
+                Err(err) => {
+                    log::error!("key is unknown, err: {err}");
+                    continue;
+                }
+            };
+            // the keys are sorted by offset, so the last value is the latest error
+            let entry = streams.entry(index.stream_full_id).or_default();
+            entry.0 += 1;
+            entry.1 = value;
+        }
+
+        let mut streams = streams
+            .into_iter()
+            .filter(|(_, (failures, _))| *failures > threshold)
+            .map(|(id, (failures, last_error))| ProblematicStream {
+                connection_id: id.cn,
+                stream_id: id.id,
+                failures,
+                last_error: String::from_utf8_lossy(&last_error).into_owned(),
+            })
+            .collect::<Vec<_>>();
+        streams.sort_by(|a, b| b.failures.cmp(&a.failures));
+        streams.truncate(limit);
+        streams
+    }
+
+    /// Scans everything recorded, the worst behaving peers go first.
+    pub fn fetch_peer_reputation(&self) -> Vec<PeerReputation> {
+        let mut peers = BTreeMap::<SocketAddr, PeerFactors>::new();
+        let mut addrs = BTreeMap::<ConnectionId, SocketAddr>::new();
+
+        let it = self
+            .inner
+            .iterator_cf(self.connections(), rocksdb::IteratorMode::Start)
+            .filter_map(Self::decode::<u64, Connection>);
+        for (id, cn) in it {
+            addrs.insert(ConnectionId(id), cn.info.addr);
+            let factors = peers.entry(cn.info.addr).or_default();
+            factors.connections += 1;
+            factors.messages += cn.stats_in.messages + cn.stats_out.messages;
+        }
+        let it = self
+            .inner
+            .iterator_cf(self.decode_errors(), rocksdb::IteratorMode::Start)
+            .filter_map(Self::decode_index::<DecodeErrorIdx>);
+        for index in it {
+            if let Some(factors) = addrs
+                .get(&index.stream_full_id.cn)
+                .and_then(|addr| peers.get_mut(addr))
+            {
+                factors.decode_failures += 1;
+            }
+        }
+
+        // resets sent by the peer
+        for stream_kind in [StreamKind::Mplex, StreamKind::Yamux] {
+            let key = StreamByKindIdx {
+                stream_kind,
+                id: MessageId(0),
+            };
+            let key = key.chain(vec![]);
+            let mode = rocksdb::IteratorMode::From(&key, rocksdb::Direction::Forward);
+            let it = self
+                .inner
+                .iterator_cf(self.stream_kind_index(), mode)
+                .filter_map(Self::decode_index::<StreamByKindIdx>)
+                .take_while(|index| index.stream_kind == stream_kind);
+            for StreamByKindIdx { id, .. } in it {
+                let msg = match self.get::<Message, _>(self.messages(), id.0.to_be_bytes()) {
+                    Ok(v) => v,
+                    Err(err) => {
+                        log::error!("message {}: {err}", id.0);
+                        continue;
+                    }
+                };
+                if !msg.incoming {
+                    continue;
+                }
+                let reset =
+                    self.with_blob(msg.connection_id, msg.offset, |buf| match stream_kind {
+                        StreamKind::Mplex => <[u8; 8]>::try_from(buf)
+                            .map(|v| matches!(u64::from_be_bytes(v) & 7, 5 | 6))
+                            .unwrap_or(false),
+                        _ => <[u8; 12]>::try_from(buf)
+                            .ok()
+                            .and_then(|v| Header::try_from(v).ok())
+                            .map(|header| header.flags.contains(HeaderFlags::RST))
+                            .unwrap_or(false),
+                    });
+                if let (Ok(true), Some(factors)) = (
+                    reset,
+                    addrs
+                        .get(&msg.connection_id)
+                        .and_then(|addr| peers.get_mut(addr)),
+                ) {
+                    factors.stream_resets += 1;
+                }
+            }
+        }
+
+        // the first incoming copy of a gossip is not a duplicate
+        let it = self
+            .inner
+            .iterator_cf(self.gossip_hash_index(), rocksdb::IteratorMode::Start)
+            .filter_map(Self::decode_index::<GossipHashIdx>);
+        let mut last_hash = None;
+        for GossipHashIdx { hash, id } in it {
+            let msg = match self.get::<Message, _>(self.messages(), id.0.to_be_bytes()) {
+                Ok(v) => v,
+                Err(err) => {
+                    log::error!("message {}: {err}", id.0);
+                    continue;
+                }
+            };
+            if !msg.incoming {
+                continue;
+            }
+            let duplicate = last_hash == Some(hash);
+            last_hash = Some(hash);
+            if let Some(factors) = addrs
+                .get(&msg.connection_id)
+                .and_then(|addr| peers.get_mut(addr))
+            {
+                factors.gossip_received += 1;
+                if duplicate {
+                    factors.gossip_duplicates += 1;
+                }
+            }
+        }
+
+        let it = self
+            .inner
+            .iterator_cf(self.ping_rtt(), rocksdb::IteratorMode::Start)
+            .filter_map(Self::decode::<PingRttKey, PingRtt>);
+        for (key, v) in it {
+            if let Some(factors) = addrs.get(&key.cn).and_then(|addr| peers.get_mut(addr)) {
+                factors.observe_rtt(v.rtt);
+            }
+        }
+
+        let it = self
+            .inner
+            .iterator_cf(self.prunes(), rocksdb::IteratorMode::Start)
+            .filter_map(Result::ok)
+            .filter_map(|(_, value)| PruneStats::absorb_ext(&value).ok());
+        for stats in it {
+            peers.entry(stats.peer).or_default().prunes += stats.prunes;
+        }
+
+        reputation::rank(peers)
+    }
+
+    fn fetch_details(
+        &self,
+        snapshot: &rocksdb::Snapshot,
+        (key, msg): (u64, Message),
+    ) -> Option<(u64, FullMessage)> {
+        let r = self.get_at::<Connection, _>(
+            snapshot,
+            self.connections(),
+            msg.connection_id.0.to_be_bytes(),
+        );
         let connection = match r {
             Ok(v) => v,
             Err(err) => {
@@ -581,63 +2066,94 @@ impl DbCore {
     }
 
     // TODO: preview is useless
-    fn fetch_details_inner(&self, msg: Message, preview: bool) -> Result<FullMessage, DbError> {
-        let connection =
-            self.get::<Connection, _>(self.connections(), msg.connection_id.0.to_be_bytes())?;
-        let buf = self.fetch_blob(msg.connection_id, msg.offset)?;
-        let message = match msg.stream_kind {
-            StreamKind::Kad => crate::decode::kademlia::parse(buf, preview)?,
-            StreamKind::Meshsub => crate::decode::meshsub::parse(buf, preview)?,
-            StreamKind::Handshake => crate::decode::noise::parse(buf, preview)?,
-            StreamKind::Rpc => crate::decode::rpc::parse(buf, preview)?,
-            StreamKind::IpfsId => crate::decode::identify::parse(buf, preview, msg.stream_kind)?,
-            StreamKind::IpfsPush => crate::decode::identify::parse(buf, preview, msg.stream_kind)?,
-            // TODO: proper decode
-            StreamKind::IpfsDelta => serde_json::Value::String(hex::encode(&buf)),
-            StreamKind::PeerExchange => crate::decode::json_string::parse(buf, preview)?,
-            // TODO: proper decode
-            StreamKind::BitswapExchange => serde_json::Value::String(hex::encode(&buf)),
-            // TODO: proper decode
-            StreamKind::NodeStatus => serde_json::Value::String(hex::encode(&buf)),
-            StreamKind::Select => {
-                let s = String::from_utf8(buf)
-                    .map_err(|err| DbError::Decode(DecodeError::Utf8(err)))?;
-                serde_json::Value::String(s)
-            }
-            StreamKind::Mplex => {
-                let v = buf.as_slice().try_into().map_err(|_| {
-                    DbError::Decode(DecodeError::UnexpectedSize {
-                        actual: buf.len(),
-                        expected: 8,
-                    })
-                })?;
-                let v = u64::from_be_bytes(v);
-                let stream = v >> 3;
-                let header = v & 7;
-                let action = match header {
-                    0 => "create stream",
-                    3 => "close receiver",
-                    4 => "close initiator",
-                    5 => "reset receiver",
-                    6 => "reset initiator",
-                    1 | 2 | 7 => panic!("unexpected header {header}"),
-                    _ => unreachable!(),
-                };
-
-                #[derive(Serialize)]
-                struct MplexMessage {
-                    action: &'static str,
-                    stream: u64,
+    fn fetch_details_inner(
+        &self,
+        snapshot: &rocksdb::Snapshot,
+        id: u64,
+        msg: Message,
+        preview: bool,
+    ) -> Result<FullMessage, DbError> {
+        let connection = self.get_at::<Connection, _>(
+            snapshot,
+            self.connections(),
+            msg.connection_id.0.to_be_bytes(),
+        )?;
+        let message = self.with_blob_at(snapshot, msg.connection_id, msg.offset, |buf| {
+            Ok::<_, DbError>(match msg.stream_kind {
+                kind if kind.decode_disabled() => serde_json::Value::String(hex::encode(buf)),
+                StreamKind::Kad => crate::decode::kademlia::parse(buf, preview)?,
+                StreamKind::Meshsub => {
+                    let topics = self.fetch_topic_registry(snapshot, msg.connection_id);
+                    crate::decode::meshsub::parse(buf, preview, &topics)?
                 }
+                StreamKind::Handshake => crate::decode::noise::parse(buf, preview)?,
+                StreamKind::Rpc => crate::decode::rpc::parse(buf, preview)?,
+                StreamKind::IpfsId => {
+                    crate::decode::identify::parse(buf, preview, msg.stream_kind)?
+                }
+                StreamKind::IpfsPush => {
+                    crate::decode::identify::parse(buf, preview, msg.stream_kind)?
+                }
+                StreamKind::IpfsDelta => crate::decode::identify::parse_delta(buf, preview)?,
+                StreamKind::Ping => {
+                    let rtt = self.fetch_ping_rtt(snapshot, msg.connection_id, MessageId(id))?;
+                    crate::decode::ping::parse(buf, preview, rtt.map(|v| v.rtt))?
+                }
+                StreamKind::RelayHop | StreamKind::RelayStop => {
+                    crate::decode::relay::parse(buf, preview, msg.stream_kind)?
+                }
+                StreamKind::PeerExchange => crate::decode::json_string::parse(buf, preview)?,
+                StreamKind::BitswapExchange => crate::decode::bitswap::parse(buf, preview)?,
+                StreamKind::NodeStatus => crate::decode::node_status::parse(buf, preview)?,
+                StreamKind::Select => {
+                    let stream = if preview {
+                        vec![]
+                    } else {
+                        self.fetch_select_tokens(
+                            snapshot,
+                            StreamFullId {
+                                cn: msg.connection_id,
+                                id: msg.stream_id,
+                            },
+                        )
+                    };
+                    crate::decode::multistream_select::parse(buf, preview, stream)?
+                }
+                StreamKind::Mplex => {
+                    let v = buf.try_into().map_err(|_| {
+                        DbError::Decode(DecodeError::UnexpectedSize {
+                            actual: buf.len(),
+                            expected: 8,
+                        })
+                    })?;
+                    let v = u64::from_be_bytes(v);
+                    let stream = v >> 3;
+                    let header = v & 7;
+                    let action = match header {
+                        0 => "create stream",
+                        3 => "close receiver",
+                        4 => "close initiator",
+                        5 => "reset receiver",
+                        6 => "reset initiator",
+                        1 | 2 | 7 => panic!("unexpected header {header}"),
+                        _ => unreachable!(),
+                    };
 
-                let msg = MplexMessage { action, stream };
+                    #[derive(Serialize)]
+                    struct MplexMessage {
+                        action: &'static str,
+                        stream: u64,
+                    }
 
-                serde_json::to_value(&msg)
-                    .map_err(|err| DbError::Decode(DecodeError::Serde(err)))?
-            }
-            StreamKind::Yamux => crate::decode::yamux::parse(buf, preview)?,
-            StreamKind::Unknown => serde_json::Value::String(hex::encode(&buf)),
-        };
+                    let msg = MplexMessage { action, stream };
+
+                    serde_json::to_value(&msg)
+                        .map_err(|err| DbError::Decode(DecodeError::Serde(err)))?
+                }
+                StreamKind::Yamux => crate::decode::yamux::parse(buf, preview)?,
+                StreamKind::Unknown => serde_json::Value::String(hex::encode(buf)),
+            })
+        })??;
         Ok(FullMessage {
             connection_id: msg.connection_id,
             remote_addr: connection.info.addr,
@@ -658,7 +2174,7 @@ impl DbCore {
                 match self.search_timestamp::<Connection>(self.connections(), total, timestamp) {
                     Ok(c) => (true, c),
                     Err(err) => {
-                        log::error!("cannot find timestamp {timestamp}, err: {err}");
+                        log::error!("cannot find timestamp {timestamp:?}, err: {err}");
                         (false, 0)
                     }
                 }
@@ -674,7 +2190,7 @@ impl DbCore {
                 match self.search_timestamp::<Message>(self.messages(), total, timestamp) {
                     Ok(c) => (true, c),
                     Err(err) => {
-                        log::error!("cannot find timestamp {timestamp}, err: {err}");
+                        log::error!("cannot find timestamp {timestamp:?}, err: {err}");
                         (false, 0)
                     }
                 }
@@ -684,25 +2200,30 @@ impl DbCore {
 
     fn fetch_messages_by_indexes<'a, It>(
         &'a self,
+        snapshot: Arc<rocksdb::Snapshot<'a>>,
         it: It,
     ) -> Box<dyn Iterator<Item = (u64, Message)> + 'a>
     where
         It: Iterator<Item = MessageId> + 'a,
     {
-        let it = it.filter_map(|id| match self.get(self.messages(), id.0.to_be_bytes()) {
-            Ok(v) => Some((id.0, v)),
-            Err(err) => {
-                log::error!("{err}");
-                None
+        let it = it.filter_map(move |id| {
+            match self.get_at(&snapshot, self.messages(), id.0.to_be_bytes()) {
+                Ok(v) => Some((id.0, v)),
+                Err(err) => {
+                    log::error!("{err}");
+                    None
+                }
             }
         });
         Box::new(it) as Box<dyn Iterator<Item = (u64, Message)>>
     }
 
+    /// Same as `fetch_messages`, reads through a snapshot.
     pub fn fetch_connections(
         &self,
         params: &ValidParamsConnection,
     ) -> impl Iterator<Item = (u64, serde_json::Value)> + '_ {
+        let snapshot = self.inner.snapshot();
         let (present, id) = self.connection_id(params);
 
         let coordinate = &params.coordinate;
@@ -715,24 +2236,145 @@ impl DbCore {
             direction.into()
         };
 
+        let it = SnapshotIter {
+            inner: self
+                .inner
+                .iterator_cf_opt(self.connections(), Self::read_opts(&snapshot), mode),
+            _snapshot: snapshot,
+        }
+        .filter_map(Self::decode);
+        let it = Box::new(it) as Box<dyn Iterator<Item = (u64, Connection)>>;
+        let now = SystemTime::now();
+        params.limit(it.filter_map(move |(id, cn)| {
+            if cn.stats_in.total_bytes == 0 && cn.stats_out.total_bytes == 0 {
+                return None;
+            }
+            Some((id, cn.post_process(Some(now))))
+        }))
+    }
+
+    /// Number of connections and distinct peers per remote subnet, the most populated first.
+    pub fn fetch_subnet_stats(&self) -> Vec<SubnetStats> {
+        let mut subnets = BTreeMap::<String, (u64, BTreeSet<IpAddr>)>::new();
+        let it = self
+            .inner
+            .iterator_cf(self.connections(), rocksdb::IteratorMode::Start)
+            .filter_map(Self::decode::<u64, Connection>);
+        for (_, cn) in it {
+            let ip = cn.info.addr.ip();
+            let entry = subnets.entry(SubnetStats::subnet_of(ip)).or_default();
+            entry.0 += 1;
+            entry.1.insert(ip);
+        }
+
+        let mut subnets = subnets
+            .into_iter()
+            .map(|(subnet, (connections, peers))| SubnetStats {
+                subnet,
+                connections,
+                peers: peers.len() as u64,
+            })
+            .collect::<Vec<_>>();
+        subnets.sort_by(|a, b| {
+            b.connections
+                .cmp(&a.connections)
+                .then(b.peers.cmp(&a.peers))
+        });
+        subnets
+    }
+
+    pub fn fetch_decrypt_stats(&self) -> DecryptStats {
+        let it = self
+            .inner
+            .iterator_cf(self.connections(), rocksdb::IteratorMode::Start)
+            .filter_map(Self::decode::<u64, Connection>)
+            .map(|(_, cn)| cn);
+        let mut stats = DecryptStats::new(it);
+        self.count_failed_to_decrypt(&mut stats);
+        stats
+    }
+
+    // only the messages, the byte counters are kept by the recorder
+    fn count_failed_to_decrypt(&self, stats: &mut DecryptStats) {
+        let key = MessageKindIdx {
+            ty: MessageType::FailedToDecrypt,
+            id: MessageId(0),
+        }
+        .chain(vec![]);
+        let mode = rocksdb::IteratorMode::From(&key, rocksdb::Direction::Forward);
+        let mut connections = BTreeSet::new();
+        let it = self
+            .inner
+            .iterator_cf(self.message_kind_index(), mode)
+            .filter_map(Self::decode_index::<MessageKindIdx>)
+            .take_while(|index| index.ty == MessageType::FailedToDecrypt);
+        for MessageKindIdx { id, .. } in it {
+            stats.failed_messages += 1;
+            // the message might be pruned
+            if let Ok(msg) = self.get::<Message, _>(self.messages(), id.0.to_be_bytes()) {
+                connections.insert(msg.connection_id);
+            }
+        }
+        stats.failed_connections = connections.len() as u64;
+    }
+
+    /// Within a stream the message ids must grow together with timestamps,
+    /// the ring buffer may reorder events, so allow going back by `tolerance`.
+    pub fn check_stream_order(&self, tolerance: Duration) -> StreamOrderReport {
+        let mut report = StreamOrderReport::default();
+        // stream, id and timestamp of the latest message
+        let mut last = None::<(StreamFullId, u64, SystemTime)>;
         let it = self
             .inner
-            .iterator_cf(self.connections(), mode)
-            .filter_map(Self::decode);
-        let it = Box::new(it) as Box<dyn Iterator<Item = (u64, Connection)>>;
-        let now = SystemTime::now();
-        params.limit(it.filter_map(move |(id, cn)| {
-            if cn.stats_in.total_bytes == 0 && cn.stats_out.total_bytes == 0 {
-                return None;
+            .iterator_cf(self.stream_id_index(), rocksdb::IteratorMode::Start)
+            .filter_map(Self::decode_index::<StreamIdx>);
+        for StreamIdx { stream_full_id, id } in it {
+            let msg = match self.get::<Message, _>(self.messages(), id.0.to_be_bytes()) {
+                Ok(v) => v,
+                Err(err) => {
+                    log::error!("{stream_full_id}, message {id}: {err}");
+                    continue;
+                }
+            };
+            report.messages += 1;
+            match &mut last {
+                Some((stream, previous_id, previous_timestamp)) if *stream == stream_full_id => {
+                    if msg.timestamp >= *previous_timestamp {
+                        *previous_id = id.0;
+                        *previous_timestamp = msg.timestamp;
+                    } else {
+                        let backwards = previous_timestamp
+                            .duration_since(msg.timestamp)
+                            .unwrap_or_default();
+                        if backwards > tolerance {
+                            report.violations.push(StreamOrderViolation {
+                                stream: stream_full_id,
+                                message_id: id.0,
+                                previous_id: *previous_id,
+                                timestamp: msg.timestamp,
+                                previous_timestamp: *previous_timestamp,
+                                backwards_ms: backwards.as_millis() as u64,
+                            });
+                        }
+                    }
+                }
+                _ => {
+                    report.streams += 1;
+                    last = Some((stream_full_id, id.0, msg.timestamp));
+                }
             }
-            Some((id, cn.post_process(Some(now))))
-        }))
+        }
+
+        report
     }
 
+    /// Reads through a snapshot taken at the start, a page is consistent
+    /// even if the recorder writes while the iterator is consumed.
     pub fn fetch_messages(
         &self,
         params: &ValidParams,
     ) -> impl Iterator<Item = (u64, FullMessage)> + '_ {
+        let snapshot = Arc::new(self.inner.snapshot());
         let (present, id) = self.message_id(params);
 
         let coordinate = &params.coordinate;
@@ -752,7 +2394,7 @@ impl DbCore {
 
                     let it = self
                         .inner
-                        .iterator_cf(self.addr_index(), mode)
+                        .iterator_cf_opt(self.addr_index(), Self::read_opts(&snapshot), mode)
                         .filter_map(Self::decode_index::<AddressIdx>)
                         .take_while(move |index| index.addr == addr)
                         .map(|AddressIdx { id, .. }| id);
@@ -769,7 +2411,11 @@ impl DbCore {
 
                     let it = self
                         .inner
-                        .iterator_cf(self.connection_id_index(), mode)
+                        .iterator_cf_opt(
+                            self.connection_id_index(),
+                            Self::read_opts(&snapshot),
+                            mode,
+                        )
                         .filter_map(Self::decode_index::<ConnectionIdx>)
                         .take_while(move |index| index.connection_id == connection_id)
                         .map(|ConnectionIdx { id, .. }| id);
@@ -786,12 +2432,54 @@ impl DbCore {
 
                     let it = self
                         .inner
-                        .iterator_cf(self.stream_id_index(), mode)
+                        .iterator_cf_opt(self.stream_id_index(), Self::read_opts(&snapshot), mode)
                         .filter_map(Self::decode_index::<StreamIdx>)
                         .take_while(move |index| index.stream_full_id == stream_full_id)
                         .map(|StreamIdx { id, .. }| id);
                     Some(Box::new(it) as Box<dyn Iterator<Item = MessageId>>)
                 }
+                Some(StreamFilter::AnyStreamByPeerId(peer_id)) => {
+                    let peer_id = PeerIdIdx::hash(peer_id);
+                    let key = PeerIdIdx {
+                        peer_id,
+                        id: ConnectionId(0),
+                    };
+                    let key = key.chain(vec![]);
+                    let mode = rocksdb::IteratorMode::From(&key, rocksdb::Direction::Forward);
+                    let connections = self
+                        .inner
+                        .iterator_cf_opt(self.peer_id_index(), Self::read_opts(&snapshot), mode)
+                        .filter_map(Self::decode_index::<PeerIdIdx>)
+                        .take_while(|index| index.peer_id == peer_id)
+                        .map(|PeerIdIdx { id, .. }| id)
+                        .collect::<Vec<_>>();
+
+                    // messages of every connection with the peer
+                    let its = connections.into_iter().map(|connection_id| {
+                        let id = ConnectionIdx {
+                            connection_id,
+                            id: MessageId(id),
+                        };
+                        let id = id.chain(vec![]);
+                        let mode = rocksdb::IteratorMode::From(&id, direction.into());
+
+                        self.inner
+                            .iterator_cf_opt(
+                                self.connection_id_index(),
+                                Self::read_opts(&snapshot),
+                                mode,
+                            )
+                            .filter_map(Self::decode_index::<ConnectionIdx>)
+                            .take_while(move |index| index.connection_id == connection_id)
+                            .map(|ConnectionIdx { id, .. }| id)
+                    });
+
+                    let reverse = matches!(direction, Direction::Reverse);
+                    let predicate = move |a: &MessageId, b: &MessageId| (*a < *b) ^ reverse;
+                    let it = itertools::kmerge_by(its, predicate);
+
+                    Some(Box::new(it) as Box<dyn Iterator<Item = MessageId>>)
+                }
                 None => None,
             };
             let kind_indexes = match &params.kind_filter {
@@ -806,7 +2494,11 @@ impl DbCore {
                         let mode = rocksdb::IteratorMode::From(&id, direction.into());
 
                         self.inner
-                            .iterator_cf(self.stream_kind_index(), mode)
+                            .iterator_cf_opt(
+                                self.stream_kind_index(),
+                                Self::read_opts(&snapshot),
+                                mode,
+                            )
                             .filter_map(Self::decode_index::<StreamByKindIdx>)
                             .take_while(move |index| index.stream_kind == stream_kind)
                             .map(|StreamByKindIdx { id, .. }| id)
@@ -829,7 +2521,11 @@ impl DbCore {
 
                         let message_kind = message_kind.clone();
                         self.inner
-                            .iterator_cf(self.message_kind_index(), mode)
+                            .iterator_cf_opt(
+                                self.message_kind_index(),
+                                Self::read_opts(&snapshot),
+                                mode,
+                            )
                             .filter_map(Self::decode_index::<MessageKindIdx>)
                             .take_while(move |index| index.ty == message_kind.clone())
                             .map(|MessageKindIdx { id, .. }| id)
@@ -847,10 +2543,10 @@ impl DbCore {
                 (Some(a), Some(b)) => {
                     let forward = matches!(&direction, &Direction::Forward);
                     let it = sorted_intersect(&mut [a, b], coordinate.limit, forward).into_iter();
-                    self.fetch_messages_by_indexes(it)
+                    self.fetch_messages_by_indexes(snapshot.clone(), it)
                 }
-                (Some(i), None) => self.fetch_messages_by_indexes(i),
-                (None, Some(i)) => self.fetch_messages_by_indexes(i),
+                (Some(i), None) => self.fetch_messages_by_indexes(snapshot.clone(), i),
+                (None, Some(i)) => self.fetch_messages_by_indexes(snapshot.clone(), i),
                 (None, None) => unreachable!(),
             }
         } else {
@@ -863,27 +2559,209 @@ impl DbCore {
 
             let it = self
                 .inner
-                .iterator_cf(self.messages(), mode)
+                .iterator_cf_opt(self.messages(), Self::read_opts(&snapshot), mode)
                 .filter_map(Self::decode);
             Box::new(it) as Box<dyn Iterator<Item = (u64, Message)>>
         };
-        params.limit(it.filter_map(|v| self.fetch_details(v)))
+        params.limit(it.filter_map(move |v| self.fetch_details(&snapshot, v)))
+    }
+
+    // the negotiation is at the start of the stream
+    fn fetch_select_tokens(
+        &self,
+        snapshot: &rocksdb::Snapshot,
+        stream_full_id: StreamFullId,
+    ) -> Vec<(bool, String)> {
+        let key = StreamIdx {
+            stream_full_id,
+            id: MessageId(0),
+        };
+        let key = key.chain(vec![]);
+        let mode = rocksdb::IteratorMode::From(&key, rocksdb::Direction::Forward);
+        snapshot
+            .iterator_cf(self.stream_id_index(), mode)
+            .filter_map(Self::decode_index::<StreamIdx>)
+            .take_while(|index| index.stream_full_id == stream_full_id)
+            .take(32)
+            .filter_map(|StreamIdx { id, .. }| {
+                let msg = self
+                    .get_at::<Message, _>(snapshot, self.messages(), id.0.to_be_bytes())
+                    .ok()?;
+                if msg.stream_kind != StreamKind::Select {
+                    return None;
+                }
+                let token = self
+                    .with_blob_at(snapshot, msg.connection_id, msg.offset, |b| {
+                        String::from_utf8_lossy(b).into_owned()
+                    })
+                    .ok()?;
+                Some((msg.incoming, token))
+            })
+            .collect()
     }
 
     pub fn fetch_full_message(&self, id: u64) -> Result<FullMessage, DbError> {
-        let msg = self.get::<Message, _>(self.messages(), id.to_be_bytes())?;
-        self.fetch_details_inner(msg, false)
+        self.fetch_full_message_at(&self.inner.snapshot(), id)
+    }
+
+    fn fetch_full_message_at(
+        &self,
+        snapshot: &rocksdb::Snapshot,
+        id: u64,
+    ) -> Result<FullMessage, DbError> {
+        let msg = self.get_at::<Message, _>(snapshot, self.messages(), id.to_be_bytes())?;
+        self.fetch_details_inner(snapshot, id, msg, false)
+    }
+
+    /// Messages of the single stream in order, each one fully decoded.
+    pub fn fetch_stream_frames(
+        &self,
+        stream_full_id: StreamFullId,
+        limit: usize,
+    ) -> Vec<(u64, FullMessage)> {
+        let key = StreamIdx {
+            stream_full_id,
+            id: MessageId(0),
+        };
+        let key = key.chain(vec![]);
+        let mode = rocksdb::IteratorMode::From(&key, rocksdb::Direction::Forward);
+        let snapshot = self.inner.snapshot();
+        snapshot
+            .iterator_cf(self.stream_id_index(), mode)
+            .filter_map(Self::decode_index::<StreamIdx>)
+            .take_while(|index| index.stream_full_id == stream_full_id)
+            .take(limit)
+            .map(|StreamIdx { id, .. }| id.0)
+            .filter_map(|id| match self.fetch_full_message_at(&snapshot, id) {
+                Ok(msg) => Some((id, msg)),
+                Err(err) => {
+                    log::error!("{stream_full_id}, message {id}: {err}");
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Every connection the gossip message traversed, in time order.
+    pub fn fetch_gossip_path(&self, hash: Hash) -> Vec<GossipHop> {
+        let key = GossipHashIdx {
+            hash,
+            id: MessageId(0),
+        };
+        let key = key.chain(vec![]);
+        let mode = rocksdb::IteratorMode::From(&key, rocksdb::Direction::Forward);
+        let mut addrs = BTreeMap::new();
+        let mut path = self
+            .inner
+            .iterator_cf(self.gossip_hash_index(), mode)
+            .filter_map(Self::decode_index::<GossipHashIdx>)
+            .take_while(|index| index.hash == hash)
+            .filter_map(|GossipHashIdx { id, .. }| {
+                let msg = match self.get::<Message, _>(self.messages(), id.0.to_be_bytes()) {
+                    Ok(v) => v,
+                    Err(err) => {
+                        log::error!("message {}: {err}", id.0);
+                        return None;
+                    }
+                };
+                let remote_addr = match addrs.get(&msg.connection_id) {
+                    Some(addr) => *addr,
+                    None => {
+                        let addr = self.fetch_connection(msg.connection_id.0).ok()?.info.addr;
+                        addrs.insert(msg.connection_id, addr);
+                        addr
+                    }
+                };
+                Some(GossipHop {
+                    message_id: id,
+                    connection_id: msg.connection_id,
+                    remote_addr,
+                    incoming: msg.incoming,
+                    timestamp: msg.timestamp,
+                })
+            })
+            .collect::<Vec<_>>();
+        path.sort_by_key(|hop| hop.timestamp);
+        path
     }
 
     pub fn fetch_full_message_bin(&self, id: u64) -> Result<Vec<u8>, DbError> {
         let msg = self.get::<Message, _>(self.messages(), id.to_be_bytes())?;
 
-        self.fetch_blob(msg.connection_id, msg.offset)
+        // the reply owns its body, it is the only copy
+        self.with_blob(msg.connection_id, msg.offset, <[u8]>::to_vec)
     }
 
     pub fn fetch_full_message_hex(&self, id: u64) -> Result<String, DbError> {
-        let buf = self.fetch_full_message_bin(id)?;
-        Ok(hex::encode(&buf))
+        let msg = self.get::<Message, _>(self.messages(), id.to_be_bytes())?;
+
+        self.with_blob(msg.connection_id, msg.offset, |data| hex::encode(data))
+    }
+
+    pub fn fetch_ping_rtt(
+        &self,
+        snapshot: &rocksdb::Snapshot,
+        cn: ConnectionId,
+        id: MessageId,
+    ) -> Result<Option<PingRtt>, DbError> {
+        let key = PingRttKey { cn, message_id: id };
+        match snapshot.get_cf(self.ping_rtt(), key.chain(vec![]))? {
+            None => Ok(None),
+            Some(v) => Ok(Some(AbsorbExt::absorb_ext(&v)?)),
+        }
+    }
+
+    pub fn fetch_connection_ping_rtt(&self, id: u64) -> impl Iterator<Item = PingRtt> + '_ {
+        let cn = ConnectionId(id);
+        let key = cn.chain(vec![]);
+        let mode = rocksdb::IteratorMode::From(&key, rocksdb::Direction::Forward);
+        self.inner
+            .iterator_cf(self.ping_rtt(), mode)
+            .filter_map(Self::decode::<PingRttKey, PingRtt>)
+            .take_while(move |(key, _)| key.cn == cn)
+            .map(|(_, v)| v)
+    }
+
+    /// The latest flow control snapshot of every yamux stream in the connection.
+    pub fn fetch_window_stats(&self, id: u64) -> impl Iterator<Item = WindowStats> + '_ {
+        let cn = ConnectionId(id);
+        let key = cn.chain(vec![]);
+        let mode = rocksdb::IteratorMode::From(&key, rocksdb::Direction::Forward);
+        self.inner
+            .iterator_cf(self.yamux_windows(), mode)
+            .filter_map(Self::decode::<WindowStatsKey, WindowStats>)
+            .take_while(move |(key, _)| key.cn == cn)
+            .map(|(_, v)| v)
+    }
+
+    /// Bytes transferred in the connection, grouped in buckets of `bucket` seconds.
+    pub fn fetch_connection_bandwidth(&self, id: u64, bucket: u64) -> Vec<BandwidthBucket> {
+        let cn = ConnectionId(id);
+        let bucket = bucket.max(1);
+        let key = cn.chain(vec![]);
+        let mode = rocksdb::IteratorMode::From(&key, rocksdb::Direction::Forward);
+        let it = self
+            .inner
+            .iterator_cf(self.connection_bandwidth(), mode)
+            .filter_map(Self::decode::<BandwidthKey, Bandwidth>)
+            .take_while(move |(key, _)| key.cn == cn);
+
+        let mut buckets = Vec::<BandwidthBucket>::new();
+        for (BandwidthKey { secs, .. }, v) in it {
+            let timestamp = secs - secs % bucket;
+            match buckets.last_mut() {
+                Some(last) if last.timestamp == timestamp => {
+                    last.bytes_in += v.bytes_in;
+                    last.bytes_out += v.bytes_out;
+                }
+                _ => buckets.push(BandwidthBucket {
+                    timestamp,
+                    bytes_in: v.bytes_in,
+                    bytes_out: v.bytes_out,
+                }),
+            }
+        }
+        buckets
     }
 
     pub fn fetch_strace(
@@ -897,6 +2775,7 @@ impl DbCore {
             id
         } else {
             let total = self.total::<{ Self::STRACE_CNT }>().unwrap_or(0);
+            let timestamp = Duration::from_secs(timestamp);
             self.search_timestamp::<StraceLine>(self.strace(), total, timestamp)?
         };
 
@@ -958,6 +2837,78 @@ impl DbCore {
             .collect()
     }
 
+    pub fn fetch_block_producers(&self, height: u32) -> Vec<ProducerSummary> {
+        let mut producers = BTreeMap::<_, BTreeSet<_>>::new();
+        for event in self.fetch_stats_block_v2(height) {
+            producers
+                .entry(event.producer_id.to_string())
+                .or_default()
+                .insert(event.hash);
+        }
+        producers
+            .into_iter()
+            .map(|(producer_id, hashes)| ProducerSummary {
+                producer_id,
+                blocks: hashes.len(),
+                hashes: hashes.into_iter().collect(),
+            })
+            .collect()
+    }
+
+    pub fn fetch_block_propagation(&self, height: u32) -> BlockPropagation {
+        // the earliest incoming message of each block from each peer
+        let mut arrivals =
+            BTreeMap::<Hash, (String, BTreeMap<SocketAddr, (SystemTime, u64)>)>::new();
+        for event in self.fetch_stats_block_v2(height) {
+            if !event.incoming {
+                continue;
+            }
+            let (_, peers) = arrivals
+                .entry(event.hash)
+                .or_insert_with(|| (event.producer_id.to_string(), BTreeMap::new()));
+            let arrival = peers
+                .entry(event.sender_addr)
+                .or_insert((event.time, event.message_id));
+            if event.time < arrival.0 {
+                *arrival = (event.time, event.message_id);
+            }
+        }
+
+        let mut blocks = arrivals
+            .into_iter()
+            .filter_map(|(hash, (producer_id, peers))| {
+                let first_seen = peers.values().map(|(time, _)| *time).min()?;
+                let mut peers = peers
+                    .into_iter()
+                    .map(|(peer, (time, message_id))| {
+                        let latency = time.duration_since(first_seen).unwrap_or_default();
+                        (latency, peer, message_id)
+                    })
+                    .collect::<Vec<_>>();
+                peers.sort();
+                let latency = LatencySummary::new(peers.iter().map(|(l, _, _)| *l).collect());
+                Some(BlockArrivals {
+                    hash,
+                    producer_id,
+                    first_seen,
+                    first_peer: peers[0].1,
+                    latency,
+                    peers: peers
+                        .into_iter()
+                        .map(|(latency, peer, message_id)| PeerArrival {
+                            peer,
+                            message_id,
+                            latency: latency.as_secs_f64(),
+                        })
+                        .collect(),
+                })
+            })
+            .collect::<Vec<_>>();
+        blocks.sort_by_key(|block| block.first_seen);
+
+        BlockPropagation { height, blocks }
+    }
+
     pub fn fetch_last_stat_tx(&self) -> Option<(u32, TxStat)> {
         use rocksdb::IteratorMode;
 
@@ -967,6 +2918,31 @@ impl DbCore {
             .and_then(Self::decode)
     }
 
+    /// Percentiles over the heights `from..=to`, a height without transactions adds nothing.
+    pub fn fetch_tx_latency_summary(&self, from: u32, to: u32) -> TxLatencySummary {
+        let from_bytes = from.to_be_bytes();
+        let mode = rocksdb::IteratorMode::From(&from_bytes, rocksdb::Direction::Forward);
+        let mut heights = 0;
+        let (mut transactions, mut snarks) = (vec![], vec![]);
+        let it = self
+            .inner
+            .iterator_cf(self.stats_tx(), mode)
+            .filter_map(Self::decode::<u32, TxStat>)
+            .take_while(|(height, _)| *height <= to);
+        for (_, stat) in it {
+            heights += 1;
+            transactions.extend(stat.transactions.iter().map(|tx| tx.latency));
+            snarks.extend(stat.snarks.iter().map(|snark| snark.latency));
+        }
+        TxLatencySummary {
+            from,
+            to,
+            heights,
+            transactions: LatencySummary::new(transactions),
+            snarks: LatencySummary::new(snarks),
+        }
+    }
+
     pub fn fetch_stats_tx(&self, id: u32) -> Result<Option<(u32, TxStat)>, DbError> {
         match self.inner.get_cf(self.stats_tx(), id.to_be_bytes())? {
             None => Ok(None),
@@ -987,8 +2963,10 @@ impl DbCore {
                 .filter_map(Self::decode_index::<LedgerHashIdx>)
                 .take_while(|idx| idx.get_31().eq(&key_b[1..32]));
             for id in indexes {
-                let buf = self.fetch_blob(id.id.cn, id.offset)?;
-                for event in crate::decode::meshsub::parse_it(&buf, false, true)? {
+                let events = self.with_blob(id.id.cn, id.offset, |buf| {
+                    crate::decode::meshsub::parse_it(buf, false, true)
+                })??;
+                for event in events {
                     if let Event::PublishV2 { message, hash, .. } = event {
                         use self::SnarkWithHash::*;
                         match &*message {
@@ -1114,6 +3092,44 @@ impl DbCore {
             })
     }
 
+    pub fn fetch_gossip_correlation(&self, height: u32) -> Vec<GossipCorrelation> {
+        fn earliest(acc: &mut Option<u64>, time: u64) {
+            *acc = Some(acc.map_or(time, |t| t.min(time)));
+        }
+
+        let mut blocks = BTreeMap::<Hash, (Option<u64>, Option<u64>)>::new();
+        for event in self.fetch_stats_block_v2(height) {
+            let time = event
+                .time
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_micros() as u64;
+            earliest(&mut blocks.entry(event.hash).or_default().0, time);
+        }
+        for row in self.fetch_capnp(height, true) {
+            for event in row.events {
+                let (msg, hash) = match event {
+                    CapnpEventDecoded::ReceivedGossip { msg, hash, .. } => (msg, hash),
+                    CapnpEventDecoded::PublishGossip { msg, hash } => (msg, hash),
+                };
+                // network side only tracks blocks
+                if let GossipNetMessageV2Short::NewState { .. }
+                | GossipNetMessageV2Short::TestMessage { .. } = msg
+                {
+                    earliest(
+                        &mut blocks.entry(hash).or_default().1,
+                        row.time_microseconds,
+                    );
+                }
+            }
+        }
+
+        blocks
+            .into_iter()
+            .map(|(hash, (network, ipc))| GossipCorrelation::new(hash, network, ipc))
+            .collect()
+    }
+
     pub fn get_sk(&self, pk: &[u8; 32]) -> Result<Option<[u8; 32]>, DbError> {
         let Some(v) = self.inner.get_cf(self.keys(), pk)? else {
             return Ok(None);
@@ -1123,7 +3139,7 @@ impl DbCore {
     }
 
     pub fn put_sk(&self, pk: [u8; 32], sk: [u8; 32]) -> Result<(), DbError> {
-        self.inner.put_cf(self.keys(), pk, sk)?;
+        self.put_cf(self.keys(), pk, sk)?;
 
         Ok(())
     }
@@ -1264,3 +3280,618 @@ fn duplicates_removed() {
     assert_eq!(result.next().unwrap().events.len(), 1);
     assert!(result.next().is_none());
 }
+
+/// An empty database in a temporary directory, it is deleted with the `TempDir`.
+#[cfg(test)]
+pub(crate) fn test_db() -> (temp_dir::TempDir, super::DbFacade) {
+    let d = temp_dir::TempDir::new().expect("cannot create temporary directory");
+    let db = super::DbFacade::open(d.path()).unwrap();
+    (d, db)
+}
+
+/// An incoming connection opened at `time`, it is closed when the group is dropped.
+#[cfg(test)]
+pub(crate) fn test_connection(db: &super::DbFacade, time: SystemTime) -> super::DbGroup {
+    let metadata = crate::EventMetadata {
+        time,
+        ..Default::default()
+    };
+    db.add(
+        metadata.id,
+        true,
+        super::StableConnectionId::default(),
+        String::new(),
+        metadata.time,
+        metadata.time_source,
+        metadata.lag,
+    )
+    .unwrap()
+}
+
+/// Adds an incoming message to the stream of the connection, as if it came at `time`.
+#[cfg(test)]
+pub(crate) fn add_test_message(
+    group: &super::DbGroup,
+    time: SystemTime,
+    stream_id: super::StreamId,
+    kind: StreamKind,
+    bytes: &[u8],
+) -> MessageId {
+    let did = crate::event::DirectedId {
+        metadata: crate::EventMetadata {
+            time,
+            ..Default::default()
+        },
+        alias: String::new(),
+        incoming: true,
+        buffered: 0,
+    };
+    group.get(stream_id).add(&did, kind, bytes).unwrap()
+}
+
+/// A database with a single connection, already closed, and a message of `kind`
+/// for each of `payloads`, the ids start from `0`.
+#[cfg(test)]
+pub(crate) fn db_with_messages<I>(
+    kind: StreamKind,
+    payloads: I,
+) -> (temp_dir::TempDir, super::DbFacade)
+where
+    I: IntoIterator,
+    I::Item: AsRef<[u8]>,
+{
+    let (d, db) = test_db();
+    let time = SystemTime::UNIX_EPOCH;
+    let group = test_connection(&db, time);
+    for payload in payloads {
+        add_test_message(
+            &group,
+            time,
+            super::StreamId::Handshake,
+            kind,
+            payload.as_ref(),
+        );
+    }
+    (d, db)
+}
+
+#[cfg(test)]
+#[test]
+fn reindex_restores_indexes() {
+    let (_d, db) = db_with_messages(StreamKind::Ping, [[0; 32]]);
+
+    let core = db.core();
+    let count = |cf: &rocksdb::ColumnFamily| {
+        core.inner
+            .iterator_cf(cf, rocksdb::IteratorMode::Start)
+            .count()
+    };
+    core.inner
+        .delete_cf(
+            core.message_kind_index(),
+            MessageKindIdx {
+                ty: MessageType::IpfsPing,
+                id: MessageId(0),
+            }
+            .chain(vec![]),
+        )
+        .unwrap();
+    assert_eq!(count(core.message_kind_index()), 0);
+
+    let report = core.reindex().unwrap();
+    assert_eq!(report.messages, 1);
+    assert_eq!(report.missing, 0);
+    assert_eq!(count(core.message_kind_index()), 1);
+    assert_eq!(count(core.connection_id_index()), 1);
+    // counted once, not again on reindex
+    assert_eq!(
+        core.fetch_messages_by_kind().get("/ipfs/ping/1.0.0"),
+        Some(&1)
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn prune_keeps_open_connections() {
+    let (_d, db) = test_db();
+    let time = SystemTime::UNIX_EPOCH + Duration::from_secs(100);
+    let add = || {
+        let group = test_connection(&db, time);
+        add_test_message(
+            &group,
+            time,
+            super::StreamId::Handshake,
+            StreamKind::Ping,
+            &[0; 32],
+        );
+        group
+    };
+    // closed once dropped
+    drop(add());
+    let open = add();
+
+    let core = db.core();
+    let count = |cf: &rocksdb::ColumnFamily| {
+        core.inner
+            .iterator_cf(cf, rocksdb::IteratorMode::Start)
+            .count()
+    };
+    assert_eq!(count(core.messages()), 2);
+    for cn in [ConnectionId(0), open.id()] {
+        core.put_meshsub_topic(cn, "mina/block/1.0.0").unwrap();
+        core.add_bitswap(cn, true, time, 32, &[]).unwrap();
+    }
+
+    let pruned = core
+        .prune_before(SystemTime::now() + Duration::from_secs(1))
+        .unwrap();
+    assert_eq!(pruned, 1);
+    assert_eq!(count(core.messages()), 1);
+    assert_eq!(count(core.connections()), 1);
+    assert_eq!(count(core.blobs()), 1);
+    assert_eq!(count(core.connection_id_index()), 1);
+    assert_eq!(count(core.message_kind_index()), 1);
+    assert_eq!(count(core.addr_index()), 1);
+    assert_eq!(count(core.stream_kind_index()), 1);
+    assert_eq!(count(core.meshsub_topics()), 1);
+    assert_eq!(count(core.bitswap_sessions()), 1);
+    assert!(core.fetch_bitswap_session(0).unwrap().is_none());
+    assert!(core.fetch_connection(open.id().0).is_ok());
+    assert_eq!(core.message_count().unwrap(), 1);
+    assert_eq!(core.fetch_connection_count().total, 1);
+    assert_eq!(
+        core.fetch_messages_by_kind().get("/ipfs/ping/1.0.0"),
+        Some(&1)
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn prune_reordered_messages() {
+    let (_d, db) = test_db();
+    let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+    let group = test_connection(&db, at(100));
+    // the newer message got the smaller id
+    for secs in [200, 100] {
+        add_test_message(
+            &group,
+            at(secs),
+            super::StreamId::Handshake,
+            StreamKind::Ping,
+            &[0; 32],
+        );
+    }
+    drop(group);
+
+    let core = db.core();
+    assert_eq!(core.prune_before(at(150)).unwrap(), 1);
+    let message = |id: u64| core.get::<Message, _>(core.messages(), id.to_be_bytes());
+    assert!(message(0).is_ok());
+    assert!(message(1).is_err());
+    // the next run starts from the kept message
+    assert_eq!(core.prune_before(at(150)).unwrap(), 0);
+    assert_eq!(core.prune_before(at(250)).unwrap(), 1);
+}
+
+#[cfg(test)]
+#[test]
+fn messages_by_peer_id() {
+    use super::{Params, StreamId};
+
+    let (_d, db) = test_db();
+    let time = SystemTime::UNIX_EPOCH;
+    let key = "da91decf6f4c769327ca8ff03986e66fcfe6c59dca63d68c5ee359e52f8dc6e6";
+    let payload = hex::decode(format!("0a2408011220{key}1240{}", "00".repeat(64))).unwrap();
+
+    let mut groups = vec![];
+    // the peer reconnects, and an unrelated connection in between
+    for handshake in [true, false, true] {
+        let group = test_connection(&db, time);
+        if handshake {
+            add_test_message(
+                &group,
+                time,
+                StreamId::Handshake,
+                StreamKind::Handshake,
+                &payload,
+            );
+        }
+        add_test_message(
+            &group,
+            time,
+            StreamId::Forward(1),
+            StreamKind::Ping,
+            &[0; 32],
+        );
+        groups.push(group);
+    }
+
+    let params = serde_json::from_value::<Params>(serde_json::json!({
+        "peer_id": "12D3KooWQXa4AdCEZWe9QwoHnrANyMAXirozBdroNHkkvTMhT8bf",
+    }))
+    .unwrap()
+    .validate()
+    .unwrap();
+    let ids = db
+        .core()
+        .fetch_messages(&params)
+        .map(|(id, _)| id)
+        .collect::<Vec<_>>();
+    assert_eq!(ids, [0, 1, 3, 4]);
+}
+
+#[cfg(test)]
+#[test]
+fn checkpoint_reopens() {
+    use temp_dir::TempDir;
+
+    let (_d, db) = db_with_messages(StreamKind::Ping, [[0; 32]; 3]);
+
+    let core = db.core();
+    let c = TempDir::new().expect("cannot create temporary directory");
+    let path = c.path().join("checkpoint");
+    core.create_checkpoint(&path).unwrap();
+
+    let copy = DbCore::open(&path).unwrap();
+    let count = |db: &DbCore| {
+        db.inner
+            .iterator_cf(db.messages(), rocksdb::IteratorMode::Start)
+            .count()
+    };
+    assert_eq!(count(&core), 3);
+    assert_eq!(count(&copy), 3);
+    assert_eq!(
+        copy.total::<{ DbCore::MESSAGES_CNT }>().unwrap(),
+        core.total::<{ DbCore::MESSAGES_CNT }>().unwrap(),
+    );
+    // the stored total is the last id, the counts are seeded on open
+    assert_eq!(copy.fetch_connection_count().total, 1);
+    assert_eq!(
+        copy.fetch_messages_by_kind().get("/ipfs/ping/1.0.0"),
+        Some(&3)
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn messages_by_nanosecond_range() {
+    use super::{Params, StreamId};
+
+    let (_d, db) = test_db();
+    let base = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let group = test_connection(&db, base);
+    // every 100 ms, all within the same second
+    for i in 0..10 {
+        let time = base + Duration::from_millis(i * 100);
+        add_test_message(
+            &group,
+            time,
+            StreamId::Forward(1),
+            StreamKind::Ping,
+            &[0; 32],
+        );
+    }
+
+    let ids = |params: serde_json::Value| {
+        let params = serde_json::from_value::<Params>(params)
+            .unwrap()
+            .validate()
+            .unwrap();
+        db.core()
+            .fetch_messages(&params)
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>()
+    };
+    let ns = |ms: u64| {
+        (base + Duration::from_millis(ms))
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64
+    };
+    assert_eq!(
+        ids(serde_json::json!({
+            "timestamp_ns": ns(250),
+            "limit_timestamp_ns": ns(650),
+        })),
+        [3, 4, 5, 6],
+    );
+    assert_eq!(ids(serde_json::json!({ "timestamp_ns": ns(900) })), [9]);
+    // the seconds are still accepted
+    assert_eq!(
+        ids(serde_json::json!({ "timestamp": 1_700_000_000, "limit": 2 })),
+        [0, 1],
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn block_propagation_by_peer() {
+    use temp_dir::TempDir;
+
+    let d = TempDir::new().expect("cannot create temporary directory");
+    let db = DbCore::open(d.path()).unwrap();
+    let base = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let node = "10.0.0.1:8302".parse::<SocketAddr>().unwrap();
+    let a = "10.0.0.2:8302".parse::<SocketAddr>().unwrap();
+    let b = "10.0.0.3:8302".parse::<SocketAddr>().unwrap();
+    let event = |message_id: u64, ms: u64, incoming: bool, peer: SocketAddr| {
+        let time = base + Duration::from_millis(ms);
+        let (sender_addr, receiver_addr) = if incoming { (peer, node) } else { (node, peer) };
+        meshsub_stats::Event {
+            producer_id: "12D3KooWQXa4AdCEZWe9QwoHnrANyMAXirozBdroNHkkvTMhT8bf"
+                .parse()
+                .unwrap(),
+            hash: Hash([1; 32]),
+            block_height: 10,
+            global_slot: 20,
+            incoming,
+            message_kind: MessageType::PublishNewState,
+            message_id,
+            time,
+            better_time: time,
+            latency: None,
+            sender_addr,
+            receiver_addr,
+        }
+    };
+    // the node's own publish is not an arrival
+    db.put_stats_block_v2(event(0, 100, false, a)).unwrap();
+    db.put_stats_block_v2(event(1, 200, true, a)).unwrap();
+    db.put_stats_block_v2(event(2, 500, true, b)).unwrap();
+    db.put_stats_block_v2(event(3, 900, true, a)).unwrap();
+
+    let v = db.fetch_block_propagation(10);
+    assert_eq!(v.blocks.len(), 1);
+    let block = &v.blocks[0];
+    assert_eq!(block.first_peer, a);
+    assert_eq!(block.first_seen, base + Duration::from_millis(200));
+    let peers = block
+        .peers
+        .iter()
+        .map(|p| (p.peer, p.message_id, (p.latency * 1000.0).round() as u64))
+        .collect::<Vec<_>>();
+    assert_eq!(peers, [(a, 1, 0), (b, 2, 300)]);
+    assert_eq!(block.latency.count, 2);
+    assert!(db.fetch_block_propagation(11).blocks.is_empty());
+}
+
+#[cfg(test)]
+#[test]
+fn tx_latency_without_transactions() {
+    use temp_dir::TempDir;
+
+    let d = TempDir::new().expect("cannot create temporary directory");
+    let db = DbCore::open(d.path()).unwrap();
+    for height in [5, 6, 9] {
+        let stat = TxStat {
+            block_time: SystemTime::UNIX_EPOCH,
+            block_height: height,
+            transactions: vec![],
+            snarks: vec![],
+            pending_txs: vec![1, 2],
+        };
+        db.put_stats_tx(height, stat.chain(vec![])).unwrap();
+    }
+
+    let v = db.fetch_tx_latency_summary(5, 8);
+    assert_eq!(v.heights, 2);
+    assert_eq!(v.transactions.count, 0);
+    assert_eq!(v.snarks.count, 0);
+    assert_eq!(db.fetch_tx_latency_summary(10, 20).heights, 0);
+}
+
+#[cfg(test)]
+#[test]
+fn decrypt_stats_failed_messages() {
+    let (_d, db) = db_with_messages(StreamKind::Unknown, [b"hello"; 3]);
+
+    let core = db.core();
+    assert_eq!(core.fetch_decrypt_stats().failed_messages, 0);
+    // two messages of the same connection, and one is pruned
+    for id in [0, 2, 7] {
+        let index = MessageKindIdx {
+            ty: MessageType::FailedToDecrypt,
+            id: MessageId(id),
+        };
+        core.put_cf(core.message_kind_index(), index.chain(vec![]), vec![])
+            .unwrap();
+    }
+    let stats = core.fetch_decrypt_stats();
+    assert_eq!(stats.failed_messages, 3);
+    assert_eq!(stats.failed_connections, 1);
+    assert_eq!(stats.connections, 1);
+}
+
+#[cfg(test)]
+#[test]
+fn flushed_survives_lost_wal() {
+    let d = {
+        let (d, db) = db_with_messages(StreamKind::Unknown, [b"hello"]);
+        db.core().flush_all().unwrap();
+        d
+    };
+    // as if the process was killed before the write ahead log hit the disk
+    for entry in fs::read_dir(d.path().join("rocksdb")).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().map_or(false, |ext| ext == "log") {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    let db = DbCore::open(d.path()).unwrap();
+    assert_eq!(db.fetch_full_message_bin(0).unwrap(), b"hello");
+    assert!(db.fetch_connection(0).is_ok());
+}
+
+#[cfg(test)]
+#[test]
+fn bitswap_block_maps_to_mina_block() {
+    let (_d, db) = test_db();
+    let core = db.core();
+    let time = SystemTime::UNIX_EPOCH + Duration::from_secs(100);
+    let mina_block = BlockProvenance {
+        hash: Hash([1; 32]),
+        height: 7,
+    };
+    core.put_bitswap_block([2; 32], mina_block).unwrap();
+
+    let root = ReceivedBlock {
+        digest: [2; 32],
+        links: vec![[3; 32]],
+        size: 66,
+    };
+    let cn = ConnectionId(0);
+    core.add_bitswap(cn, true, time, 100, &[root]).unwrap();
+    let child = ReceivedBlock {
+        digest: [3; 32],
+        links: vec![],
+        size: 10,
+    };
+    core.add_bitswap(cn, true, time, 40, &[child]).unwrap();
+
+    let part = core.fetch_bitswap_block([3; 32]).unwrap().unwrap();
+    assert!(part.hash == Hash([1; 32]) && part.height == 7);
+    assert!(core.fetch_bitswap_block([4; 32]).unwrap().is_none());
+    let session = core.fetch_bitswap_session(0).unwrap().unwrap();
+    assert_eq!((session.blocks, session.block_bytes), (2, 76));
+    assert_eq!(session.mina_blocks.len(), 1);
+}
+
+#[cfg(test)]
+#[test]
+fn message_stored_with_blocks() {
+    let (_d, db) = test_db();
+    let group = test_connection(&db, SystemTime::UNIX_EPOCH);
+    let did = crate::event::DirectedId {
+        metadata: Default::default(),
+        alias: String::new(),
+        incoming: true,
+        buffered: 0,
+    };
+    let block = BlockProvenance {
+        hash: Hash([1; 32]),
+        height: 7,
+    };
+    let id = group
+        .get(super::StreamId::Forward(1))
+        .add_with_blocks(&did, StreamKind::Unknown, b"hello", vec![block])
+        .unwrap();
+
+    let core = db.core();
+    let msg = core
+        .get::<Message, _>(core.messages(), id.0.to_be_bytes())
+        .unwrap();
+    assert_eq!(msg.blocks.len(), 1);
+    assert_eq!(msg.blocks[0].height, 7);
+}
+
+#[cfg(test)]
+#[test]
+fn reads_through_snapshot() {
+    use super::{ConnectionStats, Params, StreamId};
+
+    let (_d, db) = test_db();
+    let time = SystemTime::UNIX_EPOCH;
+    let first = test_connection(&db, time);
+    first
+        .update(
+            ConnectionStats {
+                total_bytes: 5,
+                ..Default::default()
+            },
+            true,
+            time,
+        )
+        .unwrap();
+    add_test_message(
+        &first,
+        time,
+        StreamId::Handshake,
+        StreamKind::Unknown,
+        b"hello",
+    );
+
+    let core = db.core();
+    let params = serde_json::from_value::<Params>(serde_json::json!({}))
+        .unwrap()
+        .validate_connection()
+        .unwrap();
+    let connections = core.fetch_connections(&params);
+    let snapshot = core.inner.snapshot();
+
+    // written after the reads started
+    let second = test_connection(&db, time);
+    second
+        .update(
+            ConnectionStats {
+                total_bytes: 5,
+                ..Default::default()
+            },
+            true,
+            time,
+        )
+        .unwrap();
+    add_test_message(
+        &second,
+        time,
+        StreamId::Handshake,
+        StreamKind::Unknown,
+        b"world",
+    );
+
+    let ids = connections.map(|(id, _)| id).collect::<Vec<_>>();
+    assert_eq!(ids, [0]);
+    assert!(core.fetch_full_message_at(&snapshot, 0).is_ok());
+    assert!(core.fetch_full_message_at(&snapshot, 1).is_err());
+    assert!(core.fetch_full_message(1).is_ok());
+}
+
+#[cfg(test)]
+#[test]
+fn failed_connection_counted_after_reopen() {
+    let (d, db) = test_db();
+    let metadata = crate::EventMetadata::default();
+    db.add_failed(
+        metadata.id,
+        StableConnectionId::default(),
+        String::new(),
+        metadata.time,
+        metadata.time_source,
+        metadata.lag,
+        111,
+    )
+    .unwrap();
+    let count = db.core().fetch_connection_count();
+    assert_eq!((count.total, count.active, count.outgoing), (1, 0, 1));
+    drop(db);
+
+    let db = DbCore::open(d.path()).unwrap();
+    let count = db.fetch_connection_count();
+    assert_eq!((count.total, count.active, count.outgoing), (1, 0, 1));
+}
+
+#[cfg(test)]
+#[test]
+fn large_blob_read_in_place() {
+    use crate::test_alloc::peak;
+
+    let blob = vec![0x55; 4 << 20];
+    let (_d, db) = db_with_messages(StreamKind::Unknown, [&blob]);
+    let core = db.core();
+    let msg = core
+        .get::<Message, _>(core.messages(), 0_u64.to_be_bytes())
+        .unwrap();
+
+    let (len, borrowed) = peak(|| {
+        core.with_blob(msg.connection_id, msg.offset, |b| b.len())
+            .unwrap()
+    });
+    let (copy, copied) = peak(|| {
+        core.with_blob(msg.connection_id, msg.offset, <[u8]>::to_vec)
+            .unwrap()
+    });
+    assert_eq!((len, copy.len()), (blob.len(), blob.len()));
+    // the copy doubles the memory, the pinned read only allocates the key
+    assert!(copied >= blob.len());
+    assert!(borrowed < 4096, "{borrowed} bytes allocated");
+}
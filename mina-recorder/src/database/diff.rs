@@ -0,0 +1,136 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use serde::Serialize;
+
+/// What is compared between two captures.
+#[derive(Default, Serialize)]
+pub struct CaptureSummary {
+    pub connections: u64,
+    pub incoming_connections: u64,
+    pub messages: u64,
+    pub messages_by_kind: BTreeMap<String, u64>,
+    pub block_latency: LatencySummary,
+}
+
+/// Block latency relative to the first time the debugger saw the block, in seconds.
+#[derive(Default, Clone, Copy, Serialize)]
+pub struct LatencySummary {
+    pub count: u64,
+    pub mean: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl LatencySummary {
+    pub fn new(mut latencies: Vec<Duration>) -> Self {
+        if latencies.is_empty() {
+            return Self::default();
+        }
+        latencies.sort();
+        let secs = |d: Duration| d.as_secs_f64();
+        let percentile = |p: usize| secs(latencies[(latencies.len() - 1) * p / 100]);
+        let total = latencies.iter().copied().map(secs).sum::<f64>();
+        LatencySummary {
+            count: latencies.len() as u64,
+            mean: total / latencies.len() as f64,
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: secs(latencies[latencies.len() - 1]),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Delta<T> {
+    pub a: T,
+    pub b: T,
+    pub delta: f64,
+}
+
+impl Delta<u64> {
+    fn new(a: u64, b: u64) -> Self {
+        Delta {
+            a,
+            b,
+            delta: b as f64 - a as f64,
+        }
+    }
+}
+
+impl Delta<f64> {
+    fn new(a: f64, b: f64) -> Self {
+        Delta { a, b, delta: b - a }
+    }
+}
+
+/// The difference `b - a`, every kind present in either capture is listed.
+#[derive(Serialize)]
+pub struct CaptureDiff {
+    pub connections: Delta<u64>,
+    pub incoming_connections: Delta<u64>,
+    pub messages: Delta<u64>,
+    pub messages_by_kind: BTreeMap<String, Delta<u64>>,
+    pub block_latency: BTreeMap<&'static str, Delta<f64>>,
+}
+
+impl CaptureDiff {
+    pub fn new(a: &CaptureSummary, b: &CaptureSummary) -> Self {
+        let mut messages_by_kind = BTreeMap::new();
+        for kind in a.messages_by_kind.keys().chain(b.messages_by_kind.keys()) {
+            let count = |s: &CaptureSummary| s.messages_by_kind.get(kind).copied().unwrap_or(0);
+            messages_by_kind.insert(kind.clone(), Delta::<u64>::new(count(a), count(b)));
+        }
+        let (la, lb) = (a.block_latency, b.block_latency);
+        let block_latency = [
+            ("count", la.count as f64, lb.count as f64),
+            ("mean", la.mean, lb.mean),
+            ("p50", la.p50, lb.p50),
+            ("p90", la.p90, lb.p90),
+            ("p99", la.p99, lb.p99),
+            ("max", la.max, lb.max),
+        ]
+        .into_iter()
+        .map(|(name, a, b)| (name, Delta::<f64>::new(a, b)))
+        .collect();
+
+        CaptureDiff {
+            connections: Delta::<u64>::new(a.connections, b.connections),
+            incoming_connections: Delta::<u64>::new(a.incoming_connections, b.incoming_connections),
+            messages: Delta::<u64>::new(a.messages, b.messages),
+            messages_by_kind,
+            block_latency,
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn diff_lists_kinds_of_both() {
+    let a = CaptureSummary {
+        connections: 10,
+        messages: 100,
+        messages_by_kind: [("/meshsub/1.1.0".to_owned(), 100)].into_iter().collect(),
+        block_latency: LatencySummary::new(vec![Duration::from_secs(1), Duration::from_secs(3)]),
+        ..Default::default()
+    };
+    let b = CaptureSummary {
+        connections: 8,
+        messages: 150,
+        messages_by_kind: [
+            ("/meshsub/1.1.0".to_owned(), 120),
+            ("coda/rpcs/0.0.1".to_owned(), 30),
+        ]
+        .into_iter()
+        .collect(),
+        ..Default::default()
+    };
+    let diff = CaptureDiff::new(&a, &b);
+    assert_eq!(diff.connections.delta, -2.0);
+    assert_eq!(diff.messages_by_kind["/meshsub/1.1.0"].delta, 20.0);
+    assert_eq!(diff.messages_by_kind["coda/rpcs/0.0.1"].a, 0);
+    assert_eq!(diff.block_latency["mean"].a, 2.0);
+    assert_eq!(diff.block_latency["count"].delta, -2.0);
+}
@@ -1,9 +1,13 @@
-use std::net::SocketAddr;
+use std::{env, net::SocketAddr, sync::OnceLock};
 
+use libp2p_identity::PeerId;
 use radiation::{Absorb, Emit};
 
-use crate::{decode::MessageType, custom_coding};
-use super::types::{ConnectionId, MessageId, StreamFullId, StreamKind};
+use crate::{
+    decode::{MessageType, meshsub_stats::Hash},
+    custom_coding,
+};
+use super::types::{ConnectionId, MessageId, StreamFullId, StreamKind, StableConnectionId};
 
 #[derive(Absorb, Emit)]
 pub struct AddressIdx {
@@ -19,12 +23,46 @@ pub struct ConnectionIdx {
     pub id: MessageId,
 }
 
+#[derive(Absorb, Emit)]
+pub struct GossipHashIdx {
+    pub hash: Hash,
+    pub id: MessageId,
+}
+
+// the peer id has variable length, so the key is its hash
+#[derive(Absorb, Emit)]
+pub struct PeerIdIdx {
+    pub peer_id: Hash,
+    pub id: ConnectionId,
+}
+
+impl PeerIdIdx {
+    pub fn hash(peer_id: &PeerId) -> Hash {
+        use sha2::{Digest, Sha256};
+
+        Hash(Sha256::digest(peer_id.to_bytes()).into())
+    }
+}
+
+#[derive(Absorb, Emit)]
+pub struct StableIdIdx {
+    pub stable_id: StableConnectionId,
+    pub id: ConnectionId,
+}
+
 #[derive(Absorb, Emit)]
 pub struct StreamIdx {
     pub stream_full_id: StreamFullId,
     pub id: MessageId,
 }
 
+// offset of the bytes failed to decode in the connection's blob
+#[derive(Absorb, Emit)]
+pub struct DecodeErrorIdx {
+    pub stream_full_id: StreamFullId,
+    pub offset: u64,
+}
+
 #[derive(Absorb, Emit)]
 pub struct StreamByKindIdx {
     pub stream_kind: StreamKind,
@@ -47,6 +85,12 @@ pub struct LedgerHashIdx {
 }
 
 impl LedgerHashIdx {
+    /// Set by `DEBUGGER_INDEX_LEDGER_HASH`, read once.
+    pub fn enabled() -> bool {
+        static ENABLED: OnceLock<bool> = OnceLock::new();
+        *ENABLED.get_or_init(|| env::var("DEBUGGER_INDEX_LEDGER_HASH").is_ok())
+    }
+
     fn _31(h: mina_p2p_messages::v2::LedgerHash) -> [u8; 31] {
         let mut hash = [0; 31];
         hash.clone_from_slice(&h.into_inner().0.as_ref()[1..]);
@@ -1,7 +1,12 @@
 mod types;
 pub use self::types::{
     StreamKind, StreamId, ConnectionId, ConnectionStats, FullMessage, CapnpEventWithMetadata,
-    CapnpEventWithMetadataKey,
+    CapnpEventWithMetadataKey, MessageId, PingRtt, StableConnectionId, ProblematicStream,
+    BandwidthBucket, SubnetStats, ConnectionCount, DecryptStats, GossipCorrelation, StreamFullId,
+    BlockProvenance, GossipHop, BitswapSession, StreamOrderViolation, StreamOrderReport,
+    GatingConfigRecord, GatingConfig, ProducerSummary, RedecryptReport, PruneStats, ReindexReport,
+    Desync, NodeMetricSample, CheckpointReport, Sampling, BlockPropagation, TxLatencySummary,
+    RelayedPeer,
 };
 
 mod rocksdb;
@@ -15,7 +20,12 @@ pub use self::index::LedgerHash;
 
 mod sorted_intersect;
 
+mod diff;
+pub use self::diff::{CaptureSummary, CaptureDiff, LatencySummary};
+
 mod core;
 pub use self::core::{DbError, DbCore, RandomnessDatabase};
+#[cfg(test)]
+pub(crate) use self::core::{test_db, test_connection, add_test_message, db_with_messages};
 
 pub type DbResult<T> = Result<T, DbError>;
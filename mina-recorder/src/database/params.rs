@@ -1,5 +1,6 @@
-use std::{net::SocketAddr, str::FromStr};
+use std::{net::SocketAddr, str::FromStr, time::Duration};
 
+use libp2p_identity::PeerId;
 use serde::Deserialize;
 
 use thiserror::Error;
@@ -12,6 +13,8 @@ use super::types::{ConnectionId, StreamFullId, StreamKind, Timestamp};
 pub enum ParamsCoordinateValidateError {
     #[error("cannot use together id and timestamp, ambiguous start")]
     IdWithTimestamp,
+    #[error("cannot use together seconds and nanoseconds of the same timestamp")]
+    TimestampInBothUnits,
 }
 
 #[derive(Debug, Error)]
@@ -26,12 +29,16 @@ pub enum ParamsValidateError {
     ParseStreamId(String),
     #[error("cannot parse message kind")]
     ParseMessageKind,
+    #[error("cannot parse peer id {_0}")]
+    ParsePeerId(libp2p_identity::ParseError),
+    #[error("cannot filter by peer id together with address, connection or stream")]
+    PeerIdWithStream,
 }
 
 pub struct ValidParamsCoordinate {
     pub start: Coordinate,
     pub limit: usize,
-    limit_timestamp: Option<u64>,
+    limit_timestamp: Option<Duration>,
     pub direction: Direction,
 }
 
@@ -47,13 +54,16 @@ pub struct ValidParamsConnection {
 
 pub enum Coordinate {
     ById { id: u64, explicit: bool },
-    ByTimestamp(u64),
+    // since unix epoch
+    ByTimestamp(Duration),
 }
 
 pub enum StreamFilter {
     AnyStreamByAddr(SocketAddr),
     AnyStreamInConnection(ConnectionId),
     Stream(StreamFullId),
+    // every connection where the peer presented this id, across reconnects from any address
+    AnyStreamByPeerId(PeerId),
 }
 
 pub enum KindFilter {
@@ -65,8 +75,9 @@ pub enum KindFilter {
 pub struct Params {
     // the start of the list, either id of record ...
     id: Option<u64>,
-    // ... or timestamp
+    // ... or timestamp, seconds or nanoseconds since unix epoch
     timestamp: Option<u64>,
+    timestamp_ns: Option<u64>,
     // wether go `forward` or `reverse`, default is `forward`
     #[serde(default)]
     direction: Direction,
@@ -74,6 +85,7 @@ pub struct Params {
     // if `limit_timestamp` is specified, default limit is `usize::MAX`
     limit: Option<usize>,
     limit_timestamp: Option<u64>,
+    limit_timestamp_ns: Option<u64>,
     // what streams to read, comma separated
     // streams: Option<String>,
     // filter by connection id
@@ -82,6 +94,8 @@ pub struct Params {
     stream_id: Option<String>,
     stream_kind: Option<String>,
     message_kind: Option<String>,
+    // the peer id from the handshake, selects all connections with the peer
+    peer_id: Option<String>,
 }
 
 #[derive(Default, Clone, Copy, Deserialize)]
@@ -124,7 +138,20 @@ impl Params {
     }
 
     fn validate_coordinate(&self) -> Result<ValidParamsCoordinate, ParamsCoordinateValidateError> {
-        let start = match (self.id, self.timestamp) {
+        fn timestamp(
+            secs: Option<u64>,
+            nanos: Option<u64>,
+        ) -> Result<Option<Duration>, ParamsCoordinateValidateError> {
+            match (secs, nanos) {
+                (None, None) => Ok(None),
+                (Some(secs), None) => Ok(Some(Duration::from_secs(secs))),
+                (None, Some(nanos)) => Ok(Some(Duration::from_nanos(nanos))),
+                (Some(_), Some(_)) => Err(ParamsCoordinateValidateError::TimestampInBothUnits),
+            }
+        }
+
+        let limit_timestamp = timestamp(self.limit_timestamp, self.limit_timestamp_ns)?;
+        let start = match (self.id, timestamp(self.timestamp, self.timestamp_ns)?) {
             (None, None) => match self.direction {
                 Direction::Forward => Coordinate::ById {
                     id: 0,
@@ -139,7 +166,7 @@ impl Params {
             (None, Some(timestamp)) => Coordinate::ByTimestamp(timestamp),
             (Some(_), Some(_)) => return Err(ParamsCoordinateValidateError::IdWithTimestamp),
         };
-        let limit = if self.limit_timestamp.is_some() {
+        let limit = if limit_timestamp.is_some() {
             self.limit.unwrap_or(usize::MAX)
         } else {
             self.limit.unwrap_or(16)
@@ -147,7 +174,7 @@ impl Params {
         Ok(ValidParamsCoordinate {
             start,
             limit,
-            limit_timestamp: self.limit_timestamp,
+            limit_timestamp,
             direction: self.direction,
         })
     }
@@ -161,21 +188,30 @@ impl Params {
 
     pub fn validate(self) -> Result<ValidParams, ParamsValidateError> {
         let coordinate = self.validate_coordinate()?;
-        let stream_filter = match (self.addr, self.connection_id, self.stream_id) {
-            (Some(addr), _, _) => {
+        let stream_filter = match (self.peer_id, self.addr, self.connection_id, self.stream_id) {
+            (Some(peer_id), None, None, None) => {
+                let peer_id = peer_id.parse().map_err(ParamsValidateError::ParsePeerId)?;
+                Some(StreamFilter::AnyStreamByPeerId(peer_id))
+            }
+            (Some(_), _, _, _) => return Err(ParamsValidateError::PeerIdWithStream),
+            (None, Some(addr), _, _) => {
                 let addr = addr.parse().map_err(ParamsValidateError::ParseSocketAddr)?;
                 Some(StreamFilter::AnyStreamByAddr(addr))
             }
-            (None, None, None) => None,
-            (None, Some(id), None) => Some(StreamFilter::AnyStreamInConnection(ConnectionId(id))),
-            (None, Some(id), Some(s)) => {
+            (None, None, None, None) => None,
+            (None, None, Some(id), None) => {
+                Some(StreamFilter::AnyStreamInConnection(ConnectionId(id)))
+            }
+            (None, None, Some(id), Some(s)) => {
                 let stream_id = s.parse().map_err(ParamsValidateError::ParseStreamId)?;
                 Some(StreamFilter::Stream(StreamFullId {
                     cn: ConnectionId(id),
                     id: stream_id,
                 }))
             }
-            (None, None, Some(_)) => return Err(ParamsValidateError::StreamIdWithoutConnectionId),
+            (None, None, None, Some(_)) => {
+                return Err(ParamsValidateError::StreamIdWithoutConnectionId)
+            }
         };
         let kind_filter = match (self.stream_kind, self.message_kind) {
             (None, None) => None,
@@ -235,8 +271,7 @@ impl ValidParamsCoordinate {
         let forward = matches!(self.direction, Direction::Forward);
         it.take_while(move |(_, msg)| {
             if let Some(limit_timestamp) = limit_timestamp {
-                let d = msg.timestamp();
-                (d.as_secs() < limit_timestamp) == forward
+                (msg.timestamp() < limit_timestamp) == forward
             } else {
                 true
             }
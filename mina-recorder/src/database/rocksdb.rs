@@ -1,6 +1,6 @@
 use std::{
     path::Path,
-    time::SystemTime,
+    time::{SystemTime, Duration},
     sync::{
         atomic::{
             AtomicU64,
@@ -15,25 +15,34 @@ use itertools::Itertools;
 use radiation::Emit;
 
 use crate::{
-    event::{ConnectionInfo, DirectedId},
+    event::{ConnectionInfo, DirectedId, TimeSource},
     chunk::{ChunkHeader, EncryptionStatus},
     decode::{
         MessageType,
         meshsub_stats::{BlockStat, TxStat},
+        node_status::NodeStatus,
+        prometheus,
+        relay::Circuit,
     },
     strace::StraceLine,
     meshsub_stats::Event,
+    yamux::WindowStats,
 };
 
 use super::{
     core::{DbCore, DbError},
-    types::{Connection, ConnectionId, Message, MessageId, StreamId, StreamKind, ConnectionStats},
+    types::{
+        Connection, ConnectionId, Message, MessageId, StreamId, StreamKind, ConnectionStats,
+        PingRtt, StableConnectionId, StreamFullId, NodeStatusRecord, BlockProvenance, Desync,
+    },
+    index::{LedgerHash, LedgerHashIdx},
 };
 
 pub struct DbFacade {
     cns: AtomicU64,
     pub messages: Arc<AtomicU64>,
     rnd_cnt: AtomicU64,
+    rnd_retain: u64,
     inner: DbCore,
 }
 
@@ -43,11 +52,18 @@ impl DbFacade {
         P: AsRef<Path>,
     {
         let inner = DbCore::open(path)?;
+        // the key recovery tries only a window of latest seeds, the rest is useless
+        let rnd_retain = std::env::var("RANDOMNESS_RETAIN")
+            .ok()
+            .and_then(|s| s.parse::<u64>().ok())
+            .unwrap_or(64)
+            .max(crate::key_recover::seed_window() as u64);
 
         Ok(DbFacade {
             cns: AtomicU64::new(inner.total::<{ DbCore::CONNECTIONS_CNT }>()?),
             messages: Arc::new(AtomicU64::new(inner.total::<{ DbCore::MESSAGES_CNT }>()?)),
             rnd_cnt: AtomicU64::new(inner.total::<{ DbCore::RANDOMNESS_CNT }>()?),
+            rnd_retain,
             inner,
         })
     }
@@ -66,6 +82,23 @@ impl DbFacade {
         self.inner.put_stats_block_v2(event)
     }
 
+    pub fn put_bitswap_block(
+        &self,
+        digest: [u8; 32],
+        block: BlockProvenance,
+    ) -> Result<(), DbError> {
+        self.inner.put_bitswap_block(digest, block)
+    }
+
+    pub fn add_prune(
+        &self,
+        peer: SocketAddr,
+        time: SystemTime,
+        backoff: Option<u64>,
+    ) -> Result<(), DbError> {
+        self.inner.add_prune(peer, time, backoff)
+    }
+
     pub fn stats_tx(&self, height: u32, value: &TxStat) -> Result<(), DbError> {
         self.inner.put_stats_tx(height, value.chain(vec![]))
     }
@@ -81,34 +114,107 @@ impl DbFacade {
         &self,
         info: ConnectionInfo,
         incoming: bool,
+        stable_id: StableConnectionId,
         alias: String,
         timestamp: SystemTime,
+        time_source: TimeSource,
+        lag: Duration,
     ) -> Result<DbGroup, DbError> {
         let id = ConnectionId(self.cns.fetch_add(1, SeqCst));
         let addr = info.addr;
+        let generation = info.generation;
         let v = Connection {
             info,
             incoming,
+            stable_id,
             timestamp,
             stats_in: ConnectionStats::default(),
             stats_out: ConnectionStats::default(),
             timestamp_close: SystemTime::UNIX_EPOCH,
             alias,
+            timestamp_shutdown_read: SystemTime::UNIX_EPOCH,
+            timestamp_shutdown_write: SystemTime::UNIX_EPOCH,
+            noise_protocol: String::new(),
+            time_source,
+            lag_ns: lag.as_nanos() as u64,
+            connect_error: 0,
+            close_reason: None,
+            generation,
+            relayed_peers: vec![],
         };
         self.inner.put_cn(id, v)?;
+        if stable_id.is_known() {
+            self.inner.put_stable_id_index(stable_id, id)?;
+        }
         self.inner.set_total::<{ DbCore::CONNECTIONS_CNT }>(id.0)?;
+        self.inner.count_connection_open(incoming)?;
 
         Ok(DbGroup {
             addr,
             id,
             messages: self.messages.clone(),
+            clones: Arc::new(()),
+            replay: false,
+            inner: self.inner.clone(),
+        })
+    }
+
+    /// Outgoing connection which `connect` failed, stored already closed, no data follows.
+    pub fn add_failed(
+        &self,
+        info: ConnectionInfo,
+        stable_id: StableConnectionId,
+        alias: String,
+        timestamp: SystemTime,
+        time_source: TimeSource,
+        lag: Duration,
+        errno: i32,
+    ) -> Result<ConnectionId, DbError> {
+        let id = ConnectionId(self.cns.fetch_add(1, SeqCst));
+        let generation = info.generation;
+        let v = Connection {
+            info,
+            incoming: false,
+            stable_id,
+            timestamp,
+            stats_in: ConnectionStats::default(),
+            stats_out: ConnectionStats::default(),
+            timestamp_close: timestamp,
+            alias,
+            timestamp_shutdown_read: SystemTime::UNIX_EPOCH,
+            timestamp_shutdown_write: SystemTime::UNIX_EPOCH,
+            noise_protocol: String::new(),
+            time_source,
+            lag_ns: lag.as_nanos() as u64,
+            connect_error: errno,
+            close_reason: None,
+            generation,
+            relayed_peers: vec![],
+        };
+        self.inner.put_cn(id, v)?;
+        self.inner.set_total::<{ DbCore::CONNECTIONS_CNT }>(id.0)?;
+        self.inner.count_connection_failed();
+
+        Ok(id)
+    }
+
+    /// Group for the connection that is already stored, to decode its chunks once again.
+    pub fn reopen(&self, id: ConnectionId) -> Result<DbGroup, DbError> {
+        let cn = self.inner.fetch_connection(id.0)?;
+
+        Ok(DbGroup {
+            addr: cn.info.addr,
+            id,
+            messages: self.messages.clone(),
+            clones: Arc::new(()),
+            replay: true,
             inner: self.inner.clone(),
         })
     }
 
     pub fn add_randomness(&self, bytes: [u8; 32]) -> Result<(), DbError> {
         let id = self.rnd_cnt.fetch_add(1, SeqCst);
-        self.inner.put_randomness(id, bytes)?;
+        self.inner.put_randomness(id, bytes, self.rnd_retain)?;
 
         Ok(())
     }
@@ -144,6 +250,10 @@ pub struct DbGroup {
     addr: SocketAddr,
     id: ConnectionId,
     messages: Arc<AtomicU64>,
+    // shared by the clones, every stream holds one
+    clones: Arc<()>,
+    // the connection is decoded once again, its bytes are already counted
+    replay: bool,
     inner: DbCore,
 }
 
@@ -159,7 +269,24 @@ impl DbGroup {
         self.id
     }
 
-    pub fn update(&self, stats: ConnectionStats, incoming: bool) -> Result<(), DbError> {
+    pub fn update(
+        &self,
+        stats: ConnectionStats,
+        incoming: bool,
+        time: SystemTime,
+    ) -> Result<(), DbError> {
+        let stats = if self.replay {
+            ConnectionStats {
+                total_bytes: 0,
+                ..stats
+            }
+        } else {
+            stats
+        };
+        if stats.total_bytes != 0 {
+            self.inner
+                .add_bandwidth(self.id, time, incoming, stats.total_bytes)?;
+        }
         let mut cn = self.inner.fetch_connection(self.id.0)?;
         if incoming {
             cn.stats_in += stats;
@@ -169,6 +296,76 @@ impl DbGroup {
         self.inner.put_cn(self.id, cn)
     }
 
+    /// One direction of the connection is closed, the other may still carry data.
+    pub fn shutdown(&self, write: bool, time: SystemTime) -> Result<(), DbError> {
+        let mut cn = self.inner.fetch_connection(self.id.0)?;
+        if write {
+            cn.timestamp_shutdown_write = time;
+        } else {
+            cn.timestamp_shutdown_read = time;
+        }
+        self.inner.put_cn(self.id, cn)
+    }
+
+    pub fn set_desync(&self, v: Desync) -> Result<(), DbError> {
+        self.inner.put_desync(self.id, v)
+    }
+
+    pub fn add_node_metrics(
+        &self,
+        time: SystemTime,
+        samples: Vec<prometheus::Sample>,
+    ) -> Result<(), DbError> {
+        self.inner.put_node_metrics(self.id, time, samples)
+    }
+
+    pub fn set_close_reason(&self, errno: i32) -> Result<(), DbError> {
+        let mut cn = self.inner.fetch_connection(self.id.0)?;
+        cn.close_reason = Some(errno);
+        self.inner.put_cn(self.id, cn)
+    }
+
+    pub fn add_circuit(&self, circuit: Circuit) -> Result<(), DbError> {
+        let mut cn = self.inner.fetch_connection(self.id.0)?;
+        match circuit {
+            Circuit::Connect(peer) | Circuit::Incoming(peer) => {
+                // the connection may be decoded once again
+                if cn.relayed_peers.iter().any(|p| p.peer_id == peer.peer_id) {
+                    return Ok(());
+                }
+                cn.relayed_peers.push(peer);
+            }
+            Circuit::Connected => {
+                let Some(peer) = cn.relayed_peers.iter_mut().rev().find(|p| !p.connected) else {
+                    // the status of a reservation
+                    return Ok(());
+                };
+                peer.connected = true;
+            }
+        }
+        self.inner.put_cn(self.id, cn)
+    }
+
+    pub fn set_noise_protocol(&self, name: &str) -> Result<(), DbError> {
+        let mut cn = self.inner.fetch_connection(self.id.0)?;
+        cn.noise_protocol = name.to_owned();
+        self.inner.put_cn(self.id, cn)
+    }
+
+    pub fn add_ping_rtt(
+        &self,
+        message_id: MessageId,
+        timestamp: SystemTime,
+        rtt: Duration,
+    ) -> Result<(), DbError> {
+        let v = PingRtt {
+            message_id,
+            timestamp,
+            rtt,
+        };
+        self.inner.put_ping_rtt(self.id, v)
+    }
+
     pub fn add_raw(
         &self,
         encryption_status: EncryptionStatus,
@@ -194,7 +391,12 @@ impl DbGroup {
 
 impl Drop for DbGroup {
     fn drop(&mut self) {
+        // the connection is closed when the last clone is dropped
+        if self.replay || Arc::strong_count(&self.clones) != 1 {
+            return;
+        }
         let id = self.id;
+        self.inner.count_connection_close();
         if let Ok(mut cn) = self.inner.fetch_connection(id.0) {
             cn.timestamp_close = SystemTime::now();
             if let Err(err) = self.inner.put_cn(id, cn) {
@@ -217,7 +419,18 @@ impl DbStream {
         stream_kind: StreamKind,
         bytes: &[u8],
     ) -> Result<MessageId, DbError> {
-        let index_ledger_hash = std::env::var("DEBUGGER_INDEX_LEDGER_HASH").is_ok();
+        self.add_with_blocks(did, stream_kind, bytes, vec![])
+    }
+
+    /// The blocks are decoded by the caller, the message is stored once with them.
+    pub fn add_with_blocks(
+        &self,
+        did: &DirectedId,
+        stream_kind: StreamKind,
+        bytes: &[u8],
+        blocks: Vec<BlockProvenance>,
+    ) -> Result<MessageId, DbError> {
+        let index_ledger_hash = LedgerHashIdx::enabled();
 
         let offset = self.group.add_raw(
             EncryptionStatus::DecryptedNoise,
@@ -227,28 +440,38 @@ impl DbStream {
         )?;
 
         let mut ledger_hashes = vec![];
-        let tys = match stream_kind {
-            StreamKind::Unknown => vec![],
-            StreamKind::Meshsub => {
-                let (tys, hashes) = crate::decode::meshsub::parse_types(bytes, index_ledger_hash)?;
-                ledger_hashes = hashes;
-                tys
+        let mut peer_addrs = vec![];
+        let mut topics_subscribed = vec![];
+        let mut node_status = None;
+        let mut gossip_hashes = vec![];
+        let tys = Self::parse_types(
+            stream_kind,
+            bytes,
+            index_ledger_hash,
+            &mut ledger_hashes,
+            &mut peer_addrs,
+            &mut topics_subscribed,
+            &mut node_status,
+            &mut gossip_hashes,
+        );
+        let tys = match tys {
+            Ok(v) => v,
+            Err(err) => {
+                let stream_full_id = StreamFullId {
+                    cn: self.group.id,
+                    id: self.s_id,
+                };
+                self.group
+                    .inner
+                    .put_decode_error(stream_full_id, offset, &err.to_string())?;
+                return Err(err);
             }
-            StreamKind::Kad => crate::decode::kademlia::parse_types(bytes)?,
-            StreamKind::Handshake => crate::decode::noise::parse_types(bytes)?,
-            StreamKind::Rpc => crate::decode::rpc::parse_types(bytes)?,
-            StreamKind::IpfsId => vec![MessageType::Identify],
-            StreamKind::IpfsPush => vec![MessageType::IdentifyPush],
-            // TODO: message type (types)
-            StreamKind::IpfsDelta => vec![],
-            StreamKind::PeerExchange => vec![MessageType::PeerExchange],
-            StreamKind::BitswapExchange => vec![MessageType::BitswapExchange],
-            StreamKind::NodeStatus => vec![MessageType::NodeStatus],
-            StreamKind::Select => vec![MessageType::Select],
-            StreamKind::Mplex => vec![MessageType::Mplex],
-            StreamKind::Yamux => vec![MessageType::Yamux],
         };
 
+        for topic in topics_subscribed {
+            self.group.inner.put_meshsub_topic(self.group.id, &topic)?;
+        }
+
         let id = MessageId(self.group.messages.fetch_add(1, SeqCst));
         let v = Message {
             connection_id: self.group.id,
@@ -258,15 +481,156 @@ impl DbStream {
             timestamp: did.metadata.time,
             offset,
             size: bytes.len() as u32,
-            brief: tys.iter().map(|ty| ty.to_string()).join(","),
+            brief: stream_kind.truncate_brief(tys.iter().map(|ty| ty.to_string()).join(",")),
+            blocks,
+            time_source: did.metadata.time_source,
+            lag_ns: did.metadata.lag.as_nanos() as u64,
+        };
+        if stream_kind == StreamKind::BitswapExchange {
+            let blocks = if stream_kind.decode_disabled() {
+                vec![]
+            } else {
+                crate::decode::bitswap::received_blocks(bytes).unwrap_or_default()
+            };
+            self.group.inner.add_bitswap(
+                self.group.id,
+                did.incoming,
+                did.metadata.time,
+                bytes.len() as u64,
+                &blocks,
+            )?;
+        }
+        for hash in gossip_hashes {
+            self.group.inner.put_gossip_hash_index(hash, id)?;
+        }
+        if matches!(stream_kind, StreamKind::RelayHop | StreamKind::RelayStop)
+            && !stream_kind.decode_disabled()
+        {
+            if let Some(circuit) = crate::decode::relay::circuit(bytes, stream_kind) {
+                self.group.add_circuit(circuit)?;
+            }
+        }
+        // the remote peer's key, it identifies the connection
+        if did.incoming && tys.contains(&MessageType::HandshakePayload) {
+            if let Ok(Some(peer_id)) = crate::decode::noise::peer_id(bytes) {
+                self.group
+                    .inner
+                    .put_peer_id_index(&peer_id, self.group.id)?;
+            }
+        }
+        if let Some(status) = node_status {
+            let peer = status
+                .node_peer_id
+                .clone()
+                .unwrap_or_else(|| self.group.addr.to_string());
+            let record = NodeStatusRecord {
+                connection_id: self.group.id,
+                message_id: id,
+                timestamp: did.metadata.time,
+                status: String::from_utf8_lossy(crate::decode::node_status::payload(bytes))
+                    .into_owned(),
+            };
+            self.group.inner.put_node_status(&peer, record)?;
+        }
+        self.group
+            .inner
+            .put_message(&self.group.addr, id, v, tys, ledger_hashes, peer_addrs)?;
+        self.group
+            .inner
+            .set_total::<{ DbCore::MESSAGES_CNT }>(id.0)?;
+
+        Ok(id)
+    }
+
+    /// The message is counted, but neither its bytes nor its brief are stored.
+    pub fn add_header(
+        &self,
+        did: &DirectedId,
+        stream_kind: StreamKind,
+        size: usize,
+        blocks: Vec<BlockProvenance>,
+    ) -> Result<MessageId, DbError> {
+        let id = MessageId(self.group.messages.fetch_add(1, SeqCst));
+        let v = Message {
+            connection_id: self.group.id,
+            stream_id: self.s_id,
+            stream_kind,
+            incoming: did.incoming,
+            timestamp: did.metadata.time,
+            offset: Message::SAMPLED_OUT,
+            size: size as u32,
+            brief: String::new(),
+            blocks,
+            time_source: did.metadata.time_source,
+            lag_ns: did.metadata.lag.as_nanos() as u64,
         };
         self.group
             .inner
-            .put_message(&self.group.addr, id, v, tys, ledger_hashes)?;
+            .put_message(&self.group.addr, id, v, vec![], vec![], vec![])?;
         self.group
             .inner
             .set_total::<{ DbCore::MESSAGES_CNT }>(id.0)?;
 
         Ok(id)
     }
+
+    pub fn set_window_stats(&self, v: &WindowStats) -> Result<(), DbError> {
+        self.group.inner.put_window_stats(self.group.id, v)
+    }
+
+    pub(super) fn parse_types(
+        stream_kind: StreamKind,
+        bytes: &[u8],
+        index_ledger_hash: bool,
+        ledger_hashes: &mut Vec<LedgerHash>,
+        peer_addrs: &mut Vec<SocketAddr>,
+        topics_subscribed: &mut Vec<String>,
+        node_status: &mut Option<NodeStatus>,
+        gossip_hashes: &mut Vec<[u8; 32]>,
+    ) -> Result<Vec<MessageType>, DbError> {
+        if stream_kind.decode_disabled() {
+            return Ok(vec![]);
+        }
+        let tys = match stream_kind {
+            StreamKind::Unknown => vec![],
+            StreamKind::Meshsub => {
+                let (tys, hashes, topics) =
+                    crate::decode::meshsub::parse_types(bytes, index_ledger_hash)?;
+                *ledger_hashes = hashes;
+                *topics_subscribed = topics;
+                *gossip_hashes = crate::decode::meshsub::message_hashes(bytes)?;
+                tys
+            }
+            StreamKind::Kad => crate::decode::kademlia::parse_types(bytes)?,
+            StreamKind::Handshake => crate::decode::noise::parse_types(bytes)?,
+            StreamKind::Rpc => {
+                let tys = crate::decode::rpc::parse_types(bytes)?;
+                if tys.contains(&MessageType::GetSomeInitialPeers) {
+                    *peer_addrs = crate::decode::rpc::parse_peer_addrs(bytes)?;
+                }
+                tys
+            }
+            StreamKind::IpfsId => vec![MessageType::Identify],
+            StreamKind::IpfsPush => vec![MessageType::IdentifyPush],
+            // TODO: message type (types)
+            StreamKind::IpfsDelta => vec![],
+            StreamKind::Ping => vec![MessageType::IpfsPing],
+            StreamKind::RelayHop | StreamKind::RelayStop => {
+                *peer_addrs = crate::decode::relay::peer_addrs(bytes, stream_kind);
+                crate::decode::relay::parse_types(bytes, stream_kind)
+            }
+            StreamKind::PeerExchange => vec![MessageType::PeerExchange],
+            StreamKind::BitswapExchange => vec![MessageType::BitswapExchange],
+            StreamKind::NodeStatus => {
+                // may be incomplete, the index only gets the statuses that fit in one chunk
+                *node_status = crate::decode::node_status::decode(bytes).ok();
+                vec![MessageType::NodeStatus]
+            }
+            StreamKind::Select => vec![MessageType::Select],
+            StreamKind::Mplex => vec![MessageType::Mplex],
+            StreamKind::Yamux => vec![MessageType::Yamux],
+        };
+
+        Ok(tys)
+    }
 }
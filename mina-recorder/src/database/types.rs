@@ -2,8 +2,12 @@ use std::{
     time::{SystemTime, Duration, UNIX_EPOCH},
     fmt,
     str::FromStr,
-    net::SocketAddr,
+    net::{SocketAddr, IpAddr, Ipv4Addr, Ipv6Addr},
     ops::AddAssign,
+    env,
+    path::PathBuf,
+    collections::BTreeMap,
+    sync::OnceLock,
 };
 
 use mina_p2p_messages::{binprot::BinProtRead, v2, gossip::GossipNetMessageV2};
@@ -12,10 +16,15 @@ use radiation::{Absorb, Emit};
 use serde::{Serialize, Deserialize};
 
 use crate::{
-    event::ConnectionInfo, custom_coding, strace::StraceLine, libp2p_helper::CapnpEvent,
+    event::{ConnectionInfo, TimeSource},
+    custom_coding,
+    strace::StraceLine,
+    libp2p_helper::CapnpEvent,
     meshsub_stats::Hash,
 };
 
+use super::diff::LatencySummary;
+
 #[derive(
     Clone, Copy, Debug, Absorb, Emit, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord,
 )]
@@ -28,6 +37,104 @@ impl fmt::Display for ConnectionId {
     }
 }
 
+/// Identifier of the connection that both peers can compute independently.
+/// Zero means the debugger doesn't know enough to compute it.
+#[derive(Default, Clone, Copy, Debug, Absorb, Emit, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StableConnectionId(pub u64);
+
+impl StableConnectionId {
+    /// The connect time is rounded down to this granularity,
+    /// the clock skew between peers plus the handshake latency must fit in it.
+    pub const TIME_GRANULARITY: Duration = Duration::from_secs(10);
+
+    /// The initiator takes its ephemeral port from its socket, the responder sees it as the remote port.
+    pub fn new(initiator: SocketAddr, responder: SocketAddr, time: SystemTime) -> Self {
+        let secs = Self::secs(time);
+        Self::with_bucket(initiator, responder, secs - secs % Self::granularity())
+    }
+
+    /// The id of the bucket the time falls into and of the nearest adjacent bucket,
+    /// the peers might round the connect time into different buckets.
+    pub fn nearest_buckets(
+        initiator: SocketAddr,
+        responder: SocketAddr,
+        time: SystemTime,
+    ) -> [Self; 2] {
+        let secs = Self::secs(time);
+        let rounded = secs - secs % Self::granularity();
+        let adjacent = if secs % Self::granularity() < Self::granularity() / 2 {
+            rounded.saturating_sub(Self::granularity())
+        } else {
+            rounded + Self::granularity()
+        };
+        [
+            Self::with_bucket(initiator, responder, rounded),
+            Self::with_bucket(initiator, responder, adjacent),
+        ]
+    }
+
+    fn granularity() -> u64 {
+        Self::TIME_GRANULARITY.as_secs()
+    }
+
+    fn secs(time: SystemTime) -> u64 {
+        time.duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+
+    fn with_bucket(initiator: SocketAddr, responder: SocketAddr, rounded: u64) -> Self {
+        use sha2::{Sha256, Digest};
+
+        let ip_octets = |ip: IpAddr| match ip {
+            IpAddr::V4(ip) => ip.to_ipv6_mapped().octets(),
+            IpAddr::V6(ip) => ip.octets(),
+        };
+
+        let hash = Sha256::default()
+            .chain_update(ip_octets(initiator.ip()))
+            .chain_update(initiator.port().to_be_bytes())
+            .chain_update(ip_octets(responder.ip()))
+            .chain_update(responder.port().to_be_bytes())
+            .chain_update(rounded.to_be_bytes())
+            .finalize();
+        let id = u64::from_be_bytes(hash[..8].try_into().expect("cannot fail"));
+        // zero is reserved for unknown
+        StableConnectionId(id.max(1))
+    }
+
+    pub fn is_known(&self) -> bool {
+        self.0 != 0
+    }
+}
+
+impl fmt::Display for StableConnectionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+impl FromStr for StableConnectionId {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        u64::from_str_radix(s, 16).map(StableConnectionId)
+    }
+}
+
+impl Serialize for StableConnectionId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        if self.is_known() {
+            serializer.serialize_some(&self.to_string())
+        } else {
+            serializer.serialize_none()
+        }
+    }
+}
+
 #[derive(Clone, Absorb, Emit, Serialize)]
 pub struct Connection {
     pub info: ConnectionInfo,
@@ -46,6 +153,41 @@ pub struct Connection {
     pub timestamp_close: SystemTime,
 
     pub alias: String,
+
+    // half close by `shutdown`, `UNIX_EPOCH` if the direction is open
+    #[custom_absorb(custom_coding::time_absorb)]
+    #[custom_emit(custom_coding::time_emit)]
+    pub timestamp_shutdown_read: SystemTime,
+    #[custom_absorb(custom_coding::time_absorb)]
+    #[custom_emit(custom_coding::time_emit)]
+    pub timestamp_shutdown_write: SystemTime,
+
+    // noise protocol name once the handshake is verified, empty if not yet known
+    pub noise_protocol: String,
+
+    // clock of the `timestamp` and the lag of the recorder at the moment
+    pub time_source: TimeSource,
+    pub lag_ns: u64,
+
+    // errno of the failed `connect`, the connection was never established, `0` otherwise
+    pub connect_error: i32,
+    // errno of the failed `read` or `write` which ended the connection, `None` otherwise
+    pub close_reason: Option<i32>,
+    // `ConnectionInfo::generation`, tells apart the connections on a reused fd
+    pub generation: u32,
+    // both peers compute the same, zero if unknown
+    pub stable_id: StableConnectionId,
+    // the peers on the other side of the circuits the connection to the relay carries
+    pub relayed_peers: Vec<RelayedPeer>,
+}
+
+#[derive(Clone, Absorb, Serialize, Emit)]
+pub struct RelayedPeer {
+    pub peer_id: String,
+    // multiaddr
+    pub addrs: Vec<String>,
+    // the relay confirmed the circuit
+    pub connected: bool,
 }
 
 impl Connection {
@@ -58,7 +200,49 @@ impl Connection {
         let duration = end.duration_since(self.timestamp).expect("must not fail");
         let stats_in = self.stats_in.calc_speed(duration);
         let stats_out = self.stats_out.calc_speed(duration);
+        let state = if self.connect_error != 0 {
+            "failed"
+        } else if self.timestamp_close != UNIX_EPOCH {
+            "closed"
+        } else {
+            match (
+                self.timestamp_shutdown_read != UNIX_EPOCH,
+                self.timestamp_shutdown_write != UNIX_EPOCH,
+            ) {
+                (false, false) => "open",
+                (false, true) => "write-closed, read-open",
+                (true, false) => "read-closed, write-open",
+                (true, true) => "closed",
+            }
+        };
+        let (noise_pattern, noise_cipher) =
+            match crate::decode::noise::pattern_and_cipher(&self.noise_protocol) {
+                Some((pattern, cipher)) => (pattern.into(), cipher.into()),
+                None => (serde_json::Value::Null, serde_json::Value::Null),
+            };
         let mut v = serde_json::to_value(self).expect("must not fail");
+        v.as_object_mut()
+            .expect("self must be a structure")
+            .insert("state".to_owned(), state.into());
+        let errno_text =
+            |errno: i32| errno_description(errno).map_or(serde_json::Value::Null, Into::into);
+        v.as_object_mut().expect("self must be a structure").insert(
+            "close_reason_text".to_owned(),
+            self.close_reason
+                .map_or(serde_json::Value::Null, errno_text),
+        );
+        if self.connect_error != 0 {
+            v.as_object_mut().expect("self must be a structure").insert(
+                "connect_error_text".to_owned(),
+                errno_text(self.connect_error),
+            );
+        }
+        v.as_object_mut()
+            .expect("self must be a structure")
+            .insert("noise_pattern".to_owned(), noise_pattern);
+        v.as_object_mut()
+            .expect("self must be a structure")
+            .insert("noise_cipher".to_owned(), noise_cipher);
         v.as_object_mut()
             .expect("self must be a structure")
             .insert("stats_in".to_owned(), stats_in);
@@ -70,6 +254,25 @@ impl Connection {
     }
 }
 
+/// Errors which usually end a connection, linux numbers.
+pub fn errno_description(errno: i32) -> Option<&'static str> {
+    let s = match errno {
+        9 => "bad file descriptor",
+        32 => "broken pipe",
+        100 => "network is down",
+        101 => "network is unreachable",
+        103 => "connection aborted",
+        104 => "connection reset by peer",
+        107 => "socket is not connected",
+        110 => "connection timed out",
+        111 => "connection refused",
+        112 => "host is down",
+        113 => "no route to host",
+        _ => return None,
+    };
+    Some(s)
+}
+
 #[derive(Default, Clone, Absorb, Emit, Serialize)]
 pub struct ConnectionStats {
     pub total_bytes: u64,
@@ -122,8 +325,233 @@ impl fmt::Display for StreamFullId {
     }
 }
 
+#[derive(Serialize)]
+pub struct ConnectionCount {
+    pub total: u64,
+    // not closed yet
+    pub active: u64,
+    pub incoming: u64,
+    pub outgoing: u64,
+}
+
+/// Connections grouped by the remote subnet, /24 for IPv4 and /48 for IPv6.
+#[derive(Serialize)]
+pub struct SubnetStats {
+    pub subnet: String,
+    pub connections: u64,
+    // distinct remote ip addresses
+    pub peers: u64,
+}
+
+impl SubnetStats {
+    pub fn subnet_of(ip: IpAddr) -> String {
+        let ip = match ip {
+            IpAddr::V6(ip) => ip
+                .to_ipv4_mapped()
+                .map(IpAddr::V4)
+                .unwrap_or(IpAddr::V6(ip)),
+            ip => ip,
+        };
+        match ip {
+            IpAddr::V4(ip) => {
+                let ip = Ipv4Addr::from(u32::from(ip) & 0xffffff00);
+                format!("{ip}/24")
+            }
+            IpAddr::V6(ip) => {
+                let [a, b, c, ..] = ip.segments();
+                let ip = Ipv6Addr::new(a, b, c, 0, 0, 0, 0, 0);
+                format!("{ip}/48")
+            }
+        }
+    }
+}
+
+/// Decrypt coverage over all connections, tells whether key reconstruction works.
+#[derive(Default, Serialize)]
+pub struct DecryptStats {
+    pub connections: u64,
+    // every byte is decrypted
+    pub full: u64,
+    pub partial: u64,
+    // nothing is decrypted, including connections without any data
+    pub none: u64,
+    pub decrypt_ratio: f64,
+    // the counters of the running recorder, zero if it is not running
+    pub decrypted_bytes: u64,
+    pub failed_to_decrypt_bytes: u64,
+    pub failed_messages: u64,
+    // connections having at least one failed message
+    pub failed_connections: u64,
+}
+
+impl DecryptStats {
+    pub fn new<I>(it: I) -> Self
+    where
+        I: IntoIterator<Item = Connection>,
+    {
+        let mut stats = DecryptStats::default();
+        let (mut total_bytes, mut decrypted_bytes) = (0, 0);
+        for cn in it {
+            let total = cn.stats_in.total_bytes + cn.stats_out.total_bytes;
+            let decrypted = cn.stats_in.decrypted_bytes + cn.stats_out.decrypted_bytes;
+            stats.connections += 1;
+            if decrypted == 0 {
+                stats.none += 1;
+            } else if decrypted >= total {
+                stats.full += 1;
+            } else {
+                stats.partial += 1;
+            }
+            total_bytes += total;
+            decrypted_bytes += decrypted;
+        }
+        if total_bytes != 0 {
+            stats.decrypt_ratio = decrypted_bytes as f64 / total_bytes as f64;
+        }
+        stats
+    }
+}
+
+/// The first decode failure of the connection, where some layer lost the thread.
+#[derive(Absorb, Emit, Serialize)]
+pub struct Desync {
+    pub layer: String,
+    pub incoming: bool,
+    #[custom_absorb(custom_coding::time_absorb)]
+    #[custom_emit(custom_coding::time_emit)]
+    pub timestamp: SystemTime,
+    // offset of the bad byte in the stream of the layer in this direction
+    pub offset: u64,
+    pub error: String,
+    // offset of the first byte of the window
+    pub window_offset: u64,
+    #[serde(skip)]
+    pub window: Vec<u8>,
+}
+
+impl Desync {
+    /// Bytes around the bad byte, before and after.
+    const WINDOW: usize = 64;
+
+    /// `chunk_offset` is the offset of the chunk in the stream, `pos` is the bad byte in the chunk.
+    pub fn new(
+        layer: &str,
+        incoming: bool,
+        timestamp: SystemTime,
+        chunk_offset: u64,
+        chunk: &[u8],
+        pos: usize,
+        error: String,
+    ) -> Self {
+        let pos = pos.min(chunk.len());
+        let start = pos.saturating_sub(Self::WINDOW);
+        let end = (pos + Self::WINDOW).min(chunk.len());
+        Desync {
+            layer: layer.to_owned(),
+            incoming,
+            timestamp,
+            offset: chunk_offset + pos as u64,
+            error,
+            window_offset: chunk_offset + start as u64,
+            window: chunk[start..end].to_vec(),
+        }
+    }
+
+    /// Like `hexdump -C`, the offsets are in the stream.
+    pub fn hexdump(&self) -> Vec<String> {
+        self.window
+            .chunks(16)
+            .enumerate()
+            .map(|(i, line)| {
+                let hex = line
+                    .iter()
+                    .map(|b| format!("{b:02x}"))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                let ascii = line
+                    .iter()
+                    .map(|b| {
+                        if b.is_ascii_graphic() || *b == b' ' {
+                            *b as char
+                        } else {
+                            '.'
+                        }
+                    })
+                    .collect::<String>();
+                let offset = self.window_offset + (i * 16) as u64;
+                format!("{offset:08x}  {hex:<47}  |{ascii}|")
+            })
+            .collect()
+    }
+
+    pub fn post_process(&self) -> serde_json::Value {
+        let mut v = serde_json::to_value(self).expect("must not fail");
+        v.as_object_mut()
+            .expect("self must be a structure")
+            .insert("hexdump".to_owned(), self.hexdump().into());
+        v
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn desync_window() {
+    let chunk = (0..200).map(|i| i as u8).collect::<Vec<_>>();
+    let v = Desync::new(
+        "yamux",
+        true,
+        SystemTime::UNIX_EPOCH,
+        1000,
+        &chunk,
+        100,
+        String::new(),
+    );
+    assert_eq!(v.offset, 1100);
+    assert_eq!(v.window_offset, 1036);
+    assert_eq!(v.window.len(), 128);
+    let lines = v.hexdump();
+    assert_eq!(lines.len(), 8);
+    assert!(lines[0].starts_with("0000040c  24 25 26"));
+    assert!(lines[5].ends_with("|tuvwxyz{|}~.....|"));
+}
+
+/// The outcome of `/admin/reindex`.
+#[derive(Default, Serialize)]
+pub struct ReindexReport {
+    pub messages: u64,
+    // indexed without message types
+    pub decode_errors: u64,
+    // the connection or the bytes of the message are not found, not indexed
+    pub missing: u64,
+}
+
+/// The outcome of `/admin/checkpoint`.
+#[derive(Serialize)]
+pub struct CheckpointReport {
+    pub path: PathBuf,
+    // bytes, the files are hard links, the checkpoint takes little space on its own
+    pub size: u64,
+}
+
+/// The outcome of decoding a connection once again, `/connection/{id}/redecrypt`.
+#[derive(Default, Serialize)]
+pub struct RedecryptReport {
+    // chunks after pnet, the input of noise
+    pub chunks: usize,
+    pub decrypted_before: usize,
+    pub newly_decrypted: usize,
+}
+
+#[derive(Serialize)]
+pub struct ProblematicStream {
+    pub connection_id: ConnectionId,
+    pub stream_id: StreamId,
+    pub failures: u64,
+    pub last_error: String,
+}
+
 #[repr(u16)]
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub enum StreamKind {
     Unknown = 0xffff,
     Handshake = 0x0001,
@@ -131,6 +559,9 @@ pub enum StreamKind {
     IpfsId = 0x0200,
     IpfsPush = 0x0201,
     IpfsDelta = 0x0202,
+    Ping = 0x0203,
+    RelayHop = 0x0204,
+    RelayStop = 0x0205,
     PeerExchange = 0x0300,
     BitswapExchange = 0x0301,
     NodeStatus = 0x0302,
@@ -149,6 +580,9 @@ impl fmt::Display for StreamKind {
             StreamKind::IpfsId => write!(f, "/ipfs/id/1.0.0"),
             StreamKind::IpfsPush => write!(f, "/ipfs/id/push/1.0.0"),
             StreamKind::IpfsDelta => write!(f, "/p2p/id/delta/1.0.0"),
+            StreamKind::Ping => write!(f, "/ipfs/ping/1.0.0"),
+            StreamKind::RelayHop => write!(f, "/libp2p/circuit/relay/0.2.0/hop"),
+            StreamKind::RelayStop => write!(f, "/libp2p/circuit/relay/0.2.0/stop"),
             StreamKind::PeerExchange => write!(f, "/mina/peer-exchange"),
             StreamKind::BitswapExchange => write!(f, "/mina/bitswap-exchange"),
             StreamKind::NodeStatus => write!(f, "/mina/node-status"),
@@ -191,6 +625,9 @@ impl FromStr for StreamKind {
             "/ipfs/id/1.0.0" => Ok(StreamKind::IpfsId),
             "/ipfs/id/push/1.0.0" => Ok(StreamKind::IpfsPush),
             "/p2p/id/delta/1.0.0" => Ok(StreamKind::IpfsDelta),
+            "/ipfs/ping/1.0.0" => Ok(StreamKind::Ping),
+            "/libp2p/circuit/relay/0.2.0/hop" => Ok(StreamKind::RelayHop),
+            "/libp2p/circuit/relay/0.2.0/stop" => Ok(StreamKind::RelayStop),
             "/mina/peer-exchange" => Ok(StreamKind::PeerExchange),
             "/mina/bitswap-exchange" => Ok(StreamKind::BitswapExchange),
             "/mina/node-status" => Ok(StreamKind::NodeStatus),
@@ -212,6 +649,9 @@ impl StreamKind {
             StreamKind::IpfsId,
             StreamKind::IpfsPush,
             StreamKind::IpfsDelta,
+            StreamKind::Ping,
+            StreamKind::RelayHop,
+            StreamKind::RelayStop,
             StreamKind::PeerExchange,
             StreamKind::BitswapExchange,
             StreamKind::NodeStatus,
@@ -224,6 +664,184 @@ impl StreamKind {
         ]
         .into_iter()
     }
+
+    /// Messages of the kind listed in `DECODE_DISABLE` are recorded, but stored and served raw.
+    pub fn decode_disabled(&self) -> bool {
+        self.config().decode_disabled
+    }
+
+    /// With `SAMPLE_MESHSUB=10` only every 10th meshsub message is stored in full, the others
+    /// only as a header, `SAMPLE_MESHSUB_BYTES` is the size from which every message is stored.
+    /// The same for any kind, by its short name as in `listed_in`, e.g. `SAMPLE_RPC`.
+    pub fn sampling(&self) -> Option<Sampling> {
+        let (every, min_bytes) = self.config().sampling?;
+        Some(Sampling {
+            every,
+            min_bytes,
+            seen: 0,
+        })
+    }
+
+    /// Brief of the kind is truncated to the length set in `BRIEF_MAX_LEN`, unlimited by default.
+    pub fn truncate_brief(&self, brief: String) -> String {
+        match self.config().brief_max_len {
+            Some(limit) => truncate_with_ellipsis(brief, limit),
+            None => brief,
+        }
+    }
+
+    // the environment is read once, the settings are the same for the whole run
+    fn config(&self) -> &'static KindConfig {
+        static CONFIG: OnceLock<BTreeMap<StreamKind, KindConfig>> = OnceLock::new();
+        CONFIG
+            .get_or_init(|| {
+                let decode_disable = env::var("DECODE_DISABLE").ok();
+                let brief_max_len = env::var("BRIEF_MAX_LEN").ok();
+                StreamKind::iter()
+                    .map(|kind| {
+                        let config = KindConfig {
+                            decode_disabled: decode_disable
+                                .as_ref()
+                                .map_or(false, |list| kind.listed_in(list)),
+                            sampling: kind.sampling_from_env(),
+                            brief_max_len: brief_max_len
+                                .as_ref()
+                                .and_then(|list| kind.brief_max_len(list)),
+                        };
+                        (kind, config)
+                    })
+                    .collect()
+            })
+            .get(self)
+            .expect("every kind is configured")
+    }
+
+    fn sampling_from_env(&self) -> Option<(u64, Option<usize>)> {
+        let name = self.short_name()?.to_uppercase();
+        let every = env::var(format!("SAMPLE_{name}"))
+            .ok()?
+            .parse::<u64>()
+            .ok()
+            .filter(|n| *n > 1)?;
+        let min_bytes = env::var(format!("SAMPLE_{name}_BYTES"))
+            .ok()
+            .and_then(|s| s.parse().ok());
+        Some((every, min_bytes))
+    }
+
+    // comma separated `kind=length` pairs, the kind is as in `listed_in`
+    fn brief_max_len(&self, list: &str) -> Option<usize> {
+        list.split(',').find_map(|pair| {
+            let (name, len) = pair.split_once('=')?;
+            if self.named(name.trim()) {
+                len.trim().parse().ok()
+            } else {
+                None
+            }
+        })
+    }
+
+    // comma separated short names, or full protocol names
+    fn listed_in(&self, list: &str) -> bool {
+        list.split(',').map(str::trim).any(|name| self.named(name))
+    }
+
+    fn named(&self, name: &str) -> bool {
+        match self.short_name() {
+            Some(short) => name == short || name.parse() == Ok(*self),
+            None => false,
+        }
+    }
+
+    fn short_name(&self) -> Option<&'static str> {
+        let short = match self {
+            StreamKind::Handshake => "noise",
+            StreamKind::Kad => "kad",
+            StreamKind::IpfsId | StreamKind::IpfsPush | StreamKind::IpfsDelta => "identify",
+            StreamKind::Ping => "ping",
+            StreamKind::RelayHop | StreamKind::RelayStop => "relay",
+            StreamKind::PeerExchange => "peer_exchange",
+            StreamKind::BitswapExchange => "bitswap",
+            StreamKind::NodeStatus => "node_status",
+            StreamKind::Meshsub => "meshsub",
+            StreamKind::Rpc => "rpc",
+            StreamKind::Select => "select",
+            StreamKind::Mplex => "mplex",
+            StreamKind::Yamux => "yamux",
+            StreamKind::Unknown => return None,
+        };
+        Some(short)
+    }
+}
+
+struct KindConfig {
+    decode_disabled: bool,
+    sampling: Option<(u64, Option<usize>)>,
+    brief_max_len: Option<usize>,
+}
+
+/// Counts the messages of a stream, decides which are stored in full.
+pub struct Sampling {
+    every: u64,
+    min_bytes: Option<usize>,
+    seen: u64,
+}
+
+impl Sampling {
+    pub fn full(&mut self, len: usize) -> bool {
+        let n = self.seen;
+        self.seen += 1;
+        n % self.every == 0 || self.min_bytes.map_or(false, |min| len >= min)
+    }
+}
+
+// the ellipsis counts toward the `limit`
+fn truncate_with_ellipsis(s: String, limit: usize) -> String {
+    if s.chars().count() <= limit {
+        return s;
+    }
+    let mut s = s.chars().take(limit.saturating_sub(1)).collect::<String>();
+    s.push('…');
+    s
+}
+
+#[cfg(test)]
+#[test]
+fn decode_disabled_list() {
+    let list = "rpc, bitswap,/noise";
+    assert!(StreamKind::Rpc.listed_in(list));
+    assert!(StreamKind::BitswapExchange.listed_in(list));
+    assert!(StreamKind::Handshake.listed_in(list));
+    assert!(!StreamKind::Meshsub.listed_in(list));
+    assert!(!StreamKind::Unknown.listed_in("unknown"));
+}
+
+#[cfg(test)]
+#[test]
+fn sampling_one_in_n() {
+    let mut sampling = Sampling {
+        every: 3,
+        min_bytes: Some(100),
+        seen: 0,
+    };
+    let full = [10, 10, 10, 10, 200, 10, 10]
+        .map(|len| sampling.full(len))
+        .to_vec();
+    assert_eq!(full, [true, false, false, true, true, false, true]);
+}
+
+#[cfg(test)]
+#[test]
+fn brief_truncation() {
+    let list = "rpc=8, meshsub = 4";
+    assert_eq!(StreamKind::Rpc.brief_max_len(list), Some(8));
+    assert_eq!(StreamKind::Meshsub.brief_max_len(list), Some(4));
+    assert_eq!(StreamKind::Kad.brief_max_len(list), None);
+
+    let brief = "publish_new_state,control_ihave".to_owned();
+    assert_eq!(truncate_with_ellipsis(brief.clone(), 8), "publish…");
+    assert_eq!(truncate_with_ellipsis(brief.clone(), 100), brief);
+    assert_eq!(truncate_with_ellipsis(brief, 0), "…");
 }
 
 #[derive(Default, Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
@@ -268,6 +886,132 @@ impl fmt::Display for StreamId {
 #[derive(Clone, Copy, Debug, Absorb, Emit, Serialize, PartialEq, Eq, PartialOrd, Ord)]
 pub struct MessageId(pub u64);
 
+#[derive(Clone, Absorb, Emit, Serialize)]
+pub struct PingRtt {
+    pub message_id: MessageId,
+    #[custom_absorb(custom_coding::time_absorb)]
+    #[custom_emit(custom_coding::time_emit)]
+    pub timestamp: SystemTime,
+    #[custom_absorb(custom_coding::duration_absorb)]
+    #[custom_emit(custom_coding::duration_emit)]
+    pub rtt: Duration,
+}
+
+/// The latest status the peer reported on `/mina/node-status`.
+#[derive(Clone, Absorb, Emit, Serialize)]
+pub struct NodeStatusRecord {
+    pub connection_id: ConnectionId,
+    pub message_id: MessageId,
+    #[custom_absorb(custom_coding::time_absorb)]
+    #[custom_emit(custom_coding::time_emit)]
+    pub timestamp: SystemTime,
+    // json as is, decoded on fetch
+    #[serde(skip)]
+    pub status: String,
+}
+
+/// Peer gating the daemon configured for its libp2p helper, the latest one.
+#[derive(Clone, Absorb, Emit, Serialize)]
+pub struct GatingConfigRecord {
+    #[custom_absorb(custom_coding::addr_absorb)]
+    #[custom_emit(custom_coding::addr_emit)]
+    pub node_address: SocketAddr,
+    #[custom_absorb(custom_coding::time_absorb)]
+    #[custom_emit(custom_coding::time_emit)]
+    pub timestamp: SystemTime,
+    #[serde(flatten)]
+    pub config: GatingConfig,
+}
+
+#[derive(Clone, Absorb, Emit, Serialize)]
+pub struct GatingConfig {
+    pub banned_ips: Vec<String>,
+    pub banned_peer_ids: Vec<String>,
+    pub trusted_ips: Vec<String>,
+    pub trusted_peer_ids: Vec<String>,
+    pub isolate: bool,
+}
+
+#[derive(Emit, Absorb)]
+pub struct PingRttKey {
+    pub cn: ConnectionId,
+    pub message_id: MessageId,
+}
+
+impl fmt::Display for PingRttKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.cn, self.message_id.0)
+    }
+}
+
+#[derive(Emit, Absorb)]
+pub struct WindowStatsKey {
+    pub cn: ConnectionId,
+    // yamux stream id
+    pub stream_id: u32,
+}
+
+impl fmt::Display for WindowStatsKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.cn, self.stream_id)
+    }
+}
+
+/// Bytes transferred in the connection during one second.
+#[derive(Default, Clone, Absorb, Emit)]
+pub struct Bandwidth {
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+}
+
+#[derive(Emit, Absorb)]
+pub struct BandwidthKey {
+    pub cn: ConnectionId,
+    // unix time, seconds
+    pub secs: u64,
+}
+
+impl fmt::Display for BandwidthKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.cn, self.secs)
+    }
+}
+
+#[derive(Serialize)]
+pub struct BandwidthBucket {
+    // unix time, seconds, the beginning of the bucket
+    pub timestamp: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+}
+
+/// Whether the block gossip observed on the wire was also reported by the daemon over IPC.
+#[derive(Serialize)]
+pub struct GossipCorrelation {
+    pub hash: Hash,
+    // unix time, microseconds, the earliest observation
+    pub network_time: Option<u64>,
+    pub ipc_time: Option<u64>,
+    pub in_both: bool,
+    // ipc minus network, microseconds
+    pub delta_microseconds: Option<i64>,
+}
+
+impl GossipCorrelation {
+    pub fn new(hash: Hash, network_time: Option<u64>, ipc_time: Option<u64>) -> Self {
+        let delta_microseconds = network_time
+            .zip(ipc_time)
+            .map(|(network, ipc)| ipc as i64 - network as i64);
+        GossipCorrelation {
+            hash,
+            network_time,
+            ipc_time,
+            in_both: delta_microseconds.is_some(),
+            delta_microseconds,
+        }
+    }
+}
+
 #[derive(Clone, Absorb, Serialize, Emit)]
 pub struct Message {
     pub connection_id: ConnectionId,
@@ -280,6 +1024,252 @@ pub struct Message {
     pub offset: u64,
     pub size: u32,
     pub brief: String,
+    // blocks the message carries, resolved at ingest
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub blocks: Vec<BlockProvenance>,
+    // clock of the `timestamp` and the lag of the recorder at the moment
+    pub time_source: TimeSource,
+    pub lag_ns: u64,
+}
+
+impl Message {
+    /// The `offset` of a message which bytes are not stored, see `StreamKind::sampling`.
+    pub const SAMPLED_OUT: u64 = u64::MAX;
+}
+
+/// The message recorded later, but timestamped earlier than some previous message of the stream.
+#[derive(Serialize)]
+pub struct StreamOrderViolation {
+    pub stream: StreamFullId,
+    pub message_id: u64,
+    // the message with the latest timestamp so far
+    pub previous_id: u64,
+    pub timestamp: SystemTime,
+    pub previous_timestamp: SystemTime,
+    pub backwards_ms: u64,
+}
+
+#[derive(Default, Serialize)]
+pub struct StreamOrderReport {
+    pub streams: u64,
+    pub messages: u64,
+    pub violations: Vec<StreamOrderViolation>,
+}
+
+/// Bitswap traffic of the connection, ledger sync and catchup show up here.
+#[derive(Clone, Absorb, Emit, Serialize)]
+pub struct BitswapSession {
+    pub messages_in: u64,
+    pub messages_out: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    #[custom_absorb(custom_coding::time_absorb)]
+    #[custom_emit(custom_coding::time_emit)]
+    pub first: SystemTime,
+    #[custom_absorb(custom_coding::time_absorb)]
+    #[custom_emit(custom_coding::time_emit)]
+    pub last: SystemTime,
+    // the blocks fetched from the peer
+    pub blocks: u64,
+    pub block_bytes: u64,
+    // the mina blocks the fetched blocks are the parts of
+    pub mina_blocks: Vec<BlockProvenance>,
+}
+
+impl BitswapSession {
+    pub fn new(time: SystemTime) -> Self {
+        BitswapSession {
+            messages_in: 0,
+            messages_out: 0,
+            bytes_in: 0,
+            bytes_out: 0,
+            first: time,
+            last: time,
+            blocks: 0,
+            block_bytes: 0,
+            mina_blocks: vec![],
+        }
+    }
+
+    pub fn observe_block(&mut self, size: u64, block: Option<BlockProvenance>) {
+        self.blocks += 1;
+        self.block_bytes += size;
+        if let Some(block) = block {
+            if !self.mina_blocks.iter().any(|b| b.hash == block.hash) {
+                self.mina_blocks.push(block);
+            }
+        }
+    }
+
+    pub fn observe(&mut self, incoming: bool, time: SystemTime, size: u64) {
+        if incoming {
+            self.messages_in += 1;
+            self.bytes_in += size;
+        } else {
+            self.messages_out += 1;
+            self.bytes_out += size;
+        }
+        self.first = self.first.min(time);
+        self.last = self.last.max(time);
+    }
+
+    pub fn post_process(&self) -> serde_json::Value {
+        let duration = self.last.duration_since(self.first).unwrap_or_default();
+        let mut v = serde_json::to_value(self).expect("must not fail");
+        v.as_object_mut()
+            .expect("self must be a structure")
+            .insert("duration".to_owned(), duration.as_secs_f64().into());
+        v
+    }
+}
+
+/// How often the peer prunes us from its mesh, frequent prunes with long backoff
+/// mean the mesh with the peer is unstable.
+#[derive(Clone, Absorb, Emit, Serialize)]
+pub struct PruneStats {
+    #[custom_absorb(custom_coding::addr_absorb)]
+    #[custom_emit(custom_coding::addr_emit)]
+    pub peer: SocketAddr,
+    pub prunes: u64,
+    // the prune doesn't specify backoff, the default of the peer applies
+    pub without_backoff: u64,
+    pub backoff_total_secs: u64,
+    pub backoff_max_secs: u64,
+    #[custom_absorb(custom_coding::time_absorb)]
+    #[custom_emit(custom_coding::time_emit)]
+    pub first: SystemTime,
+    #[custom_absorb(custom_coding::time_absorb)]
+    #[custom_emit(custom_coding::time_emit)]
+    pub last: SystemTime,
+}
+
+impl PruneStats {
+    pub fn new(peer: SocketAddr, time: SystemTime) -> Self {
+        PruneStats {
+            peer,
+            prunes: 0,
+            without_backoff: 0,
+            backoff_total_secs: 0,
+            backoff_max_secs: 0,
+            first: time,
+            last: time,
+        }
+    }
+
+    pub fn observe(&mut self, time: SystemTime, backoff: Option<u64>) {
+        self.prunes += 1;
+        match backoff {
+            Some(backoff) => {
+                self.backoff_total_secs += backoff;
+                self.backoff_max_secs = self.backoff_max_secs.max(backoff);
+            }
+            None => self.without_backoff += 1,
+        }
+        self.first = self.first.min(time);
+        self.last = self.last.max(time);
+    }
+
+    pub fn post_process(&self) -> serde_json::Value {
+        let with_backoff = self.prunes - self.without_backoff;
+        let backoff_avg_secs = if with_backoff == 0 {
+            0.0
+        } else {
+            self.backoff_total_secs as f64 / with_backoff as f64
+        };
+        let mut v = serde_json::to_value(self).expect("must not fail");
+        v.as_object_mut()
+            .expect("self must be a structure")
+            .insert("backoff_avg_secs".to_owned(), backoff_avg_secs.into());
+        v
+    }
+}
+
+/// The metric the node exposes to Prometheus, as the debugger saw it in the scrape response.
+#[derive(Clone, Absorb, Emit, Serialize)]
+pub struct NodeMetricSample {
+    pub connection_id: ConnectionId,
+    #[custom_absorb(custom_coding::time_absorb)]
+    #[custom_emit(custom_coding::time_emit)]
+    pub timestamp: SystemTime,
+    pub labels: String,
+    pub value: String,
+}
+
+impl NodeMetricSample {
+    pub fn post_process(&self) -> serde_json::Value {
+        let mut v = serde_json::to_value(self).expect("must not fail");
+        // `NaN` and `Inf` are not json numbers, keep them as strings
+        if let Some(value) = self.value.parse::<f64>().ok().filter(|v| v.is_finite()) {
+            v.as_object_mut()
+                .expect("self must be a structure")
+                .insert("value".to_owned(), value.into());
+        }
+        v
+    }
+}
+
+/// Blocks of one producer at some height, more than one block means a fork.
+#[derive(Serialize)]
+pub struct ProducerSummary {
+    pub producer_id: String,
+    pub blocks: usize,
+    pub hashes: Vec<Hash>,
+}
+
+/// The place where the gossip message was seen.
+#[derive(Serialize)]
+pub struct GossipHop {
+    pub message_id: MessageId,
+    pub connection_id: ConnectionId,
+    pub remote_addr: SocketAddr,
+    pub incoming: bool,
+    pub timestamp: SystemTime,
+}
+
+/// How the blocks of one height arrived from the peers.
+#[derive(Serialize)]
+pub struct BlockPropagation {
+    pub height: u32,
+    // the earliest seen first
+    pub blocks: Vec<BlockArrivals>,
+}
+
+/// The arrivals of a single block, the latency is relative to its first arrival.
+#[derive(Serialize)]
+pub struct BlockArrivals {
+    pub hash: Hash,
+    pub producer_id: String,
+    pub first_seen: SystemTime,
+    pub first_peer: SocketAddr,
+    pub latency: LatencySummary,
+    // the fastest peer first
+    pub peers: Vec<PeerArrival>,
+}
+
+/// Inclusion latency of the transactions and snarks in the blocks of the heights.
+#[derive(Serialize)]
+pub struct TxLatencySummary {
+    pub from: u32,
+    pub to: u32,
+    // heights which have the stats, even without transactions
+    pub heights: u32,
+    pub transactions: LatencySummary,
+    pub snarks: LatencySummary,
+}
+
+/// The earliest arrival of the block from the peer.
+#[derive(Serialize)]
+pub struct PeerArrival {
+    pub peer: SocketAddr,
+    pub message_id: u64,
+    // seconds
+    pub latency: f64,
+}
+
+#[derive(Clone, Absorb, Serialize, Emit)]
+pub struct BlockProvenance {
+    pub hash: Hash,
+    pub height: u32,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -657,3 +1647,27 @@ impl CapnpTableRow {
         }
     }
 }
+
+#[cfg(test)]
+#[test]
+fn stable_id_adjacent_bucket() {
+    let initiator = "10.0.0.2:40000".parse().unwrap();
+    let responder = "10.0.0.1:8302".parse().unwrap();
+    let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+
+    // the peers see the connect time on different sides of the bucket boundary
+    let this = StableConnectionId::new(initiator, responder, at(1009));
+    let other = StableConnectionId::new(initiator, responder, at(1011));
+    assert_ne!(this, other);
+    assert_eq!(
+        StableConnectionId::nearest_buckets(initiator, responder, at(1011)),
+        [other, this]
+    );
+    assert_eq!(
+        StableConnectionId::nearest_buckets(initiator, responder, at(1009))[1],
+        other
+    );
+
+    let port = "10.0.0.2:40001".parse().unwrap();
+    assert_ne!(StableConnectionId::new(port, responder, at(1009)), this);
+}
@@ -0,0 +1,298 @@
+use serde::Serialize;
+
+use prost::Message;
+
+use super::DecodeError;
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+mod pb {
+    include!(concat!(env!("OUT_DIR"), "/bitswap.pb.rs"));
+}
+
+use self::pb::message::{wantlist::WantType, BlockPresenceType};
+
+// the cids are rendered in multibase base16, `f` followed by hex
+fn render_cid(cid: &[u8]) -> String {
+    format!("f{}", hex::encode(cid))
+}
+
+/// The blake2b-256 or sha256 digest of a cid rendered by the debugger.
+pub fn cid_digest(cid: &str) -> Option<[u8; 32]> {
+    let cid = hex::decode(cid.strip_prefix('f')?).ok()?;
+    cid.get(cid.len().checked_sub(32)?..)?.try_into().ok()
+}
+
+// the block carries only the prefix of its cid, the digest is computed from the data
+fn block_cid(prefix: &[u8], data: &[u8]) -> Option<Vec<u8>> {
+    use unsigned_varint::{decode, encode};
+    use sha2::{Digest, Sha256};
+    use blake2::{Blake2b, digest::typenum};
+
+    let (version, rest) = decode::u64(prefix).ok()?;
+    let (codec, rest) = decode::u64(rest).ok()?;
+    let (mh_type, rest) = decode::u64(rest).ok()?;
+    let (mh_len, _) = decode::usize(rest).ok()?;
+    let digest = match mh_type {
+        0x12 => Sha256::digest(data).to_vec(),
+        0xb220 => Blake2b::<typenum::U32>::digest(data).to_vec(),
+        _ => return None,
+    };
+    let digest = digest.get(..mh_len)?;
+
+    let mut cid = vec![];
+    // cid v0 is the bare multihash
+    if version != 0 {
+        cid.extend_from_slice(encode::u64(version, &mut encode::u64_buffer()));
+        cid.extend_from_slice(encode::u64(codec, &mut encode::u64_buffer()));
+    }
+    cid.extend_from_slice(encode::u64(mh_type, &mut encode::u64_buffer()));
+    cid.extend_from_slice(encode::usize(mh_len, &mut encode::usize_buffer()));
+    cid.extend_from_slice(digest);
+    Some(cid)
+}
+
+/// A block received over bitswap. Mina splits a blob, e.g. the body of a block, into a tree
+/// of blocks, each starts with the number of links `u16` little endian and the links,
+/// the digests of its children. The digest of the root is the body reference in the header.
+pub struct ReceivedBlock {
+    pub digest: [u8; 32],
+    pub links: Vec<[u8; 32]>,
+    pub size: usize,
+}
+
+pub fn received_blocks(bytes: &[u8]) -> Result<Vec<ReceivedBlock>, DecodeError> {
+    let msg = pb::Message::decode_length_delimited(bytes).map_err(DecodeError::Protobuf)?;
+
+    // sha256 multihash, cid v0
+    let v0 = msg
+        .blocks
+        .into_iter()
+        .map(|data| (vec![0x00, 0x70, 0x12, 0x20], data));
+    let v1 = msg
+        .payload
+        .into_iter()
+        .map(|block| (block.prefix, block.data));
+    let blocks = v0
+        .chain(v1)
+        .filter_map(|(prefix, data)| {
+            let cid = block_cid(&prefix, &data)?;
+            let digest = cid.get(cid.len().checked_sub(32)?..)?.try_into().ok()?;
+            let links = match data.get(..2) {
+                Some(&[a, b]) => {
+                    let n = u16::from_le_bytes([a, b]) as usize;
+                    data.get(2..(2 + n * 32))
+                        .map(|links| {
+                            links
+                                .chunks(32)
+                                .map(|link| link.try_into().expect("cannot fail"))
+                                .collect()
+                        })
+                        .unwrap_or_default()
+                }
+                _ => vec![],
+            };
+            Some(ReceivedBlock {
+                digest,
+                links,
+                size: data.len(),
+            })
+        })
+        .collect();
+    Ok(blocks)
+}
+
+pub fn parse(bytes: &[u8], preview: bool) -> Result<serde_json::Value, DecodeError> {
+    #[derive(Serialize)]
+    #[serde(rename_all = "snake_case")]
+    enum Want {
+        Block,
+        Have,
+    }
+
+    #[derive(Serialize)]
+    struct Entry {
+        cid: String,
+        priority: i32,
+        cancel: bool,
+        want: Want,
+        send_dont_have: bool,
+    }
+
+    #[derive(Serialize)]
+    struct Wantlist {
+        full: bool,
+        entries: Vec<Entry>,
+    }
+
+    #[derive(Serialize)]
+    struct Block {
+        // `None` if the hash function is unknown, then the prefix is here
+        cid: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        prefix: Option<String>,
+        size: usize,
+    }
+
+    #[derive(Serialize)]
+    #[serde(rename_all = "snake_case")]
+    enum Presence {
+        Have,
+        DontHave,
+    }
+
+    #[derive(Serialize)]
+    struct BlockPresence {
+        cid: String,
+        presence: Presence,
+    }
+
+    #[derive(Serialize)]
+    struct T {
+        wantlist: Option<Wantlist>,
+        blocks: Vec<Block>,
+        block_presences: Vec<BlockPresence>,
+        pending_bytes: i32,
+    }
+
+    let msg = pb::Message::decode_length_delimited(bytes).map_err(DecodeError::Protobuf)?;
+
+    if preview {
+        let wants = msg.wantlist.as_ref().map_or(0, |w| w.entries.len());
+        let blocks = msg.blocks.len() + msg.payload.len();
+        return Ok(serde_json::json!({
+            "wants": wants,
+            "blocks": blocks,
+            "block_presences": msg.block_presences.len(),
+        }));
+    }
+
+    let wantlist = msg.wantlist.map(|wantlist| Wantlist {
+        full: wantlist.full,
+        entries: wantlist
+            .entries
+            .into_iter()
+            .map(|entry| Entry {
+                cid: render_cid(&entry.block),
+                priority: entry.priority,
+                cancel: entry.cancel,
+                want: match entry.want_type() {
+                    WantType::Block => Want::Block,
+                    WantType::Have => Want::Have,
+                },
+                send_dont_have: entry.send_dont_have,
+            })
+            .collect(),
+    });
+    let blocks_v0 = msg.blocks.into_iter().map(|data| Block {
+        // sha256 multihash, cid v0
+        cid: block_cid(&[0x00, 0x70, 0x12, 0x20], &data).map(|cid| render_cid(&cid)),
+        prefix: None,
+        size: data.len(),
+    });
+    let blocks = msg.payload.into_iter().map(|block| {
+        let cid = block_cid(&block.prefix, &block.data);
+        Block {
+            prefix: cid.is_none().then(|| hex::encode(&block.prefix)),
+            cid: cid.map(|cid| render_cid(&cid)),
+            size: block.data.len(),
+        }
+    });
+    let block_presences = msg
+        .block_presences
+        .into_iter()
+        .map(|presence| BlockPresence {
+            presence: match presence.r#type() {
+                BlockPresenceType::Have => Presence::Have,
+                BlockPresenceType::DontHave => Presence::DontHave,
+            },
+            cid: render_cid(&presence.cid),
+        })
+        .collect();
+
+    let t = T {
+        wantlist,
+        blocks: blocks_v0.chain(blocks).collect(),
+        block_presences,
+        pending_bytes: msg.pending_bytes,
+    };
+    serde_json::to_value(&t).map_err(DecodeError::Serde)
+}
+
+#[cfg(test)]
+#[test]
+fn decode_exchange() {
+    use self::pb::message;
+
+    // a want for one block, and the response with it, as the node would send
+    let data = b"ledger chunk".to_vec();
+    // cid v1, raw codec, blake2b-256
+    let prefix = vec![0x01, 0x55, 0xa0, 0xe4, 0x02, 0x20];
+    let cid = block_cid(&prefix, &data).unwrap();
+    assert_eq!(&cid[..6], &prefix);
+    assert_eq!(cid.len(), 6 + 32);
+
+    let want = pb::Message {
+        wantlist: Some(message::Wantlist {
+            entries: vec![message::wantlist::Entry {
+                block: cid.clone(),
+                priority: 1,
+                cancel: false,
+                want_type: WantType::Block as i32,
+                send_dont_have: true,
+            }],
+            full: false,
+        }),
+        ..Default::default()
+    };
+    let v = parse(&want.encode_length_delimited_to_vec(), false).unwrap();
+    assert_eq!(v["wantlist"]["entries"][0]["cid"], render_cid(&cid));
+    assert_eq!(v["wantlist"]["entries"][0]["want"], "block");
+
+    let response = pb::Message {
+        payload: vec![message::Block {
+            prefix,
+            data: data.clone(),
+        }],
+        block_presences: vec![message::BlockPresence {
+            cid: vec![0x01, 0x55],
+            r#type: BlockPresenceType::DontHave as i32,
+        }],
+        ..Default::default()
+    };
+    let bytes = response.encode_length_delimited_to_vec();
+    let v = parse(&bytes, false).unwrap();
+    assert_eq!(v["blocks"][0]["cid"], render_cid(&cid));
+    assert_eq!(v["blocks"][0]["size"], data.len());
+    assert_eq!(v["block_presences"][0]["presence"], "dont_have");
+
+    let v = parse(&bytes, true).unwrap();
+    assert_eq!(v["blocks"], 1);
+}
+
+#[cfg(test)]
+#[test]
+fn received_block_links() {
+    use self::pb::message;
+
+    // the root of a mina block body, links to two parts
+    let mut data = 2u16.to_le_bytes().to_vec();
+    data.extend_from_slice(&[1; 32]);
+    data.extend_from_slice(&[2; 32]);
+    data.extend_from_slice(b"the head of the body");
+    let prefix = vec![0x01, 0x55, 0xa0, 0xe4, 0x02, 0x20];
+    let cid = block_cid(&prefix, &data).unwrap();
+
+    let response = pb::Message {
+        payload: vec![message::Block {
+            prefix,
+            data: data.clone(),
+        }],
+        ..Default::default()
+    };
+    let blocks = received_blocks(&response.encode_length_delimited_to_vec()).unwrap();
+    assert_eq!(blocks.len(), 1);
+    assert_eq!(blocks[0].digest, cid[6..]);
+    assert_eq!(blocks[0].links, [[1; 32], [2; 32]]);
+    assert_eq!(blocks[0].size, data.len());
+    assert_eq!(cid_digest(&render_cid(&cid)), Some(blocks[0].digest));
+}
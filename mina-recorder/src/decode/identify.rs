@@ -1,6 +1,6 @@
 use serde::Serialize;
 
-use prost::{bytes::Bytes, Message};
+use prost::Message;
 
 use crate::database::StreamKind;
 
@@ -12,7 +12,7 @@ mod pb {
 }
 
 pub fn parse(
-    bytes: Vec<u8>,
+    bytes: &[u8],
     preview: bool,
     stream_kind: StreamKind,
 ) -> Result<serde_json::Value, DecodeError> {
@@ -33,7 +33,6 @@ pub fn parse(
             Ok(serde_json::Value::String("identify_push".to_string()))
         }
     } else {
-        let buf = Bytes::from(bytes);
         let pb::Identify {
             protocol_version,
             agent_version,
@@ -41,7 +40,8 @@ pub fn parse(
             listen_addrs,
             observed_addr,
             protocols,
-        } = pb::Identify::decode_length_delimited(buf).map_err(DecodeError::Protobuf)?;
+            ..
+        } = pb::Identify::decode_length_delimited(bytes).map_err(DecodeError::Protobuf)?;
 
         let t = T {
             protocol_version,
@@ -58,11 +58,51 @@ pub fn parse(
     }
 }
 
+/// The protocols the peer started or stopped to support.
+pub fn parse_delta(bytes: &[u8], preview: bool) -> Result<serde_json::Value, DecodeError> {
+    #[derive(Serialize)]
+    struct T {
+        added_protocols: Vec<String>,
+        removed_protocols: Vec<String>,
+    }
+
+    if preview {
+        return Ok(serde_json::Value::String("identify_delta".to_string()));
+    }
+    let identify = pb::Identify::decode_length_delimited(bytes).map_err(DecodeError::Protobuf)?;
+    let delta = identify.delta.unwrap_or_default();
+    let t = T {
+        added_protocols: delta.added_protocols,
+        removed_protocols: delta.rm_protocols,
+    };
+    serde_json::to_value(&t).map_err(DecodeError::Serde)
+}
+
+#[cfg(test)]
+#[test]
+fn decode_delta() {
+    let identify = pb::Identify {
+        delta: Some(pb::Delta {
+            added_protocols: vec!["/mina/node-status".to_owned()],
+            rm_protocols: vec!["/ipfs/ping/1.0.0".to_owned()],
+        }),
+        ..Default::default()
+    };
+    let v = parse_delta(&identify.encode_length_delimited_to_vec(), false).unwrap();
+    assert_eq!(
+        v,
+        serde_json::json!({
+            "added_protocols": ["/mina/node-status"],
+            "removed_protocols": ["/ipfs/ping/1.0.0"],
+        })
+    );
+}
+
 #[cfg(test)]
 #[test]
 fn decode_identify() {
     let hex = include_str!("identify.hex");
     let bytes = hex::decode(hex).unwrap();
-    let msg = parse(bytes, false, StreamKind::IpfsId).unwrap();
+    let msg = parse(&bytes, false, StreamKind::IpfsId).unwrap();
     dbg!(msg);
 }
@@ -1,6 +1,8 @@
 use serde::Serialize;
 
 use prost::{bytes::Bytes, Message};
+use libp2p_identity::PeerId;
+use multiaddr::Multiaddr;
 
 use super::{DecodeError, MessageType};
 
@@ -26,7 +28,37 @@ pub fn parse_types(bytes: &[u8]) -> Result<Vec<MessageType>, DecodeError> {
     Ok(vec![ty])
 }
 
-pub fn parse(bytes: Vec<u8>, preview: bool) -> Result<serde_json::Value, DecodeError> {
+/// Base58 if the bytes are a valid peer id, otherwise hex.
+fn render_peer_id(bytes: &[u8]) -> String {
+    match PeerId::from_bytes(bytes) {
+        Ok(id) => id.to_base58(),
+        Err(_) => hex::encode(bytes),
+    }
+}
+
+fn render_multiaddr(bytes: &[u8]) -> String {
+    if let Ok(addr) = Multiaddr::try_from(bytes.to_vec()) {
+        return addr.to_string();
+    }
+    // render what is possible, and the error at the end
+    let mut acc = String::new();
+    let mut input = bytes;
+    while !input.is_empty() {
+        match multiaddr::Protocol::from_bytes(input) {
+            Ok((p, i)) => {
+                input = i;
+                acc = format!("{acc}{p}");
+            }
+            Err(err) => {
+                input = &[];
+                acc = format!("{acc}{err}");
+            }
+        }
+    }
+    acc
+}
+
+pub fn parse(bytes: &[u8], preview: bool) -> Result<serde_json::Value, DecodeError> {
     #[derive(Serialize)]
     #[serde(rename_all = "snake_case")]
     pub enum MessageType {
@@ -81,28 +113,8 @@ pub fn parse(bytes: Vec<u8>, preview: bool) -> Result<serde_json::Value, DecodeE
     impl From<pb::message::Peer> for Peer {
         fn from(v: pb::message::Peer) -> Self {
             Peer {
-                id: hex::encode(&v.id),
-                addrs: v
-                    .addrs
-                    .iter()
-                    .map(|addr| {
-                        let mut acc = String::new();
-                        let mut input = addr.as_slice();
-                        while !input.is_empty() {
-                            match multiaddr::Protocol::from_bytes(input) {
-                                Ok((p, i)) => {
-                                    input = i;
-                                    acc = format!("{acc}{p}");
-                                }
-                                Err(err) => {
-                                    input = &[];
-                                    acc = format!("{acc}{err}");
-                                }
-                            }
-                        }
-                        acc
-                    })
-                    .collect(),
+                id: render_peer_id(&v.id),
+                addrs: v.addrs.iter().map(|addr| render_multiaddr(addr)).collect(),
                 connection: match v.connection() {
                     pb::message::ConnectionType::NotConnected => ConnectionType::NotConnected,
                     pb::message::ConnectionType::Connected => ConnectionType::Connected,
@@ -123,9 +135,8 @@ pub fn parse(bytes: Vec<u8>, preview: bool) -> Result<serde_json::Value, DecodeE
         provider_peers: Vec<Peer>,
     }
 
-    let buf = Bytes::from(bytes);
     let msg =
-        <pb::Message as Message>::decode_length_delimited(buf).map_err(DecodeError::Protobuf)?;
+        <pb::Message as Message>::decode_length_delimited(bytes).map_err(DecodeError::Protobuf)?;
 
     let t = T {
         r#type: match msg.r#type() {
@@ -148,3 +159,23 @@ pub fn parse(bytes: Vec<u8>, preview: bool) -> Result<serde_json::Value, DecodeE
         serde_json::to_value(&t).map_err(DecodeError::Serde)
     }
 }
+
+#[cfg(test)]
+#[test]
+fn decode_find_node_response() {
+    // find node response, a single closer peer
+    let hex = "6008041226002408011220da91decf6f4c769327ca8ff03986e66fcfe6c59dca63d68c5ee359e52f8dc6e642340a26002408011220da91decf6f4c769327ca8ff03986e66fcfe6c59dca63d68c5ee359e52f8dc6e612080487b5d91706206e1801";
+    let bytes = hex::decode(hex).unwrap();
+    assert!(parse_types(&bytes).unwrap() == [MessageType::FindNode]);
+
+    let value = parse(&bytes, false).unwrap();
+    assert_eq!(value["type"], "find_node");
+    let peer = &value["closer_peers"][0];
+    assert_eq!(
+        peer["id"],
+        "12D3KooWQXa4AdCEZWe9QwoHnrANyMAXirozBdroNHkkvTMhT8bf"
+    );
+    assert_eq!(peer["addrs"][0], "/ip4/135.181.217.23/tcp/8302");
+    assert_eq!(peer["connection"], "connected");
+    assert_eq!(value["provider_peers"].as_array().unwrap().len(), 0);
+}
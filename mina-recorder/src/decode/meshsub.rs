@@ -1,4 +1,4 @@
-use std::io::Cursor;
+use std::{collections::BTreeMap, env, io::Cursor, sync::OnceLock};
 
 use libp2p_core::PeerId;
 use mina_p2p_messages::{
@@ -20,6 +20,110 @@ mod pb {
     include!(concat!(env!("OUT_DIR"), "/gossipsub.pb.rs"));
 }
 
+/// The wire format of messages published in the topic.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TopicFormat {
+    /// Try `GossipNetMessageV2`, then the testing message, then `GossipNetMessageV1`.
+    Auto,
+    V1,
+    V2,
+    /// Do not decode, show the bytes as is.
+    Raw,
+}
+
+/// Mapping from the topic to the expected format of the message.
+/// Configured by `DEBUGGER_MESHSUB_TOPICS` environment variable, for example
+/// `DEBUGGER_MESHSUB_TOPICS=my-fork/blocks=v2,my-fork/test=raw`.
+/// The topics not mentioned in the mapping are decoded as before.
+#[derive(Default)]
+pub struct TopicFormats(BTreeMap<String, TopicFormat>);
+
+impl TopicFormats {
+    /// Parsed once, the mapping is the same for the whole run.
+    pub fn from_env() -> &'static Self {
+        static FORMATS: OnceLock<TopicFormats> = OnceLock::new();
+        FORMATS.get_or_init(|| match env::var("DEBUGGER_MESHSUB_TOPICS") {
+            Ok(s) => Self::parse(&s),
+            Err(_) => Self::default(),
+        })
+    }
+
+    fn parse(s: &str) -> Self {
+        let mut map = BTreeMap::new();
+        for item in s.split(',').filter(|item| !item.is_empty()) {
+            let Some((topic, format)) = item.rsplit_once('=') else {
+                log::warn!("bad meshsub topic mapping {item}, expected `topic=format`");
+                continue;
+            };
+            let format = match format {
+                "auto" => TopicFormat::Auto,
+                "v1" => TopicFormat::V1,
+                "v2" => TopicFormat::V2,
+                "raw" => TopicFormat::Raw,
+                _ => {
+                    log::warn!("bad meshsub topic format {format}, expected v1, v2, raw or auto");
+                    continue;
+                }
+            };
+            map.insert(topic.to_owned(), format);
+        }
+        TopicFormats(map)
+    }
+
+    pub fn get(&self, topic: &str) -> TopicFormat {
+        self.0.get(topic).cloned().unwrap_or(TopicFormat::Auto)
+    }
+}
+
+/// Topics mina uses, resolvable even if never seen on the connection.
+const KNOWN_TOPICS: [&str; 4] = [
+    "coda/consensus-messages/0.0.1",
+    "mina/block/1.0.0",
+    "mina/tx/1.0.0",
+    "mina/snark-work/1.0.0",
+];
+
+/// Maps topic ids back to topic names. Depending on gossipsub configuration the id is either
+/// the name itself, or base64 of sha256 of the topic descriptor, the latter is unreadable.
+#[derive(Default)]
+pub struct TopicRegistry(BTreeMap<String, String>);
+
+impl TopicRegistry {
+    /// The `names` are topics subscribed on the connection.
+    pub fn new<I>(names: I) -> Self
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut registry = TopicRegistry::default();
+        let configured = TopicFormats::from_env().0.keys().cloned();
+        let known = KNOWN_TOPICS.iter().map(|s| s.to_string());
+        for name in known.chain(configured).chain(names) {
+            registry.observe(&name);
+        }
+        registry
+    }
+
+    pub fn observe(&mut self, name: &str) {
+        self.0.insert(Self::hashed_id(name), name.to_owned());
+    }
+
+    pub fn resolve(&self, id: &str) -> Option<&str> {
+        self.0.get(id).map(String::as_str)
+    }
+
+    fn hashed_id(name: &str) -> String {
+        use base64::{engine::general_purpose::STANDARD, Engine};
+        use sha2::{Digest, Sha256};
+
+        let descriptor = pb::TopicDescriptor {
+            name: Some(name.to_owned()),
+            auth: None,
+            enc: None,
+        };
+        STANDARD.encode(Sha256::digest(descriptor.encode_to_vec()))
+    }
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "type")]
@@ -53,6 +157,7 @@ pub enum Event {
     PublishPreview {
         topic: String,
         message: GossipNetMessagePreview,
+        version: GossipVersion,
     },
     PublishTestingMessage {
         from: PeerId,
@@ -60,18 +165,36 @@ pub enum Event {
         message: String,
         hash: [u8; 32],
     },
+    PublishRaw {
+        from: Option<String>,
+        topic: String,
+        data: String,
+    },
     Control {
         ihave: Vec<ControlIHave>,
         iwant: Vec<ControlIWant>,
         graft: Vec<ControlGraft>,
         prune: Vec<ControlPrune>,
+        idontwant: Vec<ControlIDontWant>,
     },
 }
 
+/// The binprot version the published message was decoded with,
+/// both versions coexist on the network during an upgrade.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GossipVersion {
+    V1,
+    V2,
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "snake_case")]
 pub struct ControlIHave {
     topic_id: Option<String>,
+    // resolved name if the id is hashed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    topic: Option<String>,
     message_ids: Vec<String>,
 }
 
@@ -97,20 +220,38 @@ impl ControlIWant {
     }
 }
 
+/// Gossipsub v1.2, the peer already has these messages, do not send them.
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct ControlIDontWant {
+    message_ids: Vec<String>,
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "snake_case")]
 pub struct ControlGraft {
     topic_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    topic: Option<String>,
 }
 
 #[derive(Serialize)]
 #[serde(rename_all = "snake_case")]
 pub struct ControlPrune {
     topic_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    topic: Option<String>,
     peers: Vec<PeerInfo>,
     backoff: Option<u64>,
 }
 
+impl ControlPrune {
+    /// Seconds the pruned peer should wait before graft again.
+    pub fn backoff(&self) -> Option<u64> {
+        self.backoff
+    }
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "snake_case")]
 pub struct PeerInfo {
@@ -198,16 +339,22 @@ impl SnarkWithHash {
     }
 }
 
+/// Returns also topics subscribed in the message.
 pub fn parse_types(
     bytes: &[u8],
     index_ledger_hash: bool,
-) -> Result<(Vec<MessageType>, Vec<LedgerHash>), DecodeError> {
+) -> Result<(Vec<MessageType>, Vec<LedgerHash>, Vec<String>), DecodeError> {
     let buf = Bytes::from(bytes.to_vec());
     let pb::Rpc {
         subscriptions,
         publish,
         control,
     } = Message::decode_length_delimited(buf).map_err(DecodeError::Protobuf)?;
+    let topics_subscribed = subscriptions
+        .iter()
+        .filter(|v| v.subscribe())
+        .filter_map(|v| v.topic_id.clone())
+        .collect();
     let subscriptions = subscriptions.into_iter().map(|v| {
         if v.subscribe() {
             MessageType::Subscribe
@@ -216,8 +363,10 @@ pub fn parse_types(
         }
     });
     let mut ledger_hashes = vec![];
+    let topics = TopicFormats::from_env();
     let publish = publish
         .into_iter()
+        .filter(|msg| topics.get(&msg.topic) != TopicFormat::Raw)
         .filter_map(|msg| msg.data)
         .filter_map(|data| Some((data.get(8).cloned()?, data)))
         .filter_map(|(tag, data)| match tag {
@@ -349,18 +498,77 @@ pub fn parse_types(
         if !c.prune.is_empty() {
             control_types.push(MessageType::ControlPrune);
         }
+        if !c.idontwant.is_empty() {
+            control_types.push(MessageType::ControlIDontWant);
+        }
     }
 
     let tys = subscriptions.chain(control_types).chain(publish).collect();
 
-    Ok((tys, ledger_hashes))
+    Ok((tys, ledger_hashes, topics_subscribed))
 }
 
-pub fn parse(bytes: Vec<u8>, preview: bool) -> Result<serde_json::Value, DecodeError> {
-    let t = parse_it(&bytes, preview, false)?.collect::<Vec<_>>();
+pub fn parse(
+    bytes: &[u8],
+    preview: bool,
+    topics: &TopicRegistry,
+) -> Result<serde_json::Value, DecodeError> {
+    let resolve = |id: &Option<String>| Some(topics.resolve(id.as_deref()?)?.to_owned());
+
+    let mut t = parse_it(bytes, preview, false)?.collect::<Vec<_>>();
+    for event in &mut t {
+        if let Event::Control {
+            ihave,
+            graft,
+            prune,
+            ..
+        } = event
+        {
+            ihave
+                .iter_mut()
+                .for_each(|c| c.topic = resolve(&c.topic_id));
+            graft
+                .iter_mut()
+                .for_each(|c| c.topic = resolve(&c.topic_id));
+            prune
+                .iter_mut()
+                .for_each(|c| c.topic = resolve(&c.topic_id));
+        }
+    }
     serde_json::to_value(&t).map_err(DecodeError::Serde)
 }
 
+/// The gossipsub message id, blake2b mac of the data keyed by the topic.
+pub fn message_hash(topic: &str, data: &[u8]) -> [u8; 32] {
+    use blake2::digest::{Mac, Update, FixedOutput, typenum};
+
+    let key;
+    let key = if topic.as_bytes().len() <= 64 {
+        topic.as_bytes()
+    } else {
+        key = blake2::Blake2b::<typenum::U32>::default()
+            .chain(topic.as_bytes())
+            .finalize_fixed();
+        key.as_slice()
+    };
+    blake2::Blake2bMac::<typenum::U32>::new_from_slice(key)
+        .expect("cannot fail, length is statically known")
+        .chain(data)
+        .finalize_fixed()
+        .into()
+}
+
+/// Ids of the messages published in the rpc, without decoding the messages.
+pub fn message_hashes(bytes: &[u8]) -> Result<Vec<[u8; 32]>, DecodeError> {
+    let pb::Rpc { publish, .. } =
+        Message::decode_length_delimited(bytes).map_err(DecodeError::Protobuf)?;
+
+    Ok(publish
+        .into_iter()
+        .filter_map(|m| Some(message_hash(&m.topic, &m.data?)))
+        .collect())
+}
+
 pub fn parse_protobuf_publish(
     bytes: &[u8],
 ) -> Result<impl Iterator<Item = Vec<u8>>, prost::DecodeError> {
@@ -388,6 +596,7 @@ pub fn parse_it(
             Event::Unsubscribe { topic }
         }
     });
+    let topics = TopicFormats::from_env();
     let publish = publish
         .into_iter()
         .filter_map(|msg| {
@@ -401,9 +610,27 @@ pub fn parse_it(
             ))
         })
         .filter_map(move |(data, topic, from, seqno, signature, key)| {
+            let format = topics.get(&topic);
+            if format == TopicFormat::Raw {
+                return Some(Event::PublishRaw {
+                    from: from.map(hex::encode),
+                    topic,
+                    data: hex::encode(data),
+                });
+            }
+
             let mut c = Cursor::new(&data[8..]);
-            match GossipNetMessageV2::binprot_read(&mut c) {
-                Ok(msg) => {
+            let v2 = if format == TopicFormat::V1 {
+                None
+            } else {
+                Some(GossipNetMessageV2::binprot_read(&mut c))
+            };
+            // on failure fall back to the older version silently,
+            // the error is only reported if no version fits
+            let mut v2_err = None;
+            match v2 {
+                None => (),
+                Some(Ok(msg)) => {
                     let message = Box::new(msg);
                     if preview {
                         let message = match &*message {
@@ -415,25 +642,14 @@ pub fn parse_it(
                                 GossipNetMessagePreview::TransactionPoolDiff
                             }
                         };
-                        return Some(Event::PublishPreview { topic, message });
+                        return Some(Event::PublishPreview {
+                            topic,
+                            message,
+                            version: GossipVersion::V2,
+                        });
                     } else {
                         let hash = if calc_hash {
-                            use blake2::digest::{Mac, Update, FixedOutput, typenum};
-
-                            let key;
-                            let key = if topic.as_bytes().len() <= 64 {
-                                topic.as_bytes()
-                            } else {
-                                key = blake2::Blake2b::<typenum::U32>::default()
-                                    .chain(topic.as_bytes())
-                                    .finalize_fixed();
-                                key.as_slice()
-                            };
-                            blake2::Blake2bMac::<typenum::U32>::new_from_slice(key)
-                                .expect("cannot fail, length is statically known")
-                                .chain(data)
-                                .finalize_fixed()
-                                .into()
+                            message_hash(&topic, &data)
                         } else {
                             [0; 32]
                         };
@@ -448,33 +664,25 @@ pub fn parse_it(
                         });
                     }
                 }
-                Err(err) => log::error!("decode {err}"),
+                Some(Err(err)) => v2_err = Some(err),
             }
 
             let mut c = Cursor::<&[u8]>::new(&data[8..]);
 
-            if let Some(3) = c.get_ref().first() {
+            if format == TopicFormat::V2 {
+                if let Some(err) = v2_err {
+                    log::error!("decode {err}");
+                }
+                return None;
+            }
+
+            if let (TopicFormat::Auto, Some(3)) = (format, c.get_ref().first()) {
                 let bytes = c.get_ref()[1..].to_vec();
                 let message = String::from_utf8(bytes).ok()?;
                 let from = PeerId::from_bytes(&from?).ok()?;
 
                 let hash = if calc_hash {
-                    use blake2::digest::{Mac, Update, FixedOutput, typenum};
-
-                    let key;
-                    let key = if topic.as_bytes().len() <= 64 {
-                        topic.as_bytes()
-                    } else {
-                        key = blake2::Blake2b::<typenum::U32>::default()
-                            .chain(topic.as_bytes())
-                            .finalize_fixed();
-                        key.as_slice()
-                    };
-                    blake2::Blake2bMac::<typenum::U32>::new_from_slice(key)
-                        .expect("cannot fail, length is statically known")
-                        .chain(data)
-                        .finalize_fixed()
-                        .into()
+                    message_hash(&topic, &data)
                 } else {
                     [0; 32]
                 };
@@ -500,7 +708,11 @@ pub fn parse_it(
                                 GossipNetMessagePreview::TransactionPoolDiff
                             }
                         };
-                        return Some(Event::PublishPreview { topic, message });
+                        return Some(Event::PublishPreview {
+                            topic,
+                            message,
+                            version: GossipVersion::V1,
+                        });
                     } else {
                         return Some(Event::Publish {
                             from: from.map(hex::encode),
@@ -512,7 +724,10 @@ pub fn parse_it(
                         });
                     }
                 }
-                Err(err) => log::error!("decode {err}"),
+                Err(err) => match v2_err {
+                    Some(v2_err) => log::error!("decode v2: {v2_err}, v1: {err}"),
+                    None => log::error!("decode {err}"),
+                },
             }
 
             None
@@ -523,11 +738,13 @@ pub fn parse_it(
              iwant,
              graft,
              prune,
+             idontwant,
          }| Event::Control {
             ihave: ihave
                 .into_iter()
                 .map(|m| ControlIHave {
                     topic_id: m.topic_id,
+                    topic: None,
                     message_ids: m.message_ids.into_iter().map(hex::encode).collect(),
                 })
                 .collect(),
@@ -541,12 +758,14 @@ pub fn parse_it(
                 .into_iter()
                 .map(|m| ControlGraft {
                     topic_id: m.topic_id,
+                    topic: None,
                 })
                 .collect(),
             prune: prune
                 .into_iter()
                 .map(|m| ControlPrune {
                     topic_id: m.topic_id,
+                    topic: None,
                     peers: m
                         .peers
                         .into_iter()
@@ -558,6 +777,12 @@ pub fn parse_it(
                     backoff: m.backoff,
                 })
                 .collect(),
+            idontwant: idontwant
+                .into_iter()
+                .map(|m| ControlIDontWant {
+                    message_ids: m.message_ids.into_iter().map(hex::encode).collect(),
+                })
+                .collect(),
         },
     );
 
@@ -597,6 +822,33 @@ mod tests {
     use std::io::Cursor;
     use mina_p2p_messages::{binprot::BinProtRead, gossip::GossipNetMessageV2};
 
+    #[test]
+    fn resolve_hashed_topic() {
+        use prost::Message as _;
+
+        use super::{pb, parse, TopicRegistry};
+
+        let registry = TopicRegistry::new(["my-fork/blocks".to_owned()]);
+        let graft = |name: &str| pb::ControlGraft {
+            topic_id: Some(TopicRegistry::hashed_id(name)),
+        };
+        let msg = pb::Rpc {
+            subscriptions: vec![],
+            publish: vec![],
+            control: Some(pb::ControlMessage {
+                ihave: vec![],
+                iwant: vec![],
+                graft: vec![graft("mina/block/1.0.0"), graft("my-fork/blocks")],
+                prune: vec![],
+                idontwant: vec![],
+            }),
+        };
+        let value = parse(&msg.encode_length_delimited_to_vec(), false, &registry).unwrap();
+        let graft = &value[0]["graft"];
+        assert_eq!(graft[0]["topic"], "mina/block/1.0.0");
+        assert_eq!(graft[1]["topic"], "my-fork/blocks");
+    }
+
     #[test]
     fn parse_new_berkeley_2() {
         let hex_str = include_str!("test_data_2.hex");
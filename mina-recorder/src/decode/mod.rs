@@ -5,11 +5,20 @@ pub mod rpc;
 pub mod identify;
 pub mod json_string;
 pub mod yamux;
+pub mod ping;
+pub mod relay;
+pub mod node_status;
 pub mod meshsub_stats;
+pub mod prometheus;
+pub mod bitswap;
+pub mod multistream_select;
 
 mod utils;
 
-use std::{fmt, str::FromStr, string::FromUtf8Error};
+use std::{
+    fmt,
+    str::{FromStr, Utf8Error},
+};
 
 use serde::{Serialize, Deserialize};
 use radiation::{Absorb, Emit, nom, ParseError};
@@ -29,7 +38,7 @@ pub enum DecodeError {
     #[error("{_0}")]
     Protobuf(prost::DecodeError),
     #[error("{_0}")]
-    Utf8(FromUtf8Error),
+    Utf8(Utf8Error),
     #[error("wrong size: {actual} != {expected}")]
     UnexpectedSize { actual: usize, expected: usize },
     #[error("parse error {_0}")]
@@ -77,6 +86,7 @@ pub enum MessageType {
     ControlIWant,
     ControlGraft,
     ControlPrune,
+    ControlIDontWant,
     // kademlia
     #[tag(0x0200)]
     PutValue,
@@ -120,6 +130,13 @@ pub enum MessageType {
     Mplex,
     #[tag(0x0b00)]
     Yamux,
+    #[tag(0x0c00)]
+    IpfsPing,
+    // circuit relay
+    #[tag(0x0d00)]
+    RelayReserve,
+    RelayConnect,
+    RelayStatus,
 }
 
 impl fmt::Display for MessageType {
@@ -134,6 +151,7 @@ impl fmt::Display for MessageType {
             MessageType::ControlIWant => write!(f, "meshsub_iwant"),
             MessageType::ControlGraft => write!(f, "meshsub_graft"),
             MessageType::ControlPrune => write!(f, "meshsub_prune"),
+            MessageType::ControlIDontWant => write!(f, "meshsub_idontwant"),
             MessageType::PutValue => write!(f, "put_value"),
             MessageType::GetValue => write!(f, "get_value"),
             MessageType::AddProvider => write!(f, "add_provider"),
@@ -164,6 +182,10 @@ impl fmt::Display for MessageType {
             MessageType::Select => write!(f, "select"),
             MessageType::Mplex => write!(f, "mplex"),
             MessageType::Yamux => write!(f, "yamux"),
+            MessageType::IpfsPing => write!(f, "ipfs_ping"),
+            MessageType::RelayReserve => write!(f, "relay_reserve"),
+            MessageType::RelayConnect => write!(f, "relay_connect"),
+            MessageType::RelayStatus => write!(f, "relay_status"),
         }
     }
 }
@@ -202,6 +224,7 @@ impl FromStr for MessageType {
             "meshsub_iwant" => Ok(MessageType::ControlIWant),
             "meshsub_graft" => Ok(MessageType::ControlGraft),
             "meshsub_prune" => Ok(MessageType::ControlPrune),
+            "meshsub_idontwant" => Ok(MessageType::ControlIDontWant),
             "put_value" => Ok(MessageType::PutValue),
             "get_value" => Ok(MessageType::GetValue),
             "add_provider" => Ok(MessageType::AddProvider),
@@ -232,6 +255,10 @@ impl FromStr for MessageType {
             "select" => Ok(MessageType::Select),
             "mplex" => Ok(MessageType::Mplex),
             "yamux" => Ok(MessageType::Yamux),
+            "ipfs_ping" => Ok(MessageType::IpfsPing),
+            "relay_reserve" => Ok(MessageType::RelayReserve),
+            "relay_connect" => Ok(MessageType::RelayConnect),
+            "relay_status" => Ok(MessageType::RelayStatus),
             _ => Err(()),
         }
     }
@@ -0,0 +1,64 @@
+use std::collections::VecDeque;
+
+use serde::Serialize;
+
+use super::DecodeError;
+
+/// Each side proposes protocols, the other side either echoes the protocol or answers `na`.
+#[derive(Default, Serialize)]
+pub struct Negotiation {
+    // this message
+    pub token: String,
+    pub proposed: Vec<String>,
+    pub accepted: Option<String>,
+    pub rejected: Vec<String>,
+}
+
+impl Negotiation {
+    /// The tokens of the whole stream in order, with the direction.
+    pub fn new<I>(token: String, tokens: I) -> Self
+    where
+        I: IntoIterator<Item = (bool, String)>,
+    {
+        let mut v = Negotiation {
+            token,
+            ..Default::default()
+        };
+        // proposals waiting for the answer, outgoing and incoming,
+        // the answers come in the same order, a proposal may be sent before the previous is answered
+        let mut pending = [VecDeque::new(), VecDeque::new()];
+        for (incoming, token) in tokens {
+            let (this, other) = (incoming as usize, !incoming as usize);
+            match token.as_str() {
+                "ls" | "initiator" | "responder" => (),
+                s if s.starts_with("/multistream/") || s.starts_with("select:") => (),
+                "na" => v.rejected.extend(pending[other].pop_front()),
+                s if pending[other].front().map(String::as_str) == Some(s) => {
+                    pending[other].pop_front();
+                    v.accepted = Some(token);
+                }
+                _ => {
+                    v.proposed.push(token.clone());
+                    pending[this].push_back(token);
+                }
+            }
+        }
+        v
+    }
+}
+
+/// The message is a single token, `stream` is every token of the stream, not needed for preview.
+pub fn parse(
+    bytes: &[u8],
+    preview: bool,
+    stream: Vec<(bool, String)>,
+) -> Result<serde_json::Value, DecodeError> {
+    let token = std::str::from_utf8(bytes)
+        .map_err(DecodeError::Utf8)?
+        .to_owned();
+    if preview {
+        Ok(serde_json::Value::String(token))
+    } else {
+        serde_json::to_value(Negotiation::new(token, stream)).map_err(DecodeError::Serde)
+    }
+}
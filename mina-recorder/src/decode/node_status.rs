@@ -0,0 +1,102 @@
+use std::collections::BTreeMap;
+
+use serde::{Serialize, Deserialize};
+
+use super::{DecodeError, MessageType};
+
+/// The status the node reports on `/mina/node-status`, it is a json object.
+/// The fields come and go between versions, so all of them are optional
+/// and the unknown ones are kept as is.
+#[derive(Serialize, Deserialize)]
+pub struct NodeStatus {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node_ip_addr: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node_peer_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sync_status: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol_state_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git_commit: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uptime_minutes: Option<u64>,
+    // older versions report no height
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_height_opt: Option<u64>,
+    #[serde(flatten)]
+    pub other: BTreeMap<String, serde_json::Value>,
+}
+
+/// The json document, without the varint length prefix if there is one.
+pub fn payload(bytes: &[u8]) -> &[u8] {
+    // `{` is a valid one byte varint, check it first
+    if bytes.starts_with(b"{") {
+        return bytes;
+    }
+    match unsigned_varint::decode::usize(bytes) {
+        Ok((length, remaining)) if length == remaining.len() => remaining,
+        _ => bytes,
+    }
+}
+
+pub fn decode(bytes: &[u8]) -> Result<NodeStatus, DecodeError> {
+    serde_json::from_slice(payload(bytes)).map_err(DecodeError::Serde)
+}
+
+pub fn parse(bytes: &[u8], preview: bool) -> Result<serde_json::Value, DecodeError> {
+    let status = match decode(bytes) {
+        Ok(v) => v,
+        // the status may be split in several chunks
+        Err(_) if preview => {
+            return serde_json::to_value(MessageType::NodeStatus).map_err(DecodeError::Serde)
+        }
+        Err(_) => return Ok(serde_json::Value::String(hex::encode(bytes))),
+    };
+    if preview {
+        Ok(serde_json::json!({
+            "sync_status": status.sync_status,
+            "best_tip": status.protocol_state_hash,
+        }))
+    } else {
+        serde_json::to_value(&status).map_err(DecodeError::Serde)
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn decode_versions() {
+    let old = br#"{"node_ip_addr":"1.2.3.4","node_peer_id":"12D3KooWA","sync_status":["Synced"],"peers":[]}"#;
+    let status = decode(old).unwrap();
+    assert_eq!(status.node_peer_id.as_deref(), Some("12D3KooWA"));
+    assert_eq!(status.block_height_opt, None);
+    assert!(status.other.contains_key("peers"));
+
+    let new = br#"{"node_peer_id":"12D3KooWA","git_commit":"abc","uptime_minutes":5,"block_height_opt":42}"#;
+    let status = decode(new).unwrap();
+    assert_eq!(status.block_height_opt, Some(42));
+    assert_eq!(status.uptime_minutes, Some(5));
+}
+
+#[cfg(test)]
+#[test]
+fn parse_length_prefixed() {
+    let json = br#"{"node_peer_id":"12D3KooWA","sync_status":"Synced","protocol_state_hash":"3NKa","block_height_opt":42,"peers":["12D3KooWB"]}"#;
+    let mut bytes =
+        unsigned_varint::encode::usize(json.len(), &mut unsigned_varint::encode::usize_buffer())
+            .to_vec();
+    bytes.extend_from_slice(json);
+
+    let full = parse(&bytes, false).unwrap();
+    let expected = serde_json::from_slice::<serde_json::Value>(json).unwrap();
+    assert_eq!(full, expected);
+
+    let preview = parse(&bytes, true).unwrap();
+    assert_eq!(
+        preview,
+        serde_json::json!({"sync_status": "Synced", "best_tip": "3NKa"})
+    );
+
+    // without the prefix too
+    assert_eq!(parse(json, false).unwrap(), expected);
+}
@@ -15,6 +15,30 @@ mod keys_proto {
     include!(concat!(env!("OUT_DIR"), "/keys_proto.rs"));
 }
 
+/// The protocol libp2p uses, the debugger verifies the handshake against it.
+pub const PROTOCOL: &str = "Noise_XX_25519_ChaChaPoly_SHA256";
+
+/// The handshake does not fit `PROTOCOL`, the peer likely uses another pattern or cipher.
+pub const UNRECOGNIZED: &str = "unrecognized";
+
+/// The noise protocol of the name selected by multistream select,
+/// the libp2p spec fixes the protocol of `/noise`.
+pub fn protocol(selected: &str) -> Option<&'static str> {
+    match selected {
+        "/noise" => Some(PROTOCOL),
+        _ => None,
+    }
+}
+
+/// Pattern and cipher from the noise protocol name.
+pub fn pattern_and_cipher(name: &str) -> Option<(&str, &str)> {
+    let mut it = name.strip_prefix("Noise_")?.split('_');
+    let pattern = it.next()?;
+    let _dh = it.next()?;
+    let cipher = it.next()?;
+    Some((pattern, cipher))
+}
+
 pub fn parse_types(bytes: &[u8]) -> Result<Vec<MessageType>, DecodeError> {
     let ty = if bytes.starts_with(b"mac_mismatch\x00\x00\x00\x00") {
         MessageType::FailedToDecrypt
@@ -30,7 +54,31 @@ pub fn payload(bytes: &[u8]) -> Result<Vec<u8>, DecodeError> {
     Ok(msg.payload)
 }
 
-pub fn parse(bytes: Vec<u8>, _: bool) -> Result<serde_json::Value, DecodeError> {
+fn public_key(pk: &keys_proto::PublicKey) -> Result<libp2p_identity::PublicKey, DecodeError> {
+    let pk = match pk.r#type() {
+        keys_proto::KeyType::Rsa => return Err(DecodeError::Rsa),
+        keys_proto::KeyType::Ed25519 => ed25519::PublicKey::try_from_bytes(&pk.data)?.into(),
+        keys_proto::KeyType::Secp256k1 => secp256k1::PublicKey::try_from_bytes(&pk.data)?.into(),
+        keys_proto::KeyType::Ecdsa => ecdsa::PublicKey::try_from_bytes(&pk.data)?.into(),
+    };
+    Ok(pk)
+}
+
+/// The peer id derived from the static key in the handshake payload,
+/// `None` if the payload carries no key.
+pub fn peer_id(bytes: &[u8]) -> Result<Option<PeerId>, DecodeError> {
+    if bytes.starts_with(b"mac_mismatch\x00\x00\x00\x00") {
+        return Ok(None);
+    }
+    let buf = Bytes::from(bytes.to_vec());
+    let msg = pb::Envelope::decode(buf).map_err(DecodeError::Protobuf)?;
+    msg.public_key
+        .as_ref()
+        .map(|pk| public_key(pk).map(|pk| PeerId::from_public_key(&pk)))
+        .transpose()
+}
+
+pub fn parse(bytes: &[u8], _: bool) -> Result<serde_json::Value, DecodeError> {
     #[derive(Serialize)]
     struct T {
         r#type: String,
@@ -56,23 +104,12 @@ pub fn parse(bytes: Vec<u8>, _: bool) -> Result<serde_json::Value, DecodeError>
         return serde_json::to_value(&f).map_err(DecodeError::Serde);
     }
 
-    let buf = Bytes::from(bytes);
-    let msg = pb::Envelope::decode(buf).map_err(DecodeError::Protobuf)?;
+    let msg = pb::Envelope::decode(bytes).map_err(DecodeError::Protobuf)?;
 
     let (r#type, public_key, peer_id) = match msg.public_key {
         None => ("".to_string(), "".to_string(), "".to_string()),
         Some(pk) => {
-            let libp2p_pk = match pk.r#type() {
-                keys_proto::KeyType::Rsa => return Err(DecodeError::Rsa),
-                keys_proto::KeyType::Ed25519 => {
-                    ed25519::PublicKey::try_from_bytes(&pk.data)?.into()
-                }
-                keys_proto::KeyType::Secp256k1 => {
-                    secp256k1::PublicKey::try_from_bytes(&pk.data)?.into()
-                }
-                keys_proto::KeyType::Ecdsa => ecdsa::PublicKey::try_from_bytes(&pk.data)?.into(),
-            };
-            let id = PeerId::from_public_key(&libp2p_pk);
+            let id = PeerId::from_public_key(&public_key(&pk)?);
             (
                 pk.r#type().as_str_name().to_string(),
                 hex::encode(pk.data),
@@ -93,6 +130,24 @@ pub fn parse(bytes: Vec<u8>, _: bool) -> Result<serde_json::Value, DecodeError>
     serde_json::to_value(&t).map_err(DecodeError::Serde)
 }
 
+#[cfg(test)]
+#[test]
+fn pattern_and_cipher_test() {
+    assert_eq!(pattern_and_cipher(PROTOCOL), Some(("XX", "ChaChaPoly")));
+    assert_eq!(
+        pattern_and_cipher("Noise_IK_448_AESGCM_BLAKE2b"),
+        Some(("IK", "AESGCM"))
+    );
+    assert_eq!(pattern_and_cipher(UNRECOGNIZED), None);
+}
+
+#[cfg(test)]
+#[test]
+fn protocol_of_selected() {
+    assert_eq!(protocol("/noise"), Some(PROTOCOL));
+    assert_eq!(protocol("/secio/1.0.0"), None);
+}
+
 #[cfg(test)]
 #[test]
 fn parse_peer_id_test() {
@@ -104,3 +159,21 @@ fn parse_peer_id_test() {
         "12D3KooWQXa4AdCEZWe9QwoHnrANyMAXirozBdroNHkkvTMhT8bf"
     );
 }
+
+#[cfg(test)]
+#[test]
+fn peer_id_from_handshake_payload() {
+    // identity key, ed25519, then the signature
+    let key = "da91decf6f4c769327ca8ff03986e66fcfe6c59dca63d68c5ee359e52f8dc6e6";
+    let hex = format!("0a2408011220{key}1240{}", "00".repeat(64));
+    let data = hex::decode(hex).unwrap();
+    let id = peer_id(&data).unwrap().unwrap();
+    assert_eq!(
+        id.to_base58(),
+        "12D3KooWQXa4AdCEZWe9QwoHnrANyMAXirozBdroNHkkvTMhT8bf"
+    );
+    let value = parse(&data, false).unwrap();
+    assert_eq!(value["peer_id"], id.to_base58());
+
+    assert!(peer_id(&hex::decode("1240").unwrap()).is_err());
+}
@@ -0,0 +1,28 @@
+use std::time::Duration;
+
+use serde::Serialize;
+
+use super::{DecodeError, MessageType};
+
+pub fn parse(
+    bytes: &[u8],
+    preview: bool,
+    rtt: Option<Duration>,
+) -> Result<serde_json::Value, DecodeError> {
+    #[derive(Serialize)]
+    struct T {
+        payload: String,
+        // present only for the echo
+        rtt: Option<Duration>,
+    }
+
+    if preview {
+        serde_json::to_value(MessageType::IpfsPing).map_err(DecodeError::Serde)
+    } else {
+        let t = T {
+            payload: hex::encode(bytes),
+            rtt,
+        };
+        serde_json::to_value(&t).map_err(DecodeError::Serde)
+    }
+}
@@ -0,0 +1,90 @@
+/// One line of the Prometheus text exposition format.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Sample {
+    pub name: String,
+    // as is, without braces, `a="1",b="2"`
+    pub labels: String,
+    // as is, may be `NaN` or `+Inf`
+    pub value: String,
+}
+
+/// The response carries metrics, the node answers the scrape with `text/plain; version=0.0.4`.
+pub fn is_exposition(content_type: &str) -> bool {
+    let content_type = content_type.trim().to_ascii_lowercase();
+    content_type.starts_with("text/plain")
+        || content_type.starts_with("application/openmetrics-text")
+}
+
+/// Skips comments and malformed lines, the timestamp of the sample is ignored.
+pub fn parse(text: &str) -> Vec<Sample> {
+    text.lines().filter_map(parse_line).collect()
+}
+
+fn parse_line(line: &str) -> Option<Sample> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let name_end = line.find(|c: char| c == '{' || c.is_whitespace())?;
+    let (name, rest) = line.split_at(name_end);
+    let (labels, rest) = if let Some(rest) = rest.strip_prefix('{') {
+        // the closing brace may be inside a quoted label value
+        let mut quoted = false;
+        let mut escaped = false;
+        let end = rest.char_indices().find_map(|(i, c)| {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => quoted = !quoted,
+                '}' if !quoted => return Some(i),
+                _ => (),
+            }
+            None
+        })?;
+        (&rest[..end], &rest[(end + 1)..])
+    } else {
+        ("", rest)
+    };
+    let value = rest.split_whitespace().next()?;
+
+    Some(Sample {
+        name: name.to_owned(),
+        labels: labels.to_owned(),
+        value: value.to_owned(),
+    })
+}
+
+#[cfg(test)]
+#[test]
+fn parse_exposition() {
+    let text = "\
+# HELP Mina_Daemon_peers number of peers
+# TYPE Mina_Daemon_peers gauge
+Mina_Daemon_peers 12
+Mina_Network_rpc_latency{rpc=\"get_best_tip\",note=\"a } b\"} 0.25 1690000000000
+
+garbage
+process_start_time_seconds NaN
+";
+    let samples = parse(text);
+    assert_eq!(
+        samples,
+        [
+            Sample {
+                name: "Mina_Daemon_peers".to_owned(),
+                labels: String::new(),
+                value: "12".to_owned(),
+            },
+            Sample {
+                name: "Mina_Network_rpc_latency".to_owned(),
+                labels: "rpc=\"get_best_tip\",note=\"a } b\"".to_owned(),
+                value: "0.25".to_owned(),
+            },
+            Sample {
+                name: "process_start_time_seconds".to_owned(),
+                labels: String::new(),
+                value: "NaN".to_owned(),
+            },
+        ]
+    );
+}
@@ -0,0 +1,289 @@
+use std::net::{IpAddr, SocketAddr};
+
+use serde::Serialize;
+
+use prost::{bytes::Bytes, Message};
+
+use crate::database::{StreamKind, RelayedPeer};
+
+use super::{DecodeError, MessageType, utils};
+
+#[allow(clippy::derive_partial_eq_without_eq)]
+mod pb {
+    include!(concat!(env!("OUT_DIR"), "/circuit.pb.rs"));
+}
+
+#[derive(Serialize)]
+pub struct Peer {
+    id: String,
+    addrs: Vec<String>,
+}
+
+impl From<&pb::Peer> for Peer {
+    fn from(v: &pb::Peer) -> Self {
+        Peer {
+            id: libp2p_identity::PeerId::from_bytes(&v.id)
+                .map(|id| id.to_base58())
+                .unwrap_or_else(|_| hex::encode(&v.id)),
+            addrs: v.addrs.iter().map(|addr| utils::parse_addr(addr)).collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Limit {
+    duration: Option<u32>,
+    data: Option<u64>,
+}
+
+impl From<pb::Limit> for Limit {
+    fn from(v: pb::Limit) -> Self {
+        Limit {
+            duration: v.duration,
+            data: v.data,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Reservation {
+    expire: u64,
+    addrs: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct T {
+    r#type: MessageType,
+    // for `connect` it is the peer on the other side of the relay
+    peer: Option<Peer>,
+    reservation: Option<Reservation>,
+    limit: Option<Limit>,
+    status: Option<String>,
+}
+
+fn status(v: Option<i32>) -> Option<String> {
+    v.map(|v| match pb::Status::try_from(v) {
+        Ok(status) => format!("{status:?}"),
+        Err(_) => v.to_string(),
+    })
+}
+
+// once the circuit is established the stream carries the relayed connection,
+// it is not a relay message, return `None` for it
+fn decode(bytes: &[u8], stream_kind: StreamKind) -> Option<(T, Option<pb::Peer>)> {
+    let buf = Bytes::from(bytes.to_vec());
+    match stream_kind {
+        StreamKind::RelayHop => {
+            let msg = pb::HopMessage::decode_length_delimited(buf).ok()?;
+            let t = T {
+                r#type: match msg.r#type() {
+                    pb::hop_message::Type::Reserve => MessageType::RelayReserve,
+                    pb::hop_message::Type::Connect => MessageType::RelayConnect,
+                    pb::hop_message::Type::Status => MessageType::RelayStatus,
+                },
+                peer: msg.peer.as_ref().map(From::from),
+                reservation: msg.reservation.map(|v| Reservation {
+                    expire: v.expire,
+                    addrs: v.addrs.iter().map(|addr| utils::parse_addr(addr)).collect(),
+                }),
+                limit: msg.limit.map(From::from),
+                status: status(msg.status),
+            };
+            Some((t, msg.peer))
+        }
+        StreamKind::RelayStop => {
+            let msg = pb::StopMessage::decode_length_delimited(buf).ok()?;
+            let t = T {
+                r#type: match msg.r#type() {
+                    pb::stop_message::Type::Connect => MessageType::RelayConnect,
+                    pb::stop_message::Type::Status => MessageType::RelayStatus,
+                },
+                peer: msg.peer.as_ref().map(From::from),
+                reservation: None,
+                limit: msg.limit.map(From::from),
+                status: status(msg.status),
+            };
+            Some((t, msg.peer))
+        }
+        _ => None,
+    }
+}
+
+pub fn parse_types(bytes: &[u8], stream_kind: StreamKind) -> Vec<MessageType> {
+    decode(bytes, stream_kind)
+        .map(|(t, _)| t.r#type)
+        .into_iter()
+        .collect()
+}
+
+/// Addresses of the peer on the other side of the relay, so the relayed connection
+/// can be found by its true endpoint rather than the relay's address.
+pub fn peer_addrs(bytes: &[u8], stream_kind: StreamKind) -> Vec<SocketAddr> {
+    decode(bytes, stream_kind)
+        .and_then(|(_, peer)| peer)
+        .map(|peer| peer.addrs.iter().filter_map(|a| socket_addr(a)).collect())
+        .unwrap_or_default()
+}
+
+/// The step of the circuit setup which names or confirms the peer on the other side.
+pub enum Circuit {
+    // HOP CONNECT, the node asks the relay for the circuit, it is pending
+    Connect(RelayedPeer),
+    // HOP STATUS OK, the relay confirms the pending circuit
+    Connected,
+    // STOP CONNECT, the relay brings the circuit from the peer
+    Incoming(RelayedPeer),
+}
+
+pub fn circuit(bytes: &[u8], stream_kind: StreamKind) -> Option<Circuit> {
+    let relayed = |peer: &pb::Peer, connected| {
+        let Peer { id, addrs } = Peer::from(peer);
+        RelayedPeer {
+            peer_id: id,
+            addrs,
+            connected,
+        }
+    };
+    let buf = Bytes::from(bytes.to_vec());
+    match stream_kind {
+        StreamKind::RelayHop => {
+            let msg = pb::HopMessage::decode_length_delimited(buf).ok()?;
+            match msg.r#type() {
+                pb::hop_message::Type::Connect => msg
+                    .peer
+                    .as_ref()
+                    .map(|peer| Circuit::Connect(relayed(peer, false))),
+                pb::hop_message::Type::Status if msg.status == Some(pb::Status::Ok as i32) => {
+                    Some(Circuit::Connected)
+                }
+                _ => None,
+            }
+        }
+        StreamKind::RelayStop => {
+            let msg = pb::StopMessage::decode_length_delimited(buf).ok()?;
+            match msg.r#type() {
+                pb::stop_message::Type::Connect => msg
+                    .peer
+                    .as_ref()
+                    .map(|peer| Circuit::Incoming(relayed(peer, true))),
+                pb::stop_message::Type::Status => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn socket_addr(addr: &[u8]) -> Option<SocketAddr> {
+    use multiaddr::Protocol;
+
+    let (ip, input): (IpAddr, _) = match Protocol::from_bytes(addr).ok()? {
+        (Protocol::Ip4(ip), input) => (ip.into(), input),
+        (Protocol::Ip6(ip), input) => (ip.into(), input),
+        _ => return None,
+    };
+    match Protocol::from_bytes(input).ok()? {
+        (Protocol::Tcp(port), _) => Some(SocketAddr::new(ip, port)),
+        _ => None,
+    }
+}
+
+pub fn parse(
+    bytes: &[u8],
+    preview: bool,
+    stream_kind: StreamKind,
+) -> Result<serde_json::Value, DecodeError> {
+    let Some((t, _)) = decode(bytes, stream_kind) else {
+        return Ok(serde_json::Value::String(hex::encode(bytes)));
+    };
+
+    if preview {
+        serde_json::to_value(&t.r#type).map_err(DecodeError::Serde)
+    } else {
+        serde_json::to_value(&t).map_err(DecodeError::Serde)
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn decode_stop_connect() {
+    let msg = pb::StopMessage {
+        r#type: pb::stop_message::Type::Connect as i32,
+        peer: Some(pb::Peer {
+            id: hex::decode(
+                "0024080112201e1d2d3d59e3cf5dcc0e54a86ad36d6c06c33d8d62a5bd7a9b9c56b1b95b7f6d",
+            )
+            .unwrap(),
+            addrs: vec![hex::decode("047f00000106206e").unwrap()],
+        }),
+        limit: None,
+        status: None,
+    };
+    let bytes = msg.encode_length_delimited_to_vec();
+
+    let tys = parse_types(&bytes, StreamKind::RelayStop);
+    assert!(tys == [MessageType::RelayConnect]);
+    assert_eq!(
+        peer_addrs(&bytes, StreamKind::RelayStop),
+        ["127.0.0.1:8302".parse::<SocketAddr>().unwrap()]
+    );
+    let value = parse(&bytes, false, StreamKind::RelayStop).unwrap();
+    assert_eq!(value["peer"]["addrs"][0], "/ip4/127.0.0.1/tcp/8302");
+}
+
+#[cfg(test)]
+#[test]
+fn relayed_peer_stored_on_connection() {
+    use std::time::SystemTime;
+
+    use crate::database::{StreamId, add_test_message, test_connection, test_db};
+
+    let peer = pb::Peer {
+        id: hex::decode(
+            "0024080112201e1d2d3d59e3cf5dcc0e54a86ad36d6c06c33d8d62a5bd7a9b9c56b1b95b7f6d",
+        )
+        .unwrap(),
+        addrs: vec![hex::decode("047f00000106206e").unwrap()],
+    };
+    let connect = pb::HopMessage {
+        r#type: pb::hop_message::Type::Connect as i32,
+        peer: Some(peer),
+        reservation: None,
+        limit: None,
+        status: None,
+    };
+    let ok = pb::HopMessage {
+        r#type: pb::hop_message::Type::Status as i32,
+        peer: None,
+        reservation: None,
+        limit: None,
+        status: Some(pb::Status::Ok as i32),
+    };
+
+    let (_d, db) = test_db();
+    let group = test_connection(&db, SystemTime::UNIX_EPOCH);
+    let fetch = || {
+        db.core()
+            .fetch_connection(group.id().0)
+            .unwrap()
+            .post_process(None)
+    };
+    for msg in [&connect, &ok] {
+        let bytes = msg.encode_length_delimited_to_vec();
+        add_test_message(
+            &group,
+            SystemTime::UNIX_EPOCH,
+            StreamId::Handshake,
+            StreamKind::RelayHop,
+            &bytes,
+        );
+        if msg.r#type() == pb::hop_message::Type::Connect {
+            assert_eq!(fetch()["relayed_peers"][0]["connected"], false);
+        }
+    }
+
+    let v = fetch();
+    let relayed = &v["relayed_peers"][0];
+    assert_eq!(relayed["addrs"][0], "/ip4/127.0.0.1/tcp/8302");
+    assert_eq!(relayed["connected"], true);
+    assert!(relayed["peer_id"].as_str().unwrap().starts_with("12D3KooW"));
+}
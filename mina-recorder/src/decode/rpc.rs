@@ -1,11 +1,18 @@
-use std::io::{Cursor, Read};
+use std::{
+    io::{Cursor, Read},
+    marker::PhantomData,
+    net::{IpAddr, SocketAddr},
+};
 
 use serde::Serialize;
 use mina_p2p_messages::{
-    binprot::{BinProtRead, Nat0},
+    binprot::{self, BinProtRead, Nat0},
+    string::CharString as BString,
     JSONifyPayloadRegistry, utils,
-    rpc_kernel::{QueryHeader, JSONinifyError},
+    rpc_kernel::{QueryHeader, JSONinifyError, RpcMethod},
+    rpc::{GetBestTipV2, AnswerSyncLedgerQueryV2, GetTransitionChainV2},
     JSONinifyPayloadReader,
+    v2::StateHash,
 };
 
 use super::{DecodeError, MessageType};
@@ -21,7 +28,162 @@ pub fn parse_types(bytes: &[u8]) -> Result<Vec<MessageType>, DecodeError> {
     Ok(tag.parse().ok().into_iter().collect())
 }
 
-pub fn parse(bytes: Vec<u8>, preview: bool) -> Result<serde_json::Value, DecodeError> {
+const GET_SOME_INITIAL_PEERS: &str = "get_some_initial_peers";
+
+const GET_TRANSITION_KNOWLEDGE: &str = "get_transition_knowledge";
+
+/// `Network_peer.Peer.Stable.V1.t`
+#[derive(Serialize)]
+pub struct Peer {
+    pub host: String,
+    pub libp2p_port: i64,
+    pub peer_id: String,
+}
+
+impl Peer {
+    pub fn addr(&self) -> Option<SocketAddr> {
+        let ip = self.host.parse::<IpAddr>().ok()?;
+        let port = u16::try_from(self.libp2p_port).ok()?;
+        Some(SocketAddr::new(ip, port))
+    }
+}
+
+// the response is `T list Rpc_result.t`, the payload is prefixed with its length
+fn read_list<T, F>(r: &mut dyn Read, read_item: F) -> Result<Option<Vec<T>>, binprot::Error>
+where
+    F: Fn(&mut dyn Read) -> Result<T, binprot::Error>,
+{
+    let mut result = [0];
+    r.read_exact(&mut result)?;
+    if result[0] != 0 {
+        // the peer responded with error
+        return Ok(None);
+    }
+    let Nat0(_) = Nat0::binprot_read(r)?;
+    let Nat0(len) = Nat0::binprot_read(r)?;
+    let mut items = Vec::with_capacity((len as usize).min(0x100));
+    for _ in 0..len {
+        items.push(read_item(r)?);
+    }
+    Ok(Some(items))
+}
+
+fn read_initial_peers(r: &mut dyn Read) -> Result<Option<Vec<Peer>>, binprot::Error> {
+    read_list(r, |r| {
+        let host = BString::binprot_read(r)?.to_string_lossy();
+        let libp2p_port = i64::binprot_read(r)?;
+        let peer_id = BString::binprot_read(r)?.to_string_lossy();
+        Ok(Peer {
+            host,
+            libp2p_port,
+            peer_id,
+        })
+    })
+}
+
+fn read_hex(r: &mut dyn Read) -> Result<serde_json::Value, JSONinifyError> {
+    let mut v = vec![];
+    r.read_to_end(&mut v)
+        .map_err(From::from)
+        .map_err(JSONinifyError::Binprot)?;
+    let t = serde_json::to_value(hex::encode(v))?;
+    Ok(t)
+}
+
+/// Addresses of the peers shared in `get_some_initial_peers` response,
+/// empty for any other message.
+pub fn parse_peer_addrs(bytes: &[u8]) -> Result<Vec<SocketAddr>, DecodeError> {
+    let mut stream = Cursor::new(&bytes);
+
+    let _len = utils::stream_decode_size(&mut stream)?;
+    let Nat0(d) = BinProtRead::binprot_read(&mut stream)?;
+    let msg = QueryHeader::binprot_read(&mut stream)?;
+    if d != 2 || msg.tag.to_string_lossy() != GET_SOME_INITIAL_PEERS {
+        return Ok(vec![]);
+    }
+    let peers = read_initial_peers(&mut stream)?.unwrap_or_default();
+
+    Ok(peers.iter().filter_map(Peer::addr).collect())
+}
+
+struct InitialPeersReader;
+
+impl JSONinifyPayloadReader for InitialPeersReader {
+    fn read_query(&self, r: &mut dyn Read) -> Result<serde_json::Value, JSONinifyError> {
+        read_hex(r)
+    }
+
+    fn read_response(&self, r: &mut dyn Read) -> Result<serde_json::Value, JSONinifyError> {
+        match read_initial_peers(r).map_err(JSONinifyError::Binprot)? {
+            Some(peers) => Ok(serde_json::to_value(peers)?),
+            None => Ok(serde_json::Value::Null),
+        }
+    }
+}
+
+/// The query is unit, the response is the state hashes of the transitions the peer knows.
+struct TransitionKnowledgeReader;
+
+impl JSONinifyPayloadReader for TransitionKnowledgeReader {
+    fn read_query(&self, r: &mut dyn Read) -> Result<serde_json::Value, JSONinifyError> {
+        read_hex(r)
+    }
+
+    fn read_response(&self, r: &mut dyn Read) -> Result<serde_json::Value, JSONinifyError> {
+        let hashes = read_list(r, StateHash::binprot_read).map_err(JSONinifyError::Binprot)?;
+        match hashes {
+            Some(hashes) => Ok(serde_json::to_value(hashes)?),
+            None => Ok(serde_json::Value::Null),
+        }
+    }
+}
+
+/// Decodes the query and the response as the types of the rpc method.
+struct TypedReader<M>(PhantomData<M>);
+
+impl<M> JSONinifyPayloadReader for TypedReader<M>
+where
+    M: RpcMethod,
+    M::Query: Serialize,
+    M::Response: Serialize,
+{
+    fn read_query(&self, r: &mut dyn Read) -> Result<serde_json::Value, JSONinifyError> {
+        let Nat0(_) = Nat0::binprot_read(r).map_err(JSONinifyError::Binprot)?;
+        let query = M::Query::binprot_read(r).map_err(JSONinifyError::Binprot)?;
+        Ok(serde_json::to_value(query)?)
+    }
+
+    fn read_response(&self, r: &mut dyn Read) -> Result<serde_json::Value, JSONinifyError> {
+        let mut result = [0];
+        r.read_exact(&mut result)
+            .map_err(From::from)
+            .map_err(JSONinifyError::Binprot)?;
+        if result[0] != 0 {
+            // the peer responded with error
+            return Ok(serde_json::Value::Null);
+        }
+        let Nat0(_) = Nat0::binprot_read(r).map_err(JSONinifyError::Binprot)?;
+        let response = M::Response::binprot_read(r).map_err(JSONinifyError::Binprot)?;
+        Ok(serde_json::to_value(response)?)
+    }
+}
+
+fn typed_reader(tag: &str, version: i32) -> Option<&'static dyn JSONinifyPayloadReader> {
+    match (tag, version) {
+        (GetBestTipV2::NAME, GetBestTipV2::VERSION) => {
+            Some(&TypedReader::<GetBestTipV2>(PhantomData))
+        }
+        (AnswerSyncLedgerQueryV2::NAME, AnswerSyncLedgerQueryV2::VERSION) => {
+            Some(&TypedReader::<AnswerSyncLedgerQueryV2>(PhantomData))
+        }
+        (GetTransitionChainV2::NAME, GetTransitionChainV2::VERSION) => {
+            Some(&TypedReader::<GetTransitionChainV2>(PhantomData))
+        }
+        _ => None,
+    }
+}
+
+pub fn parse(bytes: &[u8], preview: bool) -> Result<serde_json::Value, DecodeError> {
     #[derive(Serialize)]
     #[serde(rename_all = "snake_case")]
     #[serde(tag = "type")]
@@ -44,12 +206,7 @@ pub fn parse(bytes: Vec<u8>, preview: bool) -> Result<serde_json::Value, DecodeE
 
     impl JSONinifyPayloadReader for DefaultReader {
         fn read_query(&self, r: &mut dyn Read) -> Result<serde_json::Value, JSONinifyError> {
-            let mut v = vec![];
-            r.read_to_end(&mut v)
-                .map_err(From::from)
-                .map_err(JSONinifyError::Binprot)?;
-            let t = serde_json::to_value(hex::encode(v))?;
-            Ok(t)
+            read_hex(r)
         }
 
         fn read_response(&self, r: &mut dyn Read) -> Result<serde_json::Value, JSONinifyError> {
@@ -57,7 +214,7 @@ pub fn parse(bytes: Vec<u8>, preview: bool) -> Result<serde_json::Value, DecodeE
         }
     }
 
-    let mut stream = Cursor::new(&bytes);
+    let mut stream = Cursor::new(bytes);
 
     let _len = utils::stream_decode_size(&mut stream)?;
     let Nat0(d) = BinProtRead::binprot_read(&mut stream)?;
@@ -67,6 +224,15 @@ pub fn parse(bytes: Vec<u8>, preview: bool) -> Result<serde_json::Value, DecodeE
     let v1 = JSONifyPayloadRegistry::v1();
     let v2 = JSONifyPayloadRegistry::v2();
     let reader = None
+        .or_else(|| {
+            (tag == GET_SOME_INITIAL_PEERS)
+                .then_some(&InitialPeersReader as &dyn JSONinifyPayloadReader)
+        })
+        .or_else(|| {
+            (tag == GET_TRANSITION_KNOWLEDGE)
+                .then_some(&TransitionKnowledgeReader as &dyn JSONinifyPayloadReader)
+        })
+        .or_else(|| typed_reader(&tag, msg.version))
         .or_else(|| v2.get(&tag, msg.version))
         .or_else(|| v1.get(&tag, msg.version))
         .unwrap_or_else(|| &DefaultReader);
@@ -133,7 +299,156 @@ fn decode_get_transition_chain_proof_request() {
 #[test]
 fn decode_v2() {
     let bytes = include_bytes!("../test_data/rpc_7843");
-    let msg = parse(bytes.to_vec(), false).unwrap();
+    let msg = parse(bytes, false).unwrap();
     let value = msg.as_object().unwrap().get("value").unwrap();
     assert!(value.is_object());
 }
+
+#[cfg(test)]
+#[test]
+fn decode_get_some_initial_peers_response() {
+    use mina_p2p_messages::binprot::BinProtWrite;
+
+    let mut b = vec![0; 8];
+    b.push(2);
+    let header = QueryHeader {
+        tag: BString::from(GET_SOME_INITIAL_PEERS),
+        version: 1,
+        id: 3,
+    };
+    header.binprot_write(&mut b).unwrap();
+    // Ok
+    b.push(0);
+    let mut payload = vec![];
+    Nat0(2).binprot_write(&mut payload).unwrap();
+    for (host, port, peer_id) in [("1.2.3.4", 8302, "12D3KooWA"), ("::1", 10909, "12D3KooWB")] {
+        BString::from(host).binprot_write(&mut payload).unwrap();
+        (port as i64).binprot_write(&mut payload).unwrap();
+        BString::from(peer_id).binprot_write(&mut payload).unwrap();
+    }
+    Nat0(payload.len() as u64).binprot_write(&mut b).unwrap();
+    b.extend_from_slice(&payload);
+    let len = (b.len() - 8) as u64;
+    b[..8].clone_from_slice(&len.to_le_bytes());
+
+    let addrs = parse_peer_addrs(&b).unwrap();
+    assert_eq!(
+        addrs,
+        [
+            "1.2.3.4:8302".parse::<SocketAddr>().unwrap(),
+            "[::1]:10909".parse().unwrap()
+        ]
+    );
+
+    let msg = parse(&b, false).unwrap();
+    let value = msg.as_object().unwrap().get("value").unwrap();
+    assert_eq!(value[1]["peer_id"], "12D3KooWB");
+}
+
+#[cfg(test)]
+#[test]
+fn decode_get_transition_knowledge_response() {
+    use mina_p2p_messages::binprot::BinProtWrite;
+
+    let mut b = vec![0; 8];
+    b.push(2);
+    let header = QueryHeader {
+        tag: BString::from(GET_TRANSITION_KNOWLEDGE),
+        version: 1,
+        id: 5,
+    };
+    header.binprot_write(&mut b).unwrap();
+    // Ok
+    b.push(0);
+    let mut payload = vec![];
+    Nat0(2).binprot_write(&mut payload).unwrap();
+    // the state hash is a field element, 32 bytes
+    payload.extend_from_slice(&[1; 32]);
+    payload.extend_from_slice(&[2; 32]);
+    Nat0(payload.len() as u64).binprot_write(&mut b).unwrap();
+    b.extend_from_slice(&payload);
+    let len = (b.len() - 8) as u64;
+    b[..8].clone_from_slice(&len.to_le_bytes());
+
+    let msg = parse(&b, false).unwrap();
+    let value = msg.as_object().unwrap().get("value").unwrap();
+    let hashes = value.as_array().unwrap();
+    assert_eq!(hashes.len(), 2);
+    assert!(hashes[0].is_string());
+    assert_ne!(hashes[0], hashes[1]);
+}
+
+#[cfg(test)]
+fn rpc_frame(d: u8, tag: &str, version: i32, body: &[u8]) -> Vec<u8> {
+    use mina_p2p_messages::binprot::BinProtWrite;
+
+    let mut b = vec![0; 8];
+    b.push(d);
+    let header = QueryHeader {
+        tag: BString::from(tag),
+        version,
+        id: 7,
+    };
+    header.binprot_write(&mut b).unwrap();
+    if d == 2 {
+        // Ok
+        b.push(0);
+    }
+    Nat0(body.len() as u64).binprot_write(&mut b).unwrap();
+    b.extend_from_slice(body);
+    let len = (b.len() - 8) as u64;
+    b[..8].clone_from_slice(&len.to_le_bytes());
+    b
+}
+
+#[cfg(test)]
+#[test]
+fn decode_get_best_tip() {
+    let tag = GetBestTipV2::NAME;
+    let version = GetBestTipV2::VERSION;
+
+    // the query is unit
+    let msg = parse(&rpc_frame(1, tag, version, &[0]), false).unwrap();
+    assert_eq!(msg["query"], serde_json::Value::Null);
+    // the peer has no best tip
+    let msg = parse(&rpc_frame(2, tag, version, &[0]), false).unwrap();
+    assert_eq!(msg["value"], serde_json::Value::Null);
+}
+
+#[cfg(test)]
+#[test]
+fn decode_answer_sync_ledger_query() {
+    let tag = AnswerSyncLedgerQueryV2::NAME;
+    let version = AnswerSyncLedgerQueryV2::VERSION;
+
+    // the ledger hash and `Num_accounts` query
+    let mut body = [1; 32].to_vec();
+    body.push(2);
+    let msg = parse(&rpc_frame(1, tag, version, &body), false).unwrap();
+    assert!(!msg["query"].is_string());
+
+    // `Ok (Num_accounts (5, hash))`
+    let mut body = vec![0, 2, 5];
+    body.extend_from_slice(&[1; 32]);
+    let msg = parse(&rpc_frame(2, tag, version, &body), false).unwrap();
+    assert!(!msg["value"].is_string());
+}
+
+#[cfg(test)]
+#[test]
+fn decode_get_transition_chain() {
+    let tag = GetTransitionChainV2::NAME;
+    let version = GetTransitionChainV2::VERSION;
+
+    // a single state hash
+    let mut body = vec![1];
+    body.extend_from_slice(&[3; 32]);
+    let msg = parse(&rpc_frame(1, tag, version, &body), false).unwrap();
+    let hashes = msg["query"].as_array().unwrap();
+    assert_eq!(hashes.len(), 1);
+    assert!(hashes[0].is_string());
+
+    // the peer doesn't have the transitions
+    let msg = parse(&rpc_frame(2, tag, version, &[0]), false).unwrap();
+    assert_eq!(msg["value"], serde_json::Value::Null);
+}
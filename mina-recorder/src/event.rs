@@ -16,6 +16,28 @@ pub struct EventMetadata {
     pub time: SystemTime,
     pub better_time: SystemTime,
     pub duration: Duration,
+    // where `time` comes from
+    pub time_source: TimeSource,
+    // how late the event is processed, the kernel timestamp vs the monotonic clock now
+    pub lag: Duration,
+}
+
+/// Clock that produced the timestamp of the event, stored with connections and messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Absorb, Emit, Serialize, Deserialize)]
+#[tag(u8)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeSource {
+    /// Recorded before the source was known.
+    #[tag(0)]
+    Unknown,
+    /// The kernel monotonic timestamp plus the boot time from `/proc/stat`.
+    BootTime,
+    /// The boot time is unavailable, the kernel timestamp is relative to the first event.
+    FirstEvent,
+    /// Timestamp of the packet in the pcap file.
+    Capture,
+    /// The stored chunk is decoded once again, its timestamp is kept.
+    Replay,
 }
 
 impl Default for EventMetadata {
@@ -25,6 +47,8 @@ impl Default for EventMetadata {
             time: SystemTime::UNIX_EPOCH,
             better_time: SystemTime::UNIX_EPOCH,
             duration: Duration::from_secs(0),
+            time_source: TimeSource::Unknown,
+            lag: Duration::ZERO,
         }
     }
 }
@@ -36,6 +60,12 @@ pub struct ConnectionInfo {
     pub addr: SocketAddr,
     pub pid: u32,
     pub fd: u32,
+    // counts connections on the same `(pid, fd)`, the fd is reused once closed, `0` if unknown,
+    // in memory only, the database keeps it in `Connection::generation`
+    #[custom_absorb(custom_coding::skip_absorb)]
+    #[custom_emit(custom_coding::skip_emit)]
+    #[serde(skip)]
+    pub generation: u32,
 }
 
 impl Default for ConnectionInfo {
@@ -44,6 +74,7 @@ impl Default for ConnectionInfo {
             addr: "127.0.0.1:0".parse().expect("valid constant"),
             pid: 1,
             fd: 1,
+            generation: 0,
         }
     }
 }
@@ -75,7 +106,7 @@ impl fmt::Display for EventMetadata {
             id, time, duration, ..
         } = self;
         let (hour, minute, second, nano) = OffsetDateTime::from(*time).time().as_hms_nano();
-        let ConnectionInfo { pid, addr, fd } = id;
+        let ConnectionInfo { pid, addr, fd, .. } = id;
 
         write!(
             f,
@@ -92,7 +123,7 @@ impl fmt::Display for DirectedId {
             id, time, duration, ..
         } = &self.metadata;
         let (hour, minute, second, nano) = OffsetDateTime::from(*time).time().as_hms_nano();
-        let ConnectionInfo { pid, addr, fd } = id;
+        let ConnectionInfo { pid, addr, fd, .. } = id;
 
         let arrow = if self.incoming { "->" } else { "<-" };
         let alias = &self.alias;
@@ -0,0 +1,67 @@
+use std::sync::Arc;
+
+use tokio::sync::broadcast;
+
+use crate::database::{MessageId, StreamKind};
+
+/// Raw kernel events for `/ws/raw` subscribers, encoded by the caller.
+#[derive(Clone)]
+pub struct RawFeed(broadcast::Sender<Arc<[u8]>>);
+
+impl Default for RawFeed {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RawFeed {
+    // the slow subscriber loses events, the recorder never waits for it
+    const CAPACITY: usize = 0x1000;
+
+    pub fn new() -> Self {
+        RawFeed(broadcast::channel(Self::CAPACITY).0)
+    }
+
+    /// Encodes only if somebody listens.
+    pub fn send_with<F>(&self, encode: F)
+    where
+        F: FnOnce() -> Vec<u8>,
+    {
+        if self.0.receiver_count() != 0 {
+            let _ = self.0.send(encode().into());
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<[u8]>> {
+        self.0.subscribe()
+    }
+}
+
+/// Ids of the new messages for `/messages/live` subscribers.
+#[derive(Clone)]
+pub struct MessageFeed(broadcast::Sender<(MessageId, StreamKind)>);
+
+impl Default for MessageFeed {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MessageFeed {
+    // the slow subscriber is dropped, the recorder never waits for it
+    const CAPACITY: usize = 0x1000;
+
+    pub fn new() -> Self {
+        MessageFeed(broadcast::channel(Self::CAPACITY).0)
+    }
+
+    pub fn send(&self, id: MessageId, stream_kind: StreamKind) {
+        if self.0.receiver_count() != 0 {
+            let _ = self.0.send((id, stream_kind));
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<(MessageId, StreamKind)> {
+        self.0.subscribe()
+    }
+}
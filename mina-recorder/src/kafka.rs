@@ -0,0 +1,83 @@
+use std::{
+    env,
+    sync::mpsc::{self, SyncSender},
+    thread,
+    time::Duration,
+};
+
+use ::kafka::producer::{Producer, Record, RequiredAcks};
+use serde::Serialize;
+
+/// Publishes the stats events to a Kafka topic, the same events the aggregator receives.
+/// Sending never blocks the recorder, the event is dropped if the producer is behind.
+#[derive(Clone)]
+pub struct Kafka {
+    tx: SyncSender<Vec<u8>>,
+    debugger_name: String,
+}
+
+impl Kafka {
+    const CAPACITY: usize = 0x1000;
+
+    /// Enabled if `KAFKA_BROKERS` is set.
+    pub fn from_env() -> Option<Self> {
+        let brokers = env::var("KAFKA_BROKERS").ok()?;
+        let hosts = brokers
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(ToOwned::to_owned)
+            .collect::<Vec<_>>();
+        if hosts.is_empty() {
+            log::error!("`KAFKA_BROKERS` is empty");
+            return None;
+        }
+        let topic = env::var("KAFKA_TOPIC").unwrap_or("mina-network-debugger".to_owned());
+        let debugger_name = env::var("DEBUGGER_NAME").unwrap_or("noname".to_owned());
+        log::info!("use kafka {brokers}, topic {topic}");
+
+        let (tx, rx) = mpsc::sync_channel::<Vec<u8>>(Self::CAPACITY);
+        thread::spawn(move || {
+            let mut producer = match Producer::from_hosts(hosts)
+                .with_ack_timeout(Duration::from_secs(1))
+                .with_required_acks(RequiredAcks::One)
+                .create()
+            {
+                Ok(v) => v,
+                Err(err) => {
+                    // the receiver is dropped, every following event counts as dropped
+                    log::error!("cannot create kafka producer: {err}");
+                    return;
+                }
+            };
+            while let Ok(value) = rx.recv() {
+                if let Err(err) = producer.send(&Record::from_value(&topic, value)) {
+                    log::error!("failed to publish event on kafka {err}");
+                }
+            }
+        });
+
+        Some(Kafka { tx, debugger_name })
+    }
+
+    pub fn send<T>(&self, event: &T)
+    where
+        T: Serialize,
+    {
+        let event_str = match serde_json::to_string(event) {
+            Ok(v) => v,
+            Err(err) => {
+                log::error!("failed to publish event on kafka {err}");
+                return;
+            }
+        };
+        let value = format!(
+            "{{\"alias\": \"{}\", \"event\": {event_str} }}",
+            self.debugger_name
+        );
+        // the channel is full, or the producer is gone
+        if self.tx.try_send(value.into_bytes()).is_err() {
+            crate::metrics::kafka_dropped();
+        }
+    }
+}
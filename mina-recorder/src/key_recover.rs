@@ -9,6 +9,16 @@ use curve25519_dalek::{
 
 use super::database::{DbCore, RandomnessDatabase};
 
+/// How many latest randomness entries are tried as the seed, unless `KEY_SEED_WINDOW` is set.
+pub const DEFAULT_SEED_WINDOW: usize = 64;
+
+pub fn seed_window() -> usize {
+    std::env::var("KEY_SEED_WINDOW")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_SEED_WINDOW)
+}
+
 pub trait KeyDatabase {
     fn reproduced_sk<const EPHEMERAL: bool>(&self, pk: [u8; 32]) -> Option<[u8; 32]>;
 }
@@ -74,6 +84,10 @@ impl KeyGenerators {
 
 pub struct KeyGeneratorWithCache {
     generators: Mutex<Option<KeyGenerators>>,
+    // the seed found last time, it is tried first, the randomness entries are
+    // added all the time, so its position in the window changes
+    seed: Mutex<Option<[u8; 32]>>,
+    window: usize,
     db: DbCore,
 }
 
@@ -81,6 +95,8 @@ impl KeyGeneratorWithCache {
     pub fn new(db: DbCore) -> Self {
         KeyGeneratorWithCache {
             generators: Mutex::new(None),
+            seed: Mutex::new(None),
+            window: seed_window(),
             db,
         }
     }
@@ -111,18 +127,24 @@ impl KeyDatabase for KeyGeneratorWithCache {
                         .inspect(|_| *self.generators.lock() = Some(g))
                 } else {
                     // find a seed
-                    log::info!("searching seed");
-                    self.db
+                    log::info!("searching seed in {} latest entries", self.window);
+                    let cached = *self.seed.lock();
+                    let candidates = self
+                        .db
                         .iterate_randomness()
-                        .take(8)
-                        .filter_map(|x| <[u8; 32]>::try_from(x.to_vec()).ok())
+                        .take(self.window)
+                        .filter_map(|x| <[u8; 32]>::try_from(x.to_vec()).ok());
+                    cached
+                        .into_iter()
+                        .chain(candidates)
                         .find_map(|seed_candidate| {
-                            log::info!("try seed candidate: {}", hex::encode(seed_candidate));
+                            log::debug!("try seed candidate: {}", hex::encode(seed_candidate));
                             KeyGenerators::new(seed_candidate)
                                 .part::<EPHEMERAL>()
                                 .find(point, |_, _| ())
                                 .inspect(|_| {
                                     log::info!("found seed");
+                                    *self.seed.lock() = Some(seed_candidate);
                                     *self.generators.lock() =
                                         Some(KeyGenerators::new(seed_candidate));
                                 })
@@ -131,3 +153,69 @@ impl KeyDatabase for KeyGeneratorWithCache {
             })
     }
 }
+
+#[cfg(test)]
+#[test]
+fn seed_survives_retention() {
+    use temp_dir::TempDir;
+
+    let d = TempDir::new().unwrap();
+    let db = DbCore::open(d.path()).unwrap();
+    let seed = [0x42; 32];
+    let retain = DEFAULT_SEED_WINDOW as u64;
+    db.put_randomness(0, seed, retain).unwrap();
+    for id in 1..retain {
+        db.put_randomness(id, [id as u8; 32], retain).unwrap();
+    }
+
+    let sk = KeyGenerator::new(seed, b"ephemeral").next().unwrap();
+    let pk = (&ED25519_BASEPOINT_TABLE * &sk).to_montgomery();
+    let keys = KeyGeneratorWithCache::new(db.clone());
+    assert_eq!(
+        keys.reproduced_sk::<true>(pk.to_bytes()),
+        Some(sk.to_bytes())
+    );
+
+    for id in retain..(retain * 4) {
+        db.put_randomness(id, [id as u8; 32], retain).unwrap();
+    }
+    assert_eq!(db.iterate_randomness().count(), DEFAULT_SEED_WINDOW);
+}
+
+#[cfg(test)]
+#[test]
+fn seed_deep_in_window() {
+    use temp_dir::TempDir;
+
+    let d = TempDir::new().unwrap();
+    let db = DbCore::open(d.path()).unwrap();
+    let seed = [0x42; 32];
+    let retain = DEFAULT_SEED_WINDOW as u64;
+    db.put_randomness(0, seed, retain).unwrap();
+    // the node calls `getrandom` many times after generating the seed
+    for id in 1..40 {
+        db.put_randomness(id, [id as u8; 32], retain).unwrap();
+    }
+
+    let mut generator = KeyGenerator::new(seed, b"ephemeral");
+    let sk = generator.next().unwrap();
+    let pk = (&ED25519_BASEPOINT_TABLE * &sk).to_montgomery();
+    let keys = KeyGeneratorWithCache::new(db.clone());
+    assert_eq!(
+        keys.reproduced_sk::<true>(pk.to_bytes()),
+        Some(sk.to_bytes())
+    );
+    assert_eq!(*keys.seed.lock(), Some(seed));
+
+    // the seed is out of the window, but it is cached
+    *keys.generators.lock() = None;
+    for id in 40..(40 + retain) {
+        db.put_randomness(id, [id as u8; 32], retain).unwrap();
+    }
+    let sk = generator.next().unwrap();
+    let pk = (&ED25519_BASEPOINT_TABLE * &sk).to_montgomery();
+    assert_eq!(
+        keys.reproduced_sk::<true>(pk.to_bytes()),
+        Some(sk.to_bytes())
+    );
+}
@@ -1,6 +1,6 @@
 /// Contains header for kernel events.
 mod event;
-pub use self::event::{EventMetadata, ConnectionInfo, DirectedId};
+pub use self::event::{EventMetadata, ConnectionInfo, DirectedId, TimeSource};
 
 /// Represents chunk of raw data flown in TCP connection.
 mod chunk;
@@ -8,7 +8,7 @@ pub use self::chunk::{ChunkHeader, EncryptionStatus, ChunkParser};
 
 /// State machine that manages debuggee processes and their TCP connections.
 mod recorder;
-pub use self::recorder::P2pRecorder;
+pub use self::recorder::{P2pRecorder, CxHandle};
 
 mod key_recover;
 
@@ -46,6 +46,23 @@ pub mod ptrace;
 /// Especially, it determines block latency in the node.
 mod stats;
 
+/// Replays TCP connections captured in pcap file through the connection state machine.
+/// Useful for decoder regression testing without live nodes.
+pub mod pcap;
+
+/// Gauges of the event pipeline between the ring buffer and the recorder.
+pub mod metrics;
+
+/// Raw events broadcast to external processors.
+pub mod feed;
+
+/// Optional publishing of the stats events on Kafka.
+pub mod kafka;
+
+/// Heap usage of the tests, counted by the global allocator of the test build.
+#[cfg(test)]
+mod test_alloc;
+
 /// Tests for `stats` module.
 #[cfg(test)]
 mod stats_test;
@@ -3,13 +3,44 @@ use std::{io, net::SocketAddr, time::SystemTime, collections::BTreeMap};
 use mina_p2p_messages::{binprot::BinProtRead, gossip::GossipNetMessageV2};
 use radiation::{Absorb, Emit};
 
-use crate::database::{DbCore, CapnpEventWithMetadataKey, CapnpEventWithMetadata};
+use crate::database::{
+    DbCore, CapnpEventWithMetadataKey, CapnpEventWithMetadata, GatingConfig, GatingConfigRecord,
+};
 
 #[derive(Default)]
 pub struct CapnpReader {
     buffer: Vec<u8>,
 }
 
+/// What the caller should do with the IPC after processing the data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CapnpStatus {
+    Continue,
+    /// The stream cannot be parsed, stop processing this direction.
+    Stop,
+    /// The node uses a capnp schema different from the compiled-in one,
+    /// decoded data is garbage, stop processing the IPC of this pid entirely.
+    SchemaMismatch,
+}
+
+const SCHEMA_MISMATCH: &str = "schema mismatch";
+
+fn schema_mismatch(what: &str) -> capnp::Error {
+    capnp::Error::failed(format!("{SCHEMA_MISMATCH}: {what}"))
+}
+
+// unknown union discriminant means the peer uses a different schema
+fn check_schema<T>(v: Result<T, capnp::NotInSchema>, what: &str) -> capnp::Result<T> {
+    v.map_err(|capnp::NotInSchema(d)| schema_mismatch(&format!("{what}, discriminant {d}")))
+}
+
+// the message is prefixed with 8 bytes of something, empty message is implausible
+fn check_gossip_data<'a>(data: &'a [u8], what: &str) -> capnp::Result<&'a [u8]> {
+    data.get(8..)
+        .filter(|msg| !msg.is_empty())
+        .ok_or_else(|| schema_mismatch(&format!("{what}, data is too short {}", data.len())))
+}
+
 #[derive(Absorb, Emit)]
 pub enum CapnpEvent {
     ReceivedGossip {
@@ -40,14 +71,23 @@ impl CapnpReader {
         db: &DbCore,
         subscriptions: &mut BTreeMap<u64, String>,
         chain_id: &mut String,
-    ) -> bool {
+    ) -> CapnpStatus {
         let mut events = vec![];
-        let should_continue = loop {
+        let mut gating = None;
+        let status = loop {
             if !self.buffer.is_empty() {
                 let mut slice = self.buffer.as_slice();
 
                 let r = if incoming {
-                    process_request(pid, "<-", &mut slice, &mut events, subscriptions, chain_id)
+                    process_request(
+                        pid,
+                        "<-",
+                        &mut slice,
+                        &mut events,
+                        subscriptions,
+                        chain_id,
+                        &mut gating,
+                    )
                 } else {
                     process_response(pid, "->", &mut slice, &mut events, subscriptions)
                 };
@@ -61,7 +101,14 @@ impl CapnpReader {
                     }
                     Err(err) if err.description == "failed to fill the whole buffer" => {
                         log::debug!("capnp {pid} {incoming} waiting more data");
-                        break true;
+                        break CapnpStatus::Continue;
+                    }
+                    Err(err) if err.description.starts_with(SCHEMA_MISMATCH) => {
+                        log::error!(
+                            "capnp {pid} {incoming} {err}, the node's libp2p_helper likely uses \
+                             a different schema version, stop recording its IPC"
+                        );
+                        break CapnpStatus::SchemaMismatch;
                     }
                     Err(err) => {
                         let s0 = err.description.starts_with("Too many segments:");
@@ -69,15 +116,27 @@ impl CapnpReader {
                         if !(s0 || s1) {
                             log::error!("capnp {pid} {incoming} {err} {}", hex::encode(slice));
                         }
-                        break false;
+                        break CapnpStatus::Stop;
                     }
                 }
             } else {
-                break true;
+                break CapnpStatus::Continue;
             }
         };
 
-        if should_continue && !events.is_empty() {
+        // the latest wins, it is what the helper uses
+        if let Some(config) = gating {
+            let record = GatingConfigRecord {
+                node_address,
+                timestamp: real_time,
+                config,
+            };
+            if let Err(err) = db.put_gating_config(pid, record) {
+                log::error!("writing gating config in database {err}");
+            }
+        }
+
+        if status == CapnpStatus::Continue && !events.is_empty() {
             let height = events.iter().find_map(|e| match e {
                 CapnpEvent::Publish { msg, .. } | CapnpEvent::ReceivedGossip { msg, .. } => {
                     if msg[0] == 0 {
@@ -129,7 +188,7 @@ impl CapnpReader {
             }
         }
 
-        should_continue
+        status
     }
 }
 
@@ -152,6 +211,26 @@ fn calc_hash(data: &[u8], topic: &str) -> [u8; 32] {
         .into()
 }
 
+fn read_gating_config(
+    config: crate::libp2p_ipc_capnp::gating_config::Reader,
+) -> capnp::Result<GatingConfig> {
+    Ok(GatingConfig {
+        banned_ips: (config.get_banned_ips()?.iter())
+            .map(|x| x.map(ToOwned::to_owned))
+            .collect::<capnp::Result<_>>()?,
+        banned_peer_ids: (config.get_banned_peer_ids()?.iter())
+            .map(|x| x.get_id().map(ToOwned::to_owned))
+            .collect::<capnp::Result<_>>()?,
+        trusted_ips: (config.get_trusted_ips()?.iter())
+            .map(|x| x.map(ToOwned::to_owned))
+            .collect::<capnp::Result<_>>()?,
+        trusted_peer_ids: (config.get_trusted_peer_ids()?.iter())
+            .map(|x| x.get_id().map(ToOwned::to_owned))
+            .collect::<capnp::Result<_>>()?,
+        isolate: config.get_isolate(),
+    })
+}
+
 // TODO: figure out how to capture this, doesn't work for now
 pub fn process_request<R>(
     pid: u32,
@@ -160,6 +239,7 @@ pub fn process_request<R>(
     events: &mut Vec<CapnpEvent>,
     subscriptions: &mut BTreeMap<u64, String>,
     chain_id: &mut String,
+    gating: &mut Option<GatingConfig>,
 ) -> capnp::Result<()>
 where
     R: io::Read,
@@ -171,40 +251,45 @@ where
 
     let t = reader.get_root::<message::Reader>()?;
 
-    match t.which()? {
-        message::RpcRequest(Ok(msg)) => match msg.which() {
-            Ok(rpc_request::Configure(Ok(config))) => {
-                let network_id = config.get_config()?.get_network_id()?;
+    match check_schema(t.which(), "helper message")? {
+        message::RpcRequest(Ok(msg)) => match check_schema(msg.which(), "rpc request")? {
+            rpc_request::Configure(Ok(config)) => {
+                let config = config.get_config()?;
+                let network_id = config.get_network_id()?;
                 *chain_id = format!("/coda/0.0.1/{network_id}");
+                *gating = Some(read_gating_config(config.get_gating_config()?)?);
+            }
+            rpc_request::SetGatingConfig(Ok(x)) => {
+                *gating = Some(read_gating_config(x.get_gating_config()?)?);
             }
-            Ok(rpc_request::AddPeer(Ok(peer))) => {
+            rpc_request::AddPeer(Ok(peer)) => {
                 let addr = peer.get_multiaddr()?.get_representation()?;
                 log::debug!("capnp message {pid} {incoming} add_peer {addr}");
             }
-            Ok(rpc_request::Publish(Ok(msg))) => {
+            rpc_request::Publish(Ok(msg)) => {
                 let topic = msg.get_topic()?;
                 // log::info!("capnp message {pid} {incoming} publish {topic}");
 
                 let data = msg.get_data()?;
                 events.push(CapnpEvent::Publish {
-                    msg: data[8..].to_vec(),
+                    msg: check_gossip_data(data, "publish")?.to_vec(),
                     hash: calc_hash(data, topic),
                 });
             }
-            Ok(rpc_request::OpenStream(Ok(stream))) => {
+            rpc_request::OpenStream(Ok(stream)) => {
                 let peer = stream.get_peer()?.get_id()?;
                 let protocol = stream.get_protocol_id()?;
                 log::debug!("capnp message {pid} {incoming} open stream {peer} {protocol}");
             }
-            Ok(rpc_request::CloseStream(Ok(stream))) => {
+            rpc_request::CloseStream(Ok(stream)) => {
                 let id = stream.get_stream_id()?.get_id();
                 log::debug!("capnp message {pid} {incoming} close stream {id}");
             }
-            Ok(rpc_request::ResetStream(Ok(stream))) => {
+            rpc_request::ResetStream(Ok(stream)) => {
                 let id = stream.get_stream_id()?.get_id();
                 log::debug!("capnp message {pid} {incoming} reset stream {id}");
             }
-            Ok(rpc_request::SendStream(Ok(msg))) => {
+            rpc_request::SendStream(Ok(msg)) => {
                 let msg = msg.get_msg()?;
                 let id = msg.get_stream_id()?.get_id();
                 let data = msg.get_data()?;
@@ -213,15 +298,15 @@ where
                     data.len()
                 );
             }
-            Ok(rpc_request::Subscribe(Ok(x))) => {
+            rpc_request::Subscribe(Ok(x)) => {
                 if let (Ok(id), Ok(topic)) = (x.get_subscription_id(), x.get_topic()) {
                     subscriptions.insert(id.get_id(), topic.to_owned());
                 }
             }
             _ => (),
         },
-        message::PushMessage(Ok(msg)) => match msg.which() {
-            Ok(push_message::AddResource(Ok(resource))) => {
+        message::PushMessage(Ok(msg)) => match check_schema(msg.which(), "push message")? {
+            push_message::AddResource(Ok(resource)) => {
                 let _ = resource;
             }
             _ => (),
@@ -252,29 +337,29 @@ where
 
     let t = reader.get_root::<message::Reader>()?;
 
-    match t.which()? {
-        message::PushMessage(Ok(msg)) => match msg.which() {
-            Ok(push_message::PeerConnected(Ok(peer))) => {
+    match check_schema(t.which(), "daemon message")? {
+        message::PushMessage(Ok(msg)) => match check_schema(msg.which(), "push message")? {
+            push_message::PeerConnected(Ok(peer)) => {
                 let id = peer.get_peer_id()?.get_id()?;
                 log::info!("capnp message {pid} {incoming} connected {id}");
             }
-            Ok(push_message::PeerDisconnected(Ok(peer))) => {
+            push_message::PeerDisconnected(Ok(peer)) => {
                 let id = peer.get_peer_id()?.get_id()?;
                 log::info!("capnp message {pid} {incoming} disconnected {id}");
             }
-            Ok(push_message::IncomingStream(Ok(stream))) => {
+            push_message::IncomingStream(Ok(stream)) => {
                 let peer = stream.get_peer()?.get_peer_id()?.get_id()?;
                 let protocol = stream.get_protocol()?;
                 let id = stream.get_stream_id()?.get_id();
                 log::debug!("capnp message {pid} {incoming} open stream {peer} {protocol} {id}");
             }
-            Ok(push_message::StreamMessageReceived(Ok(msg))) => {
+            push_message::StreamMessageReceived(Ok(msg)) => {
                 let msg = msg.get_msg()?;
                 let id = msg.get_stream_id()?.get_id();
                 let data = msg.get_data()?;
                 log::debug!("capnp message {pid} {incoming} msg {id} {}", data.len());
             }
-            Ok(push_message::GossipReceived(Ok(msg))) => {
+            push_message::GossipReceived(Ok(msg)) => {
                 let sender = msg.get_sender()?;
                 let peer_id = sender.get_peer_id()?.get_id()?.to_owned();
                 let peer_host = sender.get_host()?.to_owned();
@@ -292,22 +377,24 @@ where
                     peer_id,
                     peer_host,
                     peer_port,
-                    msg: data[8..].to_vec(),
+                    msg: check_gossip_data(data, "gossip received")?.to_vec(),
                     hash: calc_hash(data, &topic),
                 });
             }
             _ => (),
         },
-        message::RpcResponse(Ok(response)) => match response.which() {
-            Ok(rpc_response::Success(Ok(response))) => match response.which() {
-                Ok(rpc_response_success::Listen(Ok(addresses))) => {
-                    for addr in addresses.get_result()? {
-                        let addr = addr.get_representation()?;
-                        log::debug!("capnp message {pid} {incoming} listen {addr}");
+        message::RpcResponse(Ok(response)) => match check_schema(response.which(), "response")? {
+            rpc_response::Success(Ok(response)) => {
+                match check_schema(response.which(), "success response")? {
+                    rpc_response_success::Listen(Ok(addresses)) => {
+                        for addr in addresses.get_result()? {
+                            let addr = addr.get_representation()?;
+                            log::debug!("capnp message {pid} {incoming} listen {addr}");
+                        }
                     }
+                    _ => (),
                 }
-                _ => (),
-            },
+            }
             _ => (),
         },
         _ => (),
@@ -0,0 +1,378 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering::SeqCst},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+/// Events sent by the ring buffer reader, but not yet taken by the consumer.
+pub static EVENT_CHANNEL_DEPTH: AtomicUsize = AtomicUsize::new(0);
+pub static EVENT_CHANNEL_MAX_DEPTH: AtomicUsize = AtomicUsize::new(0);
+pub static EVENT_CHANNEL_CAPACITY: AtomicUsize = AtomicUsize::new(0);
+
+/// Unread bytes in the kernel ring buffer, updated by the reader.
+pub static RING_BUFFER_USED: AtomicUsize = AtomicUsize::new(0);
+pub static RING_BUFFER_CAPACITY: AtomicUsize = AtomicUsize::new(0);
+/// Bytes lost on the ring buffer overflow, if `RING_BUFFER_OVERFLOW=resync`.
+pub static RING_BUFFER_SKIPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// Connections dropped from tracking, because the pid exceeded `MAX_CNS_PER_PID`.
+pub static CONNECTIONS_EVICTED: AtomicU64 = AtomicU64::new(0);
+
+/// Events not published on kafka, because the producer is behind or failed.
+pub static KAFKA_DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Free space on the database volume, updated by the disk watchdog.
+pub static DISK_FREE_BYTES: AtomicU64 = AtomicU64::new(0);
+/// Set by the disk watchdog if the free space is below the floor, new connections are not recorded.
+pub static DISK_LOW: AtomicBool = AtomicBool::new(false);
+/// Connections not recorded, because the disk was low.
+pub static CONNECTIONS_SKIPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Unix time in seconds of the last event from the kernel.
+pub static LAST_EVENT: AtomicU64 = AtomicU64::new(0);
+/// Cleared by the watchdog if the node is alive, but no events arrive.
+pub static HEALTHY: AtomicBool = AtomicBool::new(true);
+
+/// Upper bounds in seconds, the last bucket is `+Inf`.
+const BLOCK_LATENCY_BUCKETS: [f64; 9] = [0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+/// Latency of the block relative to the first time the debugger saw it.
+pub static BLOCK_LATENCY: Mutex<Histogram> = Mutex::new(Histogram::new());
+
+#[derive(Clone, Copy)]
+pub struct Exemplar {
+    /// the message id, the message is at `/message/{id}`
+    pub message_id: u64,
+    pub value: f64,
+    pub timestamp: f64,
+}
+
+/// Not cumulative, each bucket holds the latest exemplar that fell in it.
+pub struct Histogram {
+    counts: [u64; BLOCK_LATENCY_BUCKETS.len() + 1],
+    exemplars: [Option<Exemplar>; BLOCK_LATENCY_BUCKETS.len() + 1],
+    sum: f64,
+}
+
+impl Histogram {
+    pub const fn new() -> Self {
+        Histogram {
+            counts: [0; BLOCK_LATENCY_BUCKETS.len() + 1],
+            exemplars: [None; BLOCK_LATENCY_BUCKETS.len() + 1],
+            sum: 0.0,
+        }
+    }
+
+    pub fn observe(&mut self, value: f64, message_id: u64, time: SystemTime) {
+        let i = BLOCK_LATENCY_BUCKETS
+            .iter()
+            .position(|le| value <= *le)
+            .unwrap_or(BLOCK_LATENCY_BUCKETS.len());
+        self.counts[i] += 1;
+        self.sum += value;
+        let timestamp = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        self.exemplars[i] = Some(Exemplar {
+            message_id,
+            value,
+            timestamp: timestamp.as_secs_f64(),
+        });
+    }
+
+    fn encode(&self, name: &str, out: &mut String) {
+        let _ = writeln!(out, "# TYPE {name} histogram");
+        let _ = writeln!(out, "# UNIT {name} seconds");
+        let les = BLOCK_LATENCY_BUCKETS
+            .iter()
+            .map(|le| format!("{le:?}"))
+            .chain(std::iter::once("+Inf".to_owned()));
+        let mut count = 0;
+        for ((le, c), exemplar) in les.zip(self.counts).zip(self.exemplars) {
+            count += c;
+            let _ = write!(out, "{name}_bucket{{le=\"{le}\"}} {count}");
+            if let Some(e) = exemplar {
+                let _ = write!(
+                    out,
+                    " # {{trace_id=\"{}\"}} {} {}",
+                    e.message_id, e.value, e.timestamp
+                );
+            }
+            out.push('\n');
+        }
+        let _ = writeln!(out, "{name}_count {count}");
+        let _ = writeln!(out, "{name}_sum {}", self.sum);
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn block_latency(latency: Duration, message_id: u64, time: SystemTime) {
+    if let Ok(mut histogram) = BLOCK_LATENCY.lock() {
+        histogram.observe(latency.as_secs_f64(), message_id, time);
+    }
+}
+
+#[derive(Serialize)]
+pub struct Metrics {
+    pub event_channel_depth: usize,
+    pub event_channel_max_depth: usize,
+    pub event_channel_capacity: usize,
+    pub ring_buffer_used: usize,
+    pub ring_buffer_capacity: usize,
+    pub ring_buffer_fill_ratio: f32,
+    pub ring_buffer_skipped: usize,
+    pub connections_evicted: u64,
+    pub kafka_dropped: u64,
+    pub disk_free_bytes: u64,
+    pub disk_low: bool,
+    pub connections_skipped: u64,
+}
+
+pub fn event_sent() {
+    let depth = EVENT_CHANNEL_DEPTH.fetch_add(1, SeqCst) + 1;
+    EVENT_CHANNEL_MAX_DEPTH.fetch_max(depth, SeqCst);
+}
+
+pub fn event_received() {
+    EVENT_CHANNEL_DEPTH.fetch_sub(1, SeqCst);
+}
+
+pub fn connection_evicted() {
+    CONNECTIONS_EVICTED.fetch_add(1, SeqCst);
+}
+
+pub fn kafka_dropped() {
+    KAFKA_DROPPED.fetch_add(1, SeqCst);
+}
+
+pub fn disk_low() -> bool {
+    DISK_LOW.load(SeqCst)
+}
+
+pub fn connection_skipped() {
+    CONNECTIONS_SKIPPED.fetch_add(1, SeqCst);
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+pub fn event_seen(time: SystemTime) {
+    LAST_EVENT.store(unix_secs(time), SeqCst);
+}
+
+/// Seconds since the last event, `None` if there was no event yet.
+pub fn since_last_event(now: SystemTime) -> Option<u64> {
+    match LAST_EVENT.load(SeqCst) {
+        0 => None,
+        last => Some(unix_secs(now).saturating_sub(last)),
+    }
+}
+
+#[derive(Serialize)]
+pub struct Health {
+    pub ready: bool,
+    pub seconds_since_last_event: Option<u64>,
+}
+
+pub fn health() -> Health {
+    Health {
+        ready: HEALTHY.load(SeqCst),
+        seconds_since_last_event: since_last_event(SystemTime::now()),
+    }
+}
+
+pub fn snapshot() -> Metrics {
+    let ring_buffer_used = RING_BUFFER_USED.load(SeqCst);
+    let ring_buffer_capacity = RING_BUFFER_CAPACITY.load(SeqCst);
+    Metrics {
+        event_channel_depth: EVENT_CHANNEL_DEPTH.load(SeqCst),
+        event_channel_max_depth: EVENT_CHANNEL_MAX_DEPTH.load(SeqCst),
+        event_channel_capacity: EVENT_CHANNEL_CAPACITY.load(SeqCst),
+        ring_buffer_used,
+        ring_buffer_capacity,
+        ring_buffer_fill_ratio: if ring_buffer_capacity == 0 {
+            0.0
+        } else {
+            ring_buffer_used as f32 / ring_buffer_capacity as f32
+        },
+        ring_buffer_skipped: RING_BUFFER_SKIPPED.load(SeqCst),
+        connections_evicted: CONNECTIONS_EVICTED.load(SeqCst),
+        kafka_dropped: KAFKA_DROPPED.load(SeqCst),
+        disk_free_bytes: DISK_FREE_BYTES.load(SeqCst),
+        disk_low: DISK_LOW.load(SeqCst),
+        connections_skipped: CONNECTIONS_SKIPPED.load(SeqCst),
+    }
+}
+
+/// The metrics in OpenMetrics text format, the block latency histogram
+/// has exemplars, `trace_id` is the id of the message. `decrypt` is the decrypted
+/// and failed to decrypt bytes, if the recorder is running.
+pub fn openmetrics(c: &Collected, decrypt: Option<(usize, usize)>) -> String {
+    let m = snapshot();
+    let mut out = String::new();
+    let gauges = [
+        ("event_channel_depth", m.event_channel_depth),
+        ("event_channel_max_depth", m.event_channel_max_depth),
+        ("event_channel_capacity", m.event_channel_capacity),
+        ("ring_buffer_used_bytes", m.ring_buffer_used),
+        ("ring_buffer_capacity_bytes", m.ring_buffer_capacity),
+        ("disk_free_bytes", m.disk_free_bytes as usize),
+        ("disk_low", m.disk_low as usize),
+    ];
+    for (name, value) in gauges {
+        let _ = writeln!(out, "# TYPE {name} gauge");
+        let _ = writeln!(out, "{name} {value}");
+    }
+    let _ = writeln!(out, "# TYPE ring_buffer_fill_ratio gauge");
+    let _ = writeln!(out, "ring_buffer_fill_ratio {}", m.ring_buffer_fill_ratio);
+    let _ = writeln!(out, "# TYPE ring_buffer_skipped_bytes counter");
+    let _ = writeln!(
+        out,
+        "ring_buffer_skipped_bytes_total {}",
+        m.ring_buffer_skipped
+    );
+    let _ = writeln!(out, "# TYPE connections_evicted counter");
+    let _ = writeln!(out, "connections_evicted_total {}", m.connections_evicted);
+    let _ = writeln!(out, "# TYPE kafka_dropped counter");
+    let _ = writeln!(out, "kafka_dropped_total {}", m.kafka_dropped);
+    let _ = writeln!(out, "# TYPE connections_skipped counter");
+    let _ = writeln!(out, "connections_skipped_total {}", m.connections_skipped);
+
+    let mut counter = |name: &str, value: u64| {
+        let _ = writeln!(out, "# TYPE {name} counter");
+        let _ = writeln!(out, "{name}_total {value}");
+    };
+    counter("connections", c.connections);
+    counter("messages", c.messages);
+    if let Some((decrypted, failed)) = decrypt {
+        counter("decrypted_bytes", decrypted as u64);
+        counter("failed_to_decrypt_bytes", failed as u64);
+    }
+    counter("firewall_blocked_packets", c.blocked_packets);
+    counter("firewall_blocked_bytes", c.blocked_bytes);
+    let _ = writeln!(out, "# TYPE messages_by_kind counter");
+    for (kind, count) in &c.messages_by_kind {
+        let _ = writeln!(
+            out,
+            "messages_by_kind_total{{stream_kind=\"{kind}\"}} {count}"
+        );
+    }
+
+    if let Ok(histogram) = BLOCK_LATENCY.lock() {
+        histogram.encode("block_latency_seconds", &mut out);
+    }
+    out.push_str("# EOF\n");
+    out
+}
+
+/// How long the text metrics reuse the counts collected from the database and the firewall.
+const COLLECTED_TTL: Duration = Duration::from_secs(5);
+
+/// Counts which are not in the statics, the firewall stats are asked from the kernel.
+#[derive(Default)]
+pub struct Collected {
+    pub connections: u64,
+    pub messages: u64,
+    pub messages_by_kind: BTreeMap<String, u64>,
+    pub blocked_packets: u64,
+    pub blocked_bytes: u64,
+}
+
+#[derive(Default)]
+pub struct CollectedCache(Mutex<Option<(Instant, Arc<Collected>)>>);
+
+impl CollectedCache {
+    /// The cached counts, or new ones if the cached are older than `COLLECTED_TTL`.
+    /// The lock is held while collecting, so concurrent scrapes don't repeat the scan.
+    pub fn get<F>(&self, now: Instant, collect: F) -> Arc<Collected>
+    where
+        F: FnOnce() -> Collected,
+    {
+        let mut cache = self.0.lock().unwrap_or_else(|err| err.into_inner());
+        match &*cache {
+            Some((time, collected)) if now.saturating_duration_since(*time) < COLLECTED_TTL => {
+                collected.clone()
+            }
+            _ => {
+                let collected = Arc::new(collect());
+                *cache = Some((now, collected.clone()));
+                collected
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn openmetrics_text() {
+    let cache = CollectedCache::default();
+    let now = Instant::now();
+    let collect = || Collected {
+        connections: 3,
+        messages: 10,
+        messages_by_kind: [("/meshsub/1.1.0".to_owned(), 7)].into_iter().collect(),
+        blocked_packets: 2,
+        blocked_bytes: 120,
+    };
+    let c = cache.get(now, collect);
+    // cached, not collected again
+    let c_ = cache.get(now + Duration::from_secs(1), || unreachable!());
+    assert!(Arc::ptr_eq(&c, &c_));
+    let c_ = cache.get(now + COLLECTED_TTL, Collected::default);
+    assert_eq!(c_.connections, 0);
+
+    let out = openmetrics(&c, Some((100, 5)));
+    let lines = out.lines().collect::<Vec<_>>();
+    let i = lines
+        .iter()
+        .position(|l| *l == "# TYPE connections counter")
+        .unwrap();
+    assert_eq!(lines[i + 1], "connections_total 3");
+    assert!(lines.contains(&"failed_to_decrypt_bytes_total 5"));
+    assert!(lines.contains(&"firewall_blocked_bytes_total 120"));
+    assert!(lines.contains(&"messages_by_kind_total{stream_kind=\"/meshsub/1.1.0\"} 7"));
+    // each series once, the text ends with the marker
+    let types = lines.iter().filter(|l| l.starts_with("# TYPE ")).count();
+    let unique = lines
+        .iter()
+        .filter(|l| l.starts_with("# TYPE "))
+        .collect::<std::collections::BTreeSet<_>>();
+    assert_eq!(types, unique.len());
+    assert_eq!(lines.last(), Some(&"# EOF"));
+}
+
+#[cfg(test)]
+#[test]
+fn histogram_exemplars() {
+    let mut h = Histogram::new();
+    let time = UNIX_EPOCH + Duration::from_secs(10);
+    h.observe(0.3, 1, time);
+    h.observe(0.4, 2, time);
+    h.observe(100.0, 3, time);
+
+    let mut out = String::new();
+    h.encode("x_seconds", &mut out);
+    let lines = out.lines().collect::<Vec<_>>();
+    assert_eq!(lines[2], "x_seconds_bucket{le=\"0.1\"} 0");
+    assert_eq!(
+        lines[4],
+        "x_seconds_bucket{le=\"0.5\"} 2 # {trace_id=\"2\"} 0.4 10"
+    );
+    assert_eq!(
+        lines[11],
+        "x_seconds_bucket{le=\"+Inf\"} 3 # {trace_id=\"3\"} 100 10"
+    );
+    assert_eq!(lines[12], "x_seconds_count 3");
+}
@@ -0,0 +1,544 @@
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::{Duration, SystemTime, Instant},
+    thread,
+};
+
+use thiserror::Error;
+
+use super::{
+    event::{ConnectionInfo, EventMetadata, TimeSource},
+    recorder::P2pRecorder,
+};
+
+#[derive(Debug, Error)]
+pub enum PcapError {
+    #[error("unexpected end of file")]
+    Truncated,
+    #[error("bad magic number {0:08x}")]
+    BadMagic(u32),
+    #[error("unsupported link type {0}")]
+    LinkType(u32),
+}
+
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+
+/// TCP segment extracted from the pcap record.
+pub struct Segment {
+    pub time: SystemTime,
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    pub seq: u32,
+    pub flags: u8,
+    pub payload: Vec<u8>,
+}
+
+/// Parses classic pcap file, yields TCP segments, ignores everything else.
+pub fn parse(bytes: &[u8]) -> Result<Vec<Segment>, PcapError> {
+    let magic = read_u32::<false>(bytes, 0)?;
+    let (be, nanos) = match magic {
+        0xa1b2c3d4 => (false, false),
+        0xa1b23c4d => (false, true),
+        0xd4c3b2a1 => (true, false),
+        0x4d3cb2a1 => (true, true),
+        _ => return Err(PcapError::BadMagic(magic)),
+    };
+    let read = |offset| {
+        if be {
+            read_u32::<true>(bytes, offset)
+        } else {
+            read_u32::<false>(bytes, offset)
+        }
+    };
+    let link_type = read(20)?;
+
+    let mut segments = vec![];
+    let mut offset = 24;
+    while offset < bytes.len() {
+        let secs = read(offset)?;
+        let frac = read(offset + 4)?;
+        let captured = read(offset + 8)? as usize;
+        let data = bytes
+            .get((offset + 16)..(offset + 16 + captured))
+            .ok_or(PcapError::Truncated)?;
+        offset += 16 + captured;
+
+        let frac = if nanos {
+            Duration::from_nanos(frac as u64)
+        } else {
+            Duration::from_micros(frac as u64)
+        };
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(secs as u64) + frac;
+
+        let ip = match link_type {
+            LINKTYPE_ETHERNET => data.get(14..),
+            LINKTYPE_RAW => Some(data),
+            LINKTYPE_LINUX_SLL => data.get(16..),
+            _ => return Err(PcapError::LinkType(link_type)),
+        };
+        if let Some(segment) = ip.and_then(|ip| parse_ip(time, ip)) {
+            segments.push(segment);
+        }
+    }
+
+    Ok(segments)
+}
+
+fn read_u32<const BE: bool>(bytes: &[u8], offset: usize) -> Result<u32, PcapError> {
+    let v = bytes
+        .get(offset..(offset + 4))
+        .and_then(|s| s.try_into().ok())
+        .ok_or(PcapError::Truncated)?;
+    Ok(if BE {
+        u32::from_be_bytes(v)
+    } else {
+        u32::from_le_bytes(v)
+    })
+}
+
+fn parse_ip(time: SystemTime, ip: &[u8]) -> Option<Segment> {
+    let (src, dst, tcp) = match ip.first()? >> 4 {
+        4 => {
+            let header_len = ((ip[0] & 0x0f) as usize) * 4;
+            let total_len = u16::from_be_bytes(ip.get(2..4)?.try_into().ok()?) as usize;
+            if *ip.get(9)? != 6 {
+                return None;
+            }
+            let src = <[u8; 4]>::try_from(ip.get(12..16)?).ok()?;
+            let dst = <[u8; 4]>::try_from(ip.get(16..20)?).ok()?;
+            let tcp = ip.get(header_len..total_len.min(ip.len()))?;
+            (Ipv4Addr::from(src).into(), Ipv4Addr::from(dst).into(), tcp)
+        }
+        6 => {
+            // extension headers are not supported
+            let payload_len = u16::from_be_bytes(ip.get(4..6)?.try_into().ok()?) as usize;
+            if *ip.get(6)? != 6 {
+                return None;
+            }
+            let src = <[u8; 16]>::try_from(ip.get(8..24)?).ok()?;
+            let dst = <[u8; 16]>::try_from(ip.get(24..40)?).ok()?;
+            let tcp = ip.get(40..(40 + payload_len).min(ip.len()))?;
+            (Ipv6Addr::from(src).into(), Ipv6Addr::from(dst).into(), tcp)
+        }
+        _ => return None,
+    };
+    let src_port = u16::from_be_bytes(tcp.get(0..2)?.try_into().ok()?);
+    let dst_port = u16::from_be_bytes(tcp.get(2..4)?.try_into().ok()?);
+    let seq = u32::from_be_bytes(tcp.get(4..8)?.try_into().ok()?);
+    let data_offset = ((tcp.get(12)? >> 4) as usize) * 4;
+    let flags = *tcp.get(13)?;
+
+    Some(Segment {
+        time,
+        src: SocketAddr::new(src, src_port),
+        dst: SocketAddr::new(dst, dst_port),
+        seq,
+        flags,
+        payload: tcp.get(data_offset..)?.to_vec(),
+    })
+}
+
+/// Writes classic pcap of a single TCP connection, the segments are synthetic,
+/// the handshake is fabricated, checksums are not computed.
+pub struct Writer {
+    buf: Vec<u8>,
+    local: SocketAddr,
+    remote: SocketAddr,
+    // sequence number of the next byte, outgoing and incoming
+    seq: [u32; 2],
+    last: SystemTime,
+}
+
+impl Writer {
+    // ip and tcp headers must fit in the ipv4 total length
+    const MAX_PAYLOAD: usize = 0xffff - 60;
+
+    pub fn new(local: SocketAddr, remote: SocketAddr, incoming: bool, time: SystemTime) -> Self {
+        let mut buf = Vec::with_capacity(0x1000);
+        buf.extend_from_slice(&0xa1b2c3d4_u32.to_le_bytes());
+        buf.extend_from_slice(&2_u16.to_le_bytes());
+        buf.extend_from_slice(&4_u16.to_le_bytes());
+        // timezone and accuracy
+        buf.extend_from_slice(&[0; 8]);
+        buf.extend_from_slice(&0xffff_u32.to_le_bytes());
+        buf.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+
+        let mut w = Writer {
+            buf,
+            local,
+            remote,
+            seq: [0; 2],
+            last: time,
+        };
+        // the initiator sends syn, the responder syn ack
+        w.segment(time, incoming, TCP_SYN, &[]);
+        w.segment(time, !incoming, TCP_SYN | TCP_ACK, &[]);
+        w.seq = [1; 2];
+        w
+    }
+
+    pub fn data(&mut self, time: SystemTime, incoming: bool, bytes: &[u8]) {
+        for chunk in bytes.chunks(Self::MAX_PAYLOAD) {
+            self.segment(time, incoming, TCP_ACK | TCP_PSH, chunk);
+            let seq = &mut self.seq[incoming as usize];
+            *seq = seq.wrapping_add(chunk.len() as u32);
+        }
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+
+    fn segment(&mut self, time: SystemTime, incoming: bool, flags: u8, payload: &[u8]) {
+        // keep the timestamps monotonic
+        let time = time.max(self.last);
+        self.last = time;
+
+        let (src, dst) = if incoming {
+            (self.remote, self.local)
+        } else {
+            (self.local, self.remote)
+        };
+        let mut tcp = Vec::with_capacity(20 + payload.len());
+        tcp.extend_from_slice(&src.port().to_be_bytes());
+        tcp.extend_from_slice(&dst.port().to_be_bytes());
+        tcp.extend_from_slice(&self.seq[incoming as usize].to_be_bytes());
+        let ack = if flags & TCP_ACK != 0 {
+            self.seq[!incoming as usize]
+        } else {
+            0
+        };
+        tcp.extend_from_slice(&ack.to_be_bytes());
+        tcp.extend_from_slice(&[5 << 4, flags, 0xff, 0xff, 0, 0, 0, 0]);
+        tcp.extend_from_slice(payload);
+
+        let mut ip = Vec::with_capacity(40 + tcp.len());
+        match (src.ip(), dst.ip()) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                ip.extend_from_slice(&[0x45, 0]);
+                ip.extend_from_slice(&((20 + tcp.len()) as u16).to_be_bytes());
+                // id, don't fragment, ttl, tcp, checksum
+                ip.extend_from_slice(&[0, 0, 0x40, 0, 64, 6, 0, 0]);
+                ip.extend_from_slice(&src.octets());
+                ip.extend_from_slice(&dst.octets());
+            }
+            (src, dst) => {
+                let to_v6 = |ip: IpAddr| match ip {
+                    IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+                    IpAddr::V6(ip) => ip,
+                };
+                ip.extend_from_slice(&[0x60, 0, 0, 0]);
+                ip.extend_from_slice(&(tcp.len() as u16).to_be_bytes());
+                ip.extend_from_slice(&[6, 64]);
+                ip.extend_from_slice(&to_v6(src).octets());
+                ip.extend_from_slice(&to_v6(dst).octets());
+            }
+        }
+        ip.extend_from_slice(&tcp);
+
+        let d = time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        self.buf
+            .extend_from_slice(&(d.as_secs() as u32).to_le_bytes());
+        self.buf.extend_from_slice(&d.subsec_micros().to_le_bytes());
+        self.buf.extend_from_slice(&(ip.len() as u32).to_le_bytes());
+        self.buf.extend_from_slice(&(ip.len() as u32).to_le_bytes());
+        self.buf.extend_from_slice(&ip);
+    }
+}
+
+/// Feeds TCP connections captured in pcap into the recorder as if they were observed by BPF.
+/// Only connections whose TCP handshake is captured are replayed, otherwise the pnet nonce
+/// is probably missing and the connection cannot be decrypted anyway.
+pub struct Replay<'a> {
+    /// The address of the node which is the debuggee, connections not involving it are ignored.
+    pub node: IpAddr,
+    /// `mainnet`, `devnet`, `berkeley` or a literal chain id, as in `BPF_ALIAS`.
+    pub chain_id: &'a str,
+    /// Randomness the node obtained from the kernel, required to decrypt noise.
+    pub randomness: &'a [[u8; 32]],
+    /// Keep the original relative timing of the segments, otherwise as fast as possible.
+    pub realtime: bool,
+}
+
+struct ReplayedConnection {
+    info: ConnectionInfo,
+    // expected sequence number of the next byte, outgoing and incoming
+    next_seq: [u32; 2],
+    closed: bool,
+}
+
+impl Replay<'_> {
+    const PID: u32 = 1;
+
+    /// Returns the number of replayed connections.
+    pub fn run(&self, recorder: &mut P2pRecorder, segments: Vec<Segment>) -> usize {
+        // the same format as `BPF_ALIAS`, so the chain id is resolved as for a live node
+        recorder.on_alias(Self::PID, format!("{}-{}", self.chain_id, self.node));
+        let first_time = segments
+            .first()
+            .map(|s| s.time)
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        for bytes in self.randomness {
+            recorder.on_randomness(Self::PID, *bytes, first_time);
+        }
+
+        let start = Instant::now();
+        let mut cns = BTreeMap::<(SocketAddr, SocketAddr), ReplayedConnection>::new();
+        let mut fd = 0;
+        for Segment {
+            time,
+            src,
+            dst,
+            seq,
+            flags,
+            mut payload,
+        } in segments
+        {
+            let (incoming, local, remote) = if src.ip() == self.node {
+                (false, src, dst)
+            } else if dst.ip() == self.node {
+                (true, dst, src)
+            } else {
+                continue;
+            };
+            if self.realtime {
+                let due = time.duration_since(first_time).unwrap_or_default();
+                if let Some(wait) = due.checked_sub(start.elapsed()) {
+                    thread::sleep(wait);
+                }
+            }
+            let metadata = |info: &ConnectionInfo| EventMetadata {
+                id: info.clone(),
+                time,
+                better_time: time,
+                duration: Duration::ZERO,
+                time_source: TimeSource::Capture,
+                lag: Duration::ZERO,
+            };
+
+            if flags & TCP_SYN != 0 {
+                let cn = if flags & TCP_ACK == 0 {
+                    fd += 1;
+                    let info = ConnectionInfo {
+                        addr: remote,
+                        pid: Self::PID,
+                        fd,
+                        generation: 0,
+                    };
+                    recorder.on_connect::<true>(
+                        incoming,
+                        metadata(&info),
+                        local.port(),
+                        0,
+                        String::new(),
+                    );
+                    // the tuple might be reused by a new connection
+                    let cn = ReplayedConnection {
+                        info,
+                        next_seq: [0; 2],
+                        closed: false,
+                    };
+                    cns.insert((local, remote), cn);
+                    cns.get_mut(&(local, remote)).expect("just inserted")
+                } else if let Some(cn) = cns.get_mut(&(local, remote)) {
+                    cn
+                } else {
+                    continue;
+                };
+                cn.next_seq[incoming as usize] = seq.wrapping_add(1);
+                continue;
+            }
+
+            let Some(cn) = cns.get_mut(&(local, remote)) else {
+                continue;
+            };
+            if cn.closed {
+                continue;
+            }
+
+            // drop retransmitted bytes
+            let expected = cn.next_seq[incoming as usize];
+            let end = seq.wrapping_add(payload.len() as u32);
+            let ahead = seq.wrapping_sub(expected) as i32;
+            if ahead < 0 {
+                let duplicated = (ahead.unsigned_abs() as usize).min(payload.len());
+                payload.drain(..duplicated);
+            } else if ahead > 0 {
+                log::warn!("{remote} lost {ahead} bytes, decoding will likely fail");
+            }
+            if end.wrapping_sub(expected) as i32 > 0 {
+                cn.next_seq[incoming as usize] = end;
+            }
+
+            if !payload.is_empty() {
+                recorder.on_data(incoming, metadata(&cn.info), 0, payload);
+            }
+            if flags & (TCP_FIN | TCP_RST) != 0 {
+                cn.closed = true;
+                // `ECONNRESET`
+                let close_reason = (flags & TCP_RST != 0).then_some(104);
+                recorder.on_disconnect(metadata(&cn.info), 0, close_reason);
+            }
+        }
+        for cn in cns.values().filter(|cn| !cn.closed) {
+            let metadata = EventMetadata {
+                id: cn.info.clone(),
+                ..EventMetadata::default()
+            };
+            recorder.on_disconnect(metadata, 0, None);
+        }
+
+        fd as usize
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn replay_pnet_multistream() {
+    use temp_dir::TempDir;
+
+    use super::database::DbFacade;
+
+    let d = TempDir::new().expect("cannot create temporary directory");
+    let db = DbFacade::open(d.path()).unwrap();
+    let core = db.core();
+    let mut recorder = P2pRecorder::new(db, false);
+
+    let segments = parse(include_bytes!("test_data/pnet_multistream.pcap")).unwrap();
+    let replay = Replay {
+        node: "10.0.0.1".parse().unwrap(),
+        chain_id: "/coda/0.0.1/5f704cc0c82e0ed70e873f0893d7e06f148524e3f0bdae2afb02e7819a0c24d1",
+        randomness: &[],
+        realtime: false,
+    };
+    assert_eq!(replay.run(&mut recorder, segments), 1);
+
+    let cn = core.fetch_connection(0).unwrap();
+    assert!(cn.incoming);
+    assert_eq!(cn.info.addr, "10.0.0.2:40000".parse().unwrap());
+
+    let messages = (0..recorder.cx.db.next_message_id())
+        .map(|id| {
+            let msg = core.fetch_full_message(id).unwrap();
+            (msg.incoming, msg.message)
+        })
+        .collect::<Vec<_>>();
+    let tokens = messages
+        .iter()
+        .map(|(incoming, v)| (*incoming, v["token"].as_str().unwrap()))
+        .collect::<Vec<_>>();
+    let expected = [
+        (true, "/multistream/1.0.0"),
+        (true, "/noise"),
+        (false, "/multistream/1.0.0"),
+        (false, "/noise"),
+    ];
+    assert_eq!(tokens, expected);
+    assert_eq!(messages[0].1["accepted"], "/noise");
+}
+
+#[cfg(test)]
+#[test]
+fn replay_pnet_noise() {
+    use temp_dir::TempDir;
+
+    use super::database::{DbFacade, StreamKind};
+
+    let d = TempDir::new().expect("cannot create temporary directory");
+    let db = DbFacade::open(d.path()).unwrap();
+    let core = db.core();
+    let mut recorder = P2pRecorder::new(db, false);
+
+    // the same handshake as in `redecrypt_after_randomness`, encrypted with the pnet key,
+    // both ephemeral keys come from the randomness
+    let segments = parse(include_bytes!("test_data/pnet_noise.pcap")).unwrap();
+    let randomness = [
+        "d1f3bca173136dd555dd97262336ce644a76ec31d521d2befe87caec8678c1a7",
+        "1c283e25c80f64f2806d9e19da1a393873d40bdf3d903a3776e013c4fdd97cb3",
+    ]
+    .map(|s| {
+        hex::decode(s)
+            .expect("valid constant")
+            .try_into()
+            .expect("valid constant")
+    });
+    let replay = Replay {
+        node: "10.0.0.1".parse().unwrap(),
+        chain_id: "/coda/0.0.1/5f704cc0c82e0ed70e873f0893d7e06f148524e3f0bdae2afb02e7819a0c24d1",
+        randomness: &randomness,
+        realtime: false,
+    };
+    assert_eq!(replay.run(&mut recorder, segments), 1);
+
+    let cn = core.fetch_connection(0).unwrap();
+    assert!(cn.incoming);
+
+    let messages = (0..recorder.cx.db.next_message_id())
+        .map(|id| core.fetch_full_message(id).unwrap())
+        .map(|msg| {
+            let field = if msg.stream_kind == StreamKind::Handshake {
+                "peer_id"
+            } else {
+                "token"
+            };
+            (
+                msg.incoming,
+                msg.message[field].as_str().unwrap().to_owned(),
+            )
+        })
+        .collect::<Vec<_>>();
+    let expected = [
+        (true, "/multistream/1.0.0"),
+        (true, "/noise"),
+        (false, "/multistream/1.0.0"),
+        (false, "/noise"),
+        // the static keys of the node and of the peer
+        (
+            false,
+            "12D3KooWFpqySZDHx7k5FMjdwmrU3TLhDbdADECCautBcEGtG4fr",
+        ),
+        (true, "12D3KooWQDPM7sYoGTMqBC8ZKdytk4AP5euD8SifHN6jEaTzEmn3"),
+        // the first transport message, the peer proposes the muxer
+        (true, "/multistream/1.0.0"),
+        (true, "/coda/mplex/1.0.0"),
+    ]
+    .map(|(incoming, s)| (incoming, s.to_owned()));
+    assert_eq!(messages, expected);
+}
+
+#[cfg(test)]
+#[test]
+fn write_and_parse() {
+    let local = "10.0.0.1:8302".parse().unwrap();
+    let remote = "10.0.0.2:40000".parse().unwrap();
+    let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+    let mut w = Writer::new(local, remote, true, time);
+    w.data(time + Duration::from_millis(1), true, b"ping");
+    // the timestamp goes back, the record still must be in order
+    w.data(time, false, &[7; 0x10000]);
+
+    let segments = parse(&w.finish()).unwrap();
+    assert_eq!(segments.len(), 5);
+    assert_eq!(segments[0].flags, TCP_SYN);
+    assert_eq!((segments[0].src, segments[0].dst), (remote, local));
+    assert_eq!(segments[2].payload, b"ping");
+    assert_eq!(segments[2].seq, 1);
+    assert_eq!(segments[3].src, local);
+    assert_eq!(
+        segments[3].payload.len() + segments[4].payload.len(),
+        0x10000
+    );
+    assert_eq!(segments[4].seq, 1 + segments[3].payload.len() as u32);
+    assert!(segments.windows(2).all(|w| w[0].time <= w[1].time));
+}
@@ -1,6 +1,6 @@
 use std::{
     collections::BTreeMap,
-    time::SystemTime,
+    time::{SystemTime, Duration},
     net::{SocketAddr, IpAddr},
     sync::{Arc, mpsc},
     thread::{self, JoinHandle},
@@ -10,10 +10,15 @@ use serde::Serialize;
 use parking_lot::Mutex;
 
 use super::{
-    event::{EventMetadata, ConnectionInfo, DirectedId},
+    event::{EventMetadata, ConnectionInfo, DirectedId, TimeSource},
+    chunk::EncryptionStatus,
     connection::{HandleData, pnet, multistream_select, noise, mux, mina_protocol},
-    database::{DbFacade, DbGroup},
+    database::{
+        DbFacade, DbGroup, DbResult, StableConnectionId, ConnectionId, StreamId, RedecryptReport,
+    },
     key_recover::KeyGeneratorWithCache,
+    kafka::Kafka,
+    metrics,
     tester::Tester,
     stats::{Stats, StatsState},
 };
@@ -34,7 +39,8 @@ pub struct P2pRecorder {
 
 pub struct ThreadContext {
     handle: JoinHandle<()>,
-    tx: mpsc::Sender<NetworkChunk>,
+    tx: mpsc::Sender<ThreadEvent>,
+    db: DbGroup,
 }
 
 pub struct ConnectionContext {
@@ -49,6 +55,13 @@ pub struct NetworkChunk {
     pub buffered: usize,
 }
 
+// the connection thread is the only writer of the stored connection
+pub enum ThreadEvent {
+    Chunk(NetworkChunk),
+    Shutdown { write: bool, time: SystemTime },
+    CloseReason(i32),
+}
+
 // my local sandbox
 // /coda/0.0.1/dd0f3f26be5a093f00077d1cd5d89abc253c95f301e9c12ae59e2d7c6052cc4d
 const CHAINS: [(&str, &str); 3] = [
@@ -68,11 +81,27 @@ const CHAINS: [(&str, &str); 3] = [
 
 pub struct Cx {
     pub apps: Mutex<BTreeMap<u32, (String, SocketAddr)>>,
+    // by the pid and the fd of the unix socket
+    pub ipc_peers: Mutex<BTreeMap<(u32, u32), IpcPeer>>,
     pub stats_state: Mutex<BTreeMap<SocketAddr, StatsState>>,
     pub keygen: KeyGeneratorWithCache,
     pub db: DbFacade,
     pub stats: Stats,
     pub aggregator: Option<Aggregator>,
+    pub kafka: Option<Kafka>,
+}
+
+/// The process on the other side of a unix socket, the credentials are
+/// obtained by `SO_PEERCRED`, usually the libp2p_helper or the daemon.
+#[derive(Clone, Serialize)]
+pub struct IpcPeer {
+    pub pid: u32,
+    pub fd: u32,
+    pub peer_pid: u32,
+    pub uid: u32,
+    pub gid: u32,
+    // the peer is a node the debugger watches
+    pub watched: bool,
 }
 
 impl Cx {
@@ -84,6 +113,74 @@ impl Cx {
             .map(|(_, addr)| addr.clone())
             .unwrap_or(SocketAddr::new(IpAddr::V4(0.into()), 0))
     }
+
+    /// Decodes the stored connection once again starting after pnet,
+    /// the key might be reconstructed now if more randomness is captured.
+    /// Does nothing if the handshake is already verified, otherwise messages would be duplicated,
+    /// the first message of the handshake is plaintext, so it is always counted as decrypted.
+    pub fn redecrypt(&self, id: u64) -> DbResult<RedecryptReport> {
+        let core = self.db.core();
+        let cn = core.fetch_connection(id)?;
+        let decrypted_before = cn.stats_in.decrypted_chunks + cn.stats_out.decrypted_chunks;
+        let chunks = core
+            .fetch_chunks(ConnectionId(id))
+            .filter(|(header, _)| {
+                matches!(header.encryption_status, EncryptionStatus::DecryptedPnet)
+            })
+            .collect::<Vec<_>>();
+        let mut report = RedecryptReport {
+            chunks: chunks.len(),
+            decrypted_before: decrypted_before as usize,
+            newly_decrypted: 0,
+        };
+        if !cn.noise_protocol.is_empty() {
+            return Ok(report);
+        }
+
+        let db = self.db.reopen(ConnectionId(id))?;
+        let mut state = Noise::from(StreamId::Handshake);
+        for (header, mut bytes) in chunks {
+            let id = DirectedId {
+                metadata: EventMetadata {
+                    id: ConnectionInfo {
+                        generation: cn.generation,
+                        ..cn.info.clone()
+                    },
+                    time: header.time,
+                    better_time: header.time,
+                    duration: Duration::ZERO,
+                    time_source: TimeSource::Replay,
+                    lag: Duration::ZERO,
+                },
+                alias: cn.alias.clone(),
+                incoming: header.incoming,
+                buffered: 0,
+            };
+            if let Err(err) = state.on_data(id.clone(), &mut bytes, self, &db) {
+                log::error!("{id}: {err}");
+            }
+        }
+        let cn = core.fetch_connection(id)?;
+        let decrypted = cn.stats_in.decrypted_chunks + cn.stats_out.decrypted_chunks;
+        // the chunks decrypted before are counted once again
+        report.newly_decrypted = decrypted.saturating_sub(decrypted_before * 2) as usize;
+
+        Ok(report)
+    }
+}
+
+/// The recorder is created after the http server, so the server gets its context here.
+#[derive(Clone, Default)]
+pub struct CxHandle(Arc<Mutex<Option<Arc<Cx>>>>);
+
+impl CxHandle {
+    pub fn set(&self, cx: Arc<Cx>) {
+        *self.0.lock() = Some(cx);
+    }
+
+    pub fn get(&self) -> Option<Arc<Cx>> {
+        self.0.lock().clone()
+    }
 }
 
 #[derive(Clone)]
@@ -150,15 +247,29 @@ impl P2pRecorder {
             cns_main_thread: BTreeMap::default(),
             cx: Arc::new(Cx {
                 apps: Mutex::default(),
+                ipc_peers: Mutex::default(),
                 keygen: KeyGeneratorWithCache::new(db.core()),
                 db,
                 stats: Stats::default(),
                 stats_state: Mutex::default(),
                 aggregator,
+                kafka: Kafka::from_env(),
             }),
         }
     }
 
+    /// Waits for the threads of the connections still open, once it returns every
+    /// connection has stored its close time, so the database can be flushed.
+    pub fn join(self) {
+        for (_, t_cx) in self.cns {
+            let ThreadContext { handle, tx, db } = t_cx;
+            drop(tx);
+            if handle.join().is_err() {
+                log::error!("{} thread panic", db.id());
+            }
+        }
+    }
+
     pub fn set_port(&mut self, pid: u32, port: u16) {
         self.cx
             .apps
@@ -167,6 +278,10 @@ impl P2pRecorder {
             .map(|(_, addr)| addr.set_port(port));
     }
 
+    pub fn on_peer_cred(&mut self, peer: IpcPeer) {
+        self.cx.ipc_peers.lock().insert((peer.pid, peer.fd), peer);
+    }
+
     pub fn on_alias(&mut self, pid: u32, alias: String) {
         let ip = alias
             .split('-')
@@ -180,10 +295,13 @@ impl P2pRecorder {
             .insert(pid, (alias, SocketAddr::new(ip, 8302)));
     }
 
+    /// The `local_port` is the port of the node's socket, `0` if unknown,
+    /// it is only needed for outgoing connections, where it is the ephemeral port.
     pub fn on_connect<const MAIN_THREAD: bool>(
         &mut self,
         incoming: bool,
         metadata: EventMetadata,
+        local_port: u16,
         buffered: usize,
         suggested_chain_id: String,
     ) {
@@ -191,6 +309,11 @@ impl P2pRecorder {
             tester.on_connect(incoming, metadata);
             return;
         }
+        if metrics::disk_low() {
+            log::warn!("{metadata} disk is low, skip connection");
+            metrics::connection_skipped();
+            return;
+        }
         let alias = {
             let lock = self.cx.apps.lock();
             lock.get(&metadata.id.pid)
@@ -209,6 +332,17 @@ impl P2pRecorder {
                 .unwrap_or(network)
                 .to_owned()
         };
+        let node_address = self.cx.pid_to_addr(metadata.id.pid);
+        let stable_id = if node_address.ip().is_unspecified() {
+            StableConnectionId::default()
+        } else if incoming {
+            StableConnectionId::new(metadata.id.addr, node_address, metadata.better_time)
+        } else if local_port == 0 {
+            StableConnectionId::default()
+        } else {
+            let local = SocketAddr::new(node_address.ip(), local_port);
+            StableConnectionId::new(local, metadata.id.addr, metadata.better_time)
+        };
         let id = DirectedId {
             metadata,
             alias: alias.clone(),
@@ -218,8 +352,11 @@ impl P2pRecorder {
         match self.cx.db.add(
             id.metadata.id.clone(),
             incoming,
+            stable_id,
             id.alias.clone(),
             id.metadata.time,
+            id.metadata.time_source,
+            id.metadata.lag,
         ) {
             Ok(group) => {
                 log::debug!("{id} {} new connection", group.id());
@@ -227,6 +364,7 @@ impl P2pRecorder {
 
                 let (tx, rx) = mpsc::channel();
                 let cx = self.cx.clone();
+                let db = group.clone();
                 let mut cn = Cn::new(chain_id.as_bytes());
 
                 if MAIN_THREAD {
@@ -242,13 +380,27 @@ impl P2pRecorder {
                 }
 
                 let handle = thread::spawn(move || {
-                    while let Ok(NetworkChunk {
-                        metadata,
-                        mut data,
-                        incoming,
-                        buffered,
-                    }) = rx.recv()
-                    {
+                    while let Ok(event) = rx.recv() {
+                        let NetworkChunk {
+                            metadata,
+                            mut data,
+                            incoming,
+                            buffered,
+                        } = match event {
+                            ThreadEvent::Chunk(chunk) => chunk,
+                            ThreadEvent::Shutdown { write, time } => {
+                                if let Err(err) = group.shutdown(write, time) {
+                                    log::error!("{} cannot store shutdown: {err}", group.id());
+                                }
+                                continue;
+                            }
+                            ThreadEvent::CloseReason(errno) => {
+                                if let Err(err) = group.set_close_reason(errno) {
+                                    log::error!("{} cannot store close reason: {err}", group.id());
+                                }
+                                continue;
+                            }
+                        };
                         let alias = {
                             let lock = cx.apps.lock();
                             lock.get(&metadata.id.pid)
@@ -268,7 +420,7 @@ impl P2pRecorder {
                     }
                     log::debug!("{id} {} disconnect", group.id());
                 });
-                let t_cx = ThreadContext { handle, tx };
+                let t_cx = ThreadContext { handle, tx, db };
 
                 self.cns.insert(info, t_cx);
             }
@@ -278,7 +430,45 @@ impl P2pRecorder {
         }
     }
 
-    pub fn on_disconnect(&mut self, metadata: EventMetadata, buffered: usize) {
+    pub fn on_connect_failed(&mut self, metadata: EventMetadata, local_port: u16, errno: i32) {
+        if self.tester.is_some() {
+            return;
+        }
+        let alias = {
+            let lock = self.cx.apps.lock();
+            lock.get(&metadata.id.pid)
+                .cloned()
+                .map(|(a, _)| a)
+                .unwrap_or_default()
+        };
+        let node_address = self.cx.pid_to_addr(metadata.id.pid);
+        let stable_id = if node_address.ip().is_unspecified() || local_port == 0 {
+            StableConnectionId::default()
+        } else {
+            let local = SocketAddr::new(node_address.ip(), local_port);
+            StableConnectionId::new(local, metadata.id.addr, metadata.better_time)
+        };
+        match self.cx.db.add_failed(
+            metadata.id.clone(),
+            stable_id,
+            alias,
+            metadata.time,
+            metadata.time_source,
+            metadata.lag,
+            errno,
+        ) {
+            Ok(id) => log::debug!("{metadata} {id} connect failed, errno: {errno}"),
+            Err(err) => log::error!("{metadata} failed connection, cannot write in db {err}"),
+        }
+    }
+
+    // `close_reason` is the errno which ended the connection, if any
+    pub fn on_disconnect(
+        &mut self,
+        metadata: EventMetadata,
+        buffered: usize,
+        close_reason: Option<i32>,
+    ) {
         if let Some(tester) = &mut self.tester {
             tester.on_disconnect(metadata);
             return;
@@ -297,6 +487,17 @@ impl P2pRecorder {
             incoming,
             buffered,
         };
+        if let Some(errno) = close_reason {
+            if let Some(t_cx) = self.cns.get(&id.metadata.id) {
+                t_cx.tx
+                    .send(ThreadEvent::CloseReason(errno))
+                    .unwrap_or_default();
+            } else if let Some(cn_cx) = self.cns_main_thread.get(&id.metadata.id) {
+                if let Err(err) = cn_cx.db.set_close_reason(errno) {
+                    log::error!("{id} cannot store close reason: {err}");
+                }
+            }
+        }
         if let Some(t_cx) = self.cns.remove(&id.metadata.id) {
             drop(t_cx.tx);
             match t_cx.handle.join() {
@@ -308,6 +509,23 @@ impl P2pRecorder {
         }
     }
 
+    pub fn on_shutdown(&mut self, metadata: EventMetadata, write: bool) {
+        if self.tester.is_some() {
+            return;
+        }
+        let time = metadata.time;
+        if let Some(t_cx) = self.cns.get(&metadata.id) {
+            // the thread may be updating the stored connection right now
+            t_cx.tx
+                .send(ThreadEvent::Shutdown { write, time })
+                .unwrap_or_default();
+        } else if let Some(cn_cx) = self.cns_main_thread.get(&metadata.id) {
+            if let Err(err) = cn_cx.db.shutdown(write, time) {
+                log::error!("{} cannot store shutdown: {err}", cn_cx.db.id());
+            }
+        }
+    }
+
     #[rustfmt::skip]
     pub fn on_data(
         &mut self,
@@ -321,12 +539,12 @@ impl P2pRecorder {
             return;
         }
         if let Some(t_cx) = self.cns.get_mut(&metadata.id) {
-            t_cx.tx.send(NetworkChunk {
+            t_cx.tx.send(ThreadEvent::Chunk(NetworkChunk {
                 metadata,
                 data: bytes,
                 incoming,
                 buffered,
-            }).unwrap_or_default();
+            })).unwrap_or_default();
         } else if let Some(cn_cx) = self.cns_main_thread.get_mut(&metadata.id) {
             let alias = {
                 let lock = self.cx.apps.lock();
@@ -361,3 +579,105 @@ impl P2pRecorder {
         }
     }
 }
+
+#[cfg(test)]
+#[test]
+fn fd_reused() {
+    use temp_dir::TempDir;
+
+    let d = TempDir::new().expect("cannot create temporary directory");
+    let db = DbFacade::open(d.path()).unwrap();
+    let mut recorder = P2pRecorder::new(db, false);
+
+    let mut first = EventMetadata::default();
+    first.id.generation = 1;
+    recorder.on_connect::<true>(false, first.clone(), 0, 0, String::new());
+    recorder.on_disconnect(first.clone(), 0, Some(104));
+
+    // the same pid and fd
+    let mut second = first.clone();
+    second.id.generation = 2;
+    recorder.on_connect::<true>(false, second.clone(), 0, 0, String::new());
+    // a late close of the first connection must not close the second
+    recorder.on_disconnect(first, 0, None);
+    assert!(recorder.cns_main_thread.contains_key(&second.id));
+
+    let core = recorder.cx.db.core();
+    let a = core.fetch_connection(0).unwrap();
+    let b = core.fetch_connection(1).unwrap();
+    assert_eq!((a.info.fd, b.info.fd), (second.id.fd, second.id.fd));
+    assert_eq!((a.generation, b.generation), (1, 2));
+    assert_eq!((a.close_reason, b.close_reason), (Some(104), None));
+    assert_eq!(a.post_process(None)["close_reason_text"], "connection reset by peer");
+}
+
+#[cfg(test)]
+#[test]
+fn shutdown_on_connection_thread() {
+    use temp_dir::TempDir;
+
+    let d = TempDir::new().expect("cannot create temporary directory");
+    let db = DbFacade::open(d.path()).unwrap();
+    let mut recorder = P2pRecorder::new(db, false);
+
+    let mut metadata = EventMetadata::default();
+    metadata.time = SystemTime::UNIX_EPOCH + Duration::from_secs(100);
+    recorder.on_connect::<false>(false, metadata.clone(), 0, 0, String::new());
+    // the shutdown goes to the thread after the chunks, it is stored in order with them
+    for _ in 0..16 {
+        recorder.on_data(true, metadata.clone(), 0, vec![0; 64]);
+    }
+    recorder.on_shutdown(metadata.clone(), true);
+    for _ in 0..16 {
+        recorder.on_data(true, metadata.clone(), 0, vec![0; 64]);
+    }
+    recorder.on_disconnect(metadata.clone(), 0, Some(104));
+
+    let cn = recorder.cx.db.core().fetch_connection(0).unwrap();
+    assert_eq!(cn.timestamp_shutdown_write, metadata.time);
+    assert_eq!(cn.close_reason, Some(104));
+}
+
+#[cfg(test)]
+#[test]
+#[rustfmt::skip]
+fn redecrypt_after_randomness() {
+    use temp_dir::TempDir;
+
+    let d = TempDir::new().expect("cannot create temporary directory");
+    let db = DbFacade::open(d.path()).unwrap();
+    let recorder = P2pRecorder::new(db, false);
+    let cx = &recorder.cx;
+
+    let metadata = EventMetadata::default();
+    let db = cx.db.add(metadata.id.clone(), false, StableConnectionId::default(), String::new(), metadata.time, TimeSource::Unknown, Duration::ZERO).unwrap();
+    let chunks = [
+        (true, "132f6d756c746973747265616d2f312e302e300a072f6e6f6973650a"),
+        (false, "132f6d756c746973747265616d2f312e302e300a072f6e6f6973650a"),
+        (true, "00209844288f8c8f0337dff411d66e0378d950fb7590f9f44d6df969fd59a18ab849"),
+        (false, "00c8c0e8867216784ce23e6ad97120c8bfa139941424d0aebcdfe14e339798af4a377f2a97c280a913fdf6a96b4b89c5471a7f4761bec49a557d734b65495eb87e1e00b707d561da835698fe08bab7962b0491751110e8a32a260605a64dbdc18f503958be161fe9546f3c0494c0714f6e57c3eca413cec2d20a483855b4958b96ee79e05f34fa63a74c758ebe9537f4e1c733a7a7ebcd9b1bcc47c2c882ffa361f6ebb404225b60a6bae8e7a6d479d6e1b5c5c1d858ca13dde8cbd285f5bb4d9805578553e3881d5a0d"),
+        (true, "00a8e3cfaddd47cf48db1b70b83c15dbdb32bdba21cca65f9f80fb2e7f93d7a82b1b71d6241952e1205d510afad46f8d6d23de1be013618cd79d4e87eec4761292393532e7952bddaeb6709dcb266f861f92ef0eabe282d318f813d11426ac6916240bfead8994c63f10b03f6e241c2b92495a1f63d728fb63ba78e468945f7da081761102465308523dbf50064be4251468abb99db7af8afd71b99100a2fb7a37773a8062d33cc2e1d9"),
+        (true, "00375cd2640426acf52810f89147cf5446f8b4bff334c9727c0a45abd220746b2e8b10d269ff28be87c8bb1d53e43e69922ff4b19760ef875d"),
+    ];
+
+    // the randomness is not captured yet, only the plaintext first message is decrypted
+    let mut state = Noise::from(StreamId::Handshake);
+    for (incoming, chunk) in chunks {
+        let mut bytes = hex::decode(chunk).expect("valid constant");
+        db.add_raw(EncryptionStatus::DecryptedPnet, incoming, metadata.time, &bytes).unwrap();
+        let id = DirectedId { metadata: metadata.clone(), alias: String::new(), incoming, buffered: 0 };
+        state.on_data(id, &mut bytes, cx, &db).unwrap();
+    }
+    let cn = cx.db.core().fetch_connection(db.id().0).unwrap();
+    assert_eq!(cn.stats_in.decrypted_chunks + cn.stats_out.decrypted_chunks, 1);
+    assert!(cn.noise_protocol.is_empty());
+
+    cx.db.add_randomness(hex::decode("d1f3bca173136dd555dd97262336ce644a76ec31d521d2befe87caec8678c1a7").expect("valid constant").try_into().expect("valid constant")).unwrap();
+    cx.db.add_randomness(hex::decode("1c283e25c80f64f2806d9e19da1a393873d40bdf3d903a3776e013c4fdd97cb3").expect("valid constant").try_into().expect("valid constant")).unwrap();
+    let report = cx.redecrypt(db.id().0).unwrap();
+    assert_eq!((report.chunks, report.decrypted_before, report.newly_decrypted), (6, 1, 3));
+
+    // the handshake is verified now, decrypting once again would duplicate the messages
+    let report = cx.redecrypt(db.id().0).unwrap();
+    assert_eq!(report.newly_decrypted, 0);
+}
@@ -1,4 +1,10 @@
-use std::{thread, path::Path};
+use std::{
+    thread,
+    path::Path,
+    net::SocketAddr,
+    time::{SystemTime, Duration},
+    sync::Arc,
+};
 
 use warp::{
     Filter, Rejection, Reply,
@@ -6,9 +12,18 @@ use warp::{
     http::StatusCode,
 };
 
-use crate::{meshsub_stats::BlockStat, application::Application};
+use libp2p_core::PeerId;
+
+use crate::{
+    meshsub_stats::{BlockStat, Hash},
+    application::Application,
+    feed::RawFeed,
+    recorder::CxHandle,
+};
 
-use super::database::{DbCore, DbFacade, Params};
+use super::database::{
+    DbCore, DbError, DbFacade, Params, StableConnectionId, StreamFullId, ConnectionId, StreamKind,
+};
 
 fn connection(
     db: DbCore,
@@ -27,12 +42,307 @@ fn connection(
     })
 }
 
+#[derive(serde::Deserialize)]
+pub struct ByTupleParams {
+    // either the precomputed id ...
+    stable_id: Option<String>,
+    // ... or the parts to compute it
+    initiator: Option<SocketAddr>,
+    responder: Option<SocketAddr>,
+    // connect time, unix seconds
+    timestamp: Option<u64>,
+}
+
+impl ByTupleParams {
+    // the parts are looked up in the adjacent time bucket too, the peer might round differently
+    fn stable_ids(&self) -> Result<Vec<StableConnectionId>, String> {
+        match (
+            &self.stable_id,
+            self.initiator,
+            self.responder,
+            self.timestamp,
+        ) {
+            (Some(s), None, None, None) => {
+                s.parse().map(|id| vec![id]).map_err(|err| format!("{err}"))
+            }
+            (None, Some(initiator), Some(responder), Some(timestamp)) => {
+                let time = SystemTime::UNIX_EPOCH + Duration::from_secs(timestamp);
+                Ok(StableConnectionId::nearest_buckets(initiator, responder, time).to_vec())
+            }
+            _ => Err(
+                "specify either `stable_id` or `initiator`, `responder` and `timestamp`".to_owned(),
+            ),
+        }
+    }
+}
+
+fn connection_by_tuple(
+    db: DbCore,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("connection" / "by-tuple")
+        .and(warp::query::query())
+        .map(move |params: ByTupleParams| -> WithStatus<Json> {
+            match params.stable_ids() {
+                Ok(stable_ids) => {
+                    let v = stable_ids
+                        .into_iter()
+                        .flat_map(|stable_id| db.fetch_connections_by_stable_id(stable_id))
+                        .collect::<Vec<_>>();
+                    reply::with_status(reply::json(&v), StatusCode::OK)
+                }
+                Err(err) => reply::with_status(reply::json(&err), StatusCode::BAD_REQUEST),
+            }
+        })
+}
+
+fn connection_ping(
+    db: DbCore,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("connection" / u64 / "ping").map(move |id: u64| -> WithStatus<Json> {
+        let v = db.fetch_connection_ping_rtt(id).collect::<Vec<_>>();
+        reply::with_status(reply::json(&v), StatusCode::OK)
+    })
+}
+
+fn connection_yamux(
+    db: DbCore,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("connection" / u64 / "yamux").map(move |id: u64| -> WithStatus<Json> {
+        let v = db.fetch_window_stats(id).collect::<Vec<_>>();
+        reply::with_status(reply::json(&v), StatusCode::OK)
+    })
+}
+
+#[derive(serde::Deserialize)]
+pub struct BandwidthParams {
+    // seconds
+    bucket: Option<u64>,
+}
+
+fn connection_bandwidth(
+    db: DbCore,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("connection" / u64 / "bandwidth")
+        .and(warp::query::query())
+        .map(
+            move |id: u64, params: BandwidthParams| -> WithStatus<Json> {
+                let v = db.fetch_connection_bandwidth(id, params.bucket.unwrap_or(10));
+                reply::with_status(reply::json(&v), StatusCode::OK)
+            },
+        )
+}
+
+fn connection_bitswap(
+    db: DbCore,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("connection" / u64 / "bitswap").map(move |id: u64| -> WithStatus<Json> {
+        match db.fetch_bitswap_session(id) {
+            Ok(v) => {
+                let v = v.map(|v| v.post_process());
+                reply::with_status(reply::json(&v), StatusCode::OK)
+            }
+            Err(err) => reply::with_status(
+                reply::json(&err.to_string()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+        }
+    })
+}
+
+fn bitswap_block(
+    db: DbCore,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("bitswap" / String).map(move |cid: String| -> WithStatus<Json> {
+        let Some(digest) = crate::decode::bitswap::cid_digest(&cid) else {
+            return reply::with_status(reply::json(&"bad cid"), StatusCode::BAD_REQUEST);
+        };
+        match db.fetch_bitswap_block(digest) {
+            Ok(v) => reply::with_status(reply::json(&v), StatusCode::OK),
+            Err(err) => reply::with_status(
+                reply::json(&err.to_string()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+        }
+    })
+}
+
+fn connection_redecrypt(
+    cx: CxHandle,
+    timeout: Duration,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("connection" / u64 / "redecrypt")
+        .and(warp::post())
+        .and_then(move |id: u64| {
+            let cx = cx.get();
+            with_timeout(timeout, move || match cx {
+                None => reply::with_status(
+                    reply::json(&"recorder is not running"),
+                    StatusCode::SERVICE_UNAVAILABLE,
+                ),
+                Some(cx) => match cx.redecrypt(id) {
+                    Ok(v) => reply::with_status(reply::json(&v), StatusCode::OK),
+                    Err(err) => reply::with_status(
+                        reply::json(&err.to_string()),
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                    ),
+                },
+            })
+        })
+}
+
+// admin endpoints are disabled without the token, the reply is the refusal
+fn admin_denied(token: Option<&str>, authorization: Option<&str>) -> Option<WithStatus<Json>> {
+    let Some(token) = token else {
+        return Some(reply::with_status(
+            reply::json(&"set `ADMIN_TOKEN` to enable"),
+            StatusCode::NOT_FOUND,
+        ));
+    };
+    let provided = authorization.and_then(|s| s.strip_prefix("Bearer "));
+    if provided != Some(token) {
+        return Some(reply::with_status(
+            reply::json(&"wrong token"),
+            StatusCode::UNAUTHORIZED,
+        ));
+    }
+    None
+}
+
+fn admin_reindex(
+    db: DbCore,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
+    let token = std::env::var("ADMIN_TOKEN").ok().filter(|s| !s.is_empty());
+    warp::path!("admin" / "reindex")
+        .and(warp::post())
+        .and(warp::header::optional::<String>("authorization"))
+        .and_then(move |authorization: Option<String>| {
+            let db = db.clone();
+            let token = token.clone();
+            async move {
+                if let Some(denied) = admin_denied(token.as_deref(), authorization.as_deref()) {
+                    return Ok::<_, Rejection>(denied);
+                }
+                // may take long, no timeout
+                let v = match tokio::task::spawn_blocking(move || db.reindex()).await {
+                    Ok(Ok(v)) => reply::with_status(reply::json(&v), StatusCode::OK),
+                    Ok(Err(err)) => reply::with_status(
+                        reply::json(&err.to_string()),
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                    ),
+                    Err(err) => reply::with_status(
+                        reply::json(&err.to_string()),
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                    ),
+                };
+                Ok(v)
+            }
+        })
+}
+
+fn admin_checkpoint(
+    db: DbCore,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
+    let token = std::env::var("ADMIN_TOKEN").ok().filter(|s| !s.is_empty());
+    warp::path!("admin" / "checkpoint")
+        .and(warp::post())
+        .and(warp::header::optional::<String>("authorization"))
+        .and_then(move |authorization: Option<String>| {
+            let db = db.clone();
+            let token = token.clone();
+            async move {
+                if let Some(denied) = admin_denied(token.as_deref(), authorization.as_deref()) {
+                    return Ok::<_, Rejection>(denied);
+                }
+                // flushes the memtables, may take a while
+                let v = match tokio::task::spawn_blocking(move || db.checkpoint()).await {
+                    Ok(Ok(v)) => reply::with_status(reply::json(&v), StatusCode::OK),
+                    Ok(Err(err)) => reply::with_status(
+                        reply::json(&err.to_string()),
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                    ),
+                    Err(err) => reply::with_status(
+                        reply::json(&err.to_string()),
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                    ),
+                };
+                Ok(v)
+            }
+        })
+}
+
+fn connection_desync(
+    db: DbCore,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("connection" / u64 / "desync").map(move |id: u64| -> WithStatus<Json> {
+        match db.fetch_desync(id) {
+            Ok(v) => {
+                let v = v.map(|v| v.post_process());
+                reply::with_status(reply::json(&v), StatusCode::OK)
+            }
+            Err(err) => reply::with_status(
+                reply::json(&err.to_string()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+        }
+    })
+}
+
+fn connections_count(
+    db: DbCore,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("connections" / "count").map(move || -> WithStatus<Json> {
+        let v = db.fetch_connection_count();
+        reply::with_status(reply::json(&v), StatusCode::OK)
+    })
+}
+
+/// Runs the handler on the blocking pool, so a slow query cannot hold the async worker,
+/// and replies `504` if it does not finish in time.
+async fn with_timeout<F>(timeout: Duration, f: F) -> Result<WithStatus<Json>, Rejection>
+where
+    F: FnOnce() -> WithStatus<Json> + Send + 'static,
+{
+    match tokio::time::timeout(timeout, tokio::task::spawn_blocking(f)).await {
+        Ok(Ok(v)) => Ok(v),
+        Ok(Err(err)) => Ok(reply::with_status(
+            reply::json(&err.to_string()),
+            StatusCode::INTERNAL_SERVER_ERROR,
+        )),
+        Err(_) => Ok(reply::with_status(
+            reply::json(&format!("request timeout {timeout:?}")),
+            StatusCode::GATEWAY_TIMEOUT,
+        )),
+    }
+}
+
+/// Same as `with_timeout`, for the replies which are not json, the error is plain text.
+async fn with_timeout_raw<F, T>(timeout: Duration, f: F) -> Result<WithStatus<T>, Rejection>
+where
+    F: FnOnce() -> WithStatus<T> + Send + 'static,
+    T: Reply + From<String> + Send + 'static,
+{
+    match tokio::time::timeout(timeout, tokio::task::spawn_blocking(f)).await {
+        Ok(Ok(v)) => Ok(v),
+        Ok(Err(err)) => Ok(reply::with_status(
+            T::from(err.to_string()),
+            StatusCode::INTERNAL_SERVER_ERROR,
+        )),
+        Err(_) => Ok(reply::with_status(
+            T::from(format!("request timeout {timeout:?}")),
+            StatusCode::GATEWAY_TIMEOUT,
+        )),
+    }
+}
+
 fn connections(
     db: DbCore,
+    timeout: Duration,
 ) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
-    warp::path!("connections").and(warp::query::query()).map(
-        move |params: Params| -> WithStatus<Json> {
-            match params.validate_connection() {
+    warp::path!("connections")
+        .and(warp::query::query())
+        .and_then(move |params: Params| {
+            let db = db.clone();
+            with_timeout(timeout, move || match params.validate_connection() {
                 Ok(valid) => {
                     let v = db.fetch_connections(&valid);
                     reply::with_status(reply::json(&v.collect::<Vec<_>>()), StatusCode::OK)
@@ -41,17 +351,41 @@ fn connections(
                     reply::json(&err.to_string()),
                     StatusCode::INTERNAL_SERVER_ERROR,
                 ),
-            }
-        },
-    )
+            })
+        })
+}
+
+#[derive(serde::Deserialize)]
+pub struct ProblematicStreamsParams {
+    // report streams having more than this number of decode failures
+    threshold: Option<u64>,
+    limit: Option<usize>,
+}
+
+fn streams_problematic(
+    db: DbCore,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("streams" / "problematic")
+        .and(warp::query::query())
+        .map(
+            move |params: ProblematicStreamsParams| -> WithStatus<Json> {
+                let threshold = params.threshold.unwrap_or(0);
+                let limit = params.limit.unwrap_or(100);
+                let v = db.fetch_problematic_streams(threshold, limit);
+                reply::with_status(reply::json(&v), StatusCode::OK)
+            },
+        )
 }
 
 fn messages(
     db: DbCore,
+    timeout: Duration,
 ) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
-    warp::path!("messages").and(warp::query::query()).map(
-        move |params: Params| -> WithStatus<Json> {
-            match params.validate() {
+    warp::path!("messages")
+        .and(warp::query::query())
+        .and_then(move |params: Params| {
+            let db = db.clone();
+            with_timeout(timeout, move || match params.validate() {
                 Ok(valid) => {
                     let v = db.fetch_messages(&valid);
                     reply::with_status(reply::json(&v.collect::<Vec<_>>()), StatusCode::OK)
@@ -60,9 +394,55 @@ fn messages(
                     reply::json(&err.to_string()),
                     StatusCode::INTERNAL_SERVER_ERROR,
                 ),
-            }
-        },
-    )
+            })
+        })
+}
+
+/// The same items as `/messages`, one json per line, written as they are read,
+/// so the size of the result is not limited by memory.
+fn messages_stream(
+    db: DbCore,
+) -> impl Filter<Extract = (reply::Response,), Error = Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("messages" / "stream")
+        .and(warp::query::query())
+        .map(move |params: Params| -> reply::Response {
+            let valid = match params.validate() {
+                Ok(v) => v,
+                Err(err) => {
+                    let reply = reply::json(&err.to_string());
+                    return reply::with_status(reply, StatusCode::BAD_REQUEST).into_response();
+                }
+            };
+            let db = db.clone();
+            let (tx, rx) = tokio::sync::mpsc::channel::<Vec<u8>>(0x40);
+            // the iterator borrows the database, so it runs in the blocking thread
+            tokio::task::spawn_blocking(move || {
+                for item in db.fetch_messages(&valid) {
+                    let mut line = match serde_json::to_vec(&item) {
+                        Ok(v) => v,
+                        Err(err) => {
+                            log::error!("message {}: {err}", item.0);
+                            continue;
+                        }
+                    };
+                    line.push(b'\n');
+                    // the client is gone
+                    if tx.blocking_send(line).is_err() {
+                        break;
+                    }
+                }
+            });
+            let lines = futures_util::stream::unfold(rx, |mut rx| async move {
+                let line = rx.recv().await?;
+                Some((Ok::<_, std::convert::Infallible>(line), rx))
+            });
+            let mut response = reply::Response::new(warp::hyper::Body::wrap_stream(lines));
+            response.headers_mut().insert(
+                "Content-Type",
+                warp::http::HeaderValue::from_static("application/x-ndjson"),
+            );
+            response
+        })
 }
 
 fn message(
@@ -79,33 +459,105 @@ fn message(
     })
 }
 
+#[derive(serde::Deserialize)]
+pub struct FramesParams {
+    limit: Option<usize>,
+}
+
+fn stream_frames(
+    db: DbCore,
+    timeout: Duration,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("stream" / u64 / String / "frames")
+        .and(warp::query::query())
+        .and_then(move |cn: u64, stream_id: String, params: FramesParams| {
+            let db = db.clone();
+            with_timeout(timeout, move || match stream_id.parse() {
+                Ok(id) => {
+                    let stream_full_id = StreamFullId {
+                        cn: ConnectionId(cn),
+                        id,
+                    };
+                    let limit = params.limit.unwrap_or(1000);
+                    let v = db.fetch_stream_frames(stream_full_id, limit);
+                    reply::with_status(reply::json(&v), StatusCode::OK)
+                }
+                Err(err) => reply::with_status(
+                    reply::json(&format!("bad stream id: {err}")),
+                    StatusCode::BAD_REQUEST,
+                ),
+            })
+        })
+}
+
+// the message is absent, or was pruned, or its bytes were sampled out
+fn blob_error_status(err: &DbError) -> StatusCode {
+    match err {
+        DbError::NoItemAtCursor(_) | DbError::SampledOut => StatusCode::NOT_FOUND,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
 fn message_hex(
     db: DbCore,
 ) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
-    warp::path!("message_hex" / u64).map(move |id: u64| -> reply::WithStatus<Json> {
-        match db.fetch_full_message_hex(id) {
-            Ok(v) => reply::with_status(reply::json(&v), StatusCode::OK),
-            Err(err) => reply::with_status(
-                reply::json(&err.to_string()),
-                StatusCode::INTERNAL_SERVER_ERROR,
-            ),
-        }
-    })
+    warp::path!("message_hex" / u64)
+        .or(warp::path!("message" / u64 / "hex"))
+        .unify()
+        .map(move |id: u64| -> reply::WithStatus<Json> {
+            match db.fetch_full_message_hex(id) {
+                Ok(v) => reply::with_status(reply::json(&v), StatusCode::OK),
+                Err(err) => {
+                    reply::with_status(reply::json(&err.to_string()), blob_error_status(&err))
+                }
+            }
+        })
 }
 
+// the bytes as they were written to the stream, without the chunk header
 fn message_bin(
     db: DbCore,
 ) -> impl Filter<Extract = (WithStatus<Vec<u8>>,), Error = Rejection> + Clone + Sync + Send + 'static
 {
-    warp::path!("message_bin" / u64).map(move |id: u64| -> reply::WithStatus<Vec<u8>> {
-        match db.fetch_full_message_bin(id) {
-            Ok(v) => reply::with_status(v, StatusCode::OK),
-            Err(err) => reply::with_status(
-                err.to_string().as_bytes().to_vec(),
-                StatusCode::INTERNAL_SERVER_ERROR,
-            ),
-        }
-    })
+    warp::path!("message_bin" / u64)
+        .or(warp::path!("message" / u64 / "raw"))
+        .unify()
+        .map(move |id: u64| -> reply::WithStatus<Vec<u8>> {
+            match db.fetch_full_message_bin(id) {
+                Ok(v) => reply::with_status(v, StatusCode::OK),
+                Err(err) => {
+                    reply::with_status(err.to_string().as_bytes().to_vec(), blob_error_status(&err))
+                }
+            }
+        })
+}
+
+#[derive(serde::Deserialize)]
+pub struct PcapParams {
+    // noise decrypted bytes instead of raw
+    #[serde(default)]
+    decrypted: bool,
+}
+
+fn connection_pcap(
+    db: DbCore,
+    timeout: Duration,
+) -> impl Filter<Extract = (WithStatus<Vec<u8>>,), Error = Rejection> + Clone + Sync + Send + 'static
+{
+    warp::path!("connection" / u64 / "pcap")
+        .and(warp::query::query())
+        .and_then(move |id: u64, params: PcapParams| {
+            let db = db.clone();
+            with_timeout_raw(timeout, move || {
+                match db.export_pcap(ConnectionId(id), params.decrypted) {
+                    Ok(v) => reply::with_status(v, StatusCode::OK),
+                    Err(err) => {
+                        let status = blob_error_status(&err);
+                        reply::with_status(err.to_string().into_bytes(), status)
+                    }
+                }
+            })
+        })
 }
 
 fn stats(
@@ -117,12 +569,60 @@ fn stats(
     })
 }
 
+#[derive(serde::Deserialize)]
+pub struct BlockParams {
+    // peer id of the block producer
+    producer: Option<String>,
+}
+
 fn stats_block_v2(
     db: DbCore,
 ) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
-    warp::path!("block" / u32).map(move |height| -> WithStatus<Json> {
-        let events = db.fetch_stats_block_v2(height);
-        let v = BlockStat { height, events };
+    warp::path!("block" / u32).and(warp::query::query()).map(
+        move |height, params: BlockParams| -> WithStatus<Json> {
+            let producer = match params.producer.map(|s| s.parse::<PeerId>()).transpose() {
+                Ok(v) => v,
+                Err(err) => {
+                    return reply::with_status(
+                        reply::json(&err.to_string()),
+                        StatusCode::BAD_REQUEST,
+                    )
+                }
+            };
+            let mut events = db.fetch_stats_block_v2(height);
+            if let Some(producer) = producer {
+                events.retain(|event| event.producer_id == producer);
+            }
+            let v = BlockStat { height, events };
+            reply::with_status(reply::json(&v), StatusCode::OK)
+        },
+    )
+}
+
+#[derive(serde::Deserialize)]
+pub struct ProducersParams {
+    height: Option<u32>,
+}
+
+fn stats_producers(
+    db: DbCore,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("stats" / "producers")
+        .and(warp::query::query())
+        .map(move |params: ProducersParams| -> WithStatus<Json> {
+            let height = params
+                .height
+                .or_else(|| db.fetch_last_stat_block_v2().map(|(height, _)| height));
+            let v = height.map(|height| db.fetch_block_producers(height));
+            reply::with_status(reply::json(&v), StatusCode::OK)
+        })
+}
+
+fn block_propagation(
+    db: DbCore,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("block" / u32 / "propagation").map(move |height| -> WithStatus<Json> {
+        let v = db.fetch_block_propagation(height);
         reply::with_status(reply::json(&v), StatusCode::OK)
     })
 }
@@ -174,6 +674,37 @@ fn stats_tx(
     })
 }
 
+#[derive(serde::Deserialize)]
+pub struct TxLatencyParams {
+    // the latest height by default
+    from: Option<u32>,
+    to: Option<u32>,
+}
+
+fn stats_tx_latency(
+    db: DbCore,
+    timeout: Duration,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("stats" / "tx" / "latency")
+        .and(warp::query::query())
+        .and_then(move |params: TxLatencyParams| {
+            let db = db.clone();
+            with_timeout(timeout, move || {
+                let latest = || db.fetch_last_stat_tx().map_or(0, |(height, _)| height);
+                let to = params.to.unwrap_or_else(latest);
+                let from = params.from.unwrap_or(to);
+                if from > to {
+                    return reply::with_status(
+                        reply::json(&"`from` is greater than `to`"),
+                        StatusCode::BAD_REQUEST,
+                    );
+                }
+                let v = db.fetch_tx_latency_summary(from, to);
+                reply::with_status(reply::json(&v), StatusCode::OK)
+            })
+        })
+}
+
 fn stats_tx_latest(
     db: DbCore,
 ) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
@@ -183,6 +714,124 @@ fn stats_tx_latest(
     })
 }
 
+fn stats_subnets(
+    db: DbCore,
+    timeout: Duration,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("stats" / "subnets").and_then(move || {
+        let db = db.clone();
+        with_timeout(timeout, move || {
+            let v = db.fetch_subnet_stats();
+            reply::with_status(reply::json(&v), StatusCode::OK)
+        })
+    })
+}
+
+fn stats_decrypt(
+    db: DbCore,
+    cx: CxHandle,
+    timeout: Duration,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
+    use std::sync::atomic::Ordering;
+
+    warp::path!("stats" / "decrypt")
+        .or(warp::path!("stats" / "decryption"))
+        .unify()
+        .and_then(move || {
+            let db = db.clone();
+            let cx = cx.get();
+            with_timeout(timeout, move || {
+                let mut v = db.fetch_decrypt_stats();
+                if let Some(cx) = cx {
+                    v.decrypted_bytes = cx.stats.decrypted.load(Ordering::Relaxed) as u64;
+                    v.failed_to_decrypt_bytes =
+                        cx.stats.failed_to_decrypt.load(Ordering::Relaxed) as u64;
+                }
+                reply::with_status(reply::json(&v), StatusCode::OK)
+            })
+        })
+}
+
+fn stats_prunes(
+    db: DbCore,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("stats" / "prunes").map(move || -> WithStatus<Json> {
+        let v = db.fetch_prunes();
+        reply::with_status(reply::json(&v), StatusCode::OK)
+    })
+}
+
+fn peers_reputation(
+    db: DbCore,
+    timeout: Duration,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("peers" / "reputation").and_then(move || {
+        let db = db.clone();
+        with_timeout(timeout, move || {
+            let v = db.fetch_peer_reputation();
+            reply::with_status(reply::json(&v), StatusCode::OK)
+        })
+    })
+}
+
+#[derive(serde::Deserialize)]
+pub struct StreamOrderParams {
+    tolerance_ms: Option<u64>,
+}
+
+fn check_stream_order(
+    db: DbCore,
+    timeout: Duration,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("check" / "stream_order")
+        .and(warp::query::query())
+        .and_then(move |params: StreamOrderParams| {
+            let db = db.clone();
+            // same as the grace window for early data in the recorder
+            let tolerance = Duration::from_millis(params.tolerance_ms.unwrap_or(500));
+            with_timeout(timeout, move || {
+                let v = db.check_stream_order(tolerance);
+                reply::with_status(reply::json(&v), StatusCode::OK)
+            })
+        })
+}
+
+fn gating(
+    db: DbCore,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("gating").map(move || -> WithStatus<Json> {
+        let v = db.fetch_gating_configs();
+        reply::with_status(reply::json(&v), StatusCode::OK)
+    })
+}
+
+fn stats_node_status(
+    db: DbCore,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("stats" / "node_status").map(move || -> WithStatus<Json> {
+        let v = db.fetch_node_statuses();
+        reply::with_status(reply::json(&v), StatusCode::OK)
+    })
+}
+
+#[derive(serde::Deserialize)]
+pub struct NodeMetricsParams {
+    name: String,
+    limit: Option<usize>,
+}
+
+fn node_metrics(
+    db: DbCore,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("node_metrics").and(warp::query::query()).map(
+        move |params: NodeMetricsParams| -> WithStatus<Json> {
+            let limit = params.limit.unwrap_or(100);
+            let v = db.fetch_node_metrics(&params.name, limit);
+            reply::with_status(reply::json(&v), StatusCode::OK)
+        },
+    )
+}
+
 fn snark(
     db: DbCore,
 ) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
@@ -220,6 +869,43 @@ fn capnp(
         })
 }
 
+#[derive(serde::Deserialize)]
+pub struct CorrelateParams {
+    // the latest block if not specified
+    height: Option<u32>,
+}
+
+fn correlate_gossip(
+    db: DbCore,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("correlate" / "gossip")
+        .and(warp::query::query())
+        .map(move |params: CorrelateParams| -> WithStatus<Json> {
+            let height = params
+                .height
+                .or_else(|| db.fetch_last_stat_block_v2().map(|(height, _)| height));
+            let v = height.map(|height| db.fetch_gossip_correlation(height));
+            reply::with_status(reply::json(&v), StatusCode::OK)
+        })
+}
+
+fn gossip_path(
+    db: DbCore,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("gossip" / String / "path").map(move |hash: String| -> WithStatus<Json> {
+        match hash.parse::<Hash>() {
+            Ok(hash) => {
+                let v = db.fetch_gossip_path(hash);
+                reply::with_status(reply::json(&v), StatusCode::OK)
+            }
+            Err(err) => reply::with_status(
+                reply::json(&format!("bad hash: {err}")),
+                StatusCode::BAD_REQUEST,
+            ),
+        }
+    })
+}
+
 fn libp2p_ipc(
     db: DbCore,
 ) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
@@ -233,10 +919,14 @@ fn libp2p_ipc(
 
 fn libp2p_ipc_all(
     db: DbCore,
+    timeout: Duration,
 ) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
-    warp::path!("libp2p_ipc" / "block" / "all").map(move || -> WithStatus<Json> {
-        let v = db.fetch_capnp_all().collect::<Vec<_>>();
-        reply::with_status(reply::json(&v), StatusCode::OK)
+    warp::path!("libp2p_ipc" / "block" / "all").and_then(move || {
+        let db = db.clone();
+        with_timeout(timeout, move || {
+            let v = db.fetch_capnp_all().collect::<Vec<_>>();
+            reply::with_status(reply::json(&v), StatusCode::OK)
+        })
     })
 }
 
@@ -264,6 +954,23 @@ fn libp2p_ipc_latest(
         })
 }
 
+fn ipc_peers(
+    cx: CxHandle,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("ipc" / "peers").map(move || -> WithStatus<Json> {
+        match cx.get() {
+            None => reply::with_status(
+                reply::json(&"recorder is not running"),
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+            Some(cx) => {
+                let v = cx.ipc_peers.lock().values().cloned().collect::<Vec<_>>();
+                reply::with_status(reply::json(&v), StatusCode::OK)
+            }
+        }
+    })
+}
+
 fn firewall_whitelist_set(
     app: Option<Application>,
 ) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
@@ -272,8 +979,12 @@ fn firewall_whitelist_set(
         .and(warp::post())
         .map(move |enable_whitelist| -> WithStatus<Json> {
             if let Some(app) = &app {
-                app.enable_firewall(enable_whitelist);
-                reply::with_status(reply::json(&()), StatusCode::OK)
+                match app.enable_firewall(enable_whitelist) {
+                    Ok(()) => reply::with_status(reply::json(&()), StatusCode::OK),
+                    Err(err) => {
+                        reply::with_status(reply::json(&err.to_string()), StatusCode::BAD_REQUEST)
+                    }
+                }
             } else {
                 reply::with_status(reply::json(&()), StatusCode::NOT_FOUND)
             }
@@ -295,12 +1006,48 @@ fn firewall_whitelist_clear(
         })
 }
 
-fn firewall_stats(
+#[derive(serde::Deserialize)]
+pub struct BlockedParams {
+    // start counting from zero after the read
+    #[serde(default)]
+    reset: bool,
+}
+
+fn firewall_blocked(
+    app: Option<Application>,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("firewall" / "blocked")
+        .or(warp::path!("firewall" / "stats"))
+        .unify()
+        .and(warp::query::query())
+        .map(move |params: BlockedParams| -> WithStatus<Json> {
+            if let Some(app) = &app {
+                let list = app.get_firewall_stats(params.reset);
+                reply::with_status(reply::json(&list), StatusCode::OK)
+            } else {
+                reply::with_status(reply::json(&()), StatusCode::NOT_FOUND)
+            }
+        })
+}
+
+fn firewall_whitelist(
+    app: Option<Application>,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("firewall" / "whitelist").map(move || -> WithStatus<Json> {
+        if let Some(app) = &app {
+            reply::with_status(reply::json(&app.whitelist_status()), StatusCode::OK)
+        } else {
+            reply::with_status(reply::json(&()), StatusCode::NOT_FOUND)
+        }
+    })
+}
+
+fn pid_bytes(
     app: Option<Application>,
 ) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
-    warp::path!("firewall" / "stats").map(move || -> WithStatus<Json> {
+    warp::path!("pid_bytes").map(move || -> WithStatus<Json> {
         if let Some(app) = &app {
-            let list = app.get_firewall_stats();
+            let list = app.get_pid_bytes();
             reply::with_status(reply::json(&list), StatusCode::OK)
         } else {
             reply::with_status(reply::json(&()), StatusCode::NOT_FOUND)
@@ -317,6 +1064,182 @@ fn version(
         })
 }
 
+fn metrics(
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("metrics").map(move || -> WithStatus<Json> {
+        reply::with_status(reply::json(&crate::metrics::snapshot()), StatusCode::OK)
+    })
+}
+
+/// Prometheus asks for the text format, other clients get the json at the same path.
+fn accepts_text() -> impl Filter<Extract = (), Error = Rejection> + Clone + Sync + Send + 'static {
+    warp::header::optional::<String>("accept")
+        .and_then(|accept: Option<String>| async move {
+            let accept = accept.unwrap_or_default();
+            if accept.contains("text/plain") || accept.contains("application/openmetrics-text") {
+                Ok(())
+            } else {
+                Err(warp::reject::not_found())
+            }
+        })
+        .untuple_one()
+}
+
+/// The same text at `/metrics/openmetrics` and at `/metrics` if the client asks for text.
+fn metrics_openmetrics(
+    db: DbCore,
+    app: Option<Application>,
+    cx: CxHandle,
+    timeout: Duration,
+) -> impl Filter<Extract = (WithStatus<String>,), Error = Rejection> + Clone + Sync + Send + 'static
+{
+    use std::{sync::atomic::Ordering, time::Instant};
+    use crate::metrics::{Collected, CollectedCache};
+
+    let cache = Arc::new(CollectedCache::default());
+    warp::path!("metrics" / "openmetrics")
+        .or(warp::path!("metrics").and(accepts_text()))
+        .unify()
+        .and_then(move || {
+            let (db, app, cx, cache) = (db.clone(), app.clone(), cx.clone(), cache.clone());
+            with_timeout_raw(timeout, move || {
+                let collected = cache.get(Instant::now(), || {
+                    let (blocked_packets, blocked_bytes) = app
+                        .as_ref()
+                        .map(|app| app.get_firewall_stats(false))
+                        .unwrap_or_default()
+                        .into_iter()
+                        .fold((0, 0), |(p, b), entry| {
+                            (
+                                p + entry.blocked.packets as u64,
+                                b + entry.blocked.bytes as u64,
+                            )
+                        });
+                    Collected {
+                        connections: db.fetch_connection_count().total,
+                        messages: db.message_count().unwrap_or(0),
+                        messages_by_kind: db.fetch_messages_by_kind(),
+                        blocked_packets,
+                        blocked_bytes,
+                    }
+                });
+                let decrypt = cx.get().map(|cx| {
+                    (
+                        cx.stats.decrypted.load(Ordering::Relaxed),
+                        cx.stats.failed_to_decrypt.load(Ordering::Relaxed),
+                    )
+                });
+                let s = crate::metrics::openmetrics(&collected, decrypt);
+                reply::with_status(s, StatusCode::OK)
+            })
+        })
+}
+
+fn ws_raw(
+    feed: RawFeed,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("ws" / "raw")
+        .and(warp::ws())
+        .map(move |ws: warp::ws::Ws| {
+            let mut rx = feed.subscribe();
+            ws.on_upgrade(move |mut socket| async move {
+                use futures_util::SinkExt;
+                use tokio::sync::broadcast::error::RecvError;
+
+                loop {
+                    let bytes = match rx.recv().await {
+                        Ok(v) => v,
+                        Err(RecvError::Lagged(n)) => {
+                            log::warn!("raw feed subscriber is too slow, skipped {n} events");
+                            continue;
+                        }
+                        Err(RecvError::Closed) => break,
+                    };
+                    let msg = warp::ws::Message::binary(bytes.to_vec());
+                    if socket.send(msg).await.is_err() {
+                        break;
+                    }
+                }
+            })
+        })
+}
+
+#[derive(serde::Deserialize)]
+pub struct LiveParams {
+    // comma separated, every kind if not set
+    stream_kind: Option<String>,
+}
+
+/// Pushes the new messages as they are recorded, the same items as `/messages`.
+fn messages_live(
+    db: DbCore,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("messages" / "live")
+        .and(warp::query::query())
+        .and(warp::ws())
+        .map(move |params: LiveParams, ws: warp::ws::Ws| {
+            let kinds = params
+                .stream_kind
+                .map(|s| {
+                    s.split(',')
+                        .map(|s| s.parse::<StreamKind>().expect("cannot fail"))
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default();
+            let mut rx = db.subscribe_messages();
+            let db = db.clone();
+            ws.on_upgrade(move |mut socket| async move {
+                use futures_util::SinkExt;
+                use tokio::sync::broadcast::error::RecvError;
+
+                loop {
+                    let (id, stream_kind) = match rx.recv().await {
+                        Ok(v) => v,
+                        Err(RecvError::Lagged(n)) => {
+                            // the recorder doesn't wait, drop the client instead
+                            log::warn!("live messages subscriber is too slow, lost {n}, dropped");
+                            break;
+                        }
+                        Err(RecvError::Closed) => break,
+                    };
+                    if !kinds.is_empty() && !kinds.contains(&stream_kind) {
+                        continue;
+                    }
+                    let msg = match db.fetch_full_message(id.0) {
+                        Ok(v) => v,
+                        Err(err) => {
+                            log::error!("message {}: {err}", id.0);
+                            continue;
+                        }
+                    };
+                    let text = match serde_json::to_string(&(id.0, msg)) {
+                        Ok(v) => v,
+                        Err(err) => {
+                            log::error!("message {}: {err}", id.0);
+                            continue;
+                        }
+                    };
+                    if socket.send(warp::ws::Message::text(text)).await.is_err() {
+                        break;
+                    }
+                }
+            })
+        })
+}
+
+fn readyz(
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("readyz").map(move || -> WithStatus<Json> {
+        let health = crate::metrics::health();
+        let status = if health.ready {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        reply::with_status(reply::json(&health), status)
+    })
+}
+
 fn openapi(
 ) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static {
     warp::path!("openapi")
@@ -332,6 +1255,9 @@ fn openapi(
 fn routes(
     db: DbCore,
     app: Option<Application>,
+    timeout: Duration,
+    feed: RawFeed,
+    cx: CxHandle,
 ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone + Sync + Send + 'static {
     use warp::reply::with;
 
@@ -362,36 +1288,100 @@ fn routes(
         // .with(with::header("Access-Control-Allow-Origin", "*"))
         .with(cors_filter.clone());
 
+    let pcap = warp::get()
+        .and(connection_pcap(db.clone(), timeout))
+        .with(with::header("Content-Type", "application/vnd.tcpdump.pcap"))
+        .with(cors_filter.clone());
+
+    // the header is not json, so it is outside of `gets`
+    let ndjson = warp::get()
+        .and(messages_stream(db.clone()))
+        .with(cors_filter.clone());
+
+    let text = warp::get()
+        .and(metrics_openmetrics(
+            db.clone(),
+            app.clone(),
+            cx.clone(),
+            timeout,
+        ))
+        .with(with::header(
+            "Content-Type",
+            "application/openmetrics-text; version=1.0.0; charset=utf-8",
+        ))
+        .with(cors_filter.clone());
+
     let gets = warp::get().and(
         connection(db.clone())
-            .or(connections(db.clone()))
+            .or(connection_ping(db.clone()))
+            .or(connection_yamux(db.clone()))
+            .or(connection_bandwidth(db.clone()))
+            .or(connection_bitswap(db.clone()))
+            .or(bitswap_block(db.clone()))
+            .or(connection_desync(db.clone()))
+            .or(connection_by_tuple(db.clone()))
+            .or(connections_count(db.clone()))
+            .or(connections(db.clone(), timeout))
             .or(message(db.clone()))
             .or(message_hex(db.clone()))
-            .or(messages(db.clone()))
+            .or(stream_frames(db.clone(), timeout))
+            .or(messages(db.clone(), timeout))
+            .or(streams_problematic(db.clone()))
             .or(stats(db.clone()))
             .or(stats_last(db.clone()))
             .or(stats_latest(db.clone()))
             .or(stats_block_v2(db.clone()))
             .or(stats_block_v2_latest(db.clone()))
+            .or(stats_producers(db.clone()))
+            .or(block_propagation(db.clone()))
             .or(stats_tx(db.clone()))
             .or(stats_tx_latest(db.clone()))
+            .or(stats_tx_latency(db.clone(), timeout))
+            .or(stats_subnets(db.clone(), timeout))
+            .or(stats_decrypt(db.clone(), cx.clone(), timeout))
+            .or(stats_prunes(db.clone()))
+            .or(peers_reputation(db.clone(), timeout))
+            .or(stats_node_status(db.clone()))
+            .or(node_metrics(db.clone()))
+            .or(check_stream_order(db.clone(), timeout))
+            .or(gating(db.clone()))
             .or(snark(db.clone()))
             .or(capnp(db.clone()))
             .or(libp2p_ipc(db.clone()))
+            .or(correlate_gossip(db.clone()))
+            .or(gossip_path(db.clone()))
             .or(capnp_latest(db.clone()))
             .or(libp2p_ipc_latest(db.clone()))
-            .or(libp2p_ipc_all(db))
-            .or(firewall_stats(app.clone()))
-            .or(version().or(openapi())),
+            .or(libp2p_ipc_all(db.clone(), timeout))
+            .or(ipc_peers(cx.clone()))
+            .or(firewall_blocked(app.clone()))
+            .or(firewall_whitelist(app.clone()))
+            .or(pid_bytes(app.clone()))
+            .or(version().or(openapi()))
+            .or(metrics())
+            .or(readyz()),
+    );
+    let posts = warp::post().and(
+        firewall_whitelist_set(app.clone())
+            .or(firewall_whitelist_clear(app))
+            .or(connection_redecrypt(cx, timeout))
+            .or(admin_reindex(db.clone()))
+            .or(admin_checkpoint(db.clone())),
     );
-    let posts =
-        warp::post().and(firewall_whitelist_set(app.clone()).or(firewall_whitelist_clear(app)));
 
-    gets.or(posts)
+    let json = gets
+        .or(posts)
         .with(with::header("Content-Type", "application/json"))
         // .with(with::header("Access-Control-Allow-Origin", "*"))
-        .with(cors_filter)
+        .with(cors_filter);
+
+    // before `json`, it only matches `/metrics` if the client asks for the text format
+    text.or(json)
         .or(binary)
+        .or(pcap)
+        .or(ndjson)
+        .or(warp::get().and(ws_raw(feed)))
+        .or(warp::get().and(messages_live(db)))
 }
 
 pub fn spawn<P, Q, R>(
@@ -400,14 +1390,16 @@ pub fn spawn<P, Q, R>(
     app: Option<Application>,
     key_path: Option<Q>,
     cert_path: Option<R>,
+    feed: RawFeed,
+    cx: CxHandle,
 ) -> (DbFacade, impl FnOnce(), thread::JoinHandle<()>)
 where
     P: AsRef<Path>,
     Q: AsRef<Path>,
     R: AsRef<Path>,
 {
-    use std::process;
-    use tokio::{sync::oneshot, runtime::Runtime};
+    use std::{env, process};
+    use tokio::{sync::oneshot, runtime::Runtime, net::TcpSocket};
 
     let rt = match Runtime::new() {
         Ok(v) => v,
@@ -427,13 +1419,25 @@ where
         }
     };
     log::info!("using db {}", path.as_ref().display());
-    let addr = ([0, 0, 0, 0], port);
-    let routes = routes(db.core(), app);
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let backlog = env::var("SERVER_BACKLOG")
+        .ok()
+        .and_then(|s| s.parse::<u32>().ok())
+        .unwrap_or(1024);
+    let timeout = env::var("SERVER_REQUEST_TIMEOUT")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(Duration::from_secs(30));
+    let routes = routes(db.core(), app, timeout, feed, cx);
     let shutdown = async move {
         rx.await.expect("corresponding sender should exist");
         log::info!("terminating http server...");
     };
     let handle = if let (Some(key_path), Some(cert_path)) = (key_path, cert_path) {
+        if env::var("SERVER_BACKLOG").is_ok() {
+            log::warn!("`SERVER_BACKLOG` is ignored for https");
+        }
         let (_, server) = warp::serve(routes)
             .tls()
             .key_path(key_path)
@@ -441,9 +1445,202 @@ where
             .bind_with_graceful_shutdown(addr, shutdown);
         thread::spawn(move || rt.block_on(server))
     } else {
-        let (_, server) = warp::serve(routes).bind_with_graceful_shutdown(addr, shutdown);
+        // bind manually, warp does not allow to set the listen backlog
+        let listener = TcpSocket::new_v4()
+            .and_then(|socket| {
+                socket.set_reuseaddr(true)?;
+                socket.bind(addr)?;
+                socket.listen(backlog)
+            })
+            .unwrap_or_else(|err| {
+                log::error!("fatal: cannot listen {addr}: {err}");
+                process::exit(1);
+            });
+        let incoming = futures_util::stream::unfold(listener, |listener| async move {
+            let stream = listener.accept().await.map(|(stream, _)| stream);
+            Some((stream, listener))
+        });
+        let server = warp::serve(routes).serve_incoming_with_graceful_shutdown(incoming, shutdown);
         thread::spawn(move || rt.block_on(server))
     };
     let callback = move || tx.send(()).expect("corresponding receiver should exist");
     (db, callback, handle)
 }
+
+#[cfg(test)]
+#[test]
+fn live_messages() {
+    use std::time::SystemTime;
+
+    use super::database::{FullMessage, StreamId, add_test_message, test_connection, test_db};
+
+    let (_d, db) = test_db();
+    let group = test_connection(&db, SystemTime::UNIX_EPOCH);
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let path = format!("/messages/live?stream_kind={}", StreamKind::Ping);
+        let mut client = warp::test::ws()
+            .path(&path)
+            .handshake(messages_live(db.core()))
+            .await
+            .unwrap();
+
+        let time = SystemTime::UNIX_EPOCH;
+        // filtered out
+        add_test_message(
+            &group,
+            time,
+            StreamId::Handshake,
+            StreamKind::Unknown,
+            b"hello",
+        );
+        add_test_message(
+            &group,
+            time,
+            StreamId::Forward(1),
+            StreamKind::Ping,
+            &[0; 32],
+        );
+
+        let msg = client.recv().await.unwrap();
+        let (id, msg) = serde_json::from_str::<(u64, FullMessage)>(msg.to_str().unwrap()).unwrap();
+        assert_eq!(id, 1);
+        assert_eq!(msg.stream_kind, StreamKind::Ping);
+        assert_eq!(msg.size, 32);
+    });
+}
+
+#[cfg(test)]
+#[test]
+fn message_raw() {
+    use super::database::db_with_messages;
+
+    let (_d, db) = db_with_messages(StreamKind::Unknown, [b"hello"]);
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let raw = warp::test::request()
+            .path("/message/0/raw")
+            .reply(&message_bin(db.core()))
+            .await;
+        assert_eq!(raw.status(), StatusCode::OK);
+        assert_eq!(raw.body().as_ref(), b"hello");
+
+        let hex = warp::test::request()
+            .path("/message/0/hex")
+            .reply(&message_hex(db.core()))
+            .await;
+        assert_eq!(hex.body().as_ref(), b"\"68656c6c6f\"");
+
+        let absent = warp::test::request()
+            .path("/message/1/raw")
+            .reply(&message_bin(db.core()))
+            .await;
+        assert_eq!(absent.status(), StatusCode::NOT_FOUND);
+    });
+}
+
+#[cfg(test)]
+#[test]
+fn stats_decryption_alias() {
+    use super::database::db_with_messages;
+
+    let (_d, db) = db_with_messages(StreamKind::Unknown, [b"hello"]);
+    let filter = stats_decrypt(db.core(), CxHandle::default(), Duration::from_secs(1));
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let mut bodies = vec![];
+        for path in ["/stats/decrypt", "/stats/decryption"] {
+            let reply = warp::test::request().path(path).reply(&filter).await;
+            assert_eq!(reply.status(), StatusCode::OK);
+            bodies.push(reply.into_body());
+        }
+        assert_eq!(bodies[0], bodies[1]);
+    });
+}
+
+#[cfg(test)]
+#[test]
+fn connection_by_tuple_adjacent_bucket() {
+    use super::{database::test_db, EventMetadata};
+
+    let (_d, db) = test_db();
+    let initiator = "10.0.0.2:40000".parse().unwrap();
+    let responder = "10.0.0.1:8302".parse().unwrap();
+    // this side connected a second after the bucket boundary
+    let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1011);
+    let metadata = EventMetadata::default();
+    let _group = db
+        .add(
+            metadata.id,
+            true,
+            StableConnectionId::new(initiator, responder, time),
+            String::new(),
+            time,
+            metadata.time_source,
+            metadata.lag,
+        )
+        .unwrap();
+
+    let filter = connection_by_tuple(db.core());
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        // the peer connected a second before it
+        let path =
+            "/connection/by-tuple?initiator=10.0.0.2:40000&responder=10.0.0.1:8302&timestamp=1009";
+        let reply = warp::test::request().path(path).reply(&filter).await;
+        assert_eq!(reply.status(), StatusCode::OK);
+        let v = serde_json::from_slice::<Vec<(u64, serde_json::Value)>>(reply.body()).unwrap();
+        assert_eq!(v.len(), 1);
+        assert_eq!(v[0].0, 0);
+
+        // another initiator port is another connection
+        let path =
+            "/connection/by-tuple?initiator=10.0.0.2:40001&responder=10.0.0.1:8302&timestamp=1011";
+        let reply = warp::test::request().path(path).reply(&filter).await;
+        assert_eq!(reply.body().as_ref(), b"[]");
+    });
+}
+
+#[cfg(test)]
+#[test]
+fn ipc_peers_served() {
+    use super::{database::test_db, IpcPeer, P2pRecorder};
+
+    let (_d, db) = test_db();
+    let cx = CxHandle::default();
+    let filter = ipc_peers(cx.clone());
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let reply = warp::test::request()
+            .path("/ipc/peers")
+            .reply(&filter)
+            .await;
+        assert_eq!(reply.status(), StatusCode::SERVICE_UNAVAILABLE);
+    });
+
+    let mut recorder = P2pRecorder::new(db, false);
+    cx.set(recorder.cx.clone());
+    recorder.on_peer_cred(IpcPeer {
+        pid: 10,
+        fd: 7,
+        peer_pid: 11,
+        uid: 1000,
+        gid: 100,
+        watched: false,
+    });
+
+    rt.block_on(async {
+        let reply = warp::test::request()
+            .path("/ipc/peers")
+            .reply(&filter)
+            .await;
+        assert_eq!(reply.status(), StatusCode::OK);
+        let v = serde_json::from_slice::<serde_json::Value>(reply.body()).unwrap();
+        assert_eq!(v[0]["peer_pid"], 11);
+        assert_eq!(v[0]["uid"], 1000);
+    });
+}
@@ -20,6 +20,6 @@ impl SnarkWorkerState {
 fn snark_worked_rpc() {
     let data_str = "250000000000000002020021003d0e4c640f07d941490e4c640f07d941000000000000f0bf5e0e4c640f07d941";
     let data = hex::decode(data_str).unwrap();
-    let msg = crate::decode::rpc::parse(data, false).unwrap();
+    let msg = crate::decode::rpc::parse(&data, false).unwrap();
     println!("{msg}");
 }
@@ -1,3 +1,5 @@
+pub mod reputation;
+
 use std::{collections::BTreeMap, time::SystemTime, net::SocketAddr, sync::atomic::AtomicUsize};
 
 use mina_p2p_messages::{gossip::GossipNetMessageV2, v2};
@@ -10,7 +12,7 @@ use crate::{
         meshsub::{self, ControlIHave, ControlIWant},
         MessageType,
     },
-    database::{DbFacade, DbError},
+    database::{DbFacade, DbError, BlockProvenance},
 };
 
 #[derive(Default, Absorb, Emit)]
@@ -222,6 +224,9 @@ impl StatsState {
 
                                 None
                             };
+                            if let Some(latency) = latency {
+                                crate::metrics::block_latency(latency, message_id, time);
+                            }
                             let event = Event {
                                 producer_id,
                                 hash,
@@ -352,8 +357,26 @@ impl StatsState {
     }
 }
 
-pub fn update_block_stats(
-    message_id: u64,
+/// The blocks of the meshsub message and their stats events, the events get the message id
+/// once the message is stored.
+pub struct BlockStats {
+    pub blocks: Vec<BlockProvenance>,
+    events: Vec<Event>,
+}
+
+impl BlockStats {
+    pub fn store(self, message_id: u64, db: &DbFacade) -> Result<(), DbError> {
+        for event in self.events {
+            db.stats_block_v2(Event {
+                message_id,
+                ..event
+            })?;
+        }
+        Ok(())
+    }
+}
+
+pub fn block_stats(
     msg: &[u8],
     incoming: bool,
     time: SystemTime,
@@ -361,7 +384,9 @@ pub fn update_block_stats(
     peer: SocketAddr,
     node_address: SocketAddr,
     db: &DbFacade,
-) -> Result<(), DbError> {
+) -> Result<BlockStats, DbError> {
+    let mut blocks = vec![];
+    let mut events = vec![];
     let (sender_addr, receiver_addr) = if incoming {
         (peer, node_address)
     } else {
@@ -390,23 +415,40 @@ pub fn update_block_stats(
                         global_slot,
                         incoming,
                         message_kind: MessageType::PublishNewState,
-                        message_id,
+                        message_id: 0,
                         time,
                         better_time,
                         latency: None,
                         sender_addr,
                         receiver_addr,
                     };
-                    db.stats_block_v2(event)?;
+                    let provenance = BlockProvenance {
+                        hash: Hash(hash),
+                        height: block_height,
+                    };
+                    // the body is served over bitswap, its root cid is the body reference
+                    let body_reference = &block
+                        .header
+                        .protocol_state
+                        .body
+                        .blockchain_state
+                        .body_reference;
+                    if let Ok(digest) = <[u8; 32]>::try_from(body_reference.0.as_ref()) {
+                        db.put_bitswap_block(digest, provenance.clone())?;
+                    }
+                    blocks.push(provenance);
+                    events.push(event);
                 }
                 _ => {}
             },
-            meshsub::Event::Control {
-                ihave: _,
-                iwant: _,
-                graft: _,
-                prune: _,
-            } => {}
+            meshsub::Event::Control { prune, .. } => {
+                // the peer removes us from its mesh
+                if incoming {
+                    for prune in &prune {
+                        db.add_prune(peer, time, prune.backoff())?;
+                    }
+                }
+            }
             meshsub::Event::PublishTestingMessage {
                 from,
                 message,
@@ -423,18 +465,22 @@ pub fn update_block_stats(
                         global_slot: block_height,
                         incoming,
                         message_kind: MessageType::PublishNewState,
-                        message_id,
+                        message_id: 0,
                         time,
                         better_time,
                         latency: None,
                         sender_addr,
                         receiver_addr,
                     };
-                    db.stats_block_v2(event)?;
+                    blocks.push(BlockProvenance {
+                        hash: Hash(hash),
+                        height: block_height,
+                    });
+                    events.push(event);
                 }
             }
             _ => {}
         }
     }
-    Ok(())
+    Ok(BlockStats { blocks, events })
 }
@@ -0,0 +1,191 @@
+use std::{collections::BTreeMap, net::SocketAddr, time::Duration};
+
+use serde::Serialize;
+
+// each penalty is `weight * x / (x + half)`, so it grows fast at first and never exceeds the weight,
+// `half` is the amount of the bad thing that costs half of the weight
+const DECODE_FAILURES: (f64, f64) = (30.0, 10.0);
+const STREAM_RESETS: (f64, f64) = (20.0, 20.0);
+const PRUNES: (f64, f64) = (15.0, 5.0);
+// milliseconds
+const RTT: (f64, f64) = (15.0, 500.0);
+// the share of duplicates among the gossip received from the peer
+const GOSSIP_DUPLICATES: f64 = 20.0;
+
+/// What the debugger observed about the peer, summed over all its connections.
+#[derive(Default, Clone, Serialize)]
+pub struct PeerFactors {
+    pub connections: u64,
+    pub messages: u64,
+    pub decode_failures: u64,
+    // reset by the peer
+    pub stream_resets: u64,
+    pub gossip_received: u64,
+    // the peer sent us the gossip we already had
+    pub gossip_duplicates: u64,
+    pub prunes: u64,
+    #[serde(skip)]
+    pub rtt_total: Duration,
+    #[serde(skip)]
+    pub rtt_samples: u64,
+}
+
+impl PeerFactors {
+    pub fn rtt_avg(&self) -> Option<Duration> {
+        if self.rtt_samples == 0 {
+            None
+        } else {
+            Some(self.rtt_total / self.rtt_samples as u32)
+        }
+    }
+
+    pub fn observe_rtt(&mut self, rtt: Duration) {
+        self.rtt_total += rtt;
+        self.rtt_samples += 1;
+    }
+}
+
+#[derive(Default, Clone, Serialize)]
+pub struct Penalties {
+    pub decode_failures: f64,
+    pub stream_resets: f64,
+    pub gossip_duplicates: f64,
+    pub prunes: f64,
+    pub rtt: f64,
+}
+
+impl Penalties {
+    pub fn total(&self) -> f64 {
+        self.decode_failures + self.stream_resets + self.gossip_duplicates + self.prunes + self.rtt
+    }
+}
+
+#[derive(Serialize)]
+pub struct PeerReputation {
+    pub peer: SocketAddr,
+    /// From 0 (worst) to 100 (nothing bad observed).
+    pub score: f64,
+    pub factors: PeerFactors,
+    pub rtt_avg_ms: Option<f64>,
+    pub penalties: Penalties,
+}
+
+fn saturating((weight, half): (f64, f64), x: f64) -> f64 {
+    weight * x / (x + half)
+}
+
+pub fn penalties(factors: &PeerFactors) -> Penalties {
+    let gossip_duplicates = if factors.gossip_received == 0 {
+        0.0
+    } else {
+        GOSSIP_DUPLICATES * factors.gossip_duplicates as f64 / factors.gossip_received as f64
+    };
+    Penalties {
+        decode_failures: saturating(DECODE_FAILURES, factors.decode_failures as f64),
+        stream_resets: saturating(STREAM_RESETS, factors.stream_resets as f64),
+        gossip_duplicates,
+        prunes: saturating(PRUNES, factors.prunes as f64),
+        rtt: factors
+            .rtt_avg()
+            .map(|rtt| saturating(RTT, rtt.as_secs_f64() * 1000.0))
+            .unwrap_or_default(),
+    }
+}
+
+/// The worst peers go first.
+pub fn rank(peers: BTreeMap<SocketAddr, PeerFactors>) -> Vec<PeerReputation> {
+    let mut v = peers
+        .into_iter()
+        .map(|(peer, factors)| {
+            let penalties = penalties(&factors);
+            PeerReputation {
+                peer,
+                score: (100.0 - penalties.total()).max(0.0),
+                rtt_avg_ms: factors.rtt_avg().map(|rtt| rtt.as_secs_f64() * 1000.0),
+                factors,
+                penalties,
+            }
+        })
+        .collect::<Vec<_>>();
+    v.sort_by(|a, b| a.score.total_cmp(&b.score).then(a.peer.cmp(&b.peer)));
+    v
+}
+
+#[cfg(test)]
+#[test]
+fn clean_peer_has_full_score() {
+    let factors = PeerFactors {
+        connections: 1,
+        messages: 1000,
+        gossip_received: 100,
+        ..Default::default()
+    };
+    let peer = "1.1.1.1:8302".parse().unwrap();
+    let v = rank([(peer, factors)].into_iter().collect());
+    assert_eq!(v[0].score, 100.0);
+}
+
+#[cfg(test)]
+#[test]
+fn misbehaving_peer_ranked_first() {
+    let good = "1.1.1.1:8302".parse().unwrap();
+    let bad = "2.2.2.2:8302".parse().unwrap();
+    let mut good_factors = PeerFactors {
+        connections: 1,
+        messages: 1000,
+        gossip_received: 100,
+        gossip_duplicates: 5,
+        prunes: 1,
+        ..Default::default()
+    };
+    good_factors.observe_rtt(Duration::from_millis(20));
+    let mut bad_factors = PeerFactors {
+        connections: 3,
+        messages: 1000,
+        decode_failures: 10,
+        stream_resets: 20,
+        gossip_received: 100,
+        gossip_duplicates: 50,
+        prunes: 5,
+        ..Default::default()
+    };
+    bad_factors.observe_rtt(Duration::from_millis(400));
+    bad_factors.observe_rtt(Duration::from_millis(600));
+
+    let v = rank(
+        [(good, good_factors), (bad, bad_factors)]
+            .into_iter()
+            .collect(),
+    );
+    assert_eq!(v[0].peer, bad);
+    assert_eq!(v[0].rtt_avg_ms, Some(500.0));
+    // every factor is at its `half` point
+    let p = &v[0].penalties;
+    assert_eq!(p.decode_failures, 15.0);
+    assert_eq!(p.stream_resets, 10.0);
+    assert_eq!(p.gossip_duplicates, 10.0);
+    assert_eq!(p.prunes, 7.5);
+    assert_eq!(p.rtt, 7.5);
+    assert_eq!(v[0].score, 50.0);
+    assert!(v[1].score > 90.0);
+}
+
+#[cfg(test)]
+#[test]
+fn score_never_negative() {
+    let factors = PeerFactors {
+        decode_failures: u32::MAX as u64,
+        stream_resets: u32::MAX as u64,
+        gossip_received: 1,
+        gossip_duplicates: 1,
+        prunes: u32::MAX as u64,
+        rtt_total: Duration::from_secs(3600),
+        rtt_samples: 1,
+        ..Default::default()
+    };
+    let p = penalties(&factors);
+    assert!(p.total() <= 100.0);
+    let peer = "1.1.1.1:8302".parse().unwrap();
+    let v = rank([(peer, factors)].into_iter().collect());
+    assert!(v[0].score >= 0.0);
+}
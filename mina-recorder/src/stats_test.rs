@@ -6,7 +6,7 @@ use std::{
 
 use temp_dir::TempDir;
 
-use crate::{database::DbFacade, stats::update_block_stats};
+use crate::{database::DbFacade, stats::block_stats};
 
 use super::stats::StatsState;
 
@@ -25,6 +25,13 @@ fn peer(port: u16) -> SocketAddr {
     ([1, 1, 1, 1], port).into()
 }
 
+fn update_block_stats(message_id: u64, msg: &[u8], time: SystemTime, db: &DbFacade) {
+    block_stats(msg, true, time, time, peer(1), peer(2), db)
+        .unwrap()
+        .store(message_id, db)
+        .unwrap();
+}
+
 fn generic<F>(f: F)
 where
     F: Fn(SystemTime, &DbFacade, &mut StatsState),
@@ -189,9 +196,9 @@ fn check_cleanup_obsolete() {
 #[test]
 fn check_block_v2_latest() {
     generic(|now, db, _state| {
-        update_block_stats(0, FILES[0], true, now, now, peer(1), peer(2), db).unwrap();
-        update_block_stats(1, FILES[1], true, now, now, peer(1), peer(2), db).unwrap();
-        update_block_stats(0, FILES[0], true, now, now, peer(1), peer(2), db).unwrap();
+        update_block_stats(0, FILES[0], now, db);
+        update_block_stats(1, FILES[1], now, db);
+        update_block_stats(0, FILES[0], now, db);
 
         let (height, events) = db.core().fetch_last_stat_block_v2().unwrap();
         assert_eq!(height, 638);
@@ -209,7 +216,7 @@ fn check_order_block_v2() {
                 now + Duration::from_nanos(d as u64)
             };
 
-            update_block_stats(i as u64, FILES[0], true, t, t, peer(1), peer(2), db).unwrap();
+            update_block_stats(i as u64, FILES[0], t, db);
         }
 
         let (_, events) = db.core().fetch_last_stat_block_v2().unwrap();
@@ -237,10 +244,7 @@ fn check_mt_order_block_v2() {
                     now + Duration::from_nanos(d as u64)
                 };
 
-                s.spawn(move || {
-                    update_block_stats(i as u64, FILES[0], true, t, t, peer(1), peer(2), db)
-                        .unwrap();
-                });
+                s.spawn(move || update_block_stats(i as u64, FILES[0], t, db));
             }
         });
 
@@ -0,0 +1,62 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+struct Counting;
+
+thread_local! {
+    // heap bytes of this thread, only the allocations made while measuring are seen
+    static CURRENT: Cell<usize> = const { Cell::new(0) };
+    static PEAK: Cell<usize> = const { Cell::new(0) };
+}
+
+fn grow(size: usize) {
+    let _ = CURRENT.try_with(|current| {
+        current.set(current.get() + size);
+        let _ = PEAK.try_with(|peak| peak.set(peak.get().max(current.get())));
+    });
+}
+
+fn shrink(size: usize) {
+    let _ = CURRENT.try_with(|current| current.set(current.get().saturating_sub(size)));
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            grow(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        shrink(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            shrink(layout.size());
+            grow(new_size);
+        }
+        new_ptr
+    }
+}
+
+/// Runs `f` and tells the peak of the heap it used on this thread, in bytes.
+/// The buffers rocksdb allocates itself are not on the rust heap, they are not counted.
+pub fn peak<F, R>(f: F) -> (R, usize)
+where
+    F: FnOnce() -> R,
+{
+    CURRENT.with(|current| current.set(0));
+    PEAK.with(|peak| peak.set(0));
+    let r = f();
+    (r, PEAK.with(Cell::get))
+}
@@ -1,11 +1,22 @@
 use super::event::{EventMetadata, ConnectionInfo};
 
-#[derive(Default)]
-pub struct Tester {}
+pub struct Tester {
+    sentinel: Vec<u8>,
+}
+
+impl Default for Tester {
+    fn default() -> Self {
+        // must match `--sentinel` of the tester, so several testers don't stop each other
+        let sentinel = std::env::var("TEST_SENTINEL").unwrap_or_else(|_| "test-is-passed".into());
+        Tester {
+            sentinel: sentinel.into_bytes(),
+        }
+    }
+}
 
 impl Tester {
     pub fn on_connect(&mut self, incoming: bool, metadata: EventMetadata) {
-        let ConnectionInfo { addr, pid, fd } = &metadata.id;
+        let ConnectionInfo { addr, pid, fd, .. } = &metadata.id;
         if incoming {
             log::info!("{pid} accept {addr} {fd}");
         } else {
@@ -14,13 +25,13 @@ impl Tester {
     }
 
     pub fn on_disconnect(&mut self, metadata: EventMetadata) {
-        let ConnectionInfo { addr, pid, fd } = &metadata.id;
+        let ConnectionInfo { addr, pid, fd, .. } = &metadata.id;
         log::info!("{pid} disconnect {addr} {fd}");
     }
 
     pub fn on_data(&mut self, incoming: bool, metadata: EventMetadata, bytes: Vec<u8>) {
         let _ = (incoming, metadata);
-        if bytes == b"test-is-passed" {
+        if bytes == self.sentinel {
             println!("test is passed");
             std::process::exit(0);
         } else {
@@ -87,6 +87,7 @@ fn main() -> anyhow::Result<()> {
                 "stream-messages-number" => tests::test_stream_messages_number(&summary),
                 "stream-messages-order" => tests::test_stream_messages_order(&summary),
                 "stream-messages-order-time" => tests::test_stream_messages_order_time(&summary),
+                "db-stream-order" => tests::test_db_stream_order(&summary),
                 "network-checksum" => {
                     let mut ok = true;
 
@@ -12,6 +12,8 @@ pub struct TestReport {
     pub timestamps: DbTestTimestampsReport,
     pub events: DbTestEventsReport,
     pub order: DbTestOrderReport,
+    #[serde(default)]
+    pub stream_order: DbTestStreamOrderReport,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -61,6 +63,15 @@ pub struct DbTestOrderReport {
     pub unordered_time: Vec<(u64, bool, SystemTime, SystemTime)>,
 }
 
+/// Result of the debugger's own check over every recorded stream.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct DbTestStreamOrderReport {
+    pub streams: u64,
+    pub messages: u64,
+    pub violations: Vec<serde_json::Value>,
+    pub error: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct DbEventWithMetadata {
     pub time_microseconds: u64,
@@ -112,11 +123,13 @@ pub fn run(started: SystemTime, events: Vec<DbEventWithMetadata>, peer_id: Strin
     let timestamps = test_messages_timestamps(&client, started);
     let events = test_events(events, peer_id);
     let order = test_order(&client);
+    let stream_order = test_stream_order(&client);
 
     TestReport {
         timestamps,
         events,
         order,
+        stream_order,
     }
 }
 
@@ -303,6 +316,27 @@ pub fn test_order(client: &Client) -> DbTestOrderReport {
     report
 }
 
+pub fn test_stream_order(client: &Client) -> DbTestStreamOrderReport {
+    let get = || -> anyhow::Result<DbTestStreamOrderReport> {
+        let res = client
+            .get("http://localhost:8000/check/stream_order")
+            .send()?
+            .error_for_status()?
+            .text()?;
+        Ok(serde_json::from_str(&res)?)
+    };
+    match get() {
+        Ok(v) => v,
+        Err(err) => {
+            log::error!("{err}");
+            DbTestStreamOrderReport {
+                error: Some(err.to_string()),
+                ..Default::default()
+            }
+        }
+    }
+}
+
 pub fn test_events(events: Vec<DbEventWithMetadata>, peer_id: String) -> DbTestEventsReport {
     #[derive(Clone, Deserialize)]
     #[allow(dead_code)]
@@ -65,12 +65,25 @@ pub struct DebuggerReport {
     pub network: Vec<ConnectionMetadata>,
 }
 
+impl DebuggerReport {
+    /// The latest connection on the fd with the generation, the data on the fd belongs to it.
+    pub fn connection_mut(&mut self, fd: u32, generation: u32) -> Option<&mut ConnectionMetadata> {
+        self.network
+            .iter_mut()
+            .rev()
+            .find(|cn| cn.fd as u32 == fd && cn.generation == generation)
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct ConnectionMetadata {
     pub ip: IpAddr,
     pub counter: usize,
     pub incoming: bool,
     pub fd: i32,
+    // the fd may be reused, tells apart connections on the same fd
+    #[serde(default)]
+    pub generation: u32,
     pub checksum: ChecksumPair,
     pub timestamp: SystemTime,
 }
@@ -81,3 +94,39 @@ pub struct MockSplitReport {
     pub after_split: (Vec<Peer>, SystemTime),
     pub after_reunite: (Vec<Peer>, SystemTime),
 }
+
+#[cfg(test)]
+#[test]
+fn checksum_of_reused_fd() {
+    let cn = |generation| ConnectionMetadata {
+        ip: "10.0.0.2".parse().unwrap(),
+        counter: 0,
+        incoming: false,
+        fd: 7,
+        generation,
+        checksum: ChecksumPair::default(),
+        timestamp: SystemTime::UNIX_EPOCH,
+    };
+    let mut report = DebuggerReport {
+        version: String::new(),
+        ipc: ChecksumPair::default(),
+        network: vec![cn(1), cn(2)],
+    };
+
+    // a late chunk of the first connection must not go to the second
+    report.connection_mut(7, 1).unwrap().checksum.0 += &b"late"[..];
+    report.connection_mut(7, 2).unwrap().checksum.1 += &b"hello"[..];
+    let lens = report
+        .network
+        .iter()
+        .map(|cn| cn.checksum.bytes_number())
+        .collect::<Vec<_>>();
+    assert_eq!(lens, [4, 5]);
+    assert!(report.connection_mut(8, 1).is_none());
+
+    // reports written before the generation was known
+    let mut old = serde_json::to_value(cn(0)).unwrap();
+    old.as_object_mut().unwrap().remove("generation");
+    let old = serde_json::from_value::<ConnectionMetadata>(old).unwrap();
+    assert_eq!(old.generation, 0);
+}
@@ -165,6 +165,29 @@ pub fn test_stream_messages_order_time(summary: &BTreeMap<IpAddr, Summary>) -> a
     })
 }
 
+pub fn test_db_stream_order(summary: &BTreeMap<IpAddr, Summary>) -> anyhow::Result<()> {
+    println!("This test checks that within each stream recorded by the debugger message ids grow together with timestamps.");
+    test_local(summary, |mock_report| {
+        let report = &mock_report.test.stream_order;
+        if let Some(err) = &report.error {
+            log::error!("cannot check stream order: {err}");
+            return false;
+        }
+        let success = report.violations.is_empty();
+        if success {
+            log::info!(
+                "{} streams, {} messages are ordered",
+                report.streams,
+                report.messages
+            );
+        } else {
+            log::error!("{} messages are out of order", report.violations.len());
+            log::info!("{:?}", report.violations);
+        }
+        success
+    })
+}
+
 fn test_local(
     summary: &BTreeMap<IpAddr, Summary>,
     assertion: impl Fn(&MockReport) -> bool,
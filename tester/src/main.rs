@@ -6,17 +6,46 @@ use std::{
     time::Duration,
 };
 
-const P2P_PORT: u16 = 8302;
+use structopt::StructOpt;
 
-const WIDTH: usize = 16;
-const SERIES: usize = 16;
+#[derive(StructOpt)]
+struct Args {
+    #[structopt(long, default_value = "8302")]
+    port: u16,
+    // connections at the same time
+    #[structopt(long, alias = "connections", default_value = "16")]
+    width: usize,
+    // how many times to open `width` connections
+    #[structopt(long, default_value = "16")]
+    series: usize,
+    // writes on each connection
+    #[structopt(long, default_value = "16")]
+    writes: usize,
+    // maximal size of a write, the minimal is 4096 bytes, or the chunk size if it is smaller
+    #[structopt(long, default_value = "65536")]
+    chunk_size: usize,
+    // the debugger exits when it sees this message, must match its `TEST_SENTINEL`
+    #[structopt(long, default_value = "test-is-passed")]
+    sentinel: String,
+}
 
 fn main() {
+    let Args {
+        port,
+        width,
+        series,
+        writes,
+        chunk_size,
+        sentinel,
+    } = Args::from_args();
+    let chunk_size = chunk_size.max(1);
+    let min_size = chunk_size.min(0x1000);
+
     let listener_thread = thread::spawn(move || {
-        let listener = TcpListener::bind(("127.0.0.1", P2P_PORT)).unwrap();
+        let listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
 
         let mut stream_threads = vec![];
-        for _ in 0..((WIDTH * SERIES) + 1) {
+        for _ in 0..((width * series) + 1) {
             let (mut stream, _) = listener.accept().unwrap();
             let stream_thread = thread::spawn(move || {
                 let mut buf = [0; 0x1000];
@@ -30,24 +59,23 @@ fn main() {
         }
     });
 
-    let data = Arc::new([0x11; 0x10000]);
-    let data_false = Arc::new([0x22; 0x10000]);
-    for _ in 0..SERIES {
+    let data = Arc::new(vec![0x11; chunk_size]);
+    let data_false = Arc::new(vec![0x22; chunk_size]);
+    for _ in 0..series {
         let mut stream_threads = vec![];
-        for _ in 0..WIDTH {
+        for _ in 0..width {
             let stream_thread = {
                 let data = data.clone();
                 let data_false = data_false.clone();
                 thread::spawn(move || {
-                    let mut stream = TcpStream::connect(("127.0.0.1", P2P_PORT)).unwrap();
-                    for _ in 0..16 {
-                        stream
-                            .write_all(&data[..(0x1000 + (rand::random::<usize>() % 0xf000))])
-                            .unwrap();
+                    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+                    for _ in 0..writes {
+                        let len = min_size + rand::random::<usize>() % (chunk_size - min_size + 1);
+                        stream.write_all(&data[..len]).unwrap();
                     }
                     fs::File::create("/tmp/test")
                         .unwrap()
-                        .write_all(&data_false[..0x1000])
+                        .write_all(&data_false[..min_size])
                         .unwrap();
                 })
             };
@@ -60,8 +88,8 @@ fn main() {
         }
     }
 
-    let mut stream = TcpStream::connect(("127.0.0.1", P2P_PORT)).unwrap();
-    stream.write_all(b"test-is-passed").unwrap();
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    stream.write_all(sentinel.as_bytes()).unwrap();
     drop(stream);
 
     listener_thread.join().unwrap();
@@ -2,6 +2,9 @@ use std::{
     time::Duration,
     net::IpAddr,
     collections::{BTreeSet, BTreeMap},
+    str::FromStr,
+    sync::Mutex,
+    thread,
 };
 
 use reqwest::{
@@ -26,6 +29,9 @@ struct Args {
     prod0s: Option<u16>,
     #[structopt(long)]
     seeds: Option<u16>,
+    // how many nodes are queried at the same time
+    #[structopt(long, default_value = "16")]
+    concurrency: usize,
     #[structopt(subcommand)]
     command: Command,
 }
@@ -41,9 +47,50 @@ enum Command {
     ShowGraph {
         #[structopt(long)]
         expected_components: Option<usize>,
+        // `dot` or `json`
+        #[structopt(long, default_value = "dot")]
+        format: GraphFormat,
     },
 }
 
+enum GraphFormat {
+    Dot,
+    Json,
+}
+
+impl FromStr for GraphFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dot" => Ok(GraphFormat::Dot),
+            "json" => Ok(GraphFormat::Json),
+            _ => Err(format!("unknown format {s}, expected `dot` or `json`")),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct GraphJson<'a> {
+    nodes: Vec<NodeJson<'a>>,
+    edges: Vec<EdgeJson<'a>>,
+    connected_components: usize,
+}
+
+#[derive(Serialize)]
+struct NodeJson<'a> {
+    name: &'a str,
+    ip: IpAddr,
+    // hash of the latest block the node gossiped or received
+    head: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct EdgeJson<'a> {
+    from: &'a str,
+    to: &'a str,
+}
+
 #[derive(Deserialize, Debug)]
 struct GraphqlResponse {
     data: GraphqlResponseData,
@@ -289,11 +336,45 @@ fn query_peer(client: &Client, url: &str, name: &str) -> anyhow::Result<NodeInfo
     })
 }
 
-fn show_graph(graph: &[NodeInfo]) -> usize {
+/// Queries the nodes on `concurrency` threads, the result is sorted by name.
+fn query_peers<I>(client: &Client, url: &str, names: I, concurrency: usize) -> Vec<NodeInfo>
+where
+    I: Iterator<Item = String> + Send,
+{
+    let names = Mutex::new(names);
+    let mut graph = thread::scope(|s| {
+        let workers = (0..concurrency.max(1))
+            .map(|_| {
+                s.spawn(|| {
+                    let mut nodes = vec![];
+                    loop {
+                        let Some(name) = names.lock().expect("poisoned").next() else {
+                            break;
+                        };
+                        match query_peer(client, url, &name) {
+                            Ok(v) => nodes.push(v),
+                            Err(err) => log::error!("name {name}, error: {err}"),
+                        }
+                    }
+                    nodes
+                })
+            })
+            .collect::<Vec<_>>();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("query thread panicked"))
+            .collect::<Vec<_>>()
+    });
+    graph.sort_by(|a, b| a.name.cmp(&b.name));
+    graph
+}
+
+fn show_graph(graph: &[NodeInfo], format: &GraphFormat) -> usize {
     use petgraph::{prelude::DiGraph, algo, dot};
 
     let mut gr = DiGraph::new();
     let mut ips = BTreeMap::new();
+    let mut edges = vec![];
     let ip_to_name = graph
         .iter()
         .map(|NodeInfo { ip, name, .. }| (*ip, name.clone()))
@@ -310,19 +391,46 @@ fn show_graph(graph: &[NodeInfo]) -> usize {
             .or_insert_with(|| gr.add_node(name.clone()));
 
         for &ip_b in peers {
-            let Some(name) = ip_to_name.get(&ip_b) else {
+            let Some(name_b) = ip_to_name.get(&ip_b) else {
                 continue;
             };
 
-            let b = *ips.entry(ip_b).or_insert_with(|| gr.add_node(name.clone()));
+            let b = *ips
+                .entry(ip_b)
+                .or_insert_with(|| gr.add_node(name_b.clone()));
             gr.add_edge(a, b, ());
+            edges.push(EdgeJson {
+                from: name,
+                to: name_b,
+            });
         }
     }
 
-    let config = [dot::Config::EdgeNoLabel];
-
-    println!("{:?}", dot::Dot::with_config(&gr, &config));
-    algo::connected_components(&gr)
+    let connected_components = algo::connected_components(&gr);
+    match format {
+        GraphFormat::Dot => {
+            let config = [dot::Config::EdgeNoLabel];
+            println!("{:?}", dot::Dot::with_config(&gr, &config));
+        }
+        GraphFormat::Json => {
+            let nodes = graph
+                .iter()
+                .map(|NodeInfo { ip, name, head, .. }| NodeJson {
+                    name,
+                    ip: *ip,
+                    head: head.as_deref(),
+                })
+                .collect();
+            let graph = GraphJson {
+                nodes,
+                edges,
+                connected_components,
+            };
+            let s = serde_json::to_string_pretty(&graph).expect("must not fail");
+            println!("{s}");
+        }
+    }
+    connected_components
 }
 
 fn main() -> anyhow::Result<()> {
@@ -352,6 +460,7 @@ fn main() -> anyhow::Result<()> {
         prod0s,
         command,
         seeds,
+        concurrency,
     } = Args::from_args();
     let client = ClientBuilder::new()
         .timeout(Duration::from_secs(10))
@@ -373,15 +482,7 @@ fn main() -> anyhow::Result<()> {
         Command::EnableFirewall { segments } => {
             log::info!("Applying whitelists...");
             if segments.is_empty() {
-                let graph = names
-                    .filter_map(|name| match query_peer(&client, &url, &name) {
-                        Ok(v) => Some(v),
-                        Err(err) => {
-                            log::error!("name {name}, error: {err}");
-                            None
-                        }
-                    })
-                    .collect::<Vec<_>>();
+                let graph = query_peers(&client, &url, names, concurrency);
                 enable_firewall(&client, url, &graph);
             } else {
                 enable_firewall_simple(&client, url, segments);
@@ -395,18 +496,11 @@ fn main() -> anyhow::Result<()> {
         }
         Command::ShowGraph {
             expected_components,
+            format,
         } => {
-            let graph = names
-                .filter_map(|name| match query_peer(&client, &url, &name) {
-                    Ok(v) => Some(v),
-                    Err(err) => {
-                        log::error!("name {name}, error: {err}");
-                        None
-                    }
-                })
-                .collect::<Vec<_>>();
+            let graph = query_peers(&client, &url, names, concurrency);
 
-            let components = show_graph(&graph);
+            let components = show_graph(&graph, &format);
             let _heads: BTreeSet<String> = graph
                 .iter()
                 .filter_map(|NodeInfo { name, head, .. }| {